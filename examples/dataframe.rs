@@ -31,7 +31,7 @@ fn main() {
         Field::new("lng", DataType::Double, false)]);
 
     // open a CSV file as a dataframe
-    let df1 = ctx.load("test/data/uk_cities.csv", &schema).unwrap();
+    let df1 = ctx.load("test/data/uk_cities.csv", &schema, &CsvOptions { has_header: false, ..CsvOptions::default() }).unwrap();
     println!("df1: {}", df1.schema().to_string());
 
     // filter on lat > 52.0
@@ -30,8 +30,8 @@ fn main() {
         Field::new("lat", DataType::Double, false),
         Field::new("lng", DataType::Double, false)]);
 
-    // register the csv file as a table that can be queried via sql
-    ctx.define_schema("uk_cities", &schema);
+    // register the csv file as a table that can be queried via sql, which has no header row
+    ctx.define_csv_table("uk_cities", &schema, &CsvOptions { has_header: false, ..CsvOptions::default() });
 
     // define the SQL statement
     let sql = "SELECT ST_AsText(ST_Point(lat, lng)) FROM uk_cities"; // WHERE lat < 53
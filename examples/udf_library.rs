@@ -0,0 +1,60 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This example is a UDF library that can be loaded at runtime with
+//! `ExecutionContext::load_udf_library` or `CREATE FUNCTION ... LIBRARY`. Build it with
+//! `cargo build --example udf_library`.
+
+extern crate datafusion;
+use datafusion::api::*;
+use datafusion::rel::*;
+
+use std::sync::Arc;
+
+/// Multiply a number by a factor
+struct Scale {
+    name: String,
+    factor: f64
+}
+
+impl ScalarFunction for Scale {
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn args(&self) -> Vec<Field> {
+        vec![Field::new("x", DataType::Double, false)]
+    }
+
+    fn return_type(&self) -> DataType {
+        DataType::Double
+    }
+
+    fn execute(&self, args: Vec<Value>) -> Result<Value, Box<String>> {
+        match args[0] {
+            Value::Double(d) => Ok(Value::Double(d * self.factor)),
+            Value::UnsignedLong(n) => Ok(Value::Double(n as f64 * self.factor)),
+            Value::Long(n) => Ok(Value::Double(n as f64 * self.factor)),
+            ref other => Err(Box::new(format!("Unsupported argument {:?}", other)))
+        }
+    }
+}
+
+/// Entry point called when the library is loaded
+#[no_mangle]
+pub fn datafusion_register_functions(registry: &mut FunctionRegistry) {
+    registry.register_scalar_function(Arc::new(Scale { name: "double_it".to_string(), factor: 2.0 }));
+    registry.register_scalar_function(Arc::new(Scale { name: "halve".to_string(), factor: 0.5 }));
+}
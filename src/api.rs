@@ -12,13 +12,68 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use super::rel::*;
 
-/// Scalar function. User-defined implementations will be dynamically loaded at runtime.
-pub trait ScalarFunction {
+/// Scalar function. User-defined implementations can be registered on the execution context
+/// directly or loaded at runtime from a UDF library (see `FunctionRegistry`).
+pub trait ScalarFunction: Send + Sync {
     fn name(&self) -> String;
     fn args(&self) -> Vec<Field>;
     fn return_type(&self) -> DataType;
     fn execute(&self, args: Vec<Value>) -> Result<Value,Box<String>>;
+
+    /// Whether the function always returns the same result for the same arguments. Calls with
+    /// literal arguments are only evaluated when the plan is optimized, and filters are only
+    /// moved by the optimizer, when the function is deterministic.
+    fn is_deterministic(&self) -> bool {
+        true
+    }
+}
+
+/// Aggregate function. The state of each group starts with `init`, is updated by `accumulate` for
+/// every input row where none of the arguments are null, and is turned into the result with
+/// `finalize`. States computed over different partitions of the same group are combined with
+/// `merge`, so the state should hold whatever is needed to do that (e.g. a sum and a count rather
+/// than an average).
+pub trait AggregateFunction: Send + Sync {
+    fn name(&self) -> String;
+    fn args(&self) -> Vec<Field>;
+    fn return_type(&self) -> DataType;
+    fn init(&self) -> Value;
+    fn accumulate(&self, state: &mut Value, args: Vec<Value>) -> Result<(),Box<String>>;
+    fn merge(&self, state: &mut Value, other: Value) -> Result<(),Box<String>>;
+    fn finalize(&self, state: Value) -> Result<Value,Box<String>>;
+}
+
+
+/// Name of the function that a UDF library exports to register its functions
+pub const UDF_REGISTRATION_SYMBOL: &str = "datafusion_register_functions";
+
+/// Signature of the registration function exported by a UDF library
+pub type UdfRegistrationFn = fn(&mut FunctionRegistry);
+
+/// Functions provided by a UDF library. A UDF library is a `cdylib` crate built against the same
+/// version of this crate, with the same compiler, that exports a `#[no_mangle]` function named
+/// `datafusion_register_functions` with the signature `fn(&mut FunctionRegistry)`.
+#[derive(Default)]
+pub struct FunctionRegistry {
+    pub scalar_functions: Vec<Arc<dyn ScalarFunction>>,
+    pub aggregate_functions: Vec<Arc<dyn AggregateFunction>>
 }
 
+impl FunctionRegistry {
+
+    pub fn new() -> Self {
+        FunctionRegistry::default()
+    }
+
+    pub fn register_scalar_function(&mut self, func: Arc<dyn ScalarFunction>) {
+        self.scalar_functions.push(func);
+    }
+
+    pub fn register_aggregate_function(&mut self, func: Arc<dyn AggregateFunction>) {
+        self.aggregate_functions.push(func);
+    }
+}
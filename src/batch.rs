@@ -0,0 +1,1291 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Columnar execution. Relations that implement `BatchRelation` produce record batches where
+//! each column is a typed vector plus a validity bitmap, and expressions are evaluated a column
+//! at a time by the kernels in this module. `BatchesToRows` and `RowsToBatches` adapt between
+//! batches and the row-at-a-time `SimpleRelation` interface.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Take};
+use std::iter;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+
+use super::csv::{self, StringRecord, StringRecordsIntoIter};
+
+use super::exec::*;
+use super::explain::*;
+use super::rel::*;
+
+/// Number of rows in each batch unless configured otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// Bitmap with one bit per row. In a validity bitmap, the bit is set when the row is not null.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Bitmap {
+    bits: Vec<u64>,
+    len: usize
+}
+
+impl Bitmap {
+
+    pub fn new(len: usize, value: bool) -> Self {
+        let fill = if value { !0 } else { 0 };
+        let mut bitmap = Bitmap { bits: vec![fill; (len + 63) / 64], len };
+        bitmap.clear_unused_bits();
+        bitmap
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn set(&mut self, i: usize, value: bool) {
+        if value {
+            self.bits[i / 64] |= 1 << (i % 64);
+        } else {
+            self.bits[i / 64] &= !(1 << (i % 64));
+        }
+    }
+
+    pub fn push(&mut self, value: bool) {
+        if self.len % 64 == 0 {
+            self.bits.push(0);
+        }
+        self.len += 1;
+        let i = self.len - 1;
+        self.set(i, value);
+    }
+
+    /// Number of bits that are set
+    pub fn count_set(&self) -> usize {
+        self.bits.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn and(&self, other: &Bitmap) -> Bitmap {
+        Bitmap {
+            bits: self.bits.iter().zip(other.bits.iter()).map(|(a, b)| a & b).collect(),
+            len: self.len
+        }
+    }
+
+    /// Bits beyond the length are kept clear so that whole words can be counted and combined
+    fn clear_unused_bits(&mut self) {
+        if self.len % 64 != 0 {
+            let last = self.bits.len() - 1;
+            self.bits[last] &= (1 << (self.len % 64)) - 1;
+        }
+    }
+}
+
+/// Values of a column. Nulls are represented by the validity bitmap of the `ColumnVector`, and
+/// the value stored for a null row is unspecified.
+#[derive(Debug,Clone,PartialEq)]
+pub enum ColumnData {
+    Boolean(Vec<bool>),
+    UnsignedLong(Vec<u64>),
+    Long(Vec<i64>),
+    Double(Vec<f64>),
+    String(Vec<String>),
+    /// values of any other type, e.g. complex values returned by functions
+    Values(Vec<Value>)
+}
+
+/// A column of a record batch
+#[derive(Debug,Clone,PartialEq)]
+pub struct ColumnVector {
+    data: ColumnData,
+    validity: Bitmap
+}
+
+impl ColumnVector {
+
+    pub fn new(data: ColumnData, validity: Bitmap) -> Self {
+        ColumnVector { data, validity }
+    }
+
+    /// Create a column from boxed values, using a typed vector when all of the values that are
+    /// not null have the same primitive type
+    pub fn from_values(values: Vec<Value>) -> Self {
+        let mut validity = Bitmap::new(values.len(), true);
+        for (i, v) in values.iter().enumerate() {
+            if *v == Value::Null {
+                validity.set(i, false);
+            }
+        }
+
+        let first = values.iter().find(|v| **v != Value::Null).cloned();
+        let same_type = |f: &dyn Fn(&Value) -> bool| values.iter().all(|v| *v == Value::Null || f(v));
+
+        let data = match first {
+            Some(Value::Boolean(_)) if same_type(&|v| matches!(*v, Value::Boolean(_))) =>
+                ColumnData::Boolean(values.iter().map(|v| matches!(*v, Value::Boolean(true))).collect()),
+            Some(Value::UnsignedLong(_)) if same_type(&|v| matches!(*v, Value::UnsignedLong(_))) =>
+                ColumnData::UnsignedLong(values.iter().map(|v| match *v { Value::UnsignedLong(n) => n, _ => 0 }).collect()),
+            Some(Value::Long(_)) if same_type(&|v| matches!(*v, Value::Long(_))) =>
+                ColumnData::Long(values.iter().map(|v| match *v { Value::Long(n) => n, _ => 0 }).collect()),
+            Some(Value::Double(_)) if same_type(&|v| matches!(*v, Value::Double(_))) =>
+                ColumnData::Double(values.iter().map(|v| match *v { Value::Double(d) => d, _ => 0.0 }).collect()),
+            Some(Value::String(_)) if same_type(&|v| matches!(*v, Value::String(_))) =>
+                ColumnData::String(values.into_iter().map(|v| match v { Value::String(s) => s, _ => String::new() }).collect()),
+            _ => ColumnData::Values(values)
+        };
+
+        ColumnVector { data, validity }
+    }
+
+    pub fn data(&self) -> &ColumnData {
+        &self.data
+    }
+
+    pub fn validity(&self) -> &Bitmap {
+        &self.validity
+    }
+
+    pub fn len(&self) -> usize {
+        self.validity.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validity.is_empty()
+    }
+
+    pub fn is_null(&self, i: usize) -> bool {
+        !self.validity.get(i)
+    }
+
+    /// Get the value of a row as a boxed value
+    pub fn value(&self, i: usize) -> Value {
+        if self.is_null(i) {
+            return Value::Null;
+        }
+        match self.data {
+            ColumnData::Boolean(ref v) => Value::Boolean(v[i]),
+            ColumnData::UnsignedLong(ref v) => Value::UnsignedLong(v[i]),
+            ColumnData::Long(ref v) => Value::Long(v[i]),
+            ColumnData::Double(ref v) => Value::Double(v[i]),
+            ColumnData::String(ref v) => Value::String(v[i].clone()),
+            ColumnData::Values(ref v) => v[i].clone()
+        }
+    }
+
+    /// Create a column containing the rows at the given indices
+    pub fn take(&self, indices: &[usize]) -> Self {
+        let data = match self.data {
+            ColumnData::Boolean(ref v) => ColumnData::Boolean(indices.iter().map(|&i| v[i]).collect()),
+            ColumnData::UnsignedLong(ref v) => ColumnData::UnsignedLong(indices.iter().map(|&i| v[i]).collect()),
+            ColumnData::Long(ref v) => ColumnData::Long(indices.iter().map(|&i| v[i]).collect()),
+            ColumnData::Double(ref v) => ColumnData::Double(indices.iter().map(|&i| v[i]).collect()),
+            ColumnData::String(ref v) => ColumnData::String(indices.iter().map(|&i| v[i].clone()).collect()),
+            ColumnData::Values(ref v) => ColumnData::Values(indices.iter().map(|&i| v[i].clone()).collect())
+        };
+        let mut validity = Bitmap::new(indices.len(), true);
+        for (j, &i) in indices.iter().enumerate() {
+            if self.is_null(i) {
+                validity.set(j, false);
+            }
+        }
+        ColumnVector { data, validity }
+    }
+}
+
+/// A set of rows stored as columns
+#[derive(Debug,Clone)]
+pub struct RecordBatch {
+    schema: Schema,
+    columns: Vec<Arc<ColumnVector>>,
+    num_rows: usize
+}
+
+impl RecordBatch {
+
+    pub fn new(schema: Schema, columns: Vec<Arc<ColumnVector>>, num_rows: usize) -> Self {
+        RecordBatch { schema, columns, num_rows }
+    }
+
+    /// Create a batch from rows
+    pub fn from_rows(schema: &Schema, rows: &[Row]) -> Self {
+        let columns = (0..schema.columns.len())
+            .map(|c| Arc::new(ColumnVector::from_values(rows.iter().map(|r| r.values[c].clone()).collect())))
+            .collect();
+        RecordBatch { schema: schema.clone(), columns, num_rows: rows.len() }
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn columns(&self) -> &[Arc<ColumnVector>] {
+        &self.columns
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    /// Get a row as boxed values
+    pub fn row(&self, i: usize) -> Row {
+        Row::new(self.columns.iter().map(|c| c.value(i)).collect())
+    }
+
+    /// Create a batch containing the rows at the given indices
+    pub fn take(&self, indices: &[usize]) -> Self {
+        RecordBatch {
+            schema: self.schema.clone(),
+            columns: self.columns.iter().map(|c| Arc::new(c.take(indices))).collect(),
+            num_rows: indices.len()
+        }
+    }
+}
+
+/// Trait for relations that produce record batches
+pub trait BatchRelation {
+    /// scan all records in this relation, a batch at a time
+    fn scan_batches<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<RecordBatch, ExecutionError>> + 'a>;
+    /// get the schema for this relation
+    fn schema(&self) -> &Schema;
+    /// describe this relation for EXPLAIN
+    fn describe(&self) -> String;
+    /// get the relations that this relation reads from
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![]
+    }
+    /// get the number of bytes this relation has read from files
+    fn bytes_read(&self) -> u64 {
+        0
+    }
+    /// get the metrics collected while this relation was scanned, if they are being collected
+    fn metrics(&self) -> Option<Metrics> {
+        None
+    }
+}
+
+/// Builds a typed column from CSV fields
+struct ColumnBuilder<'a> {
+    field: &'a Field,
+    /// fields equal to this string are null
+    null_string: &'a str,
+    data: ColumnData,
+    validity: Bitmap
+}
+
+impl<'a> ColumnBuilder<'a> {
+
+    fn new(field: &'a Field, null_string: &'a str, capacity: usize) -> Self {
+        let data = match field.data_type {
+            DataType::Boolean => ColumnData::Boolean(Vec::with_capacity(capacity)),
+            DataType::UnsignedLong => ColumnData::UnsignedLong(Vec::with_capacity(capacity)),
+            DataType::Long => ColumnData::Long(Vec::with_capacity(capacity)),
+            DataType::Double => ColumnData::Double(Vec::with_capacity(capacity)),
+            DataType::String => ColumnData::String(Vec::with_capacity(capacity)),
+            DataType::ComplexType(_) => ColumnData::Values(Vec::with_capacity(capacity))
+        };
+        ColumnBuilder { field, null_string, data, validity: Bitmap::new(0, false) }
+    }
+
+    /// Parse and append a CSV field. Fields equal to the null string are null, which is an error
+    /// for columns that are not nullable.
+    fn append(&mut self, s: &str) -> Result<(), ExecutionError> {
+        fn invalid<E: Display>(field: &Field, s: &str, e: E) -> ExecutionError {
+            ExecutionError::Custom(format!("Invalid value '{}' for column {} of type {:?}: {}",
+                                           s, field.name, field.data_type, e))
+        }
+
+        let field = self.field;
+        let valid = s != self.null_string;
+        if !valid && !field.nullable {
+            return Err(ExecutionError::NullValue(field.name.clone()));
+        }
+
+        match self.data {
+            ColumnData::Boolean(ref mut v) =>
+                v.push(valid && s.parse::<bool>().map_err(|e| invalid(field, s, e))?),
+            ColumnData::UnsignedLong(ref mut v) =>
+                v.push(if valid { s.parse::<u64>().map_err(|e| invalid(field, s, e))? } else { 0 }),
+            ColumnData::Long(ref mut v) =>
+                v.push(if valid { s.parse::<i64>().map_err(|e| invalid(field, s, e))? } else { 0 }),
+            ColumnData::Double(ref mut v) =>
+                v.push(if valid { s.parse::<f64>().map_err(|e| invalid(field, s, e))? } else { 0.0 }),
+            ColumnData::String(ref mut v) => v.push(s.to_string()),
+            ColumnData::Values(ref mut v) => if valid {
+                return Err(invalid(field, s, "complex types are not supported in CSV files"));
+            } else {
+                v.push(Value::Null)
+            }
+        }
+        self.validity.push(valid);
+        Ok(())
+    }
+
+    /// Remove the value appended for the last row, so that a row with an invalid field is not
+    /// partially added to the batch
+    fn truncate(&mut self, len: usize) {
+        match self.data {
+            ColumnData::Boolean(ref mut v) => v.truncate(len),
+            ColumnData::UnsignedLong(ref mut v) => v.truncate(len),
+            ColumnData::Long(ref mut v) => v.truncate(len),
+            ColumnData::Double(ref mut v) => v.truncate(len),
+            ColumnData::String(ref mut v) => v.truncate(len),
+            ColumnData::Values(ref mut v) => v.truncate(len)
+        }
+        let mut validity = Bitmap::new(len, false);
+        for i in 0..len {
+            validity.set(i, self.validity.get(i));
+        }
+        self.validity = validity;
+    }
+
+    fn finish(self) -> ColumnVector {
+        ColumnVector { data: self.data, validity: self.validity }
+    }
+}
+
+/// Represents a csv file with a known schema, which is parsed directly into typed columns. The
+/// relation can be limited to a split of the file, so that splits can be read in parallel, and
+/// to some of the columns, so that the other columns are never parsed.
+#[derive(Debug)]
+pub struct CsvBatchRelation {
+    file: File,
+    /// schema of the file
+    file_schema: Schema,
+    /// indices of the columns of the file to read
+    projection: Vec<usize>,
+    /// schema of the columns that are read
+    schema: Schema,
+    batch_size: usize,
+    /// byte range of the split to read, or `None` to read the whole file
+    split: Option<(u64, u64)>,
+    options: CsvOptions,
+    /// number of bytes read from the file by scans of this relation
+    bytes_read: AtomicU64
+}
+
+impl CsvBatchRelation {
+
+    pub fn open(file: File, schema: Schema, batch_size: usize) -> Result<Self, ExecutionError> {
+        let projection = (0..schema.columns.len()).collect();
+        Ok(CsvBatchRelation { file, file_schema: schema.clone(), projection, schema, batch_size, split: None,
+                              options: CsvOptions::default(), bytes_read: AtomicU64::new(0) })
+    }
+
+    /// Only read the columns at the given indices of the file schema. Fields of other columns are
+    /// skipped without being parsed, so they are not checked for nulls or invalid values.
+    pub fn with_projection(mut self, projection: Vec<usize>) -> Self {
+        self.schema = self.file_schema.project(&projection);
+        self.projection = projection;
+        self
+    }
+
+    /// Parse the file with the given options rather than the defaults
+    pub fn with_options(mut self, options: CsvOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Open split `partition` of `partition_count` splits of roughly equal size. The boundaries
+    /// between splits are moved forward to the start of the next line, so each record is read by
+    /// exactly one split. Quoted fields containing line breaks are not supported.
+    pub fn open_split(file: File, schema: Schema, batch_size: usize, partition: usize, partition_count: usize) -> Result<Self, ExecutionError> {
+        let len = file.metadata()?.len();
+        let boundary = |i: usize| len * i as u64 / partition_count as u64;
+        let start = align_to_record(&file, boundary(partition))?;
+        let end = align_to_record(&file, boundary(partition + 1))?;
+        let mut rel = CsvBatchRelation::open(file, schema, batch_size)?;
+        rel.split = Some((start, end));
+        Ok(rel)
+    }
+}
+
+/// Find the start of the first record at or after a byte offset in a CSV file
+fn align_to_record(file: &File, offset: u64) -> Result<u64, ExecutionError> {
+    if offset == 0 {
+        return Ok(0);
+    }
+    // an offset is already aligned when the previous byte ends a line
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(offset - 1))?;
+    let mut line = vec![];
+    let skipped = reader.read_until(b'\n', &mut line)?;
+    Ok(offset - 1 + skipped as u64)
+}
+
+impl BatchRelation for CsvBatchRelation {
+
+    fn scan_batches<'a>(&'a self, _ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<RecordBatch, ExecutionError>> + 'a> {
+        let (start, end) = self.split.unwrap_or((0, u64::MAX));
+        if let Err(e) = (&self.file).seek(SeekFrom::Start(start)) {
+            return Box::new(iter::once(Err(ExecutionError::IoError(e))));
+        }
+
+        // only the first split contains the header
+        let buf_reader = BufReader::new(CountingReader { inner: (&self.file).take(end - start), count: &self.bytes_read });
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(start == 0 && self.options.has_header)
+            .delimiter(self.options.delimiter)
+            .quote(self.options.quote)
+            .escape(self.options.escape)
+            .comment(self.options.comment)
+            .trim(if self.options.trim { csv::Trim::All } else { csv::Trim::None })
+            .from_reader(buf_reader);
+        Box::new(CsvBatchIterator {
+            records: csv_reader.into_records(),
+            field_count: self.file_schema.columns.len(),
+            projection: &self.projection,
+            schema: &self.schema,
+            null_string: &self.options.null_string,
+            batch_size: self.batch_size,
+            pending_error: None
+        })
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        let columns: Vec<&str> = self.schema.columns.iter().map(|f| f.name.as_str()).collect();
+        match self.split {
+            Some((start, end)) => format!("CsvBatchRelation: columns=[{}], bytes={}..{}", columns.join(", "), start, end),
+            None => format!("CsvBatchRelation: columns=[{}]", columns.join(", "))
+        }
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(AtomicOrdering::Relaxed)
+    }
+}
+
+/// Reader that counts the bytes read through it
+struct CountingReader<'a, R> {
+    inner: R,
+    count: &'a AtomicU64
+}
+
+impl<'a, R: Read> Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, AtomicOrdering::Relaxed);
+        Ok(n)
+    }
+}
+
+struct CsvBatchIterator<'a> {
+    records: StringRecordsIntoIter<BufReader<CountingReader<'a, Take<&'a File>>>>,
+    /// number of fields in each record
+    field_count: usize,
+    projection: &'a [usize],
+    schema: &'a Schema,
+    null_string: &'a str,
+    batch_size: usize,
+    /// error found after some rows of a batch had been read, which is returned after those rows
+    pending_error: Option<ExecutionError>
+}
+
+impl<'a> CsvBatchIterator<'a> {
+
+    fn append_record(&self, builders: &mut Vec<ColumnBuilder>, record: &StringRecord, num_rows: usize) -> Result<(), ExecutionError> {
+        if self.field_count != record.len() {
+            return Err(ExecutionError::Custom(format!(
+                "Expected {} fields but found {} in CSV record", self.field_count, record.len())));
+        }
+        for (i, &field) in self.projection.iter().enumerate() {
+            if let Err(e) = builders[i].append(&record[field]) {
+                builders.iter_mut().take(i).for_each(|b| b.truncate(num_rows));
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for CsvBatchIterator<'a> {
+    type Item = Result<RecordBatch, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.pending_error.take() {
+            return Some(Err(e));
+        }
+
+        let mut builders: Vec<ColumnBuilder> = self.schema.columns.iter()
+            .map(|f| ColumnBuilder::new(f, self.null_string, self.batch_size))
+            .collect();
+
+        let mut num_rows = 0;
+        while num_rows < self.batch_size {
+            let result = match self.records.next() {
+                Some(Ok(record)) => self.append_record(&mut builders, &record, num_rows),
+                Some(Err(e)) => Err(ExecutionError::CsvError(e)),
+                None => break
+            };
+            match result {
+                Ok(()) => num_rows += 1,
+                Err(e) if num_rows == 0 => return Some(Err(e)),
+                Err(e) => {
+                    self.pending_error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        if num_rows == 0 {
+            return None;
+        }
+
+        let columns = builders.into_iter().map(|b| Arc::new(b.finish())).collect();
+        Some(Ok(RecordBatch::new(self.schema.clone(), columns, num_rows)))
+    }
+}
+
+/// Filter relation that evaluates the predicate a batch at a time
+pub struct FilterBatchRelation {
+    schema: Schema,
+    input: Box<dyn BatchRelation>,
+    expr: Expr
+}
+
+impl FilterBatchRelation {
+
+    pub fn new(schema: Schema, input: Box<dyn BatchRelation>, expr: Expr) -> Self {
+        FilterBatchRelation { schema, input, expr }
+    }
+}
+
+impl BatchRelation for FilterBatchRelation {
+
+    fn scan_batches<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<RecordBatch, ExecutionError>> + 'a> {
+        Box::new(self.input.scan_batches(ctx).filter_map(move |b| match b {
+            Ok(batch) => match evaluate_batch(ctx, &batch, &self.expr).and_then(|p| filter(&batch, &p)) {
+                Ok(ref filtered) if filtered.num_rows() == 0 => None,
+                result => Some(result)
+            },
+            Err(e) => Some(Err(e)) // let errors through the filter so they can be handled later
+        }))
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        format!("FilterBatchRelation: {}", format_expr(&self.expr, self.input.schema()))
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Batches(&*self.input)]
+    }
+}
+
+/// Projection relation that evaluates each expression a batch at a time
+pub struct ProjectBatchRelation {
+    schema: Schema,
+    input: Box<dyn BatchRelation>,
+    expr: Vec<Expr>
+}
+
+impl ProjectBatchRelation {
+
+    pub fn new(schema: Schema, input: Box<dyn BatchRelation>, expr: Vec<Expr>) -> Self {
+        ProjectBatchRelation { schema, input, expr }
+    }
+}
+
+impl BatchRelation for ProjectBatchRelation {
+
+    fn scan_batches<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<RecordBatch, ExecutionError>> + 'a> {
+        Box::new(self.input.scan_batches(ctx).map(move |b| {
+            let batch = b?;
+            let columns = self.expr.iter()
+                .map(|e| evaluate_batch(ctx, &batch, e).map(Arc::new))
+                .collect::<Result<Vec<Arc<ColumnVector>>, ExecutionError>>()?;
+            Ok(RecordBatch::new(self.schema.clone(), columns, batch.num_rows()))
+        }))
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        let expr: Vec<String> = self.expr.iter().map(|e| format_expr(e, self.input.schema())).collect();
+        format!("ProjectBatchRelation: {}", expr.join(", "))
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Batches(&*self.input)]
+    }
+}
+
+/// Adapter that groups the rows of a relation into batches
+pub struct RowsToBatches {
+    input: Box<dyn SimpleRelation>,
+    batch_size: usize
+}
+
+impl RowsToBatches {
+
+    pub fn new(input: Box<dyn SimpleRelation>, batch_size: usize) -> Self {
+        RowsToBatches { input, batch_size }
+    }
+}
+
+impl BatchRelation for RowsToBatches {
+
+    fn scan_batches<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<RecordBatch, ExecutionError>> + 'a> {
+        let mut rows = self.input.scan(ctx).fuse();
+        let mut pending_error = None;
+        Box::new(iter::repeat(()).map(move |_| {
+            if let Some(e) = pending_error.take() {
+                return Some(Err(e));
+            }
+            let mut batch = Vec::with_capacity(self.batch_size);
+            while batch.len() < self.batch_size {
+                match rows.next() {
+                    Some(Ok(row)) => batch.push(row),
+                    Some(Err(e)) if batch.is_empty() => return Some(Err(e)),
+                    Some(Err(e)) => {
+                        pending_error = Some(e);
+                        break;
+                    },
+                    None => break
+                }
+            }
+            if batch.is_empty() {
+                None
+            } else {
+                Some(Ok(RecordBatch::from_rows(self.input.schema(), &batch)))
+            }
+        }).take_while(|b| b.is_some()).map(|b| b.unwrap()))
+    }
+
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn describe(&self) -> String {
+        format!("RowsToBatches: batch_size={}", self.batch_size)
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Rows(&*self.input)]
+    }
+}
+
+/// Adapter that returns the rows of a batch relation one at a time
+pub struct BatchesToRows {
+    input: Box<dyn BatchRelation>
+}
+
+impl BatchesToRows {
+
+    pub fn new(input: Box<dyn BatchRelation>) -> Self {
+        BatchesToRows { input }
+    }
+}
+
+impl SimpleRelation for BatchesToRows {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        Box::new(self.input.scan_batches(ctx).flat_map(|b| -> Box<dyn Iterator<Item=Result<Row, ExecutionError>>> {
+            match b {
+                Ok(batch) => Box::new((0..batch.num_rows()).map(move |i| Ok(batch.row(i)))),
+                Err(e) => Box::new(iter::once(Err(e)))
+            }
+        }))
+    }
+
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn describe(&self) -> String {
+        "BatchesToRows".to_string()
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Batches(&*self.input)]
+    }
+}
+
+/// Evaluate an expression against every row of a batch. Comparisons, arithmetic, logical
+/// operators and null checks are evaluated by vectorized kernels, and anything else is
+/// evaluated row by row.
+pub fn evaluate_batch(ctx: &ExecutionContext, batch: &RecordBatch, expr: &Expr) -> Result<ColumnVector, ExecutionError> {
+    let n = batch.num_rows();
+    match *expr {
+        Expr::TupleValue(i) => Ok((*batch.columns()[i]).clone()),
+        Expr::Literal(ref value) => Ok(ColumnVector::from_values(vec![value.clone(); n])),
+        Expr::Alias { ref expr, .. } | Expr::Sort { ref expr, .. } => evaluate_batch(ctx, batch, expr),
+        Expr::IsNull(ref operand) | Expr::IsNotNull(ref operand) => {
+            let column = evaluate_batch(ctx, batch, operand)?;
+            Ok(is_null_kernel(&column, matches!(*expr, Expr::IsNull(_))))
+        },
+        Expr::Not(ref expr) => {
+            let (values, validity) = as_boolean(evaluate_batch(ctx, batch, expr)?)?;
+            Ok(ColumnVector::new(ColumnData::Boolean(values.iter().map(|b| !b).collect()), validity))
+        },
+        Expr::BinaryExpr { ref left, op: Operator::And, ref right } => evaluate_logical(ctx, batch, left, right, false),
+        Expr::BinaryExpr { ref left, op: Operator::Or, ref right } => evaluate_logical(ctx, batch, left, right, true),
+        Expr::BinaryExpr { ref left, ref op, ref right } => {
+            let left_column = evaluate_batch(ctx, batch, left)?;
+            let right_column = evaluate_batch(ctx, batch, right)?;
+            let result = if op.is_arithmetic() {
+                arithmetic(op, &left_column, &right_column)?
+            } else {
+                compare(op, &left_column, &right_column)
+            };
+            match result {
+                Some(column) => Ok(column),
+                // operand types without a kernel are evaluated a row at a time
+                None => {
+                    let expr = Expr::BinaryExpr {
+                        left: Box::new(Expr::TupleValue(0)),
+                        op: op.clone(),
+                        right: Box::new(Expr::TupleValue(1))
+                    };
+                    evaluate_rows(ctx, n, &expr, |i| Row::new(vec![left_column.value(i), right_column.value(i)]))
+                }
+            }
+        },
+        Expr::ScalarFunction { ref name, ref args } => {
+            // the arguments are evaluated as columns and the function is called for each row
+            let arg_columns = args.iter()
+                .map(|a| evaluate_batch(ctx, batch, a))
+                .collect::<Result<Vec<ColumnVector>, ExecutionError>>()?;
+            let expr = Expr::ScalarFunction {
+                name: name.clone(),
+                args: (0..args.len()).map(Expr::TupleValue).collect()
+            };
+            evaluate_rows(ctx, n, &expr, |i| Row::new(arg_columns.iter().map(|c| c.value(i)).collect()))
+        },
+        _ => evaluate_rows(ctx, n, expr, |i| batch.row(i))
+    }
+}
+
+/// Evaluate an expression against rows created on demand
+fn evaluate_rows<F>(ctx: &ExecutionContext, n: usize, expr: &Expr, row: F) -> Result<ColumnVector, ExecutionError>
+    where F: Fn(usize) -> Row {
+    let schema = Schema::empty();
+    let values = (0..n)
+        .map(|i| ctx.evaluate(&row(i), &schema, expr).map_err(|e| *e))
+        .collect::<Result<Vec<Value>, ExecutionError>>()?;
+    Ok(ColumnVector::from_values(values))
+}
+
+/// Evaluate AND (`is_or` false) or OR (`is_or` true) using three-valued logic. The right
+/// operand is only evaluated for the rows where the left operand does not determine the result.
+fn evaluate_logical(ctx: &ExecutionContext, batch: &RecordBatch, left: &Expr, right: &Expr, is_or: bool) -> Result<ColumnVector, ExecutionError> {
+    let n = batch.num_rows();
+    let (left_values, left_validity) = as_boolean(evaluate_batch(ctx, batch, left)?)?;
+
+    // a false operand determines the result of AND and a true operand determines the result of OR
+    let undetermined: Vec<usize> = (0..n)
+        .filter(|&i| !left_validity.get(i) || left_values[i] != is_or)
+        .collect();
+
+    let mut right_values = vec![false; n];
+    let mut right_validity = Bitmap::new(n, false);
+    if undetermined.len() == n {
+        let (values, validity) = as_boolean(evaluate_batch(ctx, batch, right)?)?;
+        right_values = values;
+        right_validity = validity;
+    } else if !undetermined.is_empty() {
+        let (values, validity) = as_boolean(evaluate_batch(ctx, &batch.take(&undetermined), right)?)?;
+        for (j, &i) in undetermined.iter().enumerate() {
+            right_values[i] = values[j];
+            right_validity.set(i, validity.get(j));
+        }
+    }
+
+    let mut values = Vec::with_capacity(n);
+    let mut validity = Bitmap::new(n, true);
+    for i in 0..n {
+        let l = if left_validity.get(i) { Some(left_values[i]) } else { None };
+        let r = if right_validity.get(i) { Some(right_values[i]) } else { None };
+        let result = match (l, r) {
+            (Some(b), _) | (_, Some(b)) if b == is_or => Some(is_or),
+            (Some(_), Some(_)) => Some(!is_or),
+            _ => None
+        };
+        values.push(result.unwrap_or(false));
+        validity.set(i, result.is_some());
+    }
+    Ok(ColumnVector::new(ColumnData::Boolean(values), validity))
+}
+
+/// Interpret a column as the operand of a logical operator
+fn as_boolean(column: ColumnVector) -> Result<(Vec<bool>, Bitmap), ExecutionError> {
+    let invalid = |v: Value| ExecutionError::Custom(format!(
+        "Logical operator expected a boolean operand but found {:?}", v));
+    match column.data {
+        ColumnData::Boolean(v) => Ok((v, column.validity)),
+        ColumnData::Values(ref v) => {
+            let values = v.iter()
+                .map(|v| match *v {
+                    Value::Boolean(b) => Ok(b),
+                    Value::Null => Ok(false),
+                    ref other => Err(invalid(other.clone()))
+                })
+                .collect::<Result<Vec<bool>, ExecutionError>>()?;
+            Ok((values, column.validity.clone()))
+        },
+        _ => match (0..column.len()).find(|&i| !column.is_null(i)) {
+            Some(i) => Err(invalid(column.value(i))),
+            None => Ok((vec![false; column.len()], column.validity.clone()))
+        }
+    }
+}
+
+/// Kernel for IS NULL (`is_null` true) and IS NOT NULL (`is_null` false)
+pub fn is_null_kernel(column: &ColumnVector, is_null: bool) -> ColumnVector {
+    let values = (0..column.len()).map(|i| column.is_null(i) == is_null).collect();
+    ColumnVector::new(ColumnData::Boolean(values), Bitmap::new(column.len(), true))
+}
+
+/// Kernel for filtering a batch, keeping the rows where the predicate is true. Rows where the
+/// predicate is null are removed.
+pub fn filter(batch: &RecordBatch, predicate: &ColumnVector) -> Result<RecordBatch, ExecutionError> {
+    let selected: Vec<usize> = match predicate.data {
+        ColumnData::Boolean(ref v) => (0..v.len()).filter(|&i| v[i] && !predicate.is_null(i)).collect(),
+        _ => {
+            let mut selected = vec![];
+            for i in 0..predicate.len() {
+                match predicate.value(i) {
+                    Value::Boolean(true) => selected.push(i),
+                    Value::Boolean(false) | Value::Null => {},
+                    other => return Err(ExecutionError::Custom(format!(
+                        "Predicate expression evaluated to non-boolean value {:?}", other)))
+                }
+            }
+            selected
+        }
+    };
+    if selected.len() == batch.num_rows() {
+        Ok(batch.clone())
+    } else {
+        Ok(batch.take(&selected))
+    }
+}
+
+/// Numeric column converted to doubles
+fn as_f64<'a>(column: &'a ColumnVector) -> Option<Cow<'a, [f64]>> {
+    match column.data {
+        ColumnData::Double(ref v) => Some(Cow::Borrowed(v)),
+        ColumnData::UnsignedLong(ref v) => Some(Cow::Owned(v.iter().map(|&n| n as f64).collect())),
+        ColumnData::Long(ref v) => Some(Cow::Owned(v.iter().map(|&n| n as f64).collect())),
+        _ => None
+    }
+}
+
+/// Integer column converted to a type that can represent both unsigned and signed integers
+fn as_i128(column: &ColumnVector) -> Option<Vec<i128>> {
+    match column.data {
+        ColumnData::UnsignedLong(ref v) => Some(v.iter().map(|&n| i128::from(n)).collect()),
+        ColumnData::Long(ref v) => Some(v.iter().map(|&n| i128::from(n)).collect()),
+        _ => None
+    }
+}
+
+/// Comparison kernel. Numeric operands are promoted to a common type first. Returns `None` if
+/// there is no kernel for the operand types.
+pub fn compare(op: &Operator, left: &ColumnVector, right: &ColumnVector) -> Option<ColumnVector> {
+    let validity = left.validity.and(&right.validity);
+    match (&left.data, &right.data) {
+        (ColumnData::UnsignedLong(a), ColumnData::UnsignedLong(b)) => Some(compare_slices(op, a, b, validity)),
+        (ColumnData::Long(a), ColumnData::Long(b)) => Some(compare_slices(op, a, b, validity)),
+        (ColumnData::String(a), ColumnData::String(b)) => Some(compare_slices(op, a, b, validity)),
+        (ColumnData::Boolean(a), ColumnData::Boolean(b)) => Some(compare_slices(op, a, b, validity)),
+        (ColumnData::Double(_), _) | (_, ColumnData::Double(_)) => match (as_f64(left), as_f64(right)) {
+            (Some(a), Some(b)) => Some(compare_slices(op, &a, &b, validity)),
+            _ => None
+        },
+        _ => match (as_i128(left), as_i128(right)) {
+            (Some(a), Some(b)) => Some(compare_slices(op, &a, &b, validity)),
+            _ => None
+        }
+    }
+}
+
+fn compare_slices<T: PartialOrd>(op: &Operator, a: &[T], b: &[T], validity: Bitmap) -> ColumnVector {
+    let values = a.iter().zip(b.iter())
+        .map(|(a, b)| {
+            let ordering = a.partial_cmp(b);
+            match *op {
+                Operator::Eq => ordering == Some(Ordering::Equal),
+                Operator::NotEq => ordering != Some(Ordering::Equal),
+                Operator::Lt => ordering == Some(Ordering::Less),
+                Operator::LtEq => ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal),
+                Operator::Gt => ordering == Some(Ordering::Greater),
+                Operator::GtEq => ordering == Some(Ordering::Greater) || ordering == Some(Ordering::Equal),
+                _ => false
+            }
+        })
+        .collect();
+    ColumnVector::new(ColumnData::Boolean(values), validity)
+}
+
+/// Arithmetic kernel, with the same type promotion as row-based evaluation: if either operand is
+/// a double then the result is a double, otherwise the result is an unsigned integer when both
+/// operands are unsigned integers, except for subtraction, and a signed integer in all other
+/// cases. Returns `None` if there is no kernel for the operand types.
+pub fn arithmetic(op: &Operator, left: &ColumnVector, right: &ColumnVector) -> Result<Option<ColumnVector>, ExecutionError> {
+    let validity = left.validity.and(&right.validity);
+    let is_division = *op == Operator::Divide || *op == Operator::Modulus;
+    let n = validity.len();
+
+    let overflow = |i: usize| ExecutionError::Custom(format!(
+        "Integer overflow evaluating {:?} {:?} {:?}", left.value(i), op, right.value(i)));
+    let division_by_zero = || ExecutionError::Custom("Division by zero".to_string());
+
+    let data = match (&left.data, &right.data) {
+        (ColumnData::Double(_), _) | (_, ColumnData::Double(_)) => {
+            let (a, b) = match (as_f64(left), as_f64(right)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return Ok(None)
+            };
+            let mut values = Vec::with_capacity(n);
+            for i in 0..n {
+                if !validity.get(i) {
+                    values.push(0.0);
+                    continue;
+                }
+                if is_division && b[i] == 0.0 {
+                    return Err(division_by_zero());
+                }
+                values.push(match *op {
+                    Operator::Plus => a[i] + b[i],
+                    Operator::Minus => a[i] - b[i],
+                    Operator::Multiply => a[i] * b[i],
+                    Operator::Divide => a[i] / b[i],
+                    Operator::Modulus => a[i] % b[i],
+                    _ => return Ok(None)
+                });
+            }
+            ColumnData::Double(values)
+        },
+        (ColumnData::UnsignedLong(a), ColumnData::UnsignedLong(b)) if *op != Operator::Minus => {
+            let mut values = Vec::with_capacity(n);
+            for i in 0..n {
+                if !validity.get(i) {
+                    values.push(0);
+                    continue;
+                }
+                if is_division && b[i] == 0 {
+                    return Err(division_by_zero());
+                }
+                let result = match *op {
+                    Operator::Plus => a[i].checked_add(b[i]),
+                    Operator::Multiply => a[i].checked_mul(b[i]),
+                    Operator::Divide => a[i].checked_div(b[i]),
+                    Operator::Modulus => a[i].checked_rem(b[i]),
+                    _ => return Ok(None)
+                };
+                values.push(result.ok_or_else(|| overflow(i))?);
+            }
+            ColumnData::UnsignedLong(values)
+        },
+        _ => {
+            let (a, b) = match (as_i128(left), as_i128(right)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return Ok(None)
+            };
+            let mut values = Vec::with_capacity(n);
+            for i in 0..n {
+                if !validity.get(i) {
+                    values.push(0);
+                    continue;
+                }
+                if is_division && b[i] == 0 {
+                    return Err(division_by_zero());
+                }
+                let result = match *op {
+                    Operator::Plus => a[i].checked_add(b[i]),
+                    Operator::Minus => a[i].checked_sub(b[i]),
+                    Operator::Multiply => a[i].checked_mul(b[i]),
+                    Operator::Divide => a[i].checked_div(b[i]),
+                    Operator::Modulus => a[i].checked_rem(b[i]),
+                    _ => return Ok(None)
+                };
+                values.push(result.and_then(|n| i64::try_from(n).ok()).ok_or_else(|| overflow(i))?);
+            }
+            ColumnData::Long(values)
+        }
+    };
+
+    Ok(Some(ColumnVector::new(data, validity)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn column(values: Vec<Value>) -> ColumnVector {
+        ColumnVector::from_values(values)
+    }
+
+    fn values(column: &ColumnVector) -> Vec<Value> {
+        (0..column.len()).map(|i| column.value(i)).collect()
+    }
+
+    #[test]
+    fn test_bitmap() {
+        let mut bitmap = Bitmap::new(70, true);
+        assert_eq!(70, bitmap.count_set());
+        bitmap.set(3, false);
+        bitmap.set(65, false);
+        assert!(!bitmap.get(3) && bitmap.get(4) && !bitmap.get(65));
+        assert_eq!(68, bitmap.count_set());
+
+        let mut other = Bitmap::new(0, false);
+        (0..70).for_each(|i| other.push(i % 2 == 0));
+        assert_eq!(35, bitmap.and(&other).count_set());
+    }
+
+    #[test]
+    fn test_from_values() {
+        let c = column(vec![Value::Double(1.5), Value::Null, Value::Double(2.0)]);
+        assert_eq!(&ColumnData::Double(vec![1.5, 0.0, 2.0]), c.data());
+        assert!(c.is_null(1));
+        assert_eq!(vec![Value::Double(1.5), Value::Null, Value::Double(2.0)], values(&c));
+
+        // mixed types are kept as boxed values
+        let c = column(vec![Value::Double(1.5), Value::UnsignedLong(2)]);
+        assert!(matches!(*c.data(), ColumnData::Values(_)));
+    }
+
+    #[test]
+    fn test_compare_with_promotion() {
+        let a = column(vec![Value::UnsignedLong(1), Value::UnsignedLong(2), Value::Null]);
+        let b = column(vec![Value::Double(1.0), Value::Double(1.5), Value::Double(0.0)]);
+        let result = compare(&Operator::Gt, &a, &b).unwrap();
+        assert_eq!(vec![Value::Boolean(false), Value::Boolean(true), Value::Null], values(&result));
+
+        let c = column(vec![Value::Long(-1), Value::Long(2), Value::Long(0)]);
+        let result = compare(&Operator::LtEq, &a, &c).unwrap();
+        assert_eq!(vec![Value::Boolean(false), Value::Boolean(true), Value::Null], values(&result));
+    }
+
+    #[test]
+    fn test_arithmetic_kernel() {
+        let a = column(vec![Value::UnsignedLong(5), Value::Null, Value::UnsignedLong(7)]);
+        let b = column(vec![Value::UnsignedLong(2), Value::UnsignedLong(0), Value::UnsignedLong(3)]);
+
+        let sum = arithmetic(&Operator::Plus, &a, &b).unwrap().unwrap();
+        assert_eq!(vec![Value::UnsignedLong(7), Value::Null, Value::UnsignedLong(10)], values(&sum));
+
+        let difference = arithmetic(&Operator::Minus, &b, &a).unwrap().unwrap();
+        assert_eq!(vec![Value::Long(-3), Value::Null, Value::Long(-4)], values(&difference));
+
+        // the division by zero is in a null row so it is never evaluated
+        let quotient = arithmetic(&Operator::Divide, &a, &b).unwrap().unwrap();
+        assert_eq!(vec![Value::UnsignedLong(2), Value::Null, Value::UnsignedLong(2)], values(&quotient));
+        let zero = column(vec![Value::UnsignedLong(0), Value::UnsignedLong(0), Value::UnsignedLong(0)]);
+        assert!(arithmetic(&Operator::Modulus, &a, &zero).is_err());
+
+        let d = column(vec![Value::Double(0.5), Value::Double(1.0), Value::Double(2.0)]);
+        let product = arithmetic(&Operator::Multiply, &a, &d).unwrap().unwrap();
+        assert_eq!(vec![Value::Double(2.5), Value::Null, Value::Double(14.0)], values(&product));
+
+        let max = column(vec![Value::UnsignedLong(u64::MAX)]);
+        let one = column(vec![Value::UnsignedLong(1)]);
+        assert!(arithmetic(&Operator::Plus, &max, &one).is_err());
+    }
+
+    #[test]
+    fn test_filter_kernel() {
+        let schema = Schema::new(vec![Field::new("id", DataType::UnsignedLong, false)]);
+        let rows: Vec<Row> = (0..4).map(|i| Row::new(vec![Value::UnsignedLong(i)])).collect();
+        let batch = RecordBatch::from_rows(&schema, &rows);
+
+        let predicate = column(vec![Value::Boolean(true), Value::Null, Value::Boolean(false), Value::Boolean(true)]);
+        let filtered = filter(&batch, &predicate).unwrap();
+
+        assert_eq!(2, filtered.num_rows());
+        assert_eq!("0", filtered.row(0).to_string());
+        assert_eq!("3", filtered.row(1).to_string());
+
+        assert!(filter(&batch, &column(vec![Value::UnsignedLong(1); 4])).is_err());
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let ctx = ExecutionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Boolean, true),
+            Field::new("b", DataType::Boolean, true)]);
+
+        let t = Value::Boolean(true);
+        let f = Value::Boolean(false);
+        let rows: Vec<Row> = vec![
+            (t.clone(), t.clone()), (t.clone(), f.clone()), (t.clone(), Value::Null),
+            (f.clone(), Value::Null), (Value::Null, Value::Null)]
+            .into_iter()
+            .map(|(a, b)| Row::new(vec![a, b]))
+            .collect();
+        let batch = RecordBatch::from_rows(&schema, &rows);
+
+        let and = evaluate_batch(&ctx, &batch, &Expr::TupleValue(0).and(&Expr::TupleValue(1))).unwrap();
+        assert_eq!(vec![t.clone(), f.clone(), Value::Null, f.clone(), Value::Null], values(&and));
+
+        let or = evaluate_batch(&ctx, &batch, &Expr::TupleValue(0).or(&Expr::TupleValue(1))).unwrap();
+        assert_eq!(vec![t.clone(), t.clone(), t.clone(), Value::Null, Value::Null], values(&or));
+
+        let not = evaluate_batch(&ctx, &batch, &Expr::TupleValue(0).not()).unwrap();
+        assert_eq!(vec![f.clone(), f.clone(), f.clone(), t.clone(), Value::Null], values(&not));
+    }
+
+    #[test]
+    fn test_csv_batches() {
+        let ctx = ExecutionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]);
+
+        let file = File::open("test/data/orders.csv").unwrap();
+        let relation = CsvBatchRelation::open(file, schema, 3).unwrap();
+
+        let batches = relation.scan_batches(&ctx).collect::<Result<Vec<RecordBatch>, ExecutionError>>().unwrap();
+        assert_eq!(vec![3, 3, 1], batches.iter().map(|b| b.num_rows()).collect::<Vec<usize>>());
+        assert_eq!(&ColumnData::Double(vec![10.5, 20.0, 5.25]), batches[0].columns()[2].data());
+        assert_eq!("7,11,50", batches[2].row(0).to_string());
+    }
+
+    #[test]
+    fn test_csv_splits() {
+        let ctx = ExecutionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("city", DataType::String, false),
+            Field::new("lat", DataType::Double, false),
+            Field::new("lng", DataType::Double, false)]);
+
+        let read_rows = |rel: CsvBatchRelation| -> Vec<String> {
+            rel.scan_batches(&ctx)
+                .map(|b| b.unwrap())
+                .flat_map(|b| (0..b.num_rows()).map(|i| b.row(i).to_string()).collect::<Vec<String>>())
+                .collect()
+        };
+
+        let file = File::open("test/data/uk_cities.csv").unwrap();
+        let expected = read_rows(CsvBatchRelation::open(file, schema.clone(), 5).unwrap());
+
+        // each record is read by exactly one split, including when there are more splits than lines
+        for &partition_count in &[1, 2, 3, 7, 64, 500] {
+            let rows: Vec<String> = (0..partition_count)
+                .flat_map(|partition| {
+                    let file = File::open("test/data/uk_cities.csv").unwrap();
+                    read_rows(CsvBatchRelation::open_split(file, schema.clone(), 5, partition, partition_count).unwrap())
+                })
+                .collect();
+            assert_eq!(expected, rows);
+        }
+    }
+
+    #[test]
+    fn test_csv_projection() {
+        let ctx = ExecutionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false),
+            Field::new("manager_id", DataType::UnsignedLong, false),
+            Field::new("salary", DataType::Double, true)]);
+
+        // the missing names and managers are not parsed, so they are not errors
+        let file = File::open("test/data/employees.csv").unwrap();
+        let relation = CsvBatchRelation::open(file, schema, 10).unwrap().with_projection(vec![3, 0]);
+        assert_eq!("salary: Double,id: UnsignedLong", relation.schema().to_string());
+
+        let batch = relation.scan_batches(&ctx).next().unwrap().unwrap();
+        let rows: Vec<String> = (0..batch.num_rows()).map(|i| batch.row(i).to_string()).collect();
+        assert_eq!(vec!["100,1", ",2", "80,3", "60,4", ",5"], rows);
+    }
+
+    #[test]
+    fn test_csv_options() {
+        let ctx = ExecutionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false),
+            Field::new("score", DataType::Double, true)]);
+
+        let path = env::temp_dir().join(format!("datafusion_csv_options_{}.csv", process::id()));
+        fs::write(&path, "# scores\nid|name|score\n1| Andy |1.5\n2|'Brian|Smith'|N/A\n3|'O\\'Neil'|2.0\n").unwrap();
+
+        let options = CsvOptions {
+            delimiter: b'|',
+            quote: b'\'',
+            escape: Some(b'\\'),
+            comment: Some(b'#'),
+            trim: true,
+            null_string: "N/A".to_string(),
+            ..CsvOptions::default()
+        };
+        let relation = CsvBatchRelation::open(File::open(&path).unwrap(), schema.clone(), 10).unwrap()
+            .with_options(options.clone());
+        let batch = relation.scan_batches(&ctx).next().unwrap().unwrap();
+        assert_eq!(vec!["1,Andy,1.5", "2,Brian|Smith,", "3,O'Neil,2"],
+                   (0..batch.num_rows()).map(|i| batch.row(i).to_string()).collect::<Vec<String>>());
+        assert!(batch.columns()[2].is_null(1));
+
+        // without a header, the column names are read as values
+        let relation = CsvBatchRelation::open(File::open(&path).unwrap(), schema, 10).unwrap()
+            .with_options(CsvOptions { has_header: false, ..options });
+        assert!(relation.scan_batches(&ctx).next().unwrap().is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_batch_error_after_valid_rows() {
+        let ctx = ExecutionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false),
+            Field::new("manager_id", DataType::UnsignedLong, false),
+            Field::new("salary", DataType::Double, true)]);
+
+        let file = File::open("test/data/employees.csv").unwrap();
+        let relation = CsvBatchRelation::open(file, schema, 10).unwrap();
+
+        // Alice has no manager, so the first row is an error
+        let mut batches = relation.scan_batches(&ctx);
+        match batches.next() {
+            Some(Err(ExecutionError::NullValue(ref column))) => assert_eq!("manager_id", column),
+            other => panic!("Expected null value error but found {:?}", other.map(|b| b.map(|b| b.num_rows())))
+        }
+
+        // the valid rows are returned before the error for the last row, which has no name
+        let batch = batches.next().unwrap().unwrap();
+        assert_eq!(3, batch.num_rows());
+        assert_eq!("2,Bob,1,", batch.row(0).to_string());
+        assert!(batches.next().unwrap().is_err());
+        assert!(batches.next().is_none());
+    }
+}
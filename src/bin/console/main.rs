@@ -1,6 +1,7 @@
 extern crate rprompt;
 extern crate datafusion;
 
+use datafusion::dataframe::*;
 use datafusion::exec::*;
 
 /// Interactive SQL console
@@ -11,9 +12,11 @@ struct Console {
 impl Console {
     fn execute(&mut self, command: &str) {
         println!("Executing: {}", command);
-        match self.ctx.sql(command) {
-            Ok(_ /* ref df */ ) => {
-                //TODO: show data from df
+        match self.ctx.sql(command).map_err(DataFrameError::from).and_then(|df| df.collect()) {
+            Ok(rows) => {
+                for row in rows {
+                    println!("{}", row.to_string());
+                }
                 println!("Executed OK");
             },
             Err(e) => println!("Error: {:?}", e)
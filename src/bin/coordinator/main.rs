@@ -0,0 +1,111 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate hyper;
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::server::Http;
+
+extern crate datafusion;
+use datafusion::coordinator::{Coordinator, CoordinatorService};
+use datafusion::exec::*;
+use datafusion::membership::*;
+use datafusion::rel::*;
+
+const USAGE: &str = "Usage: coordinator [--bind host:port] [--catalog catalog.json] \
+    [--workers host:port[,host:port...] | --membership-dir dir] [--worker-timeout seconds]
+
+Without --workers or --membership-dir, workers join the cluster by sending heartbeats to the
+coordinator.";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(1);
+}
+
+/// Load a catalog file, which is a JSON object mapping table names to schemas. A table can
+/// instead map to an object with its `schema` and the `options` for parsing its CSV file, which
+/// are named as in `CREATE EXTERNAL TABLE ... WITH OPTIONS (...)`.
+fn load_catalog(ctx: &mut ExecutionContext, filename: &str) {
+    let file = File::open(filename).unwrap_or_else(|e| fail(&format!("Error opening catalog {}: {}", filename, e)));
+    let tables: HashMap<String, serde_json::Value> = serde_json::from_reader(file)
+        .unwrap_or_else(|e| fail(&format!("Error reading catalog {}: {}", filename, e)));
+    for (name, table) in tables {
+        let invalid = |e: String| -> ! { fail(&format!("Invalid table {} in catalog {}: {}", name, filename, e)) };
+        let (schema, options) = match table.get("schema").cloned() {
+            Some(schema) => {
+                let options: HashMap<String, String> = match table.get("options") {
+                    Some(options) => serde_json::from_value(options.clone()).unwrap_or_else(|e| invalid(e.to_string())),
+                    None => HashMap::new()
+                };
+                (schema, options)
+            },
+            None => (table, HashMap::new())
+        };
+        let schema: Schema = serde_json::from_value(schema).unwrap_or_else(|e| invalid(e.to_string()));
+        let mut csv_options = CsvOptions::default();
+        for (option, value) in &options {
+            csv_options.set(option, value).unwrap_or_else(|e| invalid(e));
+        }
+        ctx.define_csv_table(&name, &schema, &csv_options);
+    }
+}
+
+fn main() {
+
+    let mut bind = "0.0.0.0:8090".to_string();
+    let mut workers = vec![];
+    let mut membership_dir = None;
+    let mut worker_timeout = DEFAULT_WORKER_TIMEOUT;
+    let mut ctx = ExecutionContext::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| fail(&format!("Missing value for {}", arg)));
+        match arg.as_ref() {
+            "--bind" => bind = value,
+            "--workers" => workers = value.split(',').map(|w| w.trim().to_string()).collect(),
+            "--catalog" => load_catalog(&mut ctx, &value),
+            "--membership-dir" => membership_dir = Some(value),
+            "--worker-timeout" => worker_timeout = Duration::from_secs(value.parse()
+                .unwrap_or_else(|e| fail(&format!("Invalid worker timeout {}: {}", value, e)))),
+            _ => fail(&format!("Unknown argument {}", arg))
+        }
+    }
+
+    let membership: Arc<dyn Membership> = match membership_dir {
+        Some(_) if !workers.is_empty() => fail("--workers and --membership-dir cannot both be used"),
+        Some(dir) => Arc::new(FileMembership::new(Path::new(&dir), worker_timeout)
+            .unwrap_or_else(|e| fail(&format!("Error opening membership directory {}: {:?}", dir, e)))),
+        None if !workers.is_empty() => Arc::new(StaticMembership::new(workers)),
+        None => Arc::new(InMemoryMembership::new(worker_timeout))
+    };
+
+    let addr = bind.parse().unwrap_or_else(|e| fail(&format!("Invalid bind address {}: {}", bind, e)));
+
+    println!("Coordinator listening on {}", addr);
+
+    let coordinator = Arc::new(Coordinator::new(ctx, membership));
+    let server = Http::new()
+        .bind(&addr, move || Ok(CoordinatorService::new(coordinator.clone()))).unwrap();
+    server.run().unwrap();
+}
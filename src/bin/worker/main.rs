@@ -13,132 +13,84 @@
 // limitations under the License.
 
 extern crate hyper;
-extern crate futures;
-extern crate serde;
-extern crate serde_json;
 
-use std::fs::File;
-use std::io::prelude::*;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 
-use futures::future::Future;
-use futures::Stream;
-
-use hyper::{Method, StatusCode, Chunk};
-use hyper::header::{ContentLength};
-use hyper::server::{Http, Request, Response, Service};
+use hyper::server::Http;
 
 extern crate datafusion;
-use datafusion::rel::*;
-
-struct Worker {
-    www_root: String
-}
-
-impl Worker {
-
-    fn load_static_file(&self, filename: &str) -> String {
-
-        let mut f = File::open(&filename).expect("file not found");
-
-        let mut contents = String::new();
-        f.read_to_string(&mut contents)
-            .expect("something went wrong reading the file");
-
-        contents
-    }
-
-}
-
-fn handle_request(chunk: Chunk) -> Response {
-
-    let body_bytes = chunk.iter()
-        .cloned()
-        .collect::<Vec<u8>>();
-
-    match String::from_utf8(body_bytes.clone()) {
-        Ok(json_str) => {
-            // for initial testing, just receive a relational plan directly but this should
-            // really be an execution plan
-            let rel: LogicalPlan = serde_json::from_str(&json_str).unwrap();
+use datafusion::membership::*;
+use datafusion::worker::Worker;
 
-            println!("Plan: {:?}", rel);
+const USAGE: &str = "Usage: worker [--bind host:port] [--advertise host:port] [--www-root dir] [--shuffle-dir dir] \
+    [--data-dir dir] [--coordinator host:port | --membership-dir dir] [--heartbeat-interval seconds]\n\n\
+    Plans posted to the worker can read any CSV file within the data directory, which defaults to the \
+    current directory. Only expose the worker to trusted clients.";
 
-            Response::new()
-                .with_body(body_bytes)
-        },
-        _ => panic!()
-    }
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(1);
 }
 
+fn main() {
 
-impl Service for Worker {
-    type Request = Request;
-    type Response = Response;
-    type Error = hyper::Error;
-    type Future = Box<Future<Item=Self::Response, Error=Self::Error>>;
-
-    fn call(&self, req: Request) -> Self::Future {
-
-        match req.method() {
-            &Method::Get => { // all UI calls are GET
-
-                //println!("path={:?}", req.path());
-
-                // is this a known file and/or valid path?
-                let filename = match req.path() {
-                    "/" => Some("/index.html"),
-                    "/css/main.css" => Some("/css/main.css"),
-                    _ => None
-                };
-
-                // server page, or a 404 not found error
-                match filename {
-                    Some(f) => {
-                        let fqpath = format!("{}/{}", self.www_root, f);
-                        let content = self.load_static_file(&fqpath);
-                        Box::new(futures::future::ok(
-                            Response::new()
-                                .with_header(ContentLength(content.len() as u64))
-                                .with_body(content)))
-                    }
-                    _ => {
-                        let fqpath = format!("{}/{}", self.www_root, "/404.html");
-                        let content = self.load_static_file(&fqpath);
-                        Box::new(futures::future::ok(
-                            Response::new()
-                                .with_status(StatusCode::NotFound)
-                                .with_header(ContentLength(content.len() as u64))
-                                .with_body(content)))
-                    }
-                }
-            }
-            &Method::Post => { // all REST calls are POST
-                Box::new(
-                    req.body()
-                        .concat2()
-                        .map(handle_request)
-                )
-            }
-            _ => {
-                Box::new(futures::future::ok(
-                    Response::new().with_status(StatusCode::NotFound)
-                ))
-            }
-
+    let mut bind = "0.0.0.0:8080".to_string();
+    let mut advertise = None;
+    let mut www_root = "./src/bin/worker/".to_string();
+    let mut shuffle_dir = env::temp_dir().join("datafusion_shuffle");
+    let mut data_dir = PathBuf::from(".");
+    let mut coordinator = None;
+    let mut membership_dir = None;
+    let mut heartbeat_interval = DEFAULT_HEARTBEAT_INTERVAL;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| fail(&format!("Missing value for {}", arg)));
+        match arg.as_ref() {
+            "--bind" => bind = value,
+            "--advertise" => advertise = Some(value),
+            "--www-root" => www_root = value,
+            "--shuffle-dir" => shuffle_dir = PathBuf::from(value),
+            "--data-dir" => data_dir = PathBuf::from(value),
+            "--coordinator" => coordinator = Some(value),
+            "--membership-dir" => membership_dir = Some(value),
+            "--heartbeat-interval" => heartbeat_interval = Duration::from_secs(value.parse()
+                .unwrap_or_else(|e| fail(&format!("Invalid heartbeat interval {}: {}", value, e)))),
+            _ => fail(&format!("Unknown argument {}", arg))
         }
     }
 
-}
-
-fn main() {
-
-    //TODO: make command-line params
-    let addr = "0.0.0.0:8080".parse().unwrap();
-    let www_root = "./src/bin/worker/";
+    let addr: SocketAddr = bind.parse().unwrap_or_else(|e| fail(&format!("Invalid bind address {}: {}", bind, e)));
+    let worker = Worker::new(&www_root, &shuffle_dir, &data_dir);
+
+    let membership: Option<Arc<dyn Membership>> = match (coordinator, membership_dir) {
+        (Some(_), Some(_)) => fail("--coordinator and --membership-dir cannot both be used"),
+        (Some(coordinator), None) => Some(Arc::new(CoordinatorMembership::new(&coordinator))),
+        (None, Some(dir)) => Some(Arc::new(FileMembership::new(&PathBuf::from(&dir), DEFAULT_WORKER_TIMEOUT)
+            .unwrap_or_else(|e| fail(&format!("Error opening membership directory {}: {:?}", dir, e))))),
+        (None, None) => None
+    };
+
+    // the address that other nodes use to reach this worker, which can only default to the bind
+    // address when that is a reachable address
+    let advertise = match advertise {
+        Some(advertise) => advertise,
+        None if membership.is_some() && addr.ip().is_unspecified() =>
+            fail(&format!("--advertise is required to join a cluster when binding to {}", bind)),
+        None => bind.clone()
+    };
+    if let Some(membership) = membership {
+        spawn_heartbeat(membership, advertise.clone(), worker.running_queries(), heartbeat_interval);
+    }
 
-    println!("Worker listening on {}", addr);
+    println!("Worker listening on {} as {}", addr, advertise);
 
     let server = Http::new()
-        .bind(&addr, move|| Ok(Worker { www_root: www_root.to_string() })).unwrap();
+        .bind(&addr, move|| Ok(worker.clone())).unwrap();
     server.run().unwrap();
-}
\ No newline at end of file
+}
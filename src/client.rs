@@ -0,0 +1,191 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HTTP client used by the coordinator to send plans to workers, by workers to fetch shuffle
+//! partitions from each other and by workers to send heartbeats to the coordinator. Rows are
+//! exchanged in the `values` output format.
+
+use std::str;
+use std::time::Duration;
+
+use super::futures::{self, Future, Stream};
+use super::futures::future::join_all;
+use super::hyper::{Chunk, Client, Method, StatusCode};
+use super::hyper::server::Request;
+use super::serde_json;
+use super::tokio_core::reactor::{Core, Timeout};
+
+use super::exec::*;
+use super::rel::*;
+use super::server::parse_error_json;
+
+/// Default time allowed for a request to be sent and its whole response to be received. Plans
+/// are executed before their results are returned, so this also limits the time that a worker
+/// may take to execute its part of a query.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A request to a worker or the coordinator. Requests for rows return them in the `values`
+/// format.
+#[derive(Debug,Clone)]
+pub struct WorkerRequest {
+    /// address of the server as `host:port`
+    pub worker: String,
+    pub method: Method,
+    /// path and query of the request
+    pub path: String,
+    pub body: Option<String>,
+    /// time allowed for connecting, sending the request and receiving the whole response
+    pub timeout: Duration
+}
+
+impl WorkerRequest {
+
+    /// Request the execution of a plan
+    pub fn execute(worker: &str, plan: &ExecutionPlan) -> Result<Self, ExecutionError> {
+        let body = serde_json::to_string(plan)
+            .map_err(|e| ExecutionError::Custom(format!("Error serializing plan: {}", e)))?;
+        Ok(WorkerRequest {
+            worker: worker.to_string(),
+            method: Method::Post,
+            path: "/?format=values".to_string(),
+            body: Some(body),
+            timeout: DEFAULT_REQUEST_TIMEOUT
+        })
+    }
+
+    /// Request the rows that a worker wrote to one partition of a shuffle
+    pub fn fetch_shuffle_partition(worker: &str, shuffle_id: &str, partition: usize) -> Self {
+        WorkerRequest {
+            worker: worker.to_string(),
+            method: Method::Get,
+            path: format!("/shuffle/{}/{}", shuffle_id, partition),
+            body: None,
+            timeout: DEFAULT_REQUEST_TIMEOUT
+        }
+    }
+
+    /// Request that a worker deletes the files it wrote for a shuffle
+    pub fn remove_shuffle(worker: &str, shuffle_id: &str) -> Self {
+        WorkerRequest {
+            worker: worker.to_string(),
+            method: Method::Delete,
+            path: format!("/shuffle/{}", shuffle_id),
+            body: None,
+            timeout: DEFAULT_REQUEST_TIMEOUT
+        }
+    }
+
+    /// Set the time allowed for the request
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Send requests to workers concurrently and return the rows from each response, in the order
+/// of the requests. The first failed request is returned as an error.
+pub fn send_requests(requests: Vec<WorkerRequest>) -> Result<Vec<Vec<Row>>, ExecutionError> {
+    send_all(requests, parse_response)
+}
+
+/// Send a request and return the body of the response, which must have a success status
+pub fn send_request(request: WorkerRequest) -> Result<String, ExecutionError> {
+    let mut bodies = send_all(vec![request], |status, body| response_text(status, body).map(|text| text.to_string()))?;
+    Ok(bodies.remove(0))
+}
+
+/// Send requests concurrently and parse each response. A request that has not completed within
+/// its timeout fails.
+fn send_all<T, P>(requests: Vec<WorkerRequest>, parse: P) -> Result<Vec<T>, ExecutionError>
+    where P: Fn(StatusCode, &Chunk) -> Result<T, String> {
+
+    let mut core = Core::new()?;
+    let handle = core.handle();
+    let client = Client::new(&handle);
+
+    let responses = requests.into_iter()
+        .map(|r| {
+            let worker = r.worker.clone();
+            let failed = move |message: String| ExecutionError::Custom(format!("Worker {} failed: {}", worker, message));
+
+            let uri = format!("http://{}{}", r.worker, r.path).parse()
+                .map_err(|e| ExecutionError::Custom(format!("Invalid worker address {}: {}", r.worker, e)));
+
+            let timeout = r.timeout;
+            let timed_out = failed.clone();
+            let timer = Timeout::new(timeout, &handle)?
+                .then(move |_| Err(timed_out(format!("No response within {:?}", timeout))));
+
+            let send_failed = failed.clone();
+            let response = futures::future::result(uri)
+                .and_then(|uri| {
+                    let mut req = Request::new(r.method, uri);
+                    if let Some(body) = r.body {
+                        req.set_body(body);
+                    }
+                    client.request(req)
+                        .and_then(|res| {
+                            let status = res.status();
+                            res.body().concat2().map(move |body| (status, body))
+                        })
+                        .map_err(move |e| send_failed(e.to_string()))
+                })
+                .and_then(|(status, body)| parse(status, &body).map_err(failed));
+
+            Ok(response.select(timer).map(|(t, _)| t).map_err(|(e, _)| e))
+        })
+        .collect::<Result<Vec<_>, ExecutionError>>()?;
+
+    core.run(join_all(responses))
+}
+
+/// Get the text of a response, or the error message if it does not have a success status
+fn response_text(status: StatusCode, body: &Chunk) -> Result<&str, String> {
+    let text = str::from_utf8(body).map_err(|e| e.to_string())?;
+    if status != StatusCode::Ok {
+        return Err(parse_error_json(text).unwrap_or_else(|| status.to_string()));
+    }
+    Ok(text)
+}
+
+/// Parse the rows in a response in the `values` format
+fn parse_response(status: StatusCode, body: &Chunk) -> Result<Vec<Row>, String> {
+    response_text(status, body)?.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match serde_json::from_str::<Vec<Value>>(line) {
+            Ok(values) => Ok(Row::new(values)),
+            // the only other lines are errors that occurred after the response started
+            Err(e) => Err(parse_error_json(line).unwrap_or_else(|| e.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_request_timeout() {
+        // the connection is queued by the listener but never answered
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let worker = listener.local_addr().unwrap().to_string();
+
+        let request = WorkerRequest::remove_shuffle(&worker, "s").with_timeout(Duration::from_millis(100));
+        match send_request(request) {
+            Err(ExecutionError::Custom(message)) => assert!(message.contains("No response within"), "{}", message),
+            other => panic!("Expected a timeout, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,585 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Coordinator, which plans SQL queries and executes them across a set of workers. The part of
+//! the plan that reads the input (the fragment) is executed by every worker against its own split
+//! of the input, and the results are merged by the coordinator. Joins are executed by first
+//! shuffling both inputs by the join key, so that each worker can join one shuffle partition.
+
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::futures::{self, Future, Stream};
+use super::hyper::{self, Method, StatusCode};
+use super::hyper::header::{ContentLength, ContentType};
+use super::hyper::server::{Request, Response, Service};
+use super::serde_json;
+
+use super::client::*;
+use super::exec::*;
+use super::membership::*;
+use super::parser::*;
+use super::rel::*;
+use super::server::*;
+use super::sql::*;
+
+/// Name of the in-memory table that the merge plan reads the results of the fragments from
+pub const FRAGMENT_RESULTS_TABLE: &str = "__fragment_results";
+
+/// Counter used to generate unique query ids, which name the shuffles of each query
+static QUERY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A shuffle that must be executed before the fragment, which reads it with an exchange
+#[derive(Debug,Clone)]
+pub struct Shuffle {
+    pub shuffle_id: String,
+    /// plan whose rows are shuffled, which each worker executes for its split of the input
+    pub plan: LogicalPlan,
+    pub partition_expr: Expr
+}
+
+/// A plan split into a fragment that is executed by the workers and a plan that merges the
+/// results of the fragment
+#[derive(Debug,Clone)]
+pub struct DistributedPlan {
+    /// shuffles that are executed by all workers before the fragment
+    pub shuffles: Vec<Shuffle>,
+    /// plan executed by the workers
+    pub fragment: LogicalPlan,
+    /// whether each worker executes the fragment for one partition of the input. Otherwise the
+    /// fragment is executed by a single worker.
+    pub partitioned: bool,
+    /// plan executed by the coordinator, which scans the results of the fragment from
+    /// `FRAGMENT_RESULTS_TABLE`
+    pub merge: LogicalPlan
+}
+
+/// Split a plan into a fragment and a merge plan. Filters and projections over a scan are
+/// executed by the workers, as are joins of such inputs and partial aggregates over either. The
+/// rest of the plan is executed by the coordinator. Shuffles are named after `query_id` and read
+/// from `workers`. Plans that cannot be split are executed by a single worker.
+pub fn create_distributed_plan(plan: &LogicalPlan, workers: &[String], query_id: &str) -> DistributedPlan {
+    let mut planner = DistributedPlanner { workers, query_id, shuffles: vec![] };
+    match planner.split_plan(plan) {
+        Some((fragment, merge)) => DistributedPlan { shuffles: planner.shuffles, fragment, partitioned: true, merge },
+        None => DistributedPlan {
+            shuffles: vec![],
+            fragment: plan.clone(),
+            partitioned: false,
+            merge: fragment_results(plan.schema())
+        }
+    }
+}
+
+fn fragment_results(schema: Schema) -> LogicalPlan {
+    LogicalPlan::TableScan {
+        schema_name: "default".to_string(),
+        table_name: FRAGMENT_RESULTS_TABLE.to_string(),
+        schema,
+        projection: None
+    }
+}
+
+struct DistributedPlanner<'a> {
+    workers: &'a [String],
+    query_id: &'a str,
+    shuffles: Vec<Shuffle>
+}
+
+impl<'a> DistributedPlanner<'a> {
+
+    /// Add a shuffle and return the exchange that reads it
+    fn shuffle(&mut self, plan: &LogicalPlan, partition_expr: &Expr) -> LogicalPlan {
+        let shuffle_id = format!("{}_{}", self.query_id, self.shuffles.len());
+        self.shuffles.push(Shuffle { shuffle_id: shuffle_id.clone(), plan: plan.clone(), partition_expr: partition_expr.clone() });
+        LogicalPlan::Exchange {
+            shuffle_id,
+            workers: self.workers.to_vec(),
+            partition_count: self.workers.len(),
+            schema: plan.schema()
+        }
+    }
+
+    /// Create the fragment for a plan that each worker can execute for one partition of the
+    /// input, producing a partition of the rows of the plan
+    fn partitioned_fragment(&mut self, plan: &LogicalPlan) -> Option<LogicalPlan> {
+        if is_scan_pipeline(plan) {
+            return Some(plan.clone());
+        }
+        match *plan {
+            // the inputs are shuffled on the first join key, so that matching rows are in the
+            // same partition whatever the other keys are
+            LogicalPlan::Join { ref left, ref right, ref join_type, ref on, ref schema }
+                if !on.is_empty() && is_scan_pipeline(left) && is_scan_pipeline(right) => {
+
+                let left = self.shuffle(left, &on[0].0);
+                let right = self.shuffle(right, &on[0].1);
+                Some(LogicalPlan::Join {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    join_type: join_type.clone(),
+                    on: on.clone(),
+                    schema: schema.clone()
+                })
+            },
+            LogicalPlan::Selection { ref expr, ref input, ref schema } => self.partitioned_fragment(input).map(|input|
+                LogicalPlan::Selection { expr: expr.clone(), input: Box::new(input), schema: schema.clone() }),
+            LogicalPlan::Projection { ref expr, ref input, ref schema } => self.partitioned_fragment(input).map(|input|
+                LogicalPlan::Projection { expr: expr.clone(), input: Box::new(input), schema: schema.clone() }),
+            _ => None
+        }
+    }
+
+    fn split_plan(&mut self, plan: &LogicalPlan) -> Option<(LogicalPlan, LogicalPlan)> {
+        if let Some(fragment) = self.partitioned_fragment(plan) {
+            return Some((fragment, fragment_results(plan.schema())));
+        }
+        match *plan {
+            LogicalPlan::Aggregate { ref group_expr, ref aggr_expr, ref input, ref schema, mode: AggregateMode::Complete } => {
+                match self.partitioned_fragment(input) {
+                    Some(input) => {
+                        let partial_schema = partial_aggregate_schema(schema, group_expr.len(), aggr_expr);
+                        let fragment = LogicalPlan::Aggregate {
+                            group_expr: group_expr.clone(),
+                            aggr_expr: aggr_expr.clone(),
+                            input: Box::new(input),
+                            schema: partial_schema.clone(),
+                            mode: AggregateMode::Partial
+                        };
+                        let merge = LogicalPlan::Aggregate {
+                            group_expr: group_expr.clone(),
+                            aggr_expr: aggr_expr.clone(),
+                            input: Box::new(fragment_results(partial_schema)),
+                            schema: schema.clone(),
+                            mode: AggregateMode::Final
+                        };
+                        Some((fragment, merge))
+                    },
+                    None => self.split_plan(input).map(|(fragment, merge)| (fragment, LogicalPlan::Aggregate {
+                        group_expr: group_expr.clone(),
+                        aggr_expr: aggr_expr.clone(),
+                        input: Box::new(merge),
+                        schema: schema.clone(),
+                        mode: AggregateMode::Complete
+                    }))
+                }
+            },
+            LogicalPlan::Projection { ref expr, ref input, ref schema } => self.split_plan(input).map(|(fragment, merge)|
+                (fragment, LogicalPlan::Projection { expr: expr.clone(), input: Box::new(merge), schema: schema.clone() })),
+            LogicalPlan::Selection { ref expr, ref input, ref schema } => self.split_plan(input).map(|(fragment, merge)|
+                (fragment, LogicalPlan::Selection { expr: expr.clone(), input: Box::new(merge), schema: schema.clone() })),
+            LogicalPlan::Sort { ref expr, ref input, ref schema } => self.split_plan(input).map(|(fragment, merge)|
+                (fragment, LogicalPlan::Sort { expr: expr.clone(), input: Box::new(merge), schema: schema.clone() })),
+            LogicalPlan::Limit { limit, offset, ref input, ref schema } => self.split_plan(input).map(|(fragment, merge)|
+                (fragment, LogicalPlan::Limit { limit, offset, input: Box::new(merge), schema: schema.clone() })),
+            LogicalPlan::Window { ref window_expr, ref input, ref schema } => self.split_plan(input).map(|(fragment, merge)|
+                (fragment, LogicalPlan::Window { window_expr: window_expr.clone(), input: Box::new(merge), schema: schema.clone() })),
+            LogicalPlan::Aggregate { ref group_expr, ref aggr_expr, ref input, ref schema, mode } => self.split_plan(input).map(|(fragment, merge)|
+                (fragment, LogicalPlan::Aggregate {
+                    group_expr: group_expr.clone(),
+                    aggr_expr: aggr_expr.clone(),
+                    input: Box::new(merge),
+                    schema: schema.clone(),
+                    mode
+                })),
+            _ => None
+        }
+    }
+}
+
+/// Plans SQL queries and executes them across the live workers of a cluster
+pub struct Coordinator {
+    ctx: ExecutionContext,
+    membership: Arc<dyn Membership>
+}
+
+impl Coordinator {
+
+    /// Create a coordinator that plans queries using the tables defined in the execution context
+    /// and sends fragments to the workers that are live in the membership
+    pub fn new(ctx: ExecutionContext, membership: Arc<dyn Membership>) -> Self {
+        Coordinator { ctx, membership }
+    }
+
+    pub fn membership(&self) -> &Arc<dyn Membership> {
+        &self.membership
+    }
+
+    /// Execute a query, returning the relation that merges the results from the workers along
+    /// with the context to scan it with. Only queries are accepted: other statements would only
+    /// change the copy of the context that the query is planned with, and tables and functions
+    /// are defined in the context that the coordinator is created with instead.
+    pub fn execute(&self, sql: &str) -> Result<(ExecutionContext, Box<dyn SimpleRelation>), ExecutionError> {
+        match Parser::parse_sql(sql.to_string())? {
+            ASTNode::SQLSelect { .. } => {},
+            _ => return Err(ExecutionError::ParserError(ParserError::ParserError(
+                "Only queries can be executed by the coordinator".to_string())))
+        }
+
+        let workers: Vec<String> = self.membership.live_workers()?.into_iter().map(|w| w.address).collect();
+        if workers.is_empty() {
+            return Err(ExecutionError::Custom("There are no workers to execute the query".to_string()));
+        }
+
+        let mut ctx = self.ctx.clone();
+        let plan = ctx.sql(sql)?.plan();
+        let plan = ctx.optimize(&plan)?;
+        // workers do not know the tables, so they are sent the files to read instead
+        let plan = ctx.resolve_csv_tables(&plan);
+        let query_id = format!("{}_{}", process::id(), QUERY_COUNT.fetch_add(1, Ordering::SeqCst));
+        let distributed_plan = create_distributed_plan(&plan, &workers, &query_id);
+
+        let rows = execute_fragment(&distributed_plan, &workers);
+
+        // the shuffle files are no longer needed once the fragment has been executed. Failing to
+        // remove them does not affect the results, so the failure is only logged.
+        if !distributed_plan.shuffles.is_empty() {
+            let requests = distributed_plan.shuffles.iter()
+                .flat_map(|s| workers.iter().map(move |w| WorkerRequest::remove_shuffle(w, &s.shuffle_id)))
+                .collect();
+            if let Err(e) = send_requests(requests) {
+                eprintln!("Error removing the shuffle files of query {}: {:?}", query_id, e);
+            }
+        }
+
+        ctx.register_memory_table(FRAGMENT_RESULTS_TABLE, &distributed_plan.fragment.schema(), rows?);
+        let rel = ctx.create_execution_plan(&distributed_plan.merge)?;
+        Ok((ctx, rel))
+    }
+}
+
+/// Execute the shuffles and then the fragment on the workers, returning the rows produced by the
+/// fragment
+fn execute_fragment(distributed_plan: &DistributedPlan, workers: &[String]) -> Result<Vec<Row>, ExecutionError> {
+    let partition_count = workers.len();
+
+    let shuffle_requests = distributed_plan.shuffles.iter()
+        .flat_map(|s| workers.iter().enumerate().map(move |(i, w)| WorkerRequest::execute(w, &ExecutionPlan::Partition {
+            plan: s.plan.clone(),
+            partition_count,
+            partition_expr: Some(s.partition_expr.clone()),
+            partition: Some(i),
+            shuffle_id: Some(s.shuffle_id.clone())
+        })))
+        .collect::<Result<Vec<WorkerRequest>, ExecutionError>>()?;
+    send_requests(shuffle_requests)?;
+
+    let requests = if distributed_plan.partitioned {
+        workers.iter()
+            .enumerate()
+            .map(|(i, worker)| WorkerRequest::execute(worker, &ExecutionPlan::Partition {
+                plan: distributed_plan.fragment.clone(),
+                partition_count,
+                partition_expr: None,
+                partition: Some(i),
+                shuffle_id: None
+            }))
+            .collect::<Result<Vec<WorkerRequest>, ExecutionError>>()?
+    } else {
+        vec![WorkerRequest::execute(&workers[0], &ExecutionPlan::Interactive { plan: distributed_plan.fragment.clone() })?]
+    };
+
+    Ok(send_requests(requests)?.into_iter().flat_map(|rows| rows.into_iter()).collect())
+}
+
+/// Coordinator HTTP service. Queries are posted as SQL and the results are returned in the same
+/// formats as the worker. Workers post heartbeats to `/cluster/heartbeat` and `/cluster` lists
+/// the live workers and their load.
+pub struct CoordinatorService {
+    coordinator: Arc<Coordinator>
+}
+
+impl CoordinatorService {
+
+    pub fn new(coordinator: Arc<Coordinator>) -> Self {
+        CoordinatorService { coordinator }
+    }
+}
+
+impl Service for CoordinatorService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<dyn Future<Item=Self::Response, Error=Self::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        match (req.method(), req.path()) {
+            (&Method::Get, "/cluster") => {
+                let response = match self.coordinator.membership.live_workers() {
+                    Ok(workers) => {
+                        let body = cluster_json(&workers);
+                        Response::new()
+                            .with_header(ContentType::json())
+                            .with_header(ContentLength(body.len() as u64))
+                            .with_body(body)
+                    },
+                    Err(e) => execution_error_response(&e)
+                };
+                return Box::new(futures::future::ok(response));
+            },
+            (&Method::Post, "/cluster/heartbeat") => {
+                let membership = self.coordinator.membership.clone();
+                return Box::new(req.body().concat2().map(move |chunk| {
+                    match serde_json::from_slice::<WorkerInfo>(&chunk) {
+                        Ok(worker) => match membership.heartbeat(&worker) {
+                            Ok(_) => Response::new(),
+                            Err(e) => execution_error_response(&e)
+                        },
+                        Err(e) => error_response(StatusCode::BadRequest, "invalid_heartbeat", &format!("Invalid heartbeat: {}", e))
+                    }
+                }));
+            },
+            (&Method::Post, _) => {},
+            _ => return Box::new(futures::future::ok(error_response(
+                StatusCode::MethodNotAllowed, "invalid_request", "Queries must be posted as SQL")))
+        }
+
+        let format = match OutputFormat::from_query(req.query()) {
+            Ok(format) => format,
+            Err(message) => return Box::new(futures::future::ok(
+                error_response(StatusCode::BadRequest, "invalid_format", &message)))
+        };
+
+        let coordinator = self.coordinator.clone();
+        Box::new(req.body().concat2().and_then(move |chunk| {
+            match String::from_utf8(chunk.to_vec()) {
+                Ok(sql) => stream_results(format, move || coordinator.execute(&sql)),
+                Err(e) => Box::new(futures::future::ok(error_response(
+                    StatusCode::BadRequest, "invalid_request", &format!("Query is not valid UTF-8: {}", e))))
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::hyper::server::Http;
+    use super::super::worker::Worker;
+    use std::env;
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    static WORKER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Start a worker on an unused localhost port and return its address
+    fn start_worker() -> String {
+        let (tx, rx) = mpsc::channel();
+        // each worker has its own shuffle directory, as it would on a separate machine
+        let shuffle_dir = env::temp_dir().join(format!("datafusion_coordinator_test_{}_{}",
+            process::id(), WORKER_COUNT.fetch_add(1, Ordering::SeqCst)));
+        thread::spawn(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let server = Http::new().bind(&addr, move || Ok(Worker::new("./src/bin/worker/", &shuffle_dir, Path::new(".")))).unwrap();
+            tx.send(server.local_addr().unwrap().to_string()).unwrap();
+            server.run().unwrap();
+        });
+        rx.recv().unwrap()
+    }
+
+    fn create_context() -> ExecutionContext {
+        let mut ctx = ExecutionContext::new();
+        ctx.define_schema("people", &Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false)]));
+        ctx.define_schema("orders", &Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]));
+        ctx.define_csv_table("uk_cities", &Schema::new(vec![
+            Field::new("city", DataType::String, false),
+            Field::new("lat", DataType::Double, false),
+            Field::new("lng", DataType::Double, false)]), &CsvOptions { has_header: false, ..CsvOptions::default() });
+        ctx
+    }
+
+    fn execute(coordinator: &Coordinator, sql: &str) -> Vec<String> {
+        let (ctx, rel) = coordinator.execute(sql).unwrap();
+        let rows = rel.scan(&ctx).map(|r| r.unwrap().to_string()).collect();
+        rows
+    }
+
+    fn execute_locally(sql: &str) -> Vec<String> {
+        let mut ctx = create_context();
+        let plan = ctx.sql(sql).unwrap().plan();
+        let rel = ctx.create_execution_plan(&plan).unwrap();
+        let rows = rel.scan(&ctx).map(|r| r.unwrap().to_string()).collect();
+        rows
+    }
+
+    #[test]
+    fn test_distributed_plan() {
+        let mut ctx = create_context();
+        let plan = ctx.sql("SELECT customer_id, AVG(amount) FROM orders WHERE order_id > 1 \
+            GROUP BY customer_id ORDER BY customer_id").unwrap().plan();
+
+        let workers = vec!["a:1".to_string(), "b:1".to_string()];
+        let distributed_plan = create_distributed_plan(&plan, &workers, "q");
+        assert!(distributed_plan.partitioned);
+        assert!(distributed_plan.shuffles.is_empty());
+        match distributed_plan.fragment {
+            LogicalPlan::Aggregate { mode: AggregateMode::Partial, ref schema, .. } =>
+                assert_eq!("customer_id: UnsignedLong,AVG: ComplexType([Field { name: \"sum\", data_type: Double, nullable: false }, \
+                    Field { name: \"count\", data_type: UnsignedLong, nullable: false }])", schema.to_string()),
+            ref other => panic!("Expected partial aggregate but found {:?}", other)
+        }
+
+        // both inputs of a join are shuffled by the join key
+        let plan = ctx.sql("SELECT name, order_id FROM people JOIN orders ON people.id = orders.customer_id").unwrap().plan();
+        let distributed_plan = create_distributed_plan(&plan, &workers, "q");
+        assert!(distributed_plan.partitioned);
+        let shuffles: Vec<(String, Expr)> = distributed_plan.shuffles.iter()
+            .map(|s| (s.shuffle_id.clone(), s.partition_expr.clone()))
+            .collect();
+        assert_eq!(vec![("q_0".to_string(), Expr::TupleValue(0)), ("q_1".to_string(), Expr::TupleValue(1))], shuffles);
+        match distributed_plan.fragment {
+            LogicalPlan::Projection { ref input, .. } => match **input {
+                LogicalPlan::Join { ref left, ref right, .. } => match (&**left, &**right) {
+                    (&LogicalPlan::Exchange { shuffle_id: ref l, partition_count: 2, .. },
+                     &LogicalPlan::Exchange { shuffle_id: ref r, partition_count: 2, .. }) => {
+                        assert_eq!("q_0", l);
+                        assert_eq!("q_1", r);
+                    },
+                    other => panic!("Expected exchanges but found {:?}", other)
+                },
+                ref other => panic!("Expected join but found {:?}", other)
+            },
+            ref other => panic!("Expected projection but found {:?}", other)
+        }
+
+        // joins without keys are executed by a single worker
+        let people = ctx.sql("SELECT id FROM people").unwrap().plan();
+        let orders = ctx.sql("SELECT order_id FROM orders").unwrap().plan();
+        let plan = LogicalPlan::Join {
+            schema: Schema::join(&people.schema(), &orders.schema(), &JoinType::Cross),
+            left: Box::new(people),
+            right: Box::new(orders),
+            join_type: JoinType::Cross,
+            on: vec![]
+        };
+        let distributed_plan = create_distributed_plan(&plan, &workers, "q");
+        assert!(!distributed_plan.partitioned);
+        assert!(distributed_plan.shuffles.is_empty());
+    }
+
+    #[test]
+    fn test_execute_across_workers() {
+        let workers = (0..3).map(|_| start_worker()).collect();
+        let coordinator = Coordinator::new(create_context(), Arc::new(StaticMembership::new(workers)));
+
+        assert_eq!(vec!["1,3,20,1,10.5,6.666666666666667", "2,2,27.5,2,20,13.75", "3,1,100,4,100,100", "11,1,50,7,50,50"],
+                   execute(&coordinator, "SELECT customer_id, COUNT(*), SUM(amount), MIN(order_id), MAX(amount), AVG(amount) \
+                        FROM orders GROUP BY customer_id ORDER BY customer_id"));
+
+        assert_eq!(vec!["6,147.5"],
+                   execute(&coordinator, "SELECT COUNT(*), SUM(amount) FROM orders WHERE amount < 50.0 OR customer_id = 3"));
+
+        assert_eq!(vec!["7", "6", "5"],
+                   execute(&coordinator, "SELECT order_id FROM orders ORDER BY order_id DESC LIMIT 3"));
+
+        // joins shuffle both inputs across the workers
+        assert_eq!(vec!["Andy,1", "Brian,2", "Andy,3"],
+                   execute(&coordinator, "SELECT name, order_id FROM people \
+                        JOIN orders ON people.id = orders.customer_id ORDER BY order_id LIMIT 3"));
+
+        assert_eq!(vec!["Andy,3,20", "Brian,2,27.5", "Chris,1,100"],
+                   execute(&coordinator, "SELECT name, COUNT(*), SUM(amount) FROM people \
+                        JOIN orders ON people.id = orders.customer_id GROUP BY name ORDER BY name"));
+
+        let sql = "SELECT order_id, name FROM orders LEFT JOIN people ON orders.customer_id = people.id ORDER BY order_id";
+        assert_eq!(execute_locally(sql), execute(&coordinator, sql));
+        let sql = "SELECT id, order_id FROM orders RIGHT JOIN people ON orders.customer_id = people.id ORDER BY id, order_id";
+        assert_eq!(execute_locally(sql), execute(&coordinator, sql));
+
+        // workers read tables with the CSV options that they were defined with
+        assert_eq!(vec!["37"], execute(&coordinator, "SELECT COUNT(*) FROM uk_cities"));
+        let sql = "SELECT city FROM uk_cities WHERE lat > 53.0 ORDER BY city";
+        assert_eq!(execute_locally(sql), execute(&coordinator, sql));
+    }
+
+    #[test]
+    fn test_worker_errors() {
+        let coordinator = Coordinator::new(create_context(), Arc::new(StaticMembership::new(vec![start_worker()])));
+
+        // the worker reports the division by zero while executing its fragment
+        match coordinator.execute("SELECT order_id / 0 FROM orders") {
+            Err(ExecutionError::Custom(ref message)) => assert!(message.contains("Division by zero"), "{}", message),
+            Err(e) => panic!("Expected worker error but found {:?}", e),
+            Ok(_) => panic!("Expected worker error")
+        }
+
+        let coordinator = Coordinator::new(create_context(), Arc::new(StaticMembership::new(vec![])));
+        assert!(coordinator.execute("SELECT order_id FROM orders").is_err());
+    }
+
+    #[test]
+    fn test_statements_are_rejected() {
+        let coordinator = Coordinator::new(create_context(), Arc::new(StaticMembership::new(vec![start_worker()])));
+
+        for sql in &["CREATE EXTERNAL TABLE t (a DOUBLE)",
+                     "CREATE FUNCTION f(DOUBLE) RETURNS DOUBLE LIBRARY '/tmp/libf.so'",
+                     "ANALYZE TABLE orders",
+                     "EXPLAIN SELECT order_id FROM orders"] {
+            match coordinator.execute(sql) {
+                Err(ExecutionError::ParserError(ParserError::ParserError(ref message))) =>
+                    assert_eq!("Only queries can be executed by the coordinator", message),
+                Err(e) => panic!("Expected statement to be rejected but found {:?}", e),
+                Ok(_) => panic!("Expected statement to be rejected: {}", sql)
+            }
+        }
+    }
+
+    #[test]
+    fn test_cluster() {
+        let membership = Arc::new(InMemoryMembership::new(Duration::from_secs(60)));
+        let coordinator = Arc::new(Coordinator::new(create_context(), membership.clone()));
+
+        // start the coordinator's HTTP service so that workers can send heartbeats to it
+        let (tx, rx) = mpsc::channel();
+        let service_coordinator = coordinator.clone();
+        thread::spawn(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let server = Http::new().bind(&addr, move || Ok(CoordinatorService::new(service_coordinator.clone()))).unwrap();
+            tx.send(server.local_addr().unwrap().to_string()).unwrap();
+            server.run().unwrap();
+        });
+        let coordinator_address = rx.recv().unwrap();
+        let remote = CoordinatorMembership::new(&coordinator_address);
+        assert!(remote.live_workers().unwrap().is_empty());
+
+        // the workers join the cluster with their first heartbeat
+        let workers: Vec<String> = (0..2).map(|_| start_worker()).collect();
+        for worker in &workers {
+            spawn_heartbeat(Arc::new(CoordinatorMembership::new(&coordinator_address)), worker.clone(),
+                            Arc::new(AtomicUsize::new(0)), Duration::from_millis(50));
+        }
+        while remote.live_workers().unwrap().len() < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut expected = workers.clone();
+        expected.sort();
+        assert_eq!(expected, remote.live_workers().unwrap().into_iter().map(|w| w.address).collect::<Vec<String>>());
+
+        assert_eq!(vec!["7,197.5"], execute(&coordinator, "SELECT COUNT(*), SUM(amount) FROM orders"));
+
+        // invalid heartbeats are rejected
+        let service = CoordinatorService::new(coordinator.clone());
+        let mut req = Request::new(Method::Post, "/cluster/heartbeat".parse().unwrap());
+        req.set_body("{}");
+        assert_eq!(StatusCode::BadRequest, service.call(req).wait().unwrap().status());
+    }
+}
@@ -49,13 +49,32 @@ pub trait DataFrame {
     /// Selection
     fn filter(&self, expr: Expr) -> Result<Box<DataFrame>,DataFrameError>;
 
+    /// Group by the grouping expressions and compute the aggregate expressions for each group
+    fn aggregate(&self, group_expr: Vec<Expr>, aggr_expr: Vec<Expr>) -> Result<Box<dyn DataFrame>,DataFrameError>;
+
+    /// Sort by the sort expressions (see `Expr::sort`)
+    fn sort(&self, expr: Vec<Expr>) -> Result<Box<dyn DataFrame>,DataFrameError>;
+
+    /// Return at most `n` rows
+    fn limit(&self, n: usize) -> Result<Box<dyn DataFrame>,DataFrameError>;
+
+    /// Join with another DataFrame. Each pair in `on` is a column of this DataFrame and a column
+    /// of the other DataFrame that must be equal.
+    fn join(&self, right: &dyn DataFrame, join_type: JoinType, on: Vec<(Expr, Expr)>) -> Result<Box<dyn DataFrame>,DataFrameError>;
+
     /// Write to CSV ...  will support other formats in the future
     fn write(&self, filename: &str) -> Result<(),DataFrameError>;
 
+    /// Execute the plan and return the resulting rows
+    fn collect(&self) -> Result<Vec<Row>,DataFrameError>;
+
     /// Return an expression representing the specified column
     fn col(&self, column_name: &str) -> Result<Expr,DataFrameError>;
 
     fn schema(&self) -> Schema;
 
+    /// Return the logical plan that this DataFrame represents
+    fn plan(&self) -> LogicalPlan;
+
 }
 
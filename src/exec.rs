@@ -12,22 +12,40 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io::Error;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter, Lines};
 use std::io::prelude::*;
-use std::iter::Iterator;
-use std::fs::File;
+use std::iter::{self, Iterator};
+use std::fs::{self, File};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::{mpsc, Arc};
 use std::string::String;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::convert::*;
 
 extern crate csv;
 
-use super::csv::StringRecord;
+use super::libloading::{Library, Symbol};
+use super::num_cpus;
+use super::threadpool::ThreadPool;
+use super::serde_json;
 
 use super::api::*;
+use super::batch::*;
+use super::explain::*;
+use super::optimizer::{self, OptimizerRule};
 use super::rel::*;
+use super::shuffle::*;
+use super::stats::*;
 use super::parser::*;
+use super::sql::*;
 use super::sqltorel::*;
 use super::dataframe::*;
 use super::functions::math::*;
@@ -38,6 +56,12 @@ pub enum ExecutionError {
     IoError(Error),
     CsvError(csv::Error),
     ParserError(ParserError),
+    /// A null value was found in a column that is not nullable
+    NullValue(String),
+    /// A plan tried to read a file that it is not allowed to read
+    ForbiddenPath(String),
+    /// A request was not valid, such as one for a shuffle partition that is not a number
+    InvalidRequest(String),
     Custom(String)
 }
 
@@ -59,455 +83,3498 @@ impl From<ParserError> for ExecutionError {
     }
 }
 
-/// Represents a csv file with a known schema
-#[derive(Debug)]
-pub struct CsvRelation {
-    file: File,
+/// Hash aggregate. The input is fully consumed before any groups are returned.
+pub struct AggregateRelation {
+    schema: Schema,
+    input: Box<dyn SimpleRelation>,
+    group_expr: Vec<Expr>,
+    aggr_expr: Vec<Expr>,
+    mode: AggregateMode
+}
+
+/// trait for all relations (a relation is essentially just an iterator over tuples with
+/// a known schema)
+pub trait SimpleRelation {
+    /// scan all records in this relation
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<Iterator<Item=Result<Row,ExecutionError>> + 'a>;
+    /// get the schema for this relation
+    fn schema<'a>(&'a self) -> &'a Schema;
+    /// describe this relation for EXPLAIN
+    fn describe(&self) -> String;
+    /// get the relations that this relation reads from
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![]
+    }
+    /// get the number of bytes this relation has read from files
+    fn bytes_read(&self) -> u64 {
+        0
+    }
+    /// get the metrics collected while this relation was scanned, if they are being collected
+    fn metrics(&self) -> Option<Metrics> {
+        None
+    }
+}
+
+/// Limit relation. Rows are only pulled from the input until the limit is reached, so the
+/// remainder of the input is never read.
+pub struct LimitRelation {
+    schema: Schema,
+    input: Box<dyn SimpleRelation>,
+    limit: usize,
+    offset: usize
+}
+
+impl SimpleRelation for LimitRelation {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        Box::new(self.input.scan(ctx).skip(self.offset).take(self.limit))
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        format!("LimitRelation: limit={}, offset={}", self.limit, self.offset)
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Rows(&*self.input)]
+    }
+}
+
+/// Relation with no rows, e.g. the result of a statement that does not return data
+pub struct EmptyRelation {
     schema: Schema
 }
 
-pub struct FilterRelation {
+impl SimpleRelation for EmptyRelation {
+
+    fn scan<'a>(&'a self, _ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        Box::new(iter::empty())
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        "EmptyRelation".to_string()
+    }
+}
+
+/// Relation over rows held in memory. When a plan is created for one partition, each
+/// partition scans its own range of the rows.
+pub struct MemoryRelation {
+    schema: Schema,
+    rows: Arc<Vec<Row>>,
+    range: (usize, usize)
+}
+
+impl SimpleRelation for MemoryRelation {
+
+    fn scan<'a>(&'a self, _ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        Box::new(self.rows[self.range.0..self.range.1].iter().cloned().map(Ok))
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        format!("MemoryRelation: rows={}", self.range.1 - self.range.0)
+    }
+}
+
+/// Hash join. The right input is loaded into a hash table keyed on the join keys (the build
+/// side) and the left input is then streamed through it (the probe side).
+pub struct HashJoinRelation {
+    schema: Schema,
+    left: Box<dyn SimpleRelation>,
+    right: Box<dyn SimpleRelation>,
+    join_type: JoinType,
+    on: Vec<(Expr, Expr)>
+}
+
+impl HashJoinRelation {
+
+    fn join_key(&self, ctx: &ExecutionContext, row: &Row, schema: &Schema, key_expr: &[&Expr]) -> Result<GroupKey, ExecutionError> {
+        Ok(GroupKey(key_expr.iter()
+            .map(|e| ctx.evaluate(row, schema, e))
+            .collect::<Result<Vec<Value>, Box<ExecutionError>>>()
+            .map_err(|e| *e)?))
+    }
+
+    /// Consume the right input and build the hash table
+    fn build(&self, ctx: &ExecutionContext) -> Result<(Vec<Row>, HashMap<GroupKey, Vec<usize>>), ExecutionError> {
+        let right_keys: Vec<&Expr> = self.on.iter().map(|(_, r)| r).collect();
+        let mut rows = vec![];
+        let mut table: HashMap<GroupKey, Vec<usize>> = HashMap::new();
+        for row in self.right.scan(ctx) {
+            let row = row?;
+            let key = self.join_key(ctx, &row, self.right.schema(), &right_keys)?;
+            // null never equals anything so rows with null keys can never match
+            if !key.0.contains(&Value::Null) {
+                table.entry(key).or_default().push(rows.len());
+            }
+            rows.push(row);
+        }
+        Ok((rows, table))
+    }
+}
+
+/// Streams the probe side of a hash join through the hash table
+struct HashJoinIterator<'a> {
+    relation: &'a HashJoinRelation,
+    ctx: &'a ExecutionContext,
+    left: Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a>,
+    left_keys: Vec<&'a Expr>,
+    right_rows: Vec<Row>,
+    table: HashMap<GroupKey, Vec<usize>>,
+    matched: Vec<bool>,
+    output: VecDeque<Row>,
+    /// position of the next unmatched right row to return once the left input is exhausted
+    unmatched_index: Option<usize>
+}
+
+impl<'a> HashJoinIterator<'a> {
+
+    fn null_row(len: usize) -> Vec<Value> {
+        vec![Value::Null; len]
+    }
+
+    /// Join a row from the left input with all matching rows from the right input
+    fn probe(&mut self, row: Row) -> Result<(), ExecutionError> {
+        let key = self.relation.join_key(self.ctx, &row, self.relation.left.schema(), &self.left_keys)?;
+        match self.table.get(&key) {
+            Some(indices) => for &i in indices {
+                self.matched[i] = true;
+                let mut values = row.values.clone();
+                values.extend(self.right_rows[i].values.iter().cloned());
+                self.output.push_back(Row::new(values));
+            },
+            None => match self.relation.join_type {
+                JoinType::Left | JoinType::Full => {
+                    let mut values = row.values;
+                    values.extend(Self::null_row(self.relation.right.schema().columns.len()));
+                    self.output.push_back(Row::new(values));
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the next right row that did not match any left row, for right and full joins
+    fn next_unmatched(&mut self) -> Option<Row> {
+        let mut i = self.unmatched_index?;
+        while i < self.right_rows.len() && self.matched[i] {
+            i += 1;
+        }
+        if i < self.right_rows.len() {
+            self.unmatched_index = Some(i + 1);
+            let mut values = Self::null_row(self.relation.left.schema().columns.len());
+            values.extend(self.right_rows[i].values.iter().cloned());
+            Some(Row::new(values))
+        } else {
+            self.unmatched_index = None;
+            None
+        }
+    }
+}
+
+impl<'a> Iterator for HashJoinIterator<'a> {
+    type Item = Result<Row, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.output.pop_front() {
+                return Some(Ok(row));
+            }
+            if self.unmatched_index.is_some() {
+                return self.next_unmatched().map(Ok);
+            }
+            match self.left.next() {
+                Some(Ok(row)) => if let Err(e) = self.probe(row) {
+                    return Some(Err(e));
+                },
+                Some(Err(e)) => return Some(Err(e)),
+                None => match self.relation.join_type {
+                    JoinType::Right | JoinType::Full if !self.right_rows.is_empty() => {
+                        self.unmatched_index = Some(0);
+                    },
+                    _ => return None
+                }
+            }
+        }
+    }
+}
+
+impl SimpleRelation for HashJoinRelation {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        match self.build(ctx) {
+            Ok((right_rows, table)) => Box::new(HashJoinIterator {
+                relation: self,
+                ctx,
+                left: self.left.scan(ctx),
+                left_keys: self.on.iter().map(|(l, _)| l).collect(),
+                matched: vec![false; right_rows.len()],
+                right_rows,
+                table,
+                output: VecDeque::new(),
+                unmatched_index: None
+            }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        format!("HashJoinRelation: type={:?}, on=[{}]", self.join_type,
+                format_join_keys(&self.on, self.left.schema(), self.right.schema()))
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Rows(&*self.left), RelationRef::Rows(&*self.right)]
+    }
+}
+
+/// Sort relation. Rows are sorted in memory until the memory limit is exceeded, at which point
+/// the buffered rows are sorted and written to a temporary file as a sorted run. The runs are
+/// merged to produce the final output.
+pub struct SortRelation {
     schema: Schema,
-    input: Box<SimpleRelation>,
-    expr: Expr
+    input: Box<dyn SimpleRelation>,
+    sort_expr: Vec<Expr>,
+    memory_limit: usize
+}
+
+/// Sort key along with the row it was computed from
+type SortEntry = (Vec<Value>, Row);
+
+/// Counter used to generate unique names for spill files
+static SPILL_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary file holding a sorted run. The file is deleted when this is dropped.
+struct SpillFile {
+    path: PathBuf
+}
+
+impl SpillFile {
+
+    /// Write sorted entries to a new temporary file, one JSON array per line
+    fn write(entries: &[SortEntry]) -> Result<Self, ExecutionError> {
+        let spill_file = SpillFile {
+            path: env::temp_dir().join(format!("datafusion_sort_{}_{}.json",
+                process::id(), SPILL_FILE_COUNT.fetch_add(1, AtomicOrdering::SeqCst)))
+        };
+
+        let mut writer = BufWriter::new(File::create(&spill_file.path)?);
+        for &(ref key, ref row) in entries {
+            serde_json::to_writer(&mut writer, &(key, &row.values))
+                .map_err(|e| ExecutionError::Custom(format!("Error writing sort run: {}", e)))?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+
+        Ok(spill_file)
+    }
+
+    /// Read the entries back in the order they were written
+    fn read(self) -> Result<SpillFileReader, ExecutionError> {
+        let lines = BufReader::new(File::open(&self.path)?).lines();
+        Ok(SpillFileReader { lines, _spill_file: self })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct SpillFileReader {
+    lines: Lines<BufReader<File>>,
+    _spill_file: SpillFile
+}
+
+impl Iterator for SpillFileReader {
+    type Item = Result<SortEntry, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.next().map(|line| {
+            let (key, values): (Vec<Value>, Vec<Value>) = serde_json::from_str(&line?)
+                .map_err(|e| ExecutionError::Custom(format!("Error reading sort run: {}", e)))?;
+            Ok((key, Row::new(values)))
+        })
+    }
+}
+
+/// Approximate number of bytes used by a list of values
+fn estimate_size(values: &[Value]) -> usize {
+    values.iter()
+        .map(|v| mem::size_of::<Value>() + match *v {
+            Value::String(ref s) => s.len(),
+            Value::ComplexValue(ref v) => estimate_size(v),
+            _ => 0
+        })
+        .sum()
+}
+
+/// Head of one of the sorted runs being merged
+struct MergeEntry<'a> {
+    entry: SortEntry,
+    run: usize,
+    relation: &'a SortRelation
+}
+
+impl<'a> Ord for MergeEntry<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap so reverse the ordering to pop the smallest entry first, and
+        // prefer earlier runs for equal keys so that the sort is stable
+        self.relation.compare(&self.entry.0, &other.entry.0)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl<'a> PartialOrd for MergeEntry<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for MergeEntry<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for MergeEntry<'a> {}
+
+/// K-way merge of sorted runs
+struct MergeIterator<'a> {
+    runs: Vec<Box<dyn Iterator<Item=Result<SortEntry, ExecutionError>> + 'a>>,
+    heap: BinaryHeap<MergeEntry<'a>>,
+    relation: &'a SortRelation
+}
+
+impl<'a> MergeIterator<'a> {
+
+    fn new(mut runs: Vec<Box<dyn Iterator<Item=Result<SortEntry, ExecutionError>> + 'a>>,
+           relation: &'a SortRelation) -> Result<Self, ExecutionError> {
+
+        let mut heap = BinaryHeap::new();
+        for (run, entries) in runs.iter_mut().enumerate() {
+            if let Some(entry) = entries.next() {
+                heap.push(MergeEntry { entry: entry?, run, relation });
+            }
+        }
+        Ok(MergeIterator { runs, heap, relation })
+    }
+}
+
+impl<'a> Iterator for MergeIterator<'a> {
+    type Item = Result<Row, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heap.pop()?;
+        match self.runs[head.run].next() {
+            Some(Ok(entry)) => self.heap.push(MergeEntry { entry, run: head.run, relation: self.relation }),
+            Some(Err(e)) => return Some(Err(e)),
+            None => {}
+        }
+        Some(Ok(head.entry.1))
+    }
+}
+
+/// Compare two sort keys according to the sort expressions that produced them. Expressions that
+/// are not `Expr::Sort` are sorted ascending with nulls last.
+fn compare_sort_keys(sort_expr: &[Expr], a: &[Value], b: &[Value]) -> Ordering {
+    for (i, e) in sort_expr.iter().enumerate() {
+        let (asc, nulls_first) = match *e {
+            Expr::Sort { asc, nulls_first, .. } => (asc, nulls_first),
+            _ => (true, false)
+        };
+        let ordering = match (&a[i], &b[i]) {
+            (&Value::Null, &Value::Null) => Ordering::Equal,
+            (&Value::Null, _) => if nulls_first { Ordering::Less } else { Ordering::Greater },
+            (_, &Value::Null) => if nulls_first { Ordering::Greater } else { Ordering::Less },
+            (a, b) => {
+                let ordering = a.partial_cmp(b).unwrap_or(Ordering::Equal);
+                if asc { ordering } else { ordering.reverse() }
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+impl SortRelation {
+
+    /// Compare two sort keys according to the sort expressions
+    fn compare(&self, a: &[Value], b: &[Value]) -> Ordering {
+        compare_sort_keys(&self.sort_expr, a, b)
+    }
+
+    fn sort_entry(&self, ctx: &ExecutionContext, row: Row) -> Result<SortEntry, ExecutionError> {
+        let key = self.sort_expr.iter()
+            .map(|e| ctx.evaluate(&row, self.input.schema(), e))
+            .collect::<Result<Vec<Value>, Box<ExecutionError>>>()
+            .map_err(|e| *e)?;
+        Ok((key, row))
+    }
+
+    /// Consume the input, spilling sorted runs to disk whenever the memory limit is exceeded,
+    /// and return an iterator over the sorted rows
+    fn sort<'a>(&'a self, ctx: &'a ExecutionContext) -> Result<Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a>, ExecutionError> {
+        let mut buffer: Vec<SortEntry> = vec![];
+        let mut buffer_size = 0;
+        let mut spill_files: Vec<SpillFile> = vec![];
+
+        for row in self.input.scan(ctx) {
+            let entry = self.sort_entry(ctx, row?)?;
+            buffer_size += estimate_size(&entry.0) + estimate_size(&entry.1.values);
+            buffer.push(entry);
+
+            if buffer_size > self.memory_limit {
+                buffer.sort_by(|a, b| self.compare(&a.0, &b.0));
+                spill_files.push(SpillFile::write(&buffer)?);
+                buffer.clear();
+                buffer_size = 0;
+            }
+        }
+
+        buffer.sort_by(|a, b| self.compare(&a.0, &b.0));
+
+        if spill_files.is_empty() {
+            return Ok(Box::new(buffer.into_iter().map(|(_, row)| Ok(row))));
+        }
+
+        // the rows that are still buffered form the final run
+        let mut runs: Vec<Box<dyn Iterator<Item=Result<SortEntry, ExecutionError>> + 'a>> = vec![];
+        for spill_file in spill_files {
+            runs.push(Box::new(spill_file.read()?));
+        }
+        runs.push(Box::new(buffer.into_iter().map(Ok)));
+
+        Ok(Box::new(MergeIterator::new(runs, self)?))
+    }
+}
+
+impl SimpleRelation for SortRelation {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        match self.sort(ctx) {
+            Ok(it) => it,
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        let sort_expr: Vec<String> = self.sort_expr.iter().map(|e| format_expr(e, self.input.schema())).collect();
+        format!("SortRelation: {}", sort_expr.join(", "))
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Rows(&*self.input)]
+    }
+}
+
+/// Running state of an aggregate function for a single group
+#[derive(Clone)]
+enum Accumulator {
+    Count(u64),
+    Sum(Option<Value>),
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Avg { sum: f64, count: u64 },
+    UserDefined { func: Arc<dyn AggregateFunction>, state: Value }
+}
+
+impl Accumulator {
+
+    fn new(name: &str, ctx: &ExecutionContext) -> Result<Self, ExecutionError> {
+        match name.to_lowercase().as_ref() {
+            "count" => Ok(Accumulator::Count(0)),
+            "sum" => Ok(Accumulator::Sum(None)),
+            "min" => Ok(Accumulator::Min(None)),
+            "max" => Ok(Accumulator::Max(None)),
+            "avg" => Ok(Accumulator::Avg { sum: 0.0, count: 0 }),
+            name => match ctx.aggregate_functions.get(name) {
+                Some(func) => Ok(Accumulator::UserDefined { func: func.clone(), state: func.init() }),
+                None => Err(ExecutionError::Custom(format!("Unknown aggregate function {}", name)))
+            }
+        }
+    }
+
+    /// Update the state with the argument values of the next input row (COUNT(*) has no
+    /// arguments)
+    fn accumulate(&mut self, args: Vec<Value>) -> Result<(), ExecutionError> {
+        // aggregate functions ignore null values
+        if args.contains(&Value::Null) {
+            return Ok(());
+        }
+        if let Accumulator::UserDefined { ref func, ref mut state } = *self {
+            return func.accumulate(state, args).map_err(|e| ExecutionError::Custom(
+                format!("Error executing aggregate function {}: {}", func.name(), e)));
+        }
+        let value = args.into_iter().next();
+        match *self {
+            Accumulator::Count(ref mut count) => *count += 1,
+            Accumulator::Sum(ref mut sum) => {
+                let value = value.ok_or_else(|| ExecutionError::Custom("SUM requires an argument".to_string()))?;
+                *sum = Some(match sum.take() {
+                    None => value,
+                    Some(s) => evaluate_arithmetic(&Operator::Plus, &s, &value)?
+                });
+            },
+            Accumulator::Min(ref mut min) => {
+                let value = value.ok_or_else(|| ExecutionError::Custom("MIN requires an argument".to_string()))?;
+                let replace = match *min {
+                    Some(ref m) => value < *m,
+                    None => true
+                };
+                if replace {
+                    *min = Some(value);
+                }
+            },
+            Accumulator::Max(ref mut max) => {
+                let value = value.ok_or_else(|| ExecutionError::Custom("MAX requires an argument".to_string()))?;
+                let replace = match *max {
+                    Some(ref m) => value > *m,
+                    None => true
+                };
+                if replace {
+                    *max = Some(value);
+                }
+            },
+            Accumulator::Avg { ref mut sum, ref mut count } => {
+                *sum += match value {
+                    Some(Value::UnsignedLong(n)) => n as f64,
+                    Some(Value::Long(n)) => n as f64,
+                    Some(Value::Double(d)) => d,
+                    other => return Err(ExecutionError::Custom(
+                        format!("Unsupported type for AVG: {:?}", other)))
+                };
+                *count += 1;
+            },
+            Accumulator::UserDefined { .. } => unreachable!()
+        }
+        Ok(())
+    }
+
+    /// Get the intermediate state, which can be merged with the state of another accumulator for
+    /// the same aggregate function
+    fn state(&self) -> Value {
+        match *self {
+            Accumulator::Count(count) => Value::UnsignedLong(count),
+            Accumulator::Sum(ref v) | Accumulator::Min(ref v) | Accumulator::Max(ref v) =>
+                v.clone().unwrap_or(Value::Null),
+            Accumulator::Avg { sum, count } =>
+                Value::ComplexValue(vec![Value::Double(sum), Value::UnsignedLong(count)]),
+            Accumulator::UserDefined { ref state, .. } => state.clone()
+        }
+    }
+
+    /// Merge the state of another accumulator into this accumulator
+    fn merge(&mut self, other: Value) -> Result<(), ExecutionError> {
+        let invalid = |other: &Value| ExecutionError::Custom(
+            format!("Invalid state for aggregate: {:?}", other));
+        match *self {
+            Accumulator::Count(ref mut count) => match other {
+                Value::UnsignedLong(n) => *count += n,
+                ref other => return Err(invalid(other))
+            },
+            // the states of SUM, MIN and MAX are partial results
+            Accumulator::Sum(_) | Accumulator::Min(_) | Accumulator::Max(_) => self.accumulate(vec![other])?,
+            Accumulator::Avg { ref mut sum, ref mut count } => match other {
+                Value::ComplexValue(ref v) if v.len() == 2 => match (&v[0], &v[1]) {
+                    (&Value::Double(s), &Value::UnsignedLong(c)) => {
+                        *sum += s;
+                        *count += c;
+                    },
+                    _ => return Err(invalid(&other))
+                },
+                ref other => return Err(invalid(other))
+            },
+            Accumulator::UserDefined { ref func, ref mut state } => func.merge(state, other)
+                .map_err(|e| ExecutionError::Custom(
+                    format!("Error executing aggregate function {}: {}", func.name(), e)))?
+        }
+        Ok(())
+    }
+
+    /// Get the final value of the aggregate, which is null if there were no non-null input
+    /// values (except for COUNT, which is zero)
+    fn value(&self) -> Result<Value, ExecutionError> {
+        match *self {
+            Accumulator::Count(count) => Ok(Value::UnsignedLong(count)),
+            Accumulator::Sum(Some(ref v)) |
+            Accumulator::Min(Some(ref v)) |
+            Accumulator::Max(Some(ref v)) => Ok(v.clone()),
+            Accumulator::Avg { sum, count } if count > 0 => Ok(Value::Double(sum / count as f64)),
+            Accumulator::UserDefined { ref func, ref state } => func.finalize(state.clone())
+                .map_err(|e| ExecutionError::Custom(
+                    format!("Error executing aggregate function {}: {}", func.name(), e))),
+            _ => Ok(Value::Null)
+        }
+    }
+}
+
+/// Create the schema for a partial aggregate from the schema of the aggregate, replacing each
+/// aggregate column with a column for the intermediate state of the aggregate
+pub fn partial_aggregate_schema(schema: &Schema, group_count: usize, aggr_expr: &[Expr]) -> Schema {
+    let states = schema.columns[group_count..].iter().zip(aggr_expr.iter())
+        .map(|(field, e)| {
+            let name = match *e {
+                Expr::AggregateFunction { ref name, .. } => name.to_lowercase(),
+                _ => String::new()
+            };
+            match name.as_ref() {
+                "count" => field.clone(),
+                // partial results are null if there were no input values
+                "sum" | "min" | "max" => Field::new(&field.name, field.data_type.clone(), true),
+                "avg" => Field::new(&field.name, DataType::ComplexType(vec![
+                    Field::new("sum", DataType::Double, false),
+                    Field::new("count", DataType::UnsignedLong, false)]), false),
+                // the state of a user-defined aggregate is opaque
+                _ => Field::new(&field.name, DataType::ComplexType(vec![]), true)
+            }
+        });
+    Schema::new(schema.columns[0..group_count].iter().cloned().chain(states).collect())
+}
+
+/// Grouping key for hash-based operators. Values are compared and hashed in their normalised
+/// form, so that equal numbers of different types, e.g. the keys of a join between an unsigned
+/// and a signed column, always end up in the same group.
+#[derive(Debug,Clone)]
+struct GroupKey(Vec<Value>);
+
+impl PartialEq for GroupKey {
+    fn eq(&self, other: &GroupKey) -> bool {
+        self.0.len() == other.0.len() &&
+            self.0.iter().zip(other.0.iter()).all(|(a, b)| normalize_value(a) == normalize_value(b))
+    }
+}
+
+impl Eq for GroupKey {}
+
+impl Hash for GroupKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.iter().for_each(|v| hash_value(v, state));
+    }
+}
+
+/// Choose the shuffle partition for a value by hashing its normalised encoding with FNV-1a. The
+/// hash depends only on the value, so every worker assigns equal values, including equal numbers
+/// of different types, to the same partition however the worker was built.
+pub fn hash_partition(value: &Value, partition_count: usize) -> usize {
+    let mut hasher = FnvHasher::default();
+    hash_value(value, &mut hasher);
+    (hasher.finish() % partition_count as u64) as usize
+}
+
+/// 64-bit FNV-1a hasher. The algorithm of `DefaultHasher` may change between releases of Rust,
+/// but FNV-1a always produces the same hash for the same bytes.
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
 }
 
-pub struct ProjectRelation {
-    schema: Schema,
-    input: Box<SimpleRelation>,
-    expr: Vec<Expr>
-}
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A value in the form that it is compared and hashed in by hash-based operators
+#[derive(PartialEq)]
+enum NormalizedValue<'a> {
+    /// integers, and doubles with no fractional part
+    Integer(i128),
+    /// the bits of any other double. Negative zero is an integer and all NaNs have the same bits.
+    Double(u64),
+    Other(&'a Value)
+}
+
+fn normalize_value(value: &Value) -> NormalizedValue<'_> {
+    match *value {
+        Value::UnsignedLong(n) => NormalizedValue::Integer(i128::from(n)),
+        Value::Long(n) => NormalizedValue::Integer(i128::from(n)),
+        // whole doubles within this range can be represented exactly as an i128
+        Value::Double(d) if d.fract() == 0.0 && d.abs() < 1.0e38 => NormalizedValue::Integer(d as i128),
+        Value::Double(d) if d.is_nan() => NormalizedValue::Double(f64::NAN.to_bits()),
+        Value::Double(d) => NormalizedValue::Double(d.to_bits()),
+        _ => NormalizedValue::Other(value)
+    }
+}
+
+/// Hash a value so that values that are equal once normalised have the same hash. The hasher is
+/// given a fixed encoding of the value that does not depend on the platform: a tag byte followed
+/// by the little-endian bytes of numbers, or the length and bytes of strings and lists.
+pub fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+    match normalize_value(value) {
+        NormalizedValue::Integer(n) => {
+            state.write_u8(0);
+            state.write(&n.to_le_bytes());
+        },
+        NormalizedValue::Double(bits) => {
+            state.write_u8(1);
+            state.write(&bits.to_le_bytes());
+        },
+        NormalizedValue::Other(Value::String(s)) => {
+            state.write_u8(2);
+            state.write(&(s.len() as u64).to_le_bytes());
+            state.write(s.as_bytes());
+        },
+        NormalizedValue::Other(&Value::Boolean(b)) => {
+            state.write_u8(3);
+            state.write_u8(b as u8);
+        },
+        NormalizedValue::Other(Value::ComplexValue(v)) => {
+            state.write_u8(4);
+            state.write(&(v.len() as u64).to_le_bytes());
+            v.iter().for_each(|v| hash_value(v, state));
+        },
+        NormalizedValue::Other(_) => state.write_u8(5)
+    }
+}
+
+impl AggregateRelation {
+
+    fn create_accumulators(&self, ctx: &ExecutionContext) -> Result<Vec<Accumulator>, ExecutionError> {
+        self.aggr_expr.iter()
+            .map(|e| match *e {
+                Expr::AggregateFunction { ref name, .. } => Accumulator::new(name, ctx),
+                _ => Err(ExecutionError::Custom(
+                    format!("Invalid aggregate expression {:?}", e)))
+            })
+            .collect()
+    }
+
+    /// Consume the input and compute the aggregate values for each group, returning the groups in
+    /// the order in which they were first seen
+    fn aggregate(&self, ctx: &ExecutionContext) -> Result<Vec<Row>, ExecutionError> {
+        let mut group_index: HashMap<GroupKey, usize> = HashMap::new();
+        let mut groups: Vec<(GroupKey, Vec<Accumulator>)> = vec![];
+
+        for row in self.input.scan(ctx) {
+            let row = row?;
+
+            // the input of a final aggregate starts with the grouping columns
+            let key = GroupKey(if self.mode == AggregateMode::Final {
+                row.values[0..self.group_expr.len()].to_vec()
+            } else {
+                self.group_expr.iter()
+                    .map(|e| ctx.evaluate(&row, self.input.schema(), e))
+                    .collect::<Result<Vec<Value>, Box<ExecutionError>>>()
+                    .map_err(|e| *e)?
+            });
+
+            let index = match group_index.get(&key) {
+                Some(&i) => i,
+                None => {
+                    groups.push((key.clone(), self.create_accumulators(ctx)?));
+                    group_index.insert(key, groups.len() - 1);
+                    groups.len() - 1
+                }
+            };
+
+            if self.mode == AggregateMode::Final {
+                let states = row.values.into_iter().skip(self.group_expr.len());
+                for (state, accumulator) in states.zip(groups[index].1.iter_mut()) {
+                    accumulator.merge(state)?;
+                }
+                continue;
+            }
+
+            for (e, accumulator) in self.aggr_expr.iter().zip(groups[index].1.iter_mut()) {
+                let args = match *e {
+                    Expr::AggregateFunction { ref args, .. } => args.iter()
+                        .map(|a| ctx.evaluate(&row, self.input.schema(), a))
+                        .collect::<Result<Vec<Value>, Box<ExecutionError>>>()
+                        .map_err(|e| *e)?,
+                    _ => vec![]
+                };
+                accumulator.accumulate(args)?;
+            }
+        }
+
+        // an aggregate without grouping always produces a single row, even for empty input
+        if groups.is_empty() && self.group_expr.is_empty() {
+            groups.push((GroupKey(vec![]), self.create_accumulators(ctx)?));
+        }
+
+        groups.into_iter()
+            .map(|(key, accumulators)| {
+                let mut values = key.0;
+                for accumulator in accumulators {
+                    values.push(match self.mode {
+                        AggregateMode::Partial => accumulator.state(),
+                        _ => accumulator.value()?
+                    });
+                }
+                Ok(Row::new(values))
+            })
+            .collect()
+    }
+}
+
+impl SimpleRelation for AggregateRelation {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        match self.aggregate(ctx) {
+            Ok(rows) => Box::new(rows.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        let list = |expr: &[Expr]| expr.iter().map(|e| format_expr(e, self.input.schema())).collect::<Vec<String>>().join(", ");
+        format!("AggregateRelation: mode={:?}, group_by=[{}], aggr=[{}]",
+                self.mode, list(&self.group_expr), list(&self.aggr_expr))
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Rows(&*self.input)]
+    }
+}
+
+/// Execution plans are sent to worker nodes for execution
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub enum ExecutionPlan {
+    /// Run a query and return the results to the client
+    Interactive { plan: LogicalPlan },
+    /// Partition the relation. The CSV files scanned by the plan are split into byte ranges and
+    /// each partition reads one split. When `partition` is set, only that partition is executed,
+    /// otherwise all partitions are executed in parallel and their results are combined.
+    ///
+    /// With a partitioning expression, the rows are instead shuffled: they are hash partitioned
+    /// by the expression into `partition_count` partitions and written to the shuffle files for
+    /// `shuffle_id`, and the number of rows written to each partition is returned.
+    Partition {
+        plan: LogicalPlan,
+        partition_count: usize,
+        partition_expr: Option<Expr>,
+        #[serde(default)]
+        partition: Option<usize>,
+        #[serde(default)]
+        shuffle_id: Option<String>
+    }
+
+}
+
+/// Runs a plan with one task per partition on a thread pool with a thread per core. Batches are
+/// returned in the order that they are produced, so rows from different partitions are
+/// interleaved.
+pub struct PartitionedRelation {
+    schema: Schema,
+    plan: LogicalPlan,
+    partition_count: usize
+}
+
+impl SimpleRelation for PartitionedRelation {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        let pool = ThreadPool::new(cmp::min(self.partition_count, num_cpus::get()));
+
+        // the channel is bounded so that partitions do not run too far ahead of the consumer
+        let (tx, rx) = mpsc::sync_channel(self.partition_count);
+        for partition in 0..self.partition_count {
+            let tx = tx.clone();
+            let ctx = ctx.clone();
+            let plan = self.plan.clone();
+            let partition_count = self.partition_count;
+            pool.execute(move || {
+                let rel = match ctx.create_partition_plan(&plan, partition, partition_count) {
+                    Ok(rel) => rel,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+                for batch in rel.scan_batches(&ctx) {
+                    // stop early if the consumer has gone away, e.g. because a limit was reached
+                    if tx.send(batch).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        Box::new(rx.into_iter().flat_map(|b| -> Box<dyn Iterator<Item=Result<Row, ExecutionError>>> {
+            match b {
+                Ok(batch) => Box::new((0..batch.num_rows()).map(move |i| Ok(batch.row(i)))),
+                Err(e) => Box::new(iter::once(Err(e)))
+            }
+        }))
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        format!("PartitionedRelation: partitions={}", self.partition_count)
+    }
+}
+
+/// Determine whether a plan is a pipeline of filters and projections over a CSV scan, which can
+/// be executed in parallel on splits of the file
+pub fn is_scan_pipeline(plan: &LogicalPlan) -> bool {
+    match *plan {
+        LogicalPlan::TableScan { .. } | LogicalPlan::CsvFile { .. } => true,
+        LogicalPlan::Selection { ref input, .. } | LogicalPlan::Projection { ref input, .. } => is_scan_pipeline(input),
+        _ => false
+    }
+}
+
+/// Determine whether a plan can be partitioned. Joins can only be partitioned when both inputs
+/// are read from exchanges, which hash partition the rows by the join key. Splitting the scans of
+/// a join instead would only join a split of each input in each partition.
+pub fn is_partitionable(plan: &LogicalPlan) -> bool {
+    fn check(plan: &LogicalPlan, in_join: bool) -> bool {
+        match *plan {
+            LogicalPlan::Join { ref left, ref right, .. } => check(left, true) && check(right, true),
+            LogicalPlan::Projection { ref input, .. } | LogicalPlan::Selection { ref input, .. } |
+            LogicalPlan::Aggregate { ref input, .. } | LogicalPlan::Sort { ref input, .. } |
+            LogicalPlan::Window { ref input, .. } | LogicalPlan::Limit { ref input, .. } => check(input, in_join),
+            LogicalPlan::TableScan { .. } | LogicalPlan::CsvFile { .. } => !in_join,
+            LogicalPlan::Exchange { .. } | LogicalPlan::EmptyRelation { .. } => true
+        }
+    }
+    check(plan, false)
+}
+
+
+/// Window relation. The input is fully consumed and each window function is computed by ordering
+/// the rows by their partition and sort keys. Rows are returned in input order with a column
+/// appended for each window function.
+pub struct WindowRelation {
+    schema: Schema,
+    input: Box<dyn SimpleRelation>,
+    window_expr: Vec<Expr>
+}
+
+impl SimpleRelation for WindowRelation {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        match self.compute(ctx) {
+            Ok(rows) => Box::new(rows.into_iter().map(Ok)),
+            Err(e) => Box::new(iter::once(Err(e)))
+        }
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        let window_expr: Vec<String> = self.window_expr.iter().map(|e| format_expr(e, self.input.schema())).collect();
+        format!("WindowRelation: {}", window_expr.join(", "))
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Rows(&*self.input)]
+    }
+}
+
+impl WindowRelation {
+
+    fn compute(&self, ctx: &ExecutionContext) -> Result<Vec<Row>, ExecutionError> {
+        let rows = self.input.scan(ctx).collect::<Result<Vec<Row>, ExecutionError>>()?;
+
+        let columns = self.window_expr.iter()
+            .map(|e| self.compute_window_function(ctx, e, &rows))
+            .collect::<Result<Vec<Vec<Value>>, ExecutionError>>()?;
+
+        Ok(rows.into_iter().enumerate()
+            .map(|(i, row)| {
+                let mut values = row.values;
+                values.extend(columns.iter().map(|c| c[i].clone()));
+                Row::new(values)
+            })
+            .collect())
+    }
+
+    /// Evaluate a list of expressions against every row
+    fn evaluate_all(&self, ctx: &ExecutionContext, rows: &[Row], expr: &[Expr]) -> Result<Vec<Vec<Value>>, ExecutionError> {
+        rows.iter()
+            .map(|row| expr.iter()
+                .map(|e| ctx.evaluate(row, self.input.schema(), e))
+                .collect::<Result<Vec<Value>, Box<ExecutionError>>>()
+                .map_err(|e| *e))
+            .collect()
+    }
+
+    /// Compute a window function for every row, returning the values in input order
+    fn compute_window_function(&self, ctx: &ExecutionContext, e: &Expr, rows: &[Row]) -> Result<Vec<Value>, ExecutionError> {
+        let (name, args, partition_by, order_by, frame) = match *e {
+            Expr::WindowFunction { ref name, ref args, ref partition_by, ref order_by, ref frame } =>
+                (name, args, partition_by, order_by, frame),
+            _ => return Err(ExecutionError::Custom(format!("Invalid window expression {:?}", e)))
+        };
+
+        let partition_keys = self.evaluate_all(ctx, rows, partition_by)?;
+        let sort_keys = self.evaluate_all(ctx, rows, order_by)?;
+        let arg_values = self.evaluate_all(ctx, rows, args)?;
+
+        // order the rows by partition and then by the sort keys, keeping ties in input order
+        let mut indices: Vec<usize> = (0..rows.len()).collect();
+        indices.sort_by(|&a, &b| compare_sort_keys(partition_by, &partition_keys[a], &partition_keys[b])
+            .then_with(|| compare_sort_keys(order_by, &sort_keys[a], &sort_keys[b])));
+
+        let mut result = vec![Value::Null; rows.len()];
+        let mut start = 0;
+        while start < indices.len() {
+            let key = &partition_keys[indices[start]];
+            let end = start + indices[start..].iter().take_while(|&&i| partition_keys[i] == *key).count();
+            let partition = &indices[start..end];
+
+            // the rows that sort equal to each row
+            let peers = peer_groups(partition, order_by, &sort_keys);
+
+            let values = match name.to_lowercase().as_ref() {
+                "row_number" => (0..partition.len()).map(|p| Value::UnsignedLong(p as u64 + 1)).collect(),
+                "rank" => peers.iter().map(|&(first, _)| Value::UnsignedLong(first as u64 + 1)).collect(),
+                "dense_rank" => {
+                    let mut rank = 0;
+                    peers.iter().enumerate()
+                        .map(|(p, &(first, _))| {
+                            if first == p {
+                                rank += 1;
+                            }
+                            Value::UnsignedLong(rank)
+                        })
+                        .collect()
+                },
+                "lag" | "lead" => {
+                    let offset = match args.get(1) {
+                        Some(&Expr::Literal(Value::UnsignedLong(n))) => n as usize,
+                        _ => 1
+                    };
+                    let is_lag = name.eq_ignore_ascii_case("lag");
+                    (0..partition.len())
+                        .map(|p| {
+                            let other = if is_lag { p.checked_sub(offset) } else { p.checked_add(offset) };
+                            match other {
+                                Some(other) if other < partition.len() => arg_values[partition[other]][0].clone(),
+                                _ => arg_values[partition[p]].get(2).cloned().unwrap_or(Value::Null)
+                            }
+                        })
+                        .collect()
+                },
+                _ => compute_framed_aggregate(ctx, name, frame, !order_by.is_empty(), partition, &peers, &arg_values)?
+            };
+
+            for (p, value) in values.into_iter().enumerate() {
+                result[partition[p]] = value;
+            }
+            start = end;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Find the first and last (exclusive) position of the rows sorting equal to each row of an
+/// ordered partition. All rows are peers when there are no sort keys.
+fn peer_groups(partition: &[usize], order_by: &[Expr], sort_keys: &[Vec<Value>]) -> Vec<(usize, usize)> {
+    let mut peers = Vec::with_capacity(partition.len());
+    let mut start = 0;
+    while start < partition.len() {
+        let key = &sort_keys[partition[start]];
+        let end = start + partition[start..].iter()
+            .take_while(|&&i| compare_sort_keys(order_by, &sort_keys[i], key) == Ordering::Equal)
+            .count();
+        peers.extend((start..end).map(|_| (start, end)));
+        start = end;
+    }
+    peers
+}
+
+/// Compute an aggregate function over the frame of each row of an ordered partition
+fn compute_framed_aggregate(ctx: &ExecutionContext, name: &str, frame: &Option<WindowFrame>, ordered: bool,
+                            partition: &[usize], peers: &[(usize, usize)], args: &[Vec<Value>]) -> Result<Vec<Value>, ExecutionError> {
+    let n = partition.len();
+
+    // first and last (exclusive) position of the frame of each row
+    let bounds: Vec<(usize, usize)> = (0..n)
+        .map(|p| match *frame {
+            Some(ref frame) => (frame_start(&frame.start, p, n), frame_end(&frame.end, p, n)),
+            None if ordered => (0, peers[p].1),
+            None => (0, n)
+        })
+        .collect();
+
+    let mut values = Vec::with_capacity(n);
+    if bounds.iter().all(|&(start, _)| start == 0) {
+        // the frames only ever grow, so the aggregate can be computed incrementally
+        let mut accumulator = Accumulator::new(name, ctx)?;
+        let mut next = 0;
+        for &(_, end) in &bounds {
+            while next < end {
+                accumulator.accumulate(args[partition[next]].clone())?;
+                next += 1;
+            }
+            values.push(accumulator.value()?);
+        }
+    } else {
+        for &(start, end) in &bounds {
+            let mut accumulator = Accumulator::new(name, ctx)?;
+            for &i in partition.iter().take(end).skip(start) {
+                accumulator.accumulate(args[i].clone())?;
+            }
+            values.push(accumulator.value()?);
+        }
+    }
+    Ok(values)
+}
+
+/// First position of a window frame for the row at position `p` of a partition of `n` rows
+fn frame_start(bound: &WindowFrameBound, p: usize, n: usize) -> usize {
+    match *bound {
+        WindowFrameBound::UnboundedPreceding => 0,
+        WindowFrameBound::Preceding(k) => p.saturating_sub(k),
+        WindowFrameBound::CurrentRow => p,
+        WindowFrameBound::Following(k) => p.saturating_add(k).min(n),
+        WindowFrameBound::UnboundedFollowing => n
+    }
+}
+
+/// Position after the last row of a window frame for the row at position `p` of a partition of
+/// `n` rows
+fn frame_end(bound: &WindowFrameBound, p: usize, n: usize) -> usize {
+    match *bound {
+        WindowFrameBound::UnboundedPreceding => 0,
+        WindowFrameBound::Preceding(k) => (p + 1).saturating_sub(k),
+        WindowFrameBound::CurrentRow => p + 1,
+        WindowFrameBound::Following(k) => p.saturating_add(k).saturating_add(1).min(n),
+        WindowFrameBound::UnboundedFollowing => n
+    }
+}
+
+/// Default amount of memory that a sort may use before spilling sorted runs to disk
+const DEFAULT_SORT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct ExecutionContext {
+    schemas: HashMap<String, Schema>,
+    /// statistics collected by `ANALYZE TABLE`, keyed by table name
+    statistics: HashMap<String, Arc<TableStatistics>>,
+    /// options for parsing the CSV files of tables, keyed by table name. Other tables are parsed
+    /// with the default options.
+    csv_options: HashMap<String, CsvOptions>,
+    /// tables whose rows are held in memory rather than read from CSV files
+    memory_tables: HashMap<String, Arc<Vec<Row>>>,
+    /// scalar functions keyed by lower case name
+    functions: HashMap<String, Arc<dyn ScalarFunction>>,
+    /// user-defined aggregate functions keyed by lower case name
+    aggregate_functions: HashMap<String, Arc<dyn AggregateFunction>>,
+    sort_memory_limit: usize,
+    /// number of rows in each record batch produced by columnar relations
+    batch_size: usize,
+    /// number of partitions that scans are split into
+    partition_count: usize,
+    /// split of each CSV file to read when creating the plan for a single partition
+    split: Option<(usize, usize)>,
+    /// directory that shuffle files are written to
+    shuffle_dir: PathBuf,
+    /// directory that CSV files are read from. When set, only relative paths within the directory
+    /// can be read.
+    data_dir: Option<PathBuf>,
+    /// rules applied to logical plans before they are executed
+    optimizer_rules: Vec<Arc<dyn OptimizerRule>>,
+    /// whether relations are created with metrics collection for EXPLAIN ANALYZE
+    collect_metrics: bool,
+    /// UDF libraries that registered functions have been loaded from. These are declared last so
+    /// that the functions are dropped before the libraries containing their code are unloaded.
+    libraries: Vec<Arc<Library>>
+}
+
+/// Name of the CSV file that a table which is not held in memory is read from
+fn table_filename(table_name: &str) -> String {
+    format!("test/data/{}.csv", table_name)
+}
+
+/// Load a UDF library and call its registration function to find out which functions it provides
+fn open_udf_library(path: &str) -> Result<(Arc<Library>, FunctionRegistry), ExecutionError> {
+    let library = Library::new(path).map_err(|e| ExecutionError::Custom(
+        format!("Failed to load UDF library {}: {}", path, e)))?;
+    let mut registry = FunctionRegistry::new();
+    unsafe {
+        let register: Symbol<UdfRegistrationFn> = library.get(UDF_REGISTRATION_SYMBOL.as_bytes())
+            .map_err(|e| ExecutionError::Custom(format!(
+                "UDF library {} does not export {}: {}", path, UDF_REGISTRATION_SYMBOL, e)))?;
+        register(&mut registry);
+    }
+    Ok((Arc::new(library), registry))
+}
+
+/// Interpret the result of evaluating an operand of a logical operator, where null represents
+/// an unknown value
+fn evaluate_boolean(value: Value) -> Result<Option<bool>, Box<ExecutionError>> {
+    match value {
+        Value::Boolean(b) => Ok(Some(b)),
+        Value::Null => Ok(None),
+        other => Err(Box::new(ExecutionError::Custom(format!(
+            "Logical operator expected a boolean operand but found {:?}", other))))
+    }
+}
+
+/// Convert a numeric value to a double
+pub fn to_f64(value: &Value) -> Option<f64> {
+    match *value {
+        Value::UnsignedLong(n) => Some(n as f64),
+        Value::Long(n) => Some(n as f64),
+        Value::Double(d) => Some(d),
+        _ => None
+    }
+}
+
+/// Convert an integer value to a type that can represent both unsigned and signed integers
+fn to_i128(value: &Value) -> Option<i128> {
+    match *value {
+        Value::UnsignedLong(n) => Some(i128::from(n)),
+        Value::Long(n) => Some(i128::from(n)),
+        _ => None
+    }
+}
+
+/// Compare two values, promoting numeric values to a common type first
+pub fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (&Value::Double(_), _) | (_, &Value::Double(_)) => match (to_f64(left), to_f64(right)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => left.partial_cmp(right)
+        },
+        _ => match (to_i128(left), to_i128(right)) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => left.partial_cmp(right)
+        }
+    }
+}
+
+/// Apply an arithmetic operator. If either operand is a double then the result is a double.
+/// Otherwise the result is an unsigned integer when both operands are unsigned integers, except
+/// for subtraction, and a signed integer in all other cases.
+fn evaluate_arithmetic(op: &Operator, left: &Value, right: &Value) -> Result<Value, ExecutionError> {
+    let is_division = *op == Operator::Divide || *op == Operator::Modulus;
+    match (left, right) {
+        (&Value::Double(_), _) | (_, &Value::Double(_)) => {
+            let (a, b) = match (to_f64(left), to_f64(right)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return Err(ExecutionError::Custom(format!(
+                    "Unsupported operands for {:?}: {:?} and {:?}", op, left, right)))
+            };
+            if is_division && b == 0.0 {
+                return Err(ExecutionError::Custom("Division by zero".to_string()));
+            }
+            Ok(Value::Double(match *op {
+                Operator::Plus => a + b,
+                Operator::Minus => a - b,
+                Operator::Multiply => a * b,
+                Operator::Divide => a / b,
+                Operator::Modulus => a % b,
+                _ => unreachable!()
+            }))
+        },
+        _ => {
+            let (a, b) = match (to_i128(left), to_i128(right)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return Err(ExecutionError::Custom(format!(
+                    "Unsupported operands for {:?}: {:?} and {:?}", op, left, right)))
+            };
+            if is_division && b == 0 {
+                return Err(ExecutionError::Custom("Division by zero".to_string()));
+            }
+            let result = match *op {
+                Operator::Plus => a.checked_add(b),
+                Operator::Minus => a.checked_sub(b),
+                Operator::Multiply => a.checked_mul(b),
+                Operator::Divide => a.checked_div(b),
+                Operator::Modulus => a.checked_rem(b),
+                _ => unreachable!()
+            };
+            let unsigned = match (left, right) {
+                (&Value::UnsignedLong(_), &Value::UnsignedLong(_)) => *op != Operator::Minus,
+                _ => false
+            };
+            let value = if unsigned {
+                result.and_then(|n| u64::try_from(n).ok()).map(Value::UnsignedLong)
+            } else {
+                result.and_then(|n| i64::try_from(n).ok()).map(Value::Long)
+            };
+            value.ok_or_else(|| ExecutionError::Custom(format!(
+                "Integer overflow evaluating {:?} {:?} {:?}", left, op, right)))
+        }
+    }
+}
+
+/// Functions and libraries cannot be formatted, so only the function names are shown
+impl fmt::Debug for ExecutionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut functions: Vec<&String> = self.functions.keys().collect();
+        functions.sort();
+        let mut aggregate_functions: Vec<&String> = self.aggregate_functions.keys().collect();
+        aggregate_functions.sort();
+        f.debug_struct("ExecutionContext")
+            .field("schemas", &self.schemas)
+            .field("functions", &functions)
+            .field("aggregate_functions", &aggregate_functions)
+            .finish()
+    }
+}
+
+impl ExecutionContext {
+
+    pub fn new() -> Self {
+        let mut ctx = ExecutionContext {
+            schemas: HashMap::new(),
+            statistics: HashMap::new(),
+            csv_options: HashMap::new(),
+            memory_tables: HashMap::new(),
+            functions: HashMap::new(),
+            aggregate_functions: HashMap::new(),
+            sort_memory_limit: DEFAULT_SORT_MEMORY_LIMIT,
+            batch_size: DEFAULT_BATCH_SIZE,
+            partition_count: 1,
+            split: None,
+            shuffle_dir: env::temp_dir().join("datafusion_shuffle"),
+            data_dir: None,
+            optimizer_rules: optimizer::default_rules(),
+            collect_metrics: false,
+            libraries: vec![]
+        };
+
+        // built-in functions, which can be replaced by registering a function with the same name
+        ctx.define_function(Arc::new(SqrtFunction {}));
+        ctx.define_function(Arc::new(STPointFunc {}));
+        ctx.define_function(Arc::new(STAsText {}));
+
+        ctx
+    }
+
+    /// Set the approximate number of bytes that each sort may buffer in memory before writing
+    /// sorted runs to temporary files
+    pub fn set_sort_memory_limit(&mut self, bytes: usize) {
+        self.sort_memory_limit = bytes;
+    }
+
+    /// Set the number of rows in each record batch produced by scans, filters and projections
+    pub fn set_batch_size(&mut self, rows: usize) {
+        assert!(rows > 0, "batch size must be greater than zero");
+        self.batch_size = rows;
+    }
+
+    /// Set the number of partitions that CSV scans are split into. Filters and projections over
+    /// the scans are executed for each partition in parallel.
+    pub fn set_partition_count(&mut self, n: usize) {
+        assert!(n > 0, "partition count must be greater than zero");
+        self.partition_count = n;
+    }
+
+    /// Set the directory that shuffle files are written to
+    pub fn set_shuffle_dir(&mut self, dir: &Path) {
+        self.shuffle_dir = dir.to_path_buf();
+    }
+
+    pub fn shuffle_dir(&self) -> &Path {
+        &self.shuffle_dir
+    }
+
+    /// Restrict the CSV files that plans read to relative paths within a directory, which they
+    /// are resolved against. Absolute paths and paths containing `..` are rejected.
+    pub fn set_data_dir(&mut self, dir: &Path) {
+        self.data_dir = Some(dir.to_path_buf());
+    }
+
+    /// Get the path of a CSV file that a plan reads
+    fn data_file(&self, filename: &str) -> Result<PathBuf, ExecutionError> {
+        match self.data_dir {
+            Some(ref dir) => {
+                let path = Path::new(filename);
+                let relative = path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+                if relative {
+                    Ok(dir.join(path))
+                } else {
+                    Err(ExecutionError::ForbiddenPath(format!("Cannot read {}, only files within the data directory can be read", filename)))
+                }
+            },
+            None => Ok(PathBuf::from(filename))
+        }
+    }
+
+    /// Replace the rules that are applied to logical plans before they are executed. Passing no
+    /// rules disables optimization.
+    pub fn set_optimizer_rules(&mut self, rules: Vec<Arc<dyn OptimizerRule>>) {
+        self.optimizer_rules = rules;
+    }
+
+    /// Apply the optimizer rules to a logical plan, in order
+    pub fn optimize(&self, plan: &LogicalPlan) -> Result<LogicalPlan, ExecutionError> {
+        let mut plan = plan.clone();
+        for rule in &self.optimizer_rules {
+            plan = rule.optimize(&plan, self).map_err(|e| ExecutionError::Custom(
+                format!("Optimizer rule {} failed: {}", rule.name(), e)))?;
+        }
+        Ok(plan)
+    }
+
+    /// Determine whether an expression always has the same value for the same input row, which
+    /// is not the case when it calls a function that is not deterministic or is not registered
+    pub fn is_deterministic(&self, expr: &Expr) -> bool {
+        match *expr {
+            Expr::TupleValue(_) | Expr::Literal(_) => true,
+            Expr::BinaryExpr { ref left, ref right, .. } => self.is_deterministic(left) && self.is_deterministic(right),
+            Expr::ScalarFunction { ref name, ref args } =>
+                self.functions.get(&name.to_lowercase()).map_or(false, |f| f.is_deterministic()) &&
+                    args.iter().all(|a| self.is_deterministic(a)),
+            Expr::AggregateFunction { ref args, .. } | Expr::Coalesce(ref args) => args.iter().all(|a| self.is_deterministic(a)),
+            Expr::Not(ref expr) | Expr::IsNull(ref expr) | Expr::IsNotNull(ref expr) |
+            Expr::Alias { ref expr, .. } | Expr::Sort { ref expr, .. } => self.is_deterministic(expr),
+            Expr::WindowFunction { ref args, ref partition_by, ref order_by, .. } =>
+                args.iter().chain(partition_by.iter()).chain(order_by.iter()).all(|a| self.is_deterministic(a))
+        }
+    }
+
+    /// Define the schema of a table. Any statistics collected, CSV options set and rows
+    /// registered in memory for a previous definition of the table are discarded.
+    pub fn define_schema(&mut self, name: &str, schema: &Schema) {
+        self.schemas.insert(name.to_string(), schema.clone());
+        self.statistics.remove(name);
+        self.csv_options.remove(name);
+        self.memory_tables.remove(name);
+    }
+
+    /// Define the schema of a table along with the options for parsing its CSV file
+    pub fn define_csv_table(&mut self, name: &str, schema: &Schema, options: &CsvOptions) {
+        self.define_schema(name, schema);
+        self.csv_options.insert(name.to_string(), options.clone());
+    }
+
+    /// Replace the scans of tables that are read from CSV files with scans of the files, carrying
+    /// the options set for each table. The plan can then be executed with a context that does not
+    /// define the tables, such as that of a worker.
+    pub fn resolve_csv_tables(&self, plan: &LogicalPlan) -> LogicalPlan {
+        match *plan {
+            LogicalPlan::TableScan { ref table_name, ref schema, ref projection, .. } if !self.memory_tables.contains_key(table_name) =>
+                LogicalPlan::CsvFile {
+                    filename: table_filename(table_name),
+                    schema: schema.clone(),
+                    projection: projection.clone(),
+                    options: self.csv_options.get(table_name).cloned().unwrap_or_default()
+                },
+            _ => optimizer::map_inputs(plan, |input| Ok(self.resolve_csv_tables(input)))
+                .expect("Resolving tables does not fail")
+        }
+    }
+
+    /// Scan a table to collect its statistics, which the optimizer uses to estimate the number of
+    /// rows produced by plans that read the table
+    pub fn analyze_table(&mut self, name: &str) -> Result<(), ExecutionError> {
+        let schema = self.schemas.get(name)
+            .ok_or_else(|| ExecutionError::Custom(format!("Unknown table {}", name)))?;
+        let plan = LogicalPlan::TableScan {
+            schema_name: "default".to_string(),
+            table_name: name.to_string(),
+            schema: schema.clone(),
+            projection: None
+        };
+        let rel = self.create_execution_plan(&plan)?;
+        let statistics = TableStatistics::compute(rel.as_ref(), self)?;
+        self.statistics.insert(name.to_string(), Arc::new(statistics));
+        Ok(())
+    }
+
+    /// Get the statistics collected for a table, if it has been analyzed
+    pub fn table_statistics(&self, name: &str) -> Option<&TableStatistics> {
+        self.statistics.get(name).map(|s| s.as_ref())
+    }
+
+    /// Register a table whose rows are held in memory. Table scans of this table read these rows
+    /// instead of a CSV file.
+    pub fn register_memory_table(&mut self, name: &str, schema: &Schema, rows: Vec<Row>) {
+        self.define_schema(name, schema);
+        self.memory_tables.insert(name.to_string(), Arc::new(rows));
+    }
+
+    /// Register a scalar function, replacing any existing function with the same name. Function
+    /// names are case-insensitive.
+    pub fn define_function(&mut self, func: Arc<dyn ScalarFunction>) {
+        self.functions.insert(func.name().to_lowercase(), func);
+    }
+
+    /// Register an aggregate function, replacing any existing function with the same name. The
+    /// built-in COUNT, SUM, MIN, MAX and AVG aggregates cannot be replaced.
+    pub fn define_aggregate_function(&mut self, func: Arc<dyn AggregateFunction>) {
+        self.aggregate_functions.insert(func.name().to_lowercase(), func);
+    }
+
+    /// Load a UDF library (see `FunctionRegistry`) and register all of the functions it provides
+    pub fn load_udf_library(&mut self, path: &str) -> Result<(), ExecutionError> {
+        let (library, registry) = open_udf_library(path)?;
+        for func in registry.scalar_functions {
+            self.define_function(func);
+        }
+        for func in registry.aggregate_functions {
+            self.define_aggregate_function(func);
+        }
+        self.libraries.push(library);
+        Ok(())
+    }
+
+    /// Register a single function from a UDF library, checking that it has the declared
+    /// signature
+    fn create_function(&mut self, name: &str, args: &Option<Vec<SQLType>>,
+                       return_type: &Option<SQLType>, path: &str) -> Result<(), ExecutionError> {
+
+        let (library, registry) = open_udf_library(path)?;
+
+        let check_signature = |actual_args: Vec<Field>, actual_return_type: DataType| {
+            if let Some(ref args) = *args {
+                let args: Vec<DataType> = args.iter().map(convert_data_type).collect();
+                let actual_args: Vec<DataType> = actual_args.into_iter().map(|f| f.data_type).collect();
+                if args != actual_args {
+                    return Err(ExecutionError::Custom(format!(
+                        "Function {} in {} has arguments {:?} but {:?} were declared",
+                        name, path, actual_args, args)));
+                }
+            }
+            if let Some(ref return_type) = *return_type {
+                if convert_data_type(return_type) != actual_return_type {
+                    return Err(ExecutionError::Custom(format!(
+                        "Function {} in {} returns {:?} but {:?} was declared",
+                        name, path, actual_return_type, return_type)));
+                }
+            }
+            Ok(())
+        };
+
+        if let Some(func) = registry.scalar_functions.into_iter()
+            .find(|f| f.name().eq_ignore_ascii_case(name)) {
+            check_signature(func.args(), func.return_type())?;
+            self.define_function(func);
+        } else if let Some(func) = registry.aggregate_functions.into_iter()
+            .find(|f| f.name().eq_ignore_ascii_case(name)) {
+            check_signature(func.args(), func.return_type())?;
+            self.define_aggregate_function(func);
+        } else {
+            return Err(ExecutionError::Custom(format!("Library {} does not provide function {}", path, name)));
+        }
+
+        self.libraries.push(library);
+        Ok(())
+    }
+
+    /// Get the metadata for all registered functions, which is used to plan queries
+    fn function_meta(&self) -> HashMap<String, FunctionMeta> {
+        let scalar = self.functions.iter()
+            .map(|(name, func)| (name.clone(), FunctionMeta {
+                name: func.name(),
+                args: func.args(),
+                return_type: func.return_type(),
+                function_type: FunctionType::Scalar
+            }));
+        let aggregate = self.aggregate_functions.iter()
+            .map(|(name, func)| (name.clone(), FunctionMeta {
+                name: func.name(),
+                args: func.args(),
+                return_type: func.return_type(),
+                function_type: FunctionType::Aggregate
+            }));
+        scalar.chain(aggregate).collect()
+    }
+
+    pub fn sql(&mut self, sql: &str) -> Result<Box<DataFrame>, ExecutionError> {
+
+        // parse SQL into AST
+        let ast = Parser::parse_sql(String::from(sql))?;
+
+        if let ASTNode::SQLAnalyzeTable { ref name } = ast {
+            self.analyze_table(name)?;
+            return Ok(Box::new(DF { ctx: Box::new(self.clone()), plan: Box::new(LogicalPlan::EmptyRelation { schema: Schema::empty() }) }));
+        }
+
+        if let ASTNode::SQLExplain { analyze, ref statement } = ast {
+            let query_planner = SqlToRel::new(self.schemas.clone(), self.function_meta());
+            let plan = self.optimize(&*query_planner.sql_to_rel(statement)?)?;
+            let lines = self.explain(&ExecutionPlan::Interactive { plan }, analyze)?;
+            let mut ctx = self.clone();
+            let plan = explain_results(&mut ctx, lines);
+            return Ok(Box::new(DF { ctx: Box::new(ctx), plan: Box::new(plan) }));
+        }
+
+        if let ASTNode::SQLCreateTable { ref name, ref columns, ref options } = ast {
+            let schema = Schema::new(columns.iter()
+                .map(|c| Field::new(&c.name, convert_data_type(&c.data_type), c.allow_null))
+                .collect());
+            let mut csv_options = CsvOptions::default();
+            for (option, value) in options {
+                csv_options.set(option, value)?;
+            }
+            self.define_csv_table(name, &schema, &csv_options);
+            return Ok(Box::new(DF { ctx: Box::new(self.clone()), plan: Box::new(LogicalPlan::EmptyRelation { schema: Schema::empty() }) }));
+        }
+
+        if let ASTNode::SQLCreateFunction { ref name, ref args, ref return_type, ref library } = ast {
+            self.create_function(name, args, return_type, library)?;
+            return Ok(Box::new(DF { ctx: Box::new(self.clone()), plan: Box::new(LogicalPlan::EmptyRelation { schema: Schema::empty() }) }));
+        }
+
+        // create a query planner
+        let query_planner = SqlToRel::new(self.schemas.clone(), self.function_meta()); //TODO: pass reference to schemas
+
+        // plan the query (create a logical relational plan). The plan is optimized when it is
+        // executed, which also covers plans built with the DataFrame API.
+        let plan = query_planner.sql_to_rel(&ast)?;
+
+        // return the DataFrame
+        Ok(Box::new(DF { ctx: Box::new(self.clone()), plan: plan })) //TODO: don't clone context
+    }
+
+    /// Open a CSV file, which is parsed with the given options
+    ///TODO: this is building a relational plan not an execution plan so shouldn't really be here
+    pub fn load(&self, filename: &str, schema: &Schema, options: &CsvOptions) -> Result<Box<dyn DataFrame>, ExecutionError> {
+        let plan = LogicalPlan::CsvFile { filename: filename.to_string(), schema: schema.clone(), projection: None, options: options.clone() };
+        Ok(Box::new(DF { ctx: Box::new((*self).clone()), plan: Box::new(plan) }))
+    }
+
+    pub fn register_table(&mut self, name: String, schema: Schema) {
+        self.schemas.insert(name, schema);
+    }
+
+    pub fn create_execution_plan(&self, plan: &LogicalPlan) -> Result<Box<SimpleRelation>,ExecutionError> {
+        Ok(self.instrument(self.create_relation(plan)?))
+    }
+
+    /// Wrap a relation so that its metrics are collected, when collecting metrics
+    fn instrument(&self, rel: Box<dyn SimpleRelation>) -> Box<dyn SimpleRelation> {
+        if self.collect_metrics {
+            Box::new(MetricsRelation::new(rel))
+        } else {
+            rel
+        }
+    }
+
+    fn create_relation(&self, plan: &LogicalPlan) -> Result<Box<dyn SimpleRelation>, ExecutionError> {
+        match *plan {
+
+            LogicalPlan::EmptyRelation { ref schema } => {
+                Ok(Box::new(EmptyRelation { schema: schema.clone() }))
+            },
+
+            LogicalPlan::TableScan { .. } | LogicalPlan::CsvFile { .. } |
+            LogicalPlan::Selection { .. } | LogicalPlan::Projection { .. } => {
+                if self.partition_count > 1 && is_scan_pipeline(plan) {
+                    self.create_partitioned_relation(plan, self.partition_count, &None, None, &None)
+                } else {
+                    Ok(Box::new(BatchesToRows::new(self.create_batch_plan(plan)?)))
+                }
+            },
+
+            LogicalPlan::Limit { limit, offset, ref input, ref schema } => {
+                let input_rel = self.create_execution_plan(input)?;
+                let rel = LimitRelation {
+                    input: input_rel,
+                    limit,
+                    offset,
+                    schema: schema.clone()
+                };
+                Ok(Box::new(rel))
+            },
+
+            LogicalPlan::Join { ref left, ref right, ref join_type, ref on, ref schema } => {
+                let rel = HashJoinRelation {
+                    left: self.create_execution_plan(left)?,
+                    right: self.create_execution_plan(right)?,
+                    join_type: join_type.clone(),
+                    on: on.clone(),
+                    schema: schema.clone()
+                };
+                Ok(Box::new(rel))
+            },
+
+            LogicalPlan::Sort { ref expr, ref input, ref schema } => {
+                let input_rel = self.create_execution_plan(input)?;
+                let rel = SortRelation {
+                    input: input_rel,
+                    sort_expr: expr.clone(),
+                    schema: schema.clone(),
+                    memory_limit: self.sort_memory_limit
+                };
+                Ok(Box::new(rel))
+            },
+
+            LogicalPlan::Aggregate { ref group_expr, ref aggr_expr, ref input, ref schema, mode } => {
+                let input_rel = self.create_execution_plan(input)?;
+                let rel = AggregateRelation {
+                    input: input_rel,
+                    group_expr: group_expr.clone(),
+                    aggr_expr: aggr_expr.clone(),
+                    schema: schema.clone(),
+                    mode
+                };
+                Ok(Box::new(rel))
+            },
+
+            LogicalPlan::Window { ref window_expr, ref input, ref schema } => {
+                let input_rel = self.create_execution_plan(input)?;
+                let rel = WindowRelation {
+                    input: input_rel,
+                    window_expr: window_expr.clone(),
+                    schema: schema.clone()
+                };
+                Ok(Box::new(rel))
+            },
+
+            LogicalPlan::Exchange { ref shuffle_id, ref workers, partition_count, ref schema } => {
+                // when executing one partition of several, read every shuffle partition that
+                // maps to it
+                let partitions = match self.split {
+                    Some((partition, n)) => (0..partition_count).filter(|p| p % n == partition).collect(),
+                    None => (0..partition_count).collect()
+                };
+                Ok(Box::new(ExchangeRelation::new(schema.clone(), shuffle_id.clone(), workers.clone(), partitions)))
+            }
+        }
+    }
+
+    /// Create a relation for an execution plan
+    pub fn execute(&self, plan: &ExecutionPlan) -> Result<Box<dyn SimpleRelation>, ExecutionError> {
+        match *plan {
+            ExecutionPlan::Interactive { ref plan } => self.create_execution_plan(plan),
+            ExecutionPlan::Partition { ref plan, partition_count, ref partition_expr, partition, ref shuffle_id } =>
+                Ok(self.instrument(self.create_partitioned_relation(plan, partition_count, partition_expr, partition, shuffle_id)?))
+        }
+    }
+
+    /// Create a relation that executes a plan as `partition_count` partitions, as described for
+    /// `ExecutionPlan::Partition`
+    fn create_partitioned_relation(&self, plan: &LogicalPlan, partition_count: usize, partition_expr: &Option<Expr>,
+                                   partition: Option<usize>, shuffle_id: &Option<String>) -> Result<Box<dyn SimpleRelation>, ExecutionError> {
+        if !is_partitionable(plan) {
+            return Err(ExecutionError::Custom(
+                "Plans containing joins can only be partitioned when the joins read from exchanges".to_string()));
+        }
+        let input: Box<dyn SimpleRelation> = match partition {
+            Some(partition) if partition < partition_count =>
+                Box::new(BatchesToRows::new(self.create_partition_plan(plan, partition, partition_count)?)),
+            Some(partition) => return Err(ExecutionError::Custom(format!(
+                "Invalid partition {} of {} partitions", partition, partition_count))),
+            None => Box::new(PartitionedRelation {
+                schema: plan.schema(),
+                plan: plan.clone(),
+                partition_count
+            })
+        };
+        match (partition_expr.as_ref(), shuffle_id.as_ref()) {
+            (None, _) => Ok(input),
+            (Some(expr), Some(shuffle_id)) => Ok(Box::new(ShuffleWriteRelation::new(
+                self.instrument(input), expr.clone(), partition_count, partition.unwrap_or(0), shuffle_dir(&self.shuffle_dir, shuffle_id)?))),
+            (Some(_), None) => Err(ExecutionError::Custom(
+                "A shuffle id is required to partition by expression".to_string()))
+        }
+    }
+
+    /// Explain how a plan is executed, as lines of text showing the logical plan and the tree of
+    /// relations that execute it. With `analyze`, the plan is executed first and the metrics
+    /// collected for each relation are shown.
+    pub fn explain(&self, plan: &ExecutionPlan, analyze: bool) -> Result<Vec<String>, ExecutionError> {
+        let mut ctx = self.clone();
+        ctx.collect_metrics = analyze;
+        let rel = ctx.execute(plan)?;
+        if analyze {
+            for row in rel.scan(&ctx) {
+                row?;
+            }
+        }
+
+        let logical_plan = match *plan {
+            ExecutionPlan::Interactive { ref plan } | ExecutionPlan::Partition { ref plan, .. } => plan
+        };
+        let mut lines = vec!["Logical plan:".to_string()];
+        format_logical_plan(logical_plan, 1, &mut lines);
+        lines.push("Physical plan:".to_string());
+        format_relation(RelationRef::Rows(&*rel), 1, &mut lines);
+        Ok(lines)
+    }
+
+    /// Create a plan for the relations that have columnar implementations, which are scans,
+    /// filters and projections. The rows of any other relation are grouped into batches.
+    pub fn create_batch_plan(&self, plan: &LogicalPlan) -> Result<Box<dyn BatchRelation>, ExecutionError> {
+        let rel = self.create_batch_relation(plan)?;
+        if self.collect_metrics {
+            Ok(Box::new(MetricsBatchRelation::new(rel)))
+        } else {
+            Ok(rel)
+        }
+    }
+
+    fn create_batch_relation(&self, plan: &LogicalPlan) -> Result<Box<dyn BatchRelation>, ExecutionError> {
+        let open = |filename: &str, schema: &Schema, projection: &Option<Vec<usize>>, options: &CsvOptions| -> Result<Box<dyn BatchRelation>, ExecutionError> {
+            let file = File::open(self.data_file(filename)?)?;
+            let rel = match self.split {
+                Some((partition, partition_count)) =>
+                    CsvBatchRelation::open_split(file, schema.clone(), self.batch_size, partition, partition_count)?,
+                None => CsvBatchRelation::open(file, schema.clone(), self.batch_size)?
+            };
+            let rel = rel.with_options(options.clone());
+            match *projection {
+                Some(ref projection) => Ok(Box::new(rel.with_projection(projection.clone()))),
+                None => Ok(Box::new(rel))
+            }
+        };
+
+        match *plan {
+
+            LogicalPlan::TableScan { ref table_name, ref schema, ref projection, .. } => match self.memory_tables.get(table_name) {
+                Some(rows) => {
+                    let (partition, partition_count) = self.split.unwrap_or((0, 1));
+                    let rel = MemoryRelation {
+                        schema: schema.clone(),
+                        rows: rows.clone(),
+                        range: (rows.len() * partition / partition_count, rows.len() * (partition + 1) / partition_count)
+                    };
+                    let rel = Box::new(RowsToBatches::new(Box::new(rel), self.batch_size));
+                    match *projection {
+                        Some(ref projection) => Ok(Box::new(ProjectBatchRelation::new(
+                            plan.schema(), rel, projection.iter().map(|&i| Expr::TupleValue(i)).collect()))),
+                        None => Ok(rel)
+                    }
+                },
+                // for now, other tables are csv files
+                None => {
+                    let options = self.csv_options.get(table_name).cloned().unwrap_or_default();
+                    open(&table_filename(table_name), schema, projection, &options)
+                }
+            },
+
+            LogicalPlan::CsvFile { ref filename, ref schema, ref projection, ref options } => open(filename, schema, projection, options),
+
+            LogicalPlan::Selection { ref expr, ref input, ref schema } => {
+                let input_rel = self.create_batch_plan(input)?;
+                Ok(Box::new(FilterBatchRelation::new(schema.clone(), input_rel, expr.clone())))
+            },
+
+            LogicalPlan::Projection { ref expr, ref input, ref schema } => {
+                let input_rel = self.create_batch_plan(input)?;
+                Ok(Box::new(ProjectBatchRelation::new(schema.clone(), input_rel, expr.clone())))
+            },
+
+            _ => Ok(Box::new(RowsToBatches::new(self.create_execution_plan(plan)?, self.batch_size)))
+        }
+    }
+
+    /// Create a plan for one partition of a plan, where each CSV file that is scanned by the plan
+    /// is split into `partition_count` splits and only split `partition` is read
+    pub fn create_partition_plan(&self, plan: &LogicalPlan, partition: usize, partition_count: usize) -> Result<Box<dyn BatchRelation>, ExecutionError> {
+        let mut ctx = self.clone();
+        ctx.split = Some((partition, partition_count));
+        ctx.partition_count = 1;
+        ctx.create_batch_plan(plan)
+    }
+
+    /// Evaluate a relational expression against a tuple
+    pub fn evaluate(&self, tuple: &Row, tt: &Schema, rex: &Expr) -> Result<Value, Box<ExecutionError>> {
+
+        match rex {
+            &Expr::BinaryExpr { ref left, op: Operator::And, ref right } => {
+                // only evaluate the right side when the left side does not determine the result
+                let left_value = evaluate_boolean(self.evaluate(tuple, tt, left)?)?;
+                if left_value == Some(false) {
+                    return Ok(Value::Boolean(false));
+                }
+                match (left_value, evaluate_boolean(self.evaluate(tuple, tt, right)?)?) {
+                    (_, Some(false)) => Ok(Value::Boolean(false)),
+                    (Some(true), Some(true)) => Ok(Value::Boolean(true)),
+                    _ => Ok(Value::Null)
+                }
+            },
+            &Expr::BinaryExpr { ref left, op: Operator::Or, ref right } => {
+                let left_value = evaluate_boolean(self.evaluate(tuple, tt, left)?)?;
+                if left_value == Some(true) {
+                    return Ok(Value::Boolean(true));
+                }
+                match (left_value, evaluate_boolean(self.evaluate(tuple, tt, right)?)?) {
+                    (_, Some(true)) => Ok(Value::Boolean(true)),
+                    (Some(false), Some(false)) => Ok(Value::Boolean(false)),
+                    _ => Ok(Value::Null)
+                }
+            },
+            &Expr::Not(ref expr) => match evaluate_boolean(self.evaluate(tuple, tt, expr)?)? {
+                Some(b) => Ok(Value::Boolean(!b)),
+                None => Ok(Value::Null)
+            },
+            &Expr::IsNull(ref expr) => Ok(Value::Boolean(self.evaluate(tuple, tt, expr)? == Value::Null)),
+            &Expr::IsNotNull(ref expr) => Ok(Value::Boolean(self.evaluate(tuple, tt, expr)? != Value::Null)),
+            Expr::Coalesce(args) => {
+                // the remaining arguments are not evaluated once a value has been found
+                for arg in args {
+                    match self.evaluate(tuple, tt, arg)? {
+                        Value::Null => {},
+                        value => return Ok(value)
+                    }
+                }
+                Ok(Value::Null)
+            },
+            &Expr::BinaryExpr { ref left, ref op, ref right } => {
+                let left_value = self.evaluate(tuple, tt, left)?;
+                let right_value = self.evaluate(tuple, tt, right)?;
+                // comparisons and arithmetic involving an unknown value are unknown
+                if left_value == Value::Null || right_value == Value::Null {
+                    return Ok(Value::Null);
+                }
+                if op.is_arithmetic() {
+                    return evaluate_arithmetic(op, &left_value, &right_value).map_err(Box::new);
+                }
+                let ordering = compare_values(&left_value, &right_value);
+                match op {
+                    &Operator::Eq => Ok(Value::Boolean(ordering == Some(Ordering::Equal))),
+                    &Operator::NotEq => Ok(Value::Boolean(ordering != Some(Ordering::Equal))),
+                    &Operator::Lt => Ok(Value::Boolean(ordering == Some(Ordering::Less))),
+                    &Operator::LtEq => Ok(Value::Boolean(ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal))),
+                    &Operator::Gt => Ok(Value::Boolean(ordering == Some(Ordering::Greater))),
+                    &Operator::GtEq => Ok(Value::Boolean(ordering == Some(Ordering::Greater) || ordering == Some(Ordering::Equal))),
+                    _ => unreachable!()
+                }
+            },
+            &Expr::TupleValue(index) => Ok(tuple.values[index].clone()),
+            &Expr::Literal(ref value) => Ok(value.clone()),
+            &Expr::ScalarFunction { ref name, ref args } => {
+
+                // evaluate the arguments to the function
+                let arg_values : Vec<Value> = args.iter()
+                    .map(|a| self.evaluate(tuple, tt, &a))
+                    .collect::<Result<Vec<Value>, Box<ExecutionError>>>()?;
+
+                let func = self.functions.get(&name.to_lowercase())
+                    .ok_or_else(|| Box::new(ExecutionError::Custom(format!("Unknown function {}", name))))?;
+
+                func.execute(arg_values).map_err(|e| Box::new(ExecutionError::Custom(
+                    format!("Error executing function {}: {}", name, e))))
+            },
+            &Expr::Alias { ref expr, .. } | &Expr::Sort { ref expr, .. } => self.evaluate(tuple, tt, expr),
+            &Expr::AggregateFunction { ref name, .. } => Err(Box::new(ExecutionError::Custom(
+                format!("Aggregate function {} cannot be evaluated outside of an aggregate", name)))),
+            &Expr::WindowFunction { ref name, .. } => Err(Box::new(ExecutionError::Custom(
+                format!("Window function {} cannot be evaluated outside of a window", name))))
+        }
+
+    }
+
+    /// load a function implementation
+    pub fn udf(&self, name: &str, args: Vec<Expr>) -> Expr {
+        Expr::ScalarFunction { name: name.to_string(), args: args.clone() }
+    }
+
+    /// Create an expression for invoking a built-in aggregate function (COUNT, SUM, MIN, MAX or
+    /// AVG) or a registered user-defined aggregate function
+    pub fn aggregate_function(&self, name: &str, args: Vec<Expr>) -> Expr {
+        Expr::AggregateFunction { name: name.to_string(), args }
+    }
+
+}
+
+
+
+
+pub struct DF {
+    ctx: Box<ExecutionContext>,
+    plan: Box<LogicalPlan>
+}
+
+impl DataFrame for DF {
+
+    fn select(&self, expr: Vec<Expr>) -> Result<Box<DataFrame>, DataFrameError> {
+
+        let input_schema = self.plan.schema();
+        let functions = self.ctx.function_meta();
+
+        let schema = Schema::new(expr.iter()
+            .map(|e| e.to_field(&input_schema, &functions))
+            .collect::<Result<Vec<Field>, String>>()
+            .map_err(ExecutionError::Custom)?);
+
+        let plan = LogicalPlan::Projection {
+            expr,
+            input: self.plan.clone(),
+            schema
+
+        };
+
+        Ok(Box::new(DF { ctx: self.ctx.clone(), plan: Box::new(plan) }))
+
+    }
+
+    fn filter(&self, expr: Expr) -> Result<Box<DataFrame>, DataFrameError> {
+
+        check_predicate(&expr, &self.plan.schema(), &self.ctx.function_meta())
+            .map_err(ExecutionError::Custom)?;
+
+        let plan = LogicalPlan::Selection {
+            expr: expr,
+            input: self.plan.clone(),
+            schema: self.plan.schema().clone()
+        };
+
+        Ok(Box::new(DF { ctx: self.ctx.clone(), plan: Box::new(plan) }))
+    }
+
+    fn aggregate(&self, group_expr: Vec<Expr>, aggr_expr: Vec<Expr>) -> Result<Box<dyn DataFrame>, DataFrameError> {
+
+        let input_schema = self.plan.schema();
+        let functions = self.ctx.function_meta();
+
+        let schema = Schema::new(group_expr.iter()
+            .chain(aggr_expr.iter())
+            .map(|e| e.to_field(&input_schema, &functions))
+            .collect::<Result<Vec<Field>, String>>()
+            .map_err(ExecutionError::Custom)?);
+
+        let plan = LogicalPlan::Aggregate {
+            group_expr,
+            aggr_expr,
+            input: self.plan.clone(),
+            schema,
+            mode: AggregateMode::Complete
+        };
+
+        Ok(Box::new(DF { ctx: self.ctx.clone(), plan: Box::new(plan) }))
+    }
+
+    fn sort(&self, expr: Vec<Expr>) -> Result<Box<dyn DataFrame>, DataFrameError> {
+
+        let plan = LogicalPlan::Sort {
+            expr,
+            input: self.plan.clone(),
+            schema: self.plan.schema().clone()
+        };
+
+        Ok(Box::new(DF { ctx: self.ctx.clone(), plan: Box::new(plan) }))
+    }
+
+    fn limit(&self, n: usize) -> Result<Box<dyn DataFrame>, DataFrameError> {
+
+        let plan = LogicalPlan::Limit {
+            limit: n,
+            offset: 0,
+            input: self.plan.clone(),
+            schema: self.plan.schema().clone()
+        };
+
+        Ok(Box::new(DF { ctx: self.ctx.clone(), plan: Box::new(plan) }))
+    }
+
+    fn join(&self, right: &dyn DataFrame, join_type: JoinType, on: Vec<(Expr, Expr)>) -> Result<Box<dyn DataFrame>, DataFrameError> {
+
+        let right_plan = right.plan();
+
+        let schema = Schema::join(&self.plan.schema(), &right_plan.schema(), &join_type);
+
+        let plan = LogicalPlan::Join {
+            left: self.plan.clone(),
+            right: Box::new(right_plan),
+            join_type,
+            on,
+            schema
+        };
+
+        Ok(Box::new(DF { ctx: self.ctx.clone(), plan: Box::new(plan) }))
+    }
+
+    fn write(&self, filename: &str) -> Result<(), DataFrameError> {
+        let plan = self.ctx.optimize(&self.plan)?;
+        let execution_plan = self.ctx.create_execution_plan(&plan)?;
+
+        // create output file
+        println!("Writing csv to {}", filename);
+        let mut file = File::create(filename)?;
+
+        // implement execution here for now but should be a common method for processing a plan
+        for t in execution_plan.scan(&self.ctx) {
+            let csv = format!("{}\n", t?.to_string());
+            file.write_all(&csv.into_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn collect(&self) -> Result<Vec<Row>, DataFrameError> {
+        let plan = self.ctx.optimize(&self.plan)?;
+        let execution_plan = self.ctx.create_execution_plan(&plan)?;
+        Ok(execution_plan.scan(&self.ctx).collect::<Result<Vec<Row>, ExecutionError>>()?)
+    }
+
+    fn col(&self, column_name: &str) -> Result<Expr, DataFrameError> {
+        match self.plan.schema().column(column_name) {
+            Some((i,_)) => Ok(Expr::TupleValue(i)),
+            _ => Err(DataFrameError::InvalidColumn(column_name.to_string()))
+        }
+    }
+
+    fn schema(&self) -> Schema {
+        self.plan.schema().clone()
+    }
+
+    fn plan(&self) -> LogicalPlan {
+        (*self.plan).clone()
+    }
+
+    fn repartition(&self, n: u32) -> Result<Box<DataFrame>, DataFrameError> {
+        if n == 0 {
+            return Err(DataFrameError::ExecError(ExecutionError::Custom(
+                "The number of partitions must be greater than zero".to_string())));
+        }
+        let mut ctx = self.ctx.clone();
+        ctx.set_partition_count(n as usize);
+        Ok(Box::new(DF { ctx, plan: self.plan.clone() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::collections::hash_map::DefaultHasher;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_sqrt() {
+
+        let mut ctx = create_context();
+
+        ctx.define_function(Arc::new(SqrtFunction {}));
+
+        let df = ctx.sql(&"SELECT id, sqrt(id) FROM people").unwrap();
+
+        df.write("_sqrt_out.csv").unwrap();
+
+        //TODO: check that generated file has expected contents
+    }
+
+    #[test]
+    fn test_sql_udf_udt() {
+
+        let mut ctx = create_context();
+
+        ctx.define_function(Arc::new(STPointFunc {}));
+
+        let df = ctx.sql(&"SELECT ST_Point(lat, lng) FROM uk_cities").unwrap();
+
+        df.write("_uk_cities_sql.csv").unwrap();
+
+        //TODO: check that generated file has expected contents
+    }
+
+    #[test]
+    fn test_df_udf_udt() {
+
+        let mut ctx = create_context();
+
+        ctx.define_function(Arc::new(STPointFunc {}));
+
+        let schema = Schema::new(vec![
+            Field::new("city", DataType::String, false),
+            Field::new("lat", DataType::Double, false),
+            Field::new("lng", DataType::Double, false)]);
+
+        let df = ctx.load("test/data/uk_cities.csv", &schema, &CsvOptions { has_header: false, ..CsvOptions::default() }).unwrap();
+
+        // create an expression for invoking a scalar function
+//        let func_expr = Expr::ScalarFunction {
+//            name: "ST_Point".to_string(),
+//            args: vec![df.col("lat").unwrap(), df.col("lng").unwrap()]
+//        };
+
+
+        // invoke custom code as a scalar UDF
+        let func_expr = ctx.udf("ST_Point",vec![
+            df.col("lat").unwrap(),
+            df.col("lng").unwrap()]
+        );
+
+        let df2 = df.select(vec![func_expr]).unwrap();
+
+        df2.write("_uk_cities_df.csv").unwrap();
+
+        //TODO: check that generated file has expected contents
+    }
+
+    #[test]
+    fn test_chaining_functions() {
+
+        let mut ctx = create_context();
+
+        ctx.define_function(Arc::new(STPointFunc {}));
+
+        let df = ctx.sql(&"SELECT ST_AsText(ST_Point(lat, lng)) FROM uk_cities").unwrap();
+
+        df.write("_uk_cities_wkt.csv").unwrap();
+
+        //TODO: check that generated file has expected contents
+    }
+
+    #[test]
+    fn test_sql_group_by() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT customer_id, COUNT(*), SUM(amount), MIN(order_id), MAX(amount), AVG(amount) \
+            FROM orders GROUP BY customer_id").unwrap();
+
+        df.write("_orders_group_by.csv").unwrap();
+
+        let expected = "1,3,20,1,10.5,6.666666666666667\n\
+            2,2,27.5,2,20,13.75\n\
+            3,1,100,4,100,100\n\
+            11,1,50,7,50,50\n";
+
+        assert_eq!(expected, read_file("_orders_group_by.csv"));
+    }
+
+    #[test]
+    fn test_sql_group_by_having() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT customer_id, COUNT(*) FROM orders \
+            WHERE order_id > 1 GROUP BY customer_id HAVING COUNT(*) > 1").unwrap();
+
+        df.write("_orders_having.csv").unwrap();
+
+        assert_eq!("2,2\n1,2\n", read_file("_orders_having.csv"));
+    }
+
+    #[test]
+    fn test_sql_aggregate_without_group_by() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT COUNT(*), MAX(lat) FROM uk_cities").unwrap();
+
+        df.write("_uk_cities_count.csv").unwrap();
+
+        assert_eq!("37,57.653484\n", read_file("_uk_cities_count.csv"));
+    }
+
+    #[test]
+    fn test_sql_group_by_invalid_column() {
+
+        let mut ctx = create_context();
+
+        assert!(ctx.sql(&"SELECT order_id, COUNT(*) FROM orders GROUP BY customer_id").is_err());
+    }
+
+    #[test]
+    fn test_df_aggregate() {
+
+        let ctx = create_context();
+
+        let schema = Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]);
+
+        let df = ctx.load("test/data/orders.csv", &schema, &CsvOptions::default()).unwrap();
+
+        let df2 = df.aggregate(
+            vec![df.col("customer_id").unwrap()],
+            vec![ctx.aggregate_function("SUM", vec![df.col("amount").unwrap()])]).unwrap();
+
+        assert_eq!("customer_id: UnsignedLong,SUM: Double", df2.schema().to_string());
+
+        df2.write("_orders_df_aggregate.csv").unwrap();
+
+        assert_eq!("1,20\n2,27.5\n3,100\n11,50\n", read_file("_orders_df_aggregate.csv"));
+    }
+
+    #[test]
+    fn test_sql_order_by() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT order_id, amount FROM orders ORDER BY amount DESC").unwrap();
+
+        df.write("_orders_order_by.csv").unwrap();
+
+        assert_eq!("4,100\n7,50\n2,20\n1,10.5\n5,7.5\n3,5.25\n6,4.25\n",
+                   read_file("_orders_order_by.csv"));
+    }
+
+    #[test]
+    fn test_sql_order_by_column_not_in_projection() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT order_id FROM orders ORDER BY customer_id DESC, amount").unwrap();
+
+        df.write("_orders_order_by_hidden.csv").unwrap();
+
+        assert_eq!("7\n4\n5\n2\n6\n3\n1\n", read_file("_orders_order_by_hidden.csv"));
+    }
+
+    #[test]
+    fn test_sql_order_by_aggregate() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT customer_id FROM orders GROUP BY customer_id ORDER BY SUM(amount)").unwrap();
+
+        df.write("_orders_order_by_aggregate.csv").unwrap();
+
+        assert_eq!("1\n2\n11\n3\n", read_file("_orders_order_by_aggregate.csv"));
+    }
+
+    #[test]
+    fn test_sort_spills_to_disk() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT city, lat FROM uk_cities ORDER BY lat").unwrap();
+        df.write("_uk_cities_sorted.csv").unwrap();
+
+        // force the sort to write many small sorted runs to disk
+        ctx.set_sort_memory_limit(1024);
+
+        let df = ctx.sql(&"SELECT city, lat FROM uk_cities ORDER BY lat").unwrap();
+        df.write("_uk_cities_sorted_spill.csv").unwrap();
+
+        assert_eq!(read_file("_uk_cities_sorted.csv"), read_file("_uk_cities_sorted_spill.csv"));
+
+        // spill files are removed once the sort completes
+        let prefix = format!("datafusion_sort_{}_", process::id());
+        let spill_files = fs::read_dir(env::temp_dir()).unwrap()
+            .filter(|f| f.as_ref().unwrap().file_name().to_string_lossy().starts_with(&prefix))
+            .count();
+        assert_eq!(0, spill_files);
+    }
+
+    #[test]
+    fn test_sort_spills_non_finite_doubles() {
+
+        let mut ctx = create_context();
+        ctx.set_sort_memory_limit(64);
+
+        // the square root of a negative number is NaN
+        let df = ctx.sql("SELECT order_id, sqrt(0 - amount) FROM orders ORDER BY order_id").unwrap();
+        df.write("_orders_spill_non_finite.csv").unwrap();
+
+        assert_eq!("1,NaN\n2,NaN\n3,NaN\n4,NaN\n5,NaN\n6,NaN\n7,NaN\n",
+                   read_file("_orders_spill_non_finite.csv"));
+    }
+
+    #[test]
+    fn test_df_sort() {
+
+        let ctx = create_context();
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false)]);
+
+        let df = ctx.load("test/data/people.csv", &schema, &CsvOptions::default()).unwrap();
+
+        let df2 = df.sort(vec![df.col("name").unwrap().sort(false, false)]).unwrap();
+
+        df2.write("_people_sorted.csv").unwrap();
+
+        assert!(read_file("_people_sorted.csv").starts_with("10,Juliet\n9,Irene\n8,Helen\n"));
+    }
+
+    #[test]
+    fn test_sql_limit_offset() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT order_id FROM orders ORDER BY amount DESC LIMIT 3 OFFSET 1").unwrap();
+
+        df.write("_orders_limit.csv").unwrap();
+
+        assert_eq!("7\n2\n1\n", read_file("_orders_limit.csv"));
+    }
+
+    #[test]
+    fn test_df_limit() {
+
+        let ctx = create_context();
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false)]);
+
+        let df = ctx.load("test/data/people.csv", &schema, &CsvOptions::default()).unwrap();
+
+        df.limit(2).unwrap().write("_people_limit.csv").unwrap();
+
+        assert_eq!("1,Andy\n2,Brian\n", read_file("_people_limit.csv"));
+    }
+
+    /// Relation that records how many rows have been read from it
+    struct CountingRelation {
+        schema: Schema,
+        rows_read: Cell<usize>
+    }
+
+    impl SimpleRelation for CountingRelation {
+
+        fn scan<'a>(&'a self, _ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+            Box::new((0..1000).map(move |i| {
+                self.rows_read.set(self.rows_read.get() + 1);
+                Ok(Row::new(vec![Value::UnsignedLong(i)]))
+            }))
+        }
+
+        fn schema(&self) -> &Schema {
+            &self.schema
+        }
+
+        fn describe(&self) -> String {
+            "CountingRelation".to_string()
+        }
+    }
+
+    #[test]
+    fn test_limit_stops_reading_input() {
+
+        let ctx = create_context();
+
+        let input = Rc::new(CountingRelation {
+            schema: Schema::new(vec![Field::new("id", DataType::UnsignedLong, false)]),
+            rows_read: Cell::new(0)
+        });
+
+        let limit = LimitRelation {
+            schema: input.schema.clone(),
+            input: Box::new(SharedRelation(input.clone())),
+            limit: 5,
+            offset: 10
+        };
+
+        let rows: Vec<String> = limit.scan(&ctx).map(|r| r.unwrap().to_string()).collect();
+
+        assert_eq!(vec!["10", "11", "12", "13", "14"], rows);
+        assert_eq!(15, input.rows_read.get());
+    }
+
+    /// Allows the test to inspect a relation after handing it to another relation
+    struct SharedRelation(Rc<CountingRelation>);
+
+    impl SimpleRelation for SharedRelation {
+
+        fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+            self.0.scan(ctx)
+        }
+
+        fn schema(&self) -> &Schema {
+            self.0.schema()
+        }
+
+        fn describe(&self) -> String {
+            self.0.describe()
+        }
+    }
+
+    #[test]
+    fn test_sql_inner_join() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT p.name, o.order_id, amount FROM people p \
+            JOIN orders AS o ON p.id = o.customer_id WHERE o.order_id < 6 ORDER BY order_id").unwrap();
+
+        df.write("_people_orders_join.csv").unwrap();
+
+        assert_eq!("Andy,1,10.5\nBrian,2,20\nAndy,3,5.25\nChris,4,100\nBrian,5,7.5\n",
+                   read_file("_people_orders_join.csv"));
+    }
+
+    #[test]
+    fn test_sql_left_join() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT name, COUNT(order_id) FROM people \
+            LEFT JOIN orders ON orders.customer_id = people.id GROUP BY name").unwrap();
+
+        df.write("_people_orders_left_join.csv").unwrap();
+
+        // people without orders are joined with a row of nulls, which COUNT ignores
+        assert_eq!("Andy,3\nBrian,2\nChris,1\nDonna,0\nEdward,0\nFiona,0\nGary,0\nHelen,0\nIrene,0\nJuliet,0\n",
+                   read_file("_people_orders_left_join.csv"));
+    }
+
+    #[test]
+    fn test_sql_right_and_full_join() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT name, order_id FROM people \
+            RIGHT JOIN orders ON people.id = orders.customer_id ORDER BY order_id").unwrap();
+
+        df.write("_people_orders_right_join.csv").unwrap();
+
+        assert_eq!("Andy,1\nBrian,2\nAndy,3\nChris,4\nBrian,5\nAndy,6\n,7\n",
+                   read_file("_people_orders_right_join.csv"));
+
+        let df = ctx.sql(&"SELECT COUNT(*) FROM people FULL OUTER JOIN orders ON people.id = orders.customer_id").unwrap();
+
+        df.write("_people_orders_full_join.csv").unwrap();
+
+        // six matched orders, one order without a customer and seven people without orders
+        assert_eq!("14\n", read_file("_people_orders_full_join.csv"));
+    }
+
+    #[test]
+    fn test_sql_join_using() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT customer_id, a.order_id, b.order_id FROM orders a \
+            JOIN orders b USING (customer_id) WHERE customer_id = 2 ORDER BY a.order_id, b.order_id").unwrap();
+
+        assert_eq!("customer_id: UnsignedLong,a.order_id: UnsignedLong,b.order_id: UnsignedLong",
+                   df.schema().to_string());
+
+        df.write("_orders_join_using.csv").unwrap();
+
+        assert_eq!("2,2,2\n2,2,5\n2,5,2\n2,5,5\n", read_file("_orders_join_using.csv"));
+    }
+
+    #[test]
+    fn test_sql_coalesce() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql("SELECT coalesce(name, 'none'), coalesce(manager_id, 0), coalesce(salary, 0) \
+            FROM employees ORDER BY id").unwrap();
+
+        // the result has the type of the first argument, and is only nullable if every argument is
+        assert_eq!("coalesce: String,coalesce: UnsignedLong,coalesce: Double", df.schema().to_string());
+        assert!(!df.schema().columns.iter().any(|c| c.nullable));
+
+        df.write("_employees_coalesce.csv").unwrap();
+
+        assert_eq!("Alice,0,100\nBob,1,0\nCarol,1,80\nDan,2,60\nnone,0,0\n", read_file("_employees_coalesce.csv"));
+
+        assert!(ctx.sql("SELECT coalesce(name, 0) FROM employees").is_err());
+        assert!(ctx.sql("SELECT coalesce() FROM employees").is_err());
+
+        // any number of arguments can be given, and they are nullable if every argument is
+        let df = ctx.sql("SELECT coalesce(manager_id, id, 0), coalesce(salary) FROM employees ORDER BY id").unwrap();
+        assert_eq!("coalesce: UnsignedLong,coalesce: Double", df.schema().to_string());
+        assert_eq!(vec![false, true], df.schema().columns.iter().map(|c| c.nullable).collect::<Vec<bool>>());
+        df.write("_employees_coalesce_args.csv").unwrap();
+        assert_eq!("1,100\n1,\n1,80\n2,60\n5,\n", read_file("_employees_coalesce_args.csv"));
+    }
+
+    #[test]
+    fn test_sql_cross_join() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT COUNT(*) FROM people CROSS JOIN orders").unwrap();
+
+        df.write("_people_orders_cross_join.csv").unwrap();
+
+        assert_eq!("70\n", read_file("_people_orders_cross_join.csv"));
+    }
+
+    #[test]
+    fn test_sql_join_mixed_key_types() {
+
+        let mut ctx = create_context();
+
+        // the manager ids are signed and the ids that they refer to are unsigned
+        ctx.define_schema("employees", &Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, true),
+            Field::new("manager_id", DataType::Long, true),
+            Field::new("salary", DataType::Double, true)]));
+
+        let df = ctx.sql("SELECT e.name, p.name FROM employees e \
+            JOIN people p ON e.manager_id = p.id ORDER BY e.id").unwrap();
+
+        df.write("_employees_people_mixed_join.csv").unwrap();
+
+        assert_eq!("Bob,Andy\nCarol,Andy\nDan,Brian\n", read_file("_employees_people_mixed_join.csv"));
+    }
+
+    #[test]
+    fn test_group_key_normalizes_numbers() {
+
+        fn hash(key: &GroupKey) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let keys = vec![
+            GroupKey(vec![Value::UnsignedLong(1)]),
+            GroupKey(vec![Value::Long(1)]),
+            GroupKey(vec![Value::Double(1.0)])];
+        for key in &keys {
+            assert_eq!(keys[0], *key);
+            assert_eq!(hash(&keys[0]), hash(key));
+        }
+
+        let zero = GroupKey(vec![Value::Double(0.0)]);
+        let negative_zero = GroupKey(vec![Value::Double(-0.0)]);
+        assert_eq!(zero, negative_zero);
+        assert_eq!(hash(&zero), hash(&negative_zero));
+
+        assert_ne!(GroupKey(vec![Value::Long(1)]), GroupKey(vec![Value::Double(1.5)]));
+        assert_ne!(GroupKey(vec![Value::Long(1)]), GroupKey(vec![Value::String("1".to_string())]));
+    }
+
+    #[test]
+    fn test_hash_partition() {
+
+        // FNV-1a test vectors
+        let mut hasher = FnvHasher::default();
+        assert_eq!(0xcbf2_9ce4_8422_2325, hasher.finish());
+        hasher.write(b"a");
+        assert_eq!(0xaf63_dc4c_8601_ec8c, hasher.finish());
+
+        // the partitions must not change between builds, since workers built separately rely on
+        // agreeing on them
+        assert_eq!(vec![7, 6, 5, 4, 3, 2, 1, 0],
+                   (0..8).map(|i| hash_partition(&Value::Long(i), 8)).collect::<Vec<usize>>());
+        assert_eq!(hash_partition(&Value::Long(42), 16), hash_partition(&Value::UnsignedLong(42), 16));
+        assert_eq!(hash_partition(&Value::Long(42), 16), hash_partition(&Value::Double(42.0), 16));
+    }
+
+    #[test]
+    fn test_context_debug() {
+
+        let ctx = create_context();
+
+        let debug = format!("{:?}", ctx);
+        assert!(debug.starts_with("ExecutionContext { schemas: {"));
+        assert!(debug.contains("\"people\": Schema"));
+        assert!(debug.contains("functions: ["));
+        assert!(debug.contains("\"sqrt\""));
+    }
+
+    #[test]
+    fn test_sql_join_ambiguous_column() {
+
+        let mut ctx = create_context();
+
+        assert!(ctx.sql(&"SELECT order_id FROM orders a JOIN orders b ON a.order_id = b.order_id").is_err());
+    }
+
+    #[test]
+    fn test_df_join() {
+
+        let ctx = create_context();
+
+        let people = ctx.load("test/data/people.csv", &Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false)]), &CsvOptions::default()).unwrap();
+
+        let orders = ctx.load("test/data/orders.csv", &Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]), &CsvOptions::default()).unwrap();
+
+        let df = orders.join(people.as_ref(), JoinType::Left,
+            vec![(orders.col("customer_id").unwrap(), people.col("id").unwrap())]).unwrap();
+
+        assert_eq!("order_id: UnsignedLong,customer_id: UnsignedLong,amount: Double,id: UnsignedLong,name: String",
+                   df.schema().to_string());
+        assert!(df.schema().columns[4].nullable);
+
+        df.write("_orders_people_df_join.csv").unwrap();
+
+        assert_eq!("1,1,10.5,1,Andy\n2,2,20,2,Brian\n3,1,5.25,1,Andy\n4,3,100,3,Chris\n\
+            5,2,7.5,2,Brian\n6,1,4.25,1,Andy\n7,11,50,,\n",
+                   read_file("_orders_people_df_join.csv"));
+    }
+
+    #[test]
+    fn test_sql_boolean_logic() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT order_id FROM orders \
+            WHERE customer_id = 1 AND order_id > 1 OR NOT order_id < 7").unwrap();
+
+        df.write("_orders_boolean_logic.csv").unwrap();
+
+        assert_eq!("3\n6\n7\n", read_file("_orders_boolean_logic.csv"));
+    }
+
+    #[test]
+    fn test_sql_boolean_logic_short_circuit() {
+
+        let mut ctx = create_context();
+
+        // the right operand would fail with division by zero but is never evaluated
+        let df = ctx.sql(&"SELECT order_id FROM orders WHERE customer_id = 99 AND order_id / 0 > 1").unwrap();
+        df.write("_orders_short_circuit.csv").unwrap();
+        assert_eq!("", read_file("_orders_short_circuit.csv"));
+
+        let df = ctx.sql(&"SELECT order_id FROM orders WHERE customer_id <> 99 AND order_id / 0 > 1").unwrap();
+        assert!(df.write("_orders_no_short_circuit.csv").is_err());
+    }
+
+    #[test]
+    fn test_df_boolean_logic() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT city, lat, lng FROM uk_cities").unwrap();
+
+        let lat = df.col("lat").unwrap();
+        let lng = df.col("lng").unwrap();
+
+        let north_west = lat.gt(&Expr::Literal(Value::Double(52.0)))
+            .and(&lng.lt(&Expr::Literal(Value::Double(0.0))));
+        df.filter(north_west).unwrap().write("_uk_cities_and.csv").unwrap();
+        assert_eq!(19, read_file("_uk_cities_and.csv").lines().count());
+
+        let far_north_or_east = lat.gt(&Expr::Literal(Value::Double(57.0)))
+            .or(&lng.lt(&Expr::Literal(Value::Double(0.0))).not());
+        df.filter(far_north_or_east).unwrap().write("_uk_cities_or.csv").unwrap();
+        assert_eq!(10, read_file("_uk_cities_or.csv").lines().count());
+    }
+
+    #[test]
+    fn test_sql_join_on_conjunction() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT p.name, o.order_id FROM people p \
+            JOIN orders o ON p.id = o.customer_id AND o.order_id = p.id").unwrap();
+
+        df.write("_people_orders_conjunction.csv").unwrap();
+
+        assert_eq!("Andy,1\nBrian,2\n", read_file("_people_orders_conjunction.csv"));
+    }
+
+    #[test]
+    fn test_sql_arithmetic() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT order_id, order_id * 2 + 1, amount / 2, customer_id - order_id, \
+            (order_id + 1) % 3 FROM orders WHERE amount > 20").unwrap();
+
+        assert_eq!("order_id: UnsignedLong,expr: UnsignedLong,expr: Double,expr: Long,expr: UnsignedLong",
+                   df.schema().to_string());
+
+        df.write("_orders_arithmetic.csv").unwrap();
+
+        assert_eq!("4,9,50,-1,2\n7,15,25,4,2\n", read_file("_orders_arithmetic.csv"));
+    }
+
+    #[test]
+    fn test_sql_division_by_zero() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT order_id / (customer_id - 1) FROM orders").unwrap();
+        assert!(df.write("_orders_division_by_zero.csv").is_err());
+
+        let df = ctx.sql(&"SELECT amount % 0 FROM orders").unwrap();
+        assert!(df.write("_orders_modulus_by_zero.csv").is_err());
+    }
+
+    #[test]
+    fn test_df_arithmetic() {
+
+        let ctx = create_context();
+
+        let orders = ctx.load("test/data/orders.csv", &Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]), &CsvOptions::default()).unwrap();
+
+        let with_tax = orders.col("amount").unwrap().multiply(&Expr::Literal(Value::Double(1.2)));
+        let df = orders.filter(with_tax.gt(&Expr::Literal(Value::UnsignedLong(50)))).unwrap()
+            .select(vec![orders.col("order_id").unwrap(), with_tax]).unwrap();
+
+        df.write("_orders_df_arithmetic.csv").unwrap();
+
+        assert_eq!("4,120\n7,60\n", read_file("_orders_df_arithmetic.csv"));
+    }
+
+    #[test]
+    fn test_arithmetic_promotion() {
+        assert_eq!(Value::UnsignedLong(7),
+                   evaluate_arithmetic(&Operator::Plus, &Value::UnsignedLong(3), &Value::UnsignedLong(4)).unwrap());
+        assert_eq!(Value::Long(-1),
+                   evaluate_arithmetic(&Operator::Minus, &Value::UnsignedLong(3), &Value::UnsignedLong(4)).unwrap());
+        assert_eq!(Value::Long(-6),
+                   evaluate_arithmetic(&Operator::Multiply, &Value::UnsignedLong(3), &Value::Long(-2)).unwrap());
+        assert_eq!(Value::Double(1.5),
+                   evaluate_arithmetic(&Operator::Divide, &Value::Long(3), &Value::Double(2.0)).unwrap());
+        assert_eq!(Value::Long(-1),
+                   evaluate_arithmetic(&Operator::Modulus, &Value::Long(-7), &Value::UnsignedLong(3)).unwrap());
+        assert!(evaluate_arithmetic(&Operator::Plus, &Value::UnsignedLong(u64::max_value()), &Value::UnsignedLong(1)).is_err());
+        assert!(evaluate_arithmetic(&Operator::Divide, &Value::Long(1), &Value::Long(0)).is_err());
+        assert!(evaluate_arithmetic(&Operator::Plus, &Value::String("a".to_string()), &Value::Long(1)).is_err());
+    }
+
+    #[test]
+    fn test_sql_is_null() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT id FROM employees WHERE manager_id IS NULL").unwrap();
+        df.write("_employees_is_null.csv").unwrap();
+        assert_eq!("1\n5\n", read_file("_employees_is_null.csv"));
+
+        let df = ctx.sql(&"SELECT id FROM employees WHERE salary IS NOT NULL AND manager_id IS NOT NULL").unwrap();
+        df.write("_employees_is_not_null.csv").unwrap();
+        assert_eq!("3\n4\n", read_file("_employees_is_not_null.csv"));
+    }
+
+    #[test]
+    fn test_sql_three_valued_logic() {
+
+        let mut ctx = create_context();
+
+        // rows where the predicate is unknown are filtered out
+        let df = ctx.sql(&"SELECT id FROM employees WHERE salary > 70 OR manager_id = 1").unwrap();
+        df.write("_employees_or.csv").unwrap();
+        assert_eq!("1\n2\n3\n", read_file("_employees_or.csv"));
+
+        let df = ctx.sql(&"SELECT id FROM employees WHERE NOT salary > 70").unwrap();
+        df.write("_employees_not.csv").unwrap();
+        assert_eq!("4\n", read_file("_employees_not.csv"));
+
+        let df = ctx.sql(&"SELECT id, salary * 2, salary > 70 AND manager_id = 1 FROM employees").unwrap();
+        df.write("_employees_null_expr.csv").unwrap();
+        assert_eq!("1,200,\n2,,\n3,160,true\n4,120,false\n5,,\n", read_file("_employees_null_expr.csv"));
+    }
+
+    #[test]
+    fn test_sql_aggregate_nulls() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT COUNT(*), COUNT(salary), SUM(salary), AVG(salary) FROM employees").unwrap();
+        df.write("_employees_aggregate.csv").unwrap();
+        assert_eq!("5,3,240,80\n", read_file("_employees_aggregate.csv"));
+
+        // an aggregate without GROUP BY over no rows returns a single row
+        let df = ctx.sql(&"SELECT COUNT(*), SUM(salary), MIN(id) FROM employees WHERE id > 10").unwrap();
+        df.write("_employees_aggregate_empty.csv").unwrap();
+        assert_eq!("0,,\n", read_file("_employees_aggregate_empty.csv"));
+    }
+
+    #[test]
+    fn test_df_is_null() {
+
+        let ctx = create_context();
+
+        let df = ctx.load("test/data/employees.csv", &employees_schema(true), &CsvOptions::default()).unwrap();
+
+        df.filter(df.col("name").unwrap().is_null()).unwrap().write("_employees_df_is_null.csv").unwrap();
+        assert_eq!("5,,,\n", read_file("_employees_df_is_null.csv"));
+    }
+
+    #[test]
+    fn test_non_nullable_column() {
+
+        let ctx = create_context();
+
+        let df = ctx.load("test/data/employees.csv", &employees_schema(false), &CsvOptions::default()).unwrap();
+
+        match df.write("_employees_non_nullable.csv") {
+            Err(DataFrameError::ExecError(ExecutionError::NullValue(ref column))) =>
+                assert_eq!("manager_id", column),
+            other => panic!("Expected null value error but found {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_sql_numeric_literals() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT lat FROM uk_cities WHERE lat > 57.1 AND lng < -2.5").unwrap();
+        df.write("_uk_cities_decimal.csv").unwrap();
+        assert_eq!("57.653484\n57.477772\n", read_file("_uk_cities_decimal.csv"));
+
+        let df = ctx.sql(&"SELECT lat FROM uk_cities WHERE lat > 5.7e1").unwrap();
+        df.write("_uk_cities_scientific.csv").unwrap();
+        assert_eq!("57.653484\n57.149651\n57.477772\n", read_file("_uk_cities_scientific.csv"));
+    }
+
+    #[test]
+    fn test_sql_string_literals() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT 'it''s', name FROM people WHERE name = 'Andy' OR name = 'Gary'").unwrap();
+        df.write("_people_string_literal.csv").unwrap();
+        assert_eq!("it's,Andy\nit's,Gary\n", read_file("_people_string_literal.csv"));
+    }
+
+    #[test]
+    fn test_sql_wildcard() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT * FROM people LIMIT 2").unwrap();
+        assert_eq!("id: UnsignedLong,name: String", df.schema().to_string());
+        df.write("_people_wildcard.csv").unwrap();
+        assert_eq!("1,Andy\n2,Brian\n", read_file("_people_wildcard.csv"));
+
+        let df = ctx.sql(&"SELECT p.*, o.amount total FROM people p \
+            JOIN orders o ON p.id = o.customer_id WHERE o.order_id = 4").unwrap();
+        assert_eq!("p.id: UnsignedLong,p.name: String,total: Double", df.schema().to_string());
+        df.write("_people_orders_wildcard.csv").unwrap();
+        assert_eq!("3,Chris,100\n", read_file("_people_orders_wildcard.csv"));
+
+        assert!(ctx.sql(&"SELECT x.* FROM people").is_err());
+    }
+
+    #[test]
+    fn test_sql_column_aliases() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT customer_id AS customer, COUNT(*) AS n FROM orders \
+            GROUP BY customer_id ORDER BY n DESC, customer").unwrap();
+        assert_eq!("customer: UnsignedLong,n: UnsignedLong", df.schema().to_string());
+        df.write("_orders_aliases.csv").unwrap();
+        assert_eq!("1,3\n2,2\n3,1\n11,1\n", read_file("_orders_aliases.csv"));
+
+        let df = ctx.sql(&"SELECT sqrt(lat) root, lat latitude FROM uk_cities").unwrap();
+        assert_eq!("root: Double,latitude: Double", df.schema().to_string());
+    }
+
+    #[test]
+    fn test_df_alias() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT city, lat, lng FROM uk_cities").unwrap();
 
-impl<'a> CsvRelation {
+        let df2 = df.select(vec![df.col("lat").unwrap().alias("latitude")]).unwrap();
 
-    pub fn open(file: File, schema: Schema) -> Result<Self,ExecutionError> {
-        Ok(CsvRelation { file, schema })
+        assert_eq!("latitude: Double", df2.schema().to_string());
     }
 
-    /// Convert StringRecord into our internal tuple type based on the known schema
-    fn create_tuple(&self, r: &StringRecord) -> Result<Row,ExecutionError> {
-        assert_eq!(self.schema.columns.len(), r.len());
-        let values = self.schema.columns.iter().zip(r.into_iter()).map(|(c,s)| match c.data_type {
-            //TODO: remove unwrap use here
-            DataType::UnsignedLong => Value::UnsignedLong(s.parse::<u64>().unwrap()),
-            DataType::String => Value::String(s.to_string()),
-            DataType::Double => Value::Double(s.parse::<f64>().unwrap()),
-            _ => panic!("csv unsupported type")
-        }).collect();
-        Ok(Row::new(values))
+    #[test]
+    fn test_sql_function_return_types() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT ST_AsText(ST_Point(lat, lng)), ST_Point(lat, lng), sqrt(order_id) \
+            FROM uk_cities CROSS JOIN orders").unwrap();
+
+        assert_eq!(vec![
+            Field::new("ST_AsText", DataType::String, false),
+            Field::new("ST_Point", DataType::ComplexType(vec![
+                Field::new("x", DataType::Double, false),
+                Field::new("y", DataType::Double, false)]), false),
+            Field::new("sqrt", DataType::Double, false)
+        ], df.schema().columns);
+
+        let df = ctx.sql(&"SELECT sqrt(salary) FROM employees").unwrap();
+        assert!(df.schema().columns[0].nullable);
     }
-}
 
-/// trait for all relations (a relation is essentially just an iterator over tuples with
-/// a known schema)
-pub trait SimpleRelation {
-    /// scan all records in this relation
-    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<Iterator<Item=Result<Row,ExecutionError>> + 'a>;
-    /// get the schema for this relation
-    fn schema<'a>(&'a self) -> &'a Schema;
-}
+    #[test]
+    fn test_sql_type_errors() {
 
-impl SimpleRelation for CsvRelation {
+        let mut ctx = create_context();
+
+        for sql in vec![
+            "SELECT sqrt(city) FROM uk_cities",
+            "SELECT sqrt(lat, lng) FROM uk_cities",
+            "SELECT ST_AsText(lat) FROM uk_cities",
+            "SELECT no_such_function(lat) FROM uk_cities",
+            "SELECT city + 1 FROM uk_cities",
+            "SELECT SUM(city) FROM uk_cities",
+            "SELECT city FROM uk_cities WHERE city > 1",
+            "SELECT city FROM uk_cities WHERE lat",
+            "SELECT city FROM uk_cities WHERE lat > 1 AND lng",
+            "SELECT COUNT(*) FROM uk_cities GROUP BY city HAVING COUNT(*)"
+        ] {
+            assert!(ctx.sql(sql).is_err(), "Expected planning to fail for {}", sql);
+        }
+    }
 
-    fn scan<'a>(&'a self, _ctx: &'a ExecutionContext) -> Box<Iterator<Item=Result<Row,ExecutionError>> + 'a> {
+    #[test]
+    fn test_df_type_errors() {
 
-        let buf_reader = BufReader::new(&self.file);
-        let csv_reader = csv::Reader::from_reader(buf_reader);
-        let record_iter = csv_reader.into_records();
+        let mut ctx = create_context();
 
-        let tuple_iter = record_iter.map(move|r| match r {
-            Ok(record) => self.create_tuple(&record),
-            Err(e) => Err(ExecutionError::CsvError(e))
-        });
+        let df = ctx.sql(&"SELECT city, lat, lng FROM uk_cities").unwrap();
 
-        Box::new(tuple_iter)
+        assert!(df.filter(df.col("lat").unwrap()).is_err());
+        assert!(df.select(vec![ctx.udf("sqrt", vec![df.col("city").unwrap()])]).is_err());
     }
 
-    fn schema<'a>(&'a self) -> &'a Schema {
-        &self.schema
+    /// Function that negates its argument, which can be registered under any name
+    struct NegateFunction {
+        name: String
     }
 
-}
+    impl ScalarFunction for NegateFunction {
 
-impl SimpleRelation for FilterRelation {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
 
-    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<Iterator<Item=Result<Row, ExecutionError>> + 'a> {
-        Box::new(self.input.scan(ctx).filter(move|t|
-            match t {
-                &Ok(ref tuple) => match ctx.evaluate(tuple, &self.schema, &self.expr) {
-                    Ok(Value::Boolean(b)) => b,
-                    _ => panic!("Predicate expression evaluated to non-boolean value")
-                },
-                _ => true // let errors through the filter so they can be handled later
+        fn args(&self) -> Vec<Field> {
+            vec![Field::new("x", DataType::Double, false)]
+        }
+
+        fn return_type(&self) -> DataType {
+            DataType::Double
+        }
+
+        fn execute(&self, args: Vec<Value>) -> Result<Value, Box<String>> {
+            match args[0] {
+                Value::Double(d) => Ok(Value::Double(-d)),
+                Value::UnsignedLong(n) => Ok(Value::Double(-(n as f64))),
+                ref other => Err(Box::new(format!("Unsupported argument {:?}", other)))
             }
-        ))
+        }
     }
 
-    fn schema<'a>(&'a self) -> &'a Schema {
-        &self.schema
-    }
-}
+    #[test]
+    fn test_sql_user_defined_function() {
 
-impl SimpleRelation for ProjectRelation {
+        let mut ctx = create_context();
 
-    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<Iterator<Item=Result<Row, ExecutionError>> + 'a> {
-        let foo = self.input.scan(ctx).map(move|r| match r {
-            Ok(tuple) => {
-                let values = self.expr.iter()
-                    .map(|e| match e {
-                        &Expr::TupleValue(i) => tuple.values[i].clone(),
-                        //TODO: relation delegating back to execution context seems wrong way around
-                        _ => ctx.evaluate(&tuple,&self.schema, e).unwrap() //TODO: remove unwrap
-                        //unimplemented!("Unsupported expression for projection")
-                    })
-                    .collect();
-                Ok(Row::new(values))
-            },
-            Err(_) => r
-        });
+        assert!(ctx.sql(&"SELECT negate(id) FROM people").is_err());
 
-        Box::new(foo)
-    }
+        ctx.define_function(Arc::new(NegateFunction { name: "negate".to_string() }));
 
-    fn schema<'a>(&'a self) -> &'a Schema {
-        &self.schema
+        let df = ctx.sql(&"SELECT NEGATE(id) FROM people LIMIT 2").unwrap();
+        df.write("_people_negate.csv").unwrap();
+        assert_eq!("-1\n-2\n", read_file("_people_negate.csv"));
     }
-}
 
-/// Execution plans are sent to worker nodes for execution
-pub enum ExecutionPlan {
-    /// Run a query and return the results to the client
-    Interactive { plan: LogicalPlan },
-    /// Partition the relation
-    Partition { plan: LogicalPlan, partition_count: usize, partition_expr: Expr }
+    #[test]
+    fn test_override_builtin_function() {
 
-}
+        let mut ctx = create_context();
 
+        ctx.define_function(Arc::new(NegateFunction { name: "SQRT".to_string() }));
 
-#[derive(Debug,Clone)]
-pub struct ExecutionContext {
-    schemas: HashMap<String, Schema>,
-    functions: HashMap<String, FunctionMeta>,
+        let df = ctx.sql(&"SELECT sqrt(id) FROM people LIMIT 2").unwrap();
+        df.write("_people_sqrt_override.csv").unwrap();
+        assert_eq!("-1\n-2\n", read_file("_people_sqrt_override.csv"));
+    }
 
-}
+    #[test]
+    fn test_df_user_defined_function() {
 
-impl ExecutionContext {
+        let mut ctx = create_context();
 
-    pub fn new() -> Self {
-        ExecutionContext {
-            schemas: HashMap::new(),
-            functions: HashMap::new()
-        }
+        ctx.define_function(Arc::new(NegateFunction { name: "negate".to_string() }));
+
+        let df = ctx.sql(&"SELECT id FROM people").unwrap();
+        let df2 = df.select(vec![ctx.udf("negate", vec![df.col("id").unwrap()])]).unwrap();
+
+        df2.limit(1).unwrap().write("_people_df_negate.csv").unwrap();
+        assert_eq!("-1\n", read_file("_people_df_negate.csv"));
     }
 
-    pub fn define_schema(&mut self, name: &str, schema: &Schema) {
-        self.schemas.insert(name.to_string(), schema.clone());
+    #[test]
+    fn test_sql_create_external_table() {
+        let mut ctx = ExecutionContext::new();
+
+        ctx.sql("CREATE EXTERNAL TABLE uk_cities (city VARCHAR(100) NOT NULL, lat DOUBLE NOT NULL, lng DOUBLE NOT NULL) \
+                 WITH OPTIONS (has_header = false)").unwrap();
+        let rows = ctx.sql("SELECT COUNT(*), MAX(lat) FROM uk_cities").unwrap().collect().unwrap();
+        assert_eq!("37,57.653484", rows[0].to_string());
+
+        // the header is skipped by default
+        ctx.sql("CREATE EXTERNAL TABLE orders (order_id DOUBLE, customer_id DOUBLE, amount DOUBLE)").unwrap();
+        let rows = ctx.sql("SELECT COUNT(*) FROM orders").unwrap().collect().unwrap();
+        assert_eq!("7", rows[0].to_string());
+
+        assert!(ctx.sql("CREATE EXTERNAL TABLE orders (amount DOUBLE) WITH OPTIONS (delimiter = ';;')").is_err());
+        assert!(ctx.sql("CREATE EXTERNAL TABLE orders (amount DOUBLE) WITH OPTIONS (encoding = 'utf8')").is_err());
     }
 
-    pub fn define_function(&mut self, func: &ScalarFunction) {
+    #[test]
+    fn test_redefine_memory_table() {
+        let mut ctx = ExecutionContext::new();
+        let schema = Schema::new(vec![Field::new("amount", DataType::Double, false)]);
 
-        let fm = FunctionMeta {
-            name: func.name(),
-            args: func.args(),
-            return_type: func.return_type()
-        };
+        ctx.register_memory_table("orders", &schema, vec![Row::new(vec![Value::Double(1.0)])]);
+        let rows = ctx.sql("SELECT COUNT(*) FROM orders").unwrap().collect().unwrap();
+        assert_eq!("1", rows[0].to_string());
 
-        self.functions.insert(fm.name.to_lowercase(), fm);
+        // once redefined, the table is read from its CSV file instead of the registered rows
+        ctx.sql("CREATE EXTERNAL TABLE orders (order_id DOUBLE, customer_id DOUBLE, amount DOUBLE)").unwrap();
+        let rows = ctx.sql("SELECT COUNT(*) FROM orders").unwrap().collect().unwrap();
+        assert_eq!("7", rows[0].to_string());
     }
 
-    pub fn sql(&self, sql: &str) -> Result<Box<DataFrame>, ExecutionError> {
+    /// Path of the UDF library built from `examples/udf_library.rs`, which is next to the test
+    /// binary's directory
+    fn udf_library_path() -> String {
+        let exe = env::current_exe().unwrap();
+        let dir = exe.parent().unwrap().parent().unwrap().join("examples");
+        let filename = format!("{}udf_library{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX);
+        dir.join(filename).to_str().unwrap().to_string()
+    }
 
-        // parse SQL into AST
-        let ast = Parser::parse_sql(String::from(sql))?;
+    #[test]
+    fn test_load_udf_library() {
 
-        // create a query planner
-        let query_planner = SqlToRel::new(self.schemas.clone()); //TODO: pass reference to schemas
+        let mut ctx = create_context();
 
-        // plan the query (create a logical relational plan)
-        let plan = query_planner.sql_to_rel(&ast)?;
+        assert!(ctx.sql(&"SELECT double_it(id) FROM people").is_err());
 
-        // return the DataFrame
-        Ok(Box::new(DF { ctx: Box::new(self.clone()), plan: plan })) //TODO: don't clone context
-    }
+        ctx.load_udf_library(&udf_library_path()).unwrap();
 
-    /// Open a CSV file
-    ///TODO: this is building a relational plan not an execution plan so shouldn't really be here
-    pub fn load(&self, filename: &str, schema: &Schema) -> Result<Box<DataFrame>, ExecutionError> {
-        let plan = LogicalPlan::CsvFile { filename: filename.to_string(), schema: schema.clone() };
-        Ok(Box::new(DF { ctx: Box::new((*self).clone()), plan: Box::new(plan) }))
-    }
+        let df = ctx.sql(&"SELECT double_it(id), halve(id) FROM people LIMIT 2").unwrap();
+        df.write("_people_udf_library.csv").unwrap();
+        assert_eq!("2,0.5\n4,1\n", read_file("_people_udf_library.csv"));
 
-    pub fn register_table(&mut self, name: String, schema: Schema) {
-        self.schemas.insert(name, schema);
+        assert!(ctx.load_udf_library("test/data/no_such_library.so").is_err());
     }
 
-    pub fn create_execution_plan(&self, plan: &LogicalPlan) -> Result<Box<SimpleRelation>,ExecutionError> {
-        match *plan {
+    #[test]
+    fn test_sql_create_function() {
 
-            LogicalPlan::EmptyRelation => {
-                panic!()
-            },
+        let mut ctx = create_context();
+        let path = udf_library_path();
 
-            LogicalPlan::TableScan { ref table_name, ref schema, .. } => {
-                // for now, tables are csv files
-                let file = File::open(format!("test/data/{}.csv", table_name))?;
-                let rel = CsvRelation::open(file, schema.clone())?;
-                Ok(Box::new(rel))
-            },
+        ctx.sql(&format!("CREATE FUNCTION double_it(DOUBLE) RETURNS DOUBLE LIBRARY '{}'", path)).unwrap();
 
-            LogicalPlan::CsvFile { ref filename, ref schema } => {
-                let file = File::open(filename)?;
-                let rel = CsvRelation::open(file, schema.clone())?;
-                Ok(Box::new(rel))
-            },
+        let df = ctx.sql(&"SELECT double_it(id) FROM people LIMIT 2").unwrap();
+        df.write("_people_create_function.csv").unwrap();
+        assert_eq!("2\n4\n", read_file("_people_create_function.csv"));
 
-            LogicalPlan::Selection { ref expr, ref input, ref schema } => {
-                let input_rel = self.create_execution_plan(input)?;
-                let rel = FilterRelation {
-                    input: input_rel,
-                    expr: expr.clone(),
-                    schema: schema.clone()
-                };
-                Ok(Box::new(rel))
-            },
+        // only the named function is registered
+        assert!(ctx.sql(&"SELECT halve(id) FROM people").is_err());
 
-            LogicalPlan::Projection { ref expr, ref input, .. } => {
-                let input_rel = self.create_execution_plan(&input)?;
-                let input_schema = input_rel.schema().clone();
-
-                //TODO: seems to be duplicate of sql_to_rel code
-                let project_columns: Vec<Field> = expr.iter().map(|e| {
-                    match e {
-                        &Expr::TupleValue(i) => input_schema.columns[i].clone(),
-                        &Expr::ScalarFunction {ref name, .. } => Field {
-                            name: name.clone(),
-                            data_type: DataType::Double, //TODO: hard-coded .. no function metadata yet
-                            nullable: true
-                        },
-                        _ => unimplemented!("Unsupported projection expression")
-                    }
-                }).collect();
+        // the declared signature must match the implementation
+        assert!(ctx.sql(&format!("CREATE FUNCTION halve(DOUBLE, DOUBLE) LIBRARY '{}'", path)).is_err());
+        assert!(ctx.sql(&format!("CREATE FUNCTION halve RETURNS VARCHAR(10) LIBRARY '{}'", path)).is_err());
 
-                let project_schema = Schema { columns: project_columns };
+        assert!(ctx.sql(&format!("CREATE FUNCTION triple LIBRARY '{}'", path)).is_err());
 
-                let rel = ProjectRelation {
-                    input: input_rel,
-                    expr: expr.clone(),
-                    schema: project_schema,
+        ctx.sql(&format!("CREATE FUNCTION HALVE LIBRARY '{}'", path)).unwrap();
+        assert!(ctx.sql(&"SELECT halve(id) FROM people").is_ok());
+    }
 
-                };
+    /// Aggregate function computing the average of `x` weighted by `w`, with a state holding the
+    /// running sums of `x * w` and `w`
+    struct WeightedAvg;
 
-                Ok(Box::new(rel))
-            }
+    impl AggregateFunction for WeightedAvg {
+
+        fn name(&self) -> String {
+            "weighted_avg".to_string()
         }
-    }
 
-    /// Evaluate a relational expression against a tuple
-    pub fn evaluate(&self, tuple: &Row, tt: &Schema, rex: &Expr) -> Result<Value, Box<ExecutionError>> {
+        fn args(&self) -> Vec<Field> {
+            vec![Field::new("x", DataType::Double, false),
+                 Field::new("w", DataType::Double, false)]
+        }
 
-        match rex {
-            &Expr::BinaryExpr { ref left, ref op, ref right } => {
-                let left_value = self.evaluate(tuple, tt, left)?;
-                let right_value = self.evaluate(tuple, tt, right)?;
-                match op {
-                    &Operator::Eq => Ok(Value::Boolean(left_value == right_value)),
-                    &Operator::NotEq => Ok(Value::Boolean(left_value != right_value)),
-                    &Operator::Lt => Ok(Value::Boolean(left_value < right_value)),
-                    &Operator::LtEq => Ok(Value::Boolean(left_value <= right_value)),
-                    &Operator::Gt => Ok(Value::Boolean(left_value > right_value)),
-                    &Operator::GtEq => Ok(Value::Boolean(left_value >= right_value)),
-                }
-            },
-            &Expr::TupleValue(index) => Ok(tuple.values[index].clone()),
-            &Expr::Literal(ref value) => Ok(value.clone()),
-            &Expr::ScalarFunction { ref name, ref args } => {
+        fn return_type(&self) -> DataType {
+            DataType::Double
+        }
 
-                // evaluate the arguments to the function
-                let arg_values : Vec<Value> = args.iter()
-                    .map(|a| self.evaluate(tuple, tt, &a))
-                    .collect::<Result<Vec<Value>, Box<ExecutionError>>>()?;
+        fn init(&self) -> Value {
+            Value::ComplexValue(vec![Value::Double(0.0), Value::Double(0.0)])
+        }
 
-                let func = self.load_function_impl(name.as_ref())?;
+        fn accumulate(&self, state: &mut Value, args: Vec<Value>) -> Result<(), Box<String>> {
+            let x = to_f64(&args[0])?;
+            let w = to_f64(&args[1])?;
+            self.merge(state, Value::ComplexValue(vec![Value::Double(x * w), Value::Double(w)]))
+        }
 
-                match func.execute(arg_values) {
-                    Ok(value) => Ok(value),
-                    Err(_) => Err(Box::new(ExecutionError::Custom("TBD".to_string()))) //TODO: fix
-                }
+        fn merge(&self, state: &mut Value, other: Value) -> Result<(), Box<String>> {
+            match (state, other) {
+                (&mut Value::ComplexValue(ref mut a), Value::ComplexValue(ref b)) => {
+                    for i in 0..2 {
+                        a[i] = Value::Double(to_f64(&a[i])? + to_f64(&b[i])?);
+                    }
+                    Ok(())
+                },
+                _ => Err(Box::new("Invalid state".to_string()))
             }
         }
 
+        fn finalize(&self, state: Value) -> Result<Value, Box<String>> {
+            match state {
+                Value::ComplexValue(ref v) if to_f64(&v[1])? == 0.0 => Ok(Value::Null),
+                Value::ComplexValue(ref v) => Ok(Value::Double(to_f64(&v[0])? / to_f64(&v[1])?)),
+                _ => Err(Box::new("Invalid state".to_string()))
+            }
+        }
     }
 
-    /// load a function implementation
-    fn load_function_impl(&self, function_name: &str) -> Result<Box<ScalarFunction>,Box<ExecutionError>> {
-
-        //TODO: this is a huge hack since the functions have already been registered with the
-        // execution context ... I need to implement this so it dynamically loads the functions
-
-        match function_name.to_lowercase().as_ref() {
-            "sqrt" => Ok(Box::new(SqrtFunction {})),
-            "st_point" => Ok(Box::new(STPointFunc {})),
-            "st_astext" => Ok(Box::new(STAsText {})),
-            _ => Err(Box::new(ExecutionError::Custom(format!("Unknown function {}", function_name))))
+    fn to_f64(v: &Value) -> Result<f64, Box<String>> {
+        match *v {
+            Value::Double(d) => Ok(d),
+            Value::UnsignedLong(n) => Ok(n as f64),
+            Value::Long(n) => Ok(n as f64),
+            ref other => Err(Box::new(format!("Unsupported argument {:?}", other)))
         }
     }
 
-    pub fn udf(&self, name: &str, args: Vec<Expr>) -> Expr {
-        Expr::ScalarFunction { name: name.to_string(), args: args.clone() }
-    }
+    #[test]
+    fn test_sql_user_defined_aggregate() {
 
-}
+        let mut ctx = create_context();
 
+        assert!(ctx.sql(&"SELECT weighted_avg(amount, order_id) FROM orders").is_err());
 
+        ctx.define_aggregate_function(Arc::new(WeightedAvg));
 
+        let df = ctx.sql(&"SELECT customer_id, WEIGHTED_AVG(amount, order_id) \
+            FROM orders GROUP BY customer_id").unwrap();
 
-pub struct DF {
-    ctx: Box<ExecutionContext>,
-    plan: Box<LogicalPlan>
-}
+        assert_eq!("customer_id: UnsignedLong,WEIGHTED_AVG: Double", df.schema().to_string());
 
-impl DataFrame for DF {
+        df.write("_orders_weighted_avg.csv").unwrap();
 
-    fn select(&self, expr: Vec<Expr>) -> Result<Box<DataFrame>, DataFrameError> {
+        let expected = "1,5.175\n\
+            2,11.071428571428571\n\
+            3,100\n\
+            11,50\n";
 
-        let plan = LogicalPlan::Projection {
-            expr: expr,
-            input: self.plan.clone(),
-            schema: self.plan.schema().clone()
+        assert_eq!(expected, read_file("_orders_weighted_avg.csv"));
 
-        };
+        // argument types are validated against the function metadata
+        assert!(ctx.sql(&"SELECT weighted_avg(name, id) FROM people").is_err());
+        assert!(ctx.sql(&"SELECT weighted_avg(amount) FROM orders").is_err());
+    }
 
-        Ok(Box::new(DF { ctx: self.ctx.clone(), plan: Box::new(plan) }))
+    #[test]
+    fn test_df_user_defined_aggregate() {
 
-    }
+        let mut ctx = create_context();
 
-    fn filter(&self, expr: Expr) -> Result<Box<DataFrame>, DataFrameError> {
+        ctx.define_aggregate_function(Arc::new(WeightedAvg));
 
-        let plan = LogicalPlan::Selection {
-            expr: expr,
-            input: self.plan.clone(),
-            schema: self.plan.schema().clone()
-        };
+        let df = ctx.sql(&"SELECT order_id, amount FROM orders").unwrap();
 
-        Ok(Box::new(DF { ctx: self.ctx.clone(), plan: Box::new(plan) }))
+        let df2 = df.aggregate(
+            vec![],
+            vec![ctx.aggregate_function("weighted_avg", vec![df.col("amount").unwrap(),
+                                                             df.col("order_id").unwrap()])]).unwrap();
+
+        df2.write("_orders_df_weighted_avg.csv").unwrap();
+
+        // (10.5 + 40 + 15.75 + 400 + 37.5 + 25.5 + 350) / 28
+        assert_eq!("31.401785714285715\n", read_file("_orders_df_weighted_avg.csv"));
     }
 
-    fn write(&self, filename: &str) -> Result<(), DataFrameError> {
-        let execution_plan = self.ctx.create_execution_plan(&self.plan)?;
+    #[test]
+    fn test_merge_partial_aggregates() {
 
-        // create output file
-        println!("Writing csv to {}", filename);
-        let mut file = File::create(filename)?;
+        let func = WeightedAvg;
+        let rows = vec![(10.5, 1.0), (20.0, 2.0), (5.25, 3.0), (100.0, 4.0)];
 
-        // implement execution here for now but should be a common method for processing a plan
-        let it = execution_plan.scan(&self.ctx);
-        it.for_each(|t| {
-            match t {
-                Ok(tuple) => {
-                    let csv = format!("{}\n", tuple.to_string());
-                    file.write(&csv.into_bytes()).unwrap(); //TODO: remove unwrap
-                },
-                Err(e) => panic!(format!("Error processing tuple: {:?}", e)) //TODO: error handling
+        let accumulate = |rows: &[(f64, f64)]| {
+            let mut state = func.init();
+            for &(x, w) in rows {
+                func.accumulate(&mut state, vec![Value::Double(x), Value::Double(w)]).unwrap();
             }
-        });
+            state
+        };
 
-        Ok(())
-    }
+        let mut left = accumulate(&rows[0..2]);
+        let right = accumulate(&rows[2..]);
+        func.merge(&mut left, right).unwrap();
 
-    fn col(&self, column_name: &str) -> Result<Expr, DataFrameError> {
-        match self.plan.schema().column(column_name) {
-            Some((i,_)) => Ok(Expr::TupleValue(i)),
-            _ => Err(DataFrameError::InvalidColumn(column_name.to_string()))
-        }
+        assert_eq!(func.finalize(accumulate(&rows)).unwrap(), func.finalize(left).unwrap());
     }
 
-    fn schema(&self) -> Schema {
-        self.plan.schema().clone()
-    }
+    #[test]
+    fn test_sql_ranking_window_functions() {
 
-    fn repartition(&self, _n: u32) -> Result<Box<DataFrame>, DataFrameError> {
-        unimplemented!()
-    }
-}
+        let mut ctx = create_context();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let df = ctx.sql(&"SELECT order_id, \
+            ROW_NUMBER() OVER (PARTITION BY customer_id ORDER BY amount DESC) AS rn, \
+            RANK() OVER (ORDER BY customer_id), \
+            DENSE_RANK() OVER (ORDER BY customer_id) \
+            FROM orders ORDER BY order_id").unwrap();
+
+        assert_eq!("order_id: UnsignedLong,rn: UnsignedLong,RANK: UnsignedLong,DENSE_RANK: UnsignedLong",
+                   df.schema().to_string());
+
+        df.write("_orders_ranking.csv").unwrap();
+
+        let expected = "1,1,1,1\n\
+            2,1,4,2\n\
+            3,2,1,1\n\
+            4,1,6,3\n\
+            5,2,4,2\n\
+            6,3,1,1\n\
+            7,1,7,4\n";
+
+        assert_eq!(expected, read_file("_orders_ranking.csv"));
+    }
 
     #[test]
-    fn test_sqrt() {
+    fn test_sql_framed_window_aggregates() {
 
         let mut ctx = create_context();
 
-        ctx.define_function(&SqrtFunction {});
+        let df = ctx.sql(&"SELECT order_id, \
+            SUM(amount) OVER (ORDER BY order_id ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW), \
+            AVG(amount) OVER (ORDER BY order_id ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING), \
+            SUM(amount) OVER (ORDER BY customer_id), \
+            COUNT(*) OVER (PARTITION BY customer_id) \
+            FROM orders").unwrap();
 
-        let df = ctx.sql(&"SELECT id, sqrt(id) FROM people").unwrap();
+        df.write("_orders_framed_window.csv").unwrap();
 
-        df.write("_sqrt_out.csv").unwrap();
+        let expected = "1,10.5,15.25,20,3\n\
+            2,30.5,11.916666666666666,47.5,2\n\
+            3,35.75,41.75,20,3\n\
+            4,135.75,37.583333333333336,147.5,1\n\
+            5,143.25,37.25,47.5,2\n\
+            6,147.5,20.583333333333332,20,3\n\
+            7,197.5,27.125,197.5,1\n";
 
-        //TODO: check that generated file has expected contents
+        assert_eq!(expected, read_file("_orders_framed_window.csv"));
     }
 
     #[test]
-    fn test_sql_udf_udt() {
+    fn test_sql_lag_lead() {
 
         let mut ctx = create_context();
 
-        ctx.define_function(&STPointFunc {});
+        let df = ctx.sql(&"SELECT order_id, \
+            LAG(amount) OVER (PARTITION BY customer_id ORDER BY order_id), \
+            LEAD(amount, 2, 0.0) OVER (ORDER BY order_id) \
+            FROM orders").unwrap();
 
-        let df = ctx.sql(&"SELECT ST_Point(lat, lng) FROM uk_cities").unwrap();
+        df.write("_orders_lag_lead.csv").unwrap();
 
-        df.write("_uk_cities_sql.csv").unwrap();
+        let expected = "1,,5.25\n\
+            2,,100\n\
+            3,10.5,7.5\n\
+            4,,4.25\n\
+            5,20,50\n\
+            6,5.25,0\n\
+            7,,0\n";
 
-        //TODO: check that generated file has expected contents
+        assert_eq!(expected, read_file("_orders_lag_lead.csv"));
     }
 
     #[test]
-    fn test_df_udf_udt() {
+    fn test_sql_window_over_aggregate() {
+
+        let mut ctx = create_context();
+
+        let df = ctx.sql(&"SELECT customer_id, SUM(amount), RANK() OVER (ORDER BY SUM(amount) DESC) \
+            FROM orders GROUP BY customer_id").unwrap();
+
+        df.write("_orders_window_over_aggregate.csv").unwrap();
+
+        assert_eq!("1,20,4\n2,27.5,3\n3,100,1\n11,50,2\n", read_file("_orders_window_over_aggregate.csv"));
+    }
+
+    #[test]
+    fn test_sql_invalid_window_functions() {
 
         let mut ctx = create_context();
 
-        ctx.define_function(&STPointFunc {});
+        assert!(ctx.sql(&"SELECT order_id FROM orders WHERE ROW_NUMBER() OVER (ORDER BY order_id) > 1").is_err());
+        assert!(ctx.sql(&"SELECT ROW_NUMBER(order_id) OVER (ORDER BY order_id) FROM orders").is_err());
+        assert!(ctx.sql(&"SELECT no_such_function(order_id) OVER () FROM orders").is_err());
+        assert!(ctx.sql(&"SELECT LAG(amount, order_id) OVER () FROM orders").is_err());
+        assert!(ctx.sql(&"SELECT SUM(amount) OVER (ROWS BETWEEN UNBOUNDED FOLLOWING AND CURRENT ROW) FROM orders").is_err());
+    }
+
+    #[test]
+    fn test_df_repartition() {
+
+        let ctx = create_context();
 
         let schema = Schema::new(vec![
             Field::new("city", DataType::String, false),
             Field::new("lat", DataType::Double, false),
             Field::new("lng", DataType::Double, false)]);
 
-        let df = ctx.load("test/data/uk_cities.csv", &schema).unwrap();
-
-        // create an expression for invoking a scalar function
-//        let func_expr = Expr::ScalarFunction {
-//            name: "ST_Point".to_string(),
-//            args: vec![df.col("lat").unwrap(), df.col("lng").unwrap()]
-//        };
-
-
-        // invoke custom code as a scalar UDF
-        let func_expr = ctx.udf("ST_Point",vec![
-            df.col("lat").unwrap(),
-            df.col("lng").unwrap()]
-        );
+        let df = ctx.load("test/data/uk_cities.csv", &schema, &CsvOptions { has_header: false, ..CsvOptions::default() }).unwrap();
+        let expr = df.col("lat").unwrap().gt(&Expr::Literal(Value::Double(53.0)));
+        let df = df.filter(expr).unwrap()
+            .select(vec![df.col("city").unwrap()]).unwrap();
 
-        let df2 = df.select(vec![func_expr]).unwrap();
+        df.write("_uk_cities_north.csv").unwrap();
+        df.repartition(4).unwrap().write("_uk_cities_north_partitioned.csv").unwrap();
 
-        df2.write("_uk_cities_df.csv").unwrap();
+        // partitions are merged in the order that they produce rows
+        let sorted_lines = |s: String| {
+            let mut lines: Vec<String> = s.lines().map(|l| l.to_string()).collect();
+            lines.sort();
+            lines
+        };
+        let expected = sorted_lines(read_file("_uk_cities_north.csv"));
+        assert!(!expected.is_empty());
+        assert_eq!(expected, sorted_lines(read_file("_uk_cities_north_partitioned.csv")));
 
-        //TODO: check that generated file has expected contents
+        assert!(df.repartition(0).is_err());
     }
 
     #[test]
-    fn test_chaining_functions() {
+    fn test_sql_partitioned_scan() {
 
         let mut ctx = create_context();
+        ctx.set_partition_count(3);
 
-        ctx.define_function(&STPointFunc {});
+        let df = ctx.sql(&"SELECT customer_id, COUNT(*), SUM(amount) FROM orders \
+            WHERE order_id > 1 GROUP BY customer_id ORDER BY customer_id").unwrap();
 
-        let df = ctx.sql(&"SELECT ST_AsText(ST_Point(lat, lng)) FROM uk_cities").unwrap();
+        df.write("_orders_partitioned.csv").unwrap();
 
-        df.write("_uk_cities_wkt.csv").unwrap();
+        assert_eq!("1,2,9.5\n2,2,27.5\n3,1,100\n11,1,50\n", read_file("_orders_partitioned.csv"));
 
-        //TODO: check that generated file has expected contents
+        // partitioning by expression writes shuffle files, which need a shuffle id
+        let plan = ExecutionPlan::Partition {
+            plan: df.plan(),
+            partition_count: 2,
+            partition_expr: Some(Expr::TupleValue(0)),
+            partition: None,
+            shuffle_id: None
+        };
+        assert!(ctx.execute(&plan).is_err());
+    }
+
+        fn read_file(filename: &str) -> String {
+        let mut contents = String::new();
+        File::open(filename).unwrap().read_to_string(&mut contents).unwrap();
+        contents
     }
 
     fn create_context() -> ExecutionContext {
@@ -520,11 +3587,27 @@ mod tests {
             Field::new("id", DataType::UnsignedLong, false),
             Field::new("name", DataType::String, false)]));
 
-        ctx.define_schema("uk_cities", &Schema::new(vec![
+        // uk_cities.csv does not have a header
+        ctx.define_csv_table("uk_cities", &Schema::new(vec![
             Field::new("city", DataType::String, false),
             Field::new("lat", DataType::Double, false),
-            Field::new("lng", DataType::Double, false)]));
+            Field::new("lng", DataType::Double, false)]), &CsvOptions { has_header: false, ..CsvOptions::default() });
+
+        ctx.define_schema("orders", &Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]));
+
+        ctx.define_schema("employees", &employees_schema(true));
 
         ctx
     }
+
+    fn employees_schema(manager_nullable: bool) -> Schema {
+        Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, true),
+            Field::new("manager_id", DataType::UnsignedLong, manager_nullable),
+            Field::new("salary", DataType::Double, true)])
+    }
 }
@@ -0,0 +1,438 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! EXPLAIN and EXPLAIN ANALYZE. A plan is explained as an indented tree of the logical plan
+//! followed by an indented tree of the relations that execute it. For EXPLAIN ANALYZE, each
+//! relation is wrapped in a relation that records the rows it returns and the time spent
+//! returning them while the query runs, and these are shown for each relation.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::batch::*;
+use super::exec::*;
+use super::rel::*;
+
+/// Name of the in-memory table that the lines of an explained plan are read from
+pub const EXPLAIN_TABLE: &str = "__explain";
+
+/// Metrics collected for a relation by EXPLAIN ANALYZE
+#[derive(Debug,Clone,PartialEq)]
+pub struct Metrics {
+    /// number of rows returned by the relation
+    pub rows: u64,
+    /// time spent creating the relation's iterator and returning its rows, including the time
+    /// spent in its inputs
+    pub elapsed: Duration,
+    /// number of bytes read from files by the relation
+    pub bytes_read: u64
+}
+
+/// A relation in a tree of relations, which are either row or batch relations
+#[derive(Clone,Copy)]
+pub enum RelationRef<'a> {
+    Rows(&'a dyn SimpleRelation),
+    Batches(&'a dyn BatchRelation)
+}
+
+impl<'a> RelationRef<'a> {
+
+    pub fn describe(&self) -> String {
+        match *self {
+            RelationRef::Rows(rel) => rel.describe(),
+            RelationRef::Batches(rel) => rel.describe()
+        }
+    }
+
+    pub fn inputs(&self) -> Vec<RelationRef<'a>> {
+        match *self {
+            RelationRef::Rows(rel) => rel.inputs(),
+            RelationRef::Batches(rel) => rel.inputs()
+        }
+    }
+
+    pub fn metrics(&self) -> Option<Metrics> {
+        match *self {
+            RelationRef::Rows(rel) => rel.metrics(),
+            RelationRef::Batches(rel) => rel.metrics()
+        }
+    }
+}
+
+/// Schema of the rows returned by EXPLAIN, which has one row per line of the explained plan
+pub fn explain_schema() -> Schema {
+    Schema::new(vec![Field::new("plan", DataType::String, false)])
+}
+
+/// Register the lines of an explained plan as `EXPLAIN_TABLE` and return a plan that reads them
+pub fn explain_results(ctx: &mut ExecutionContext, lines: Vec<String>) -> LogicalPlan {
+    let schema = explain_schema();
+    let rows = lines.into_iter().map(|line| Row::new(vec![Value::String(line)])).collect();
+    ctx.register_memory_table(EXPLAIN_TABLE, &schema, rows);
+    LogicalPlan::TableScan {
+        schema_name: "default".to_string(),
+        table_name: EXPLAIN_TABLE.to_string(),
+        schema,
+        projection: None
+    }
+}
+
+/// Format the lines of a logical plan with each input indented below the plan that reads it
+pub fn format_logical_plan(plan: &LogicalPlan, indent: usize, lines: &mut Vec<String>) {
+    let list = |expr: &[Expr], schema: &Schema| expr.iter()
+        .map(|e| format_expr(e, schema))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let (line, inputs) = match *plan {
+        LogicalPlan::Projection { ref expr, ref input, .. } =>
+            (format!("Projection: {}", list(expr, &input.schema())), vec![input]),
+        LogicalPlan::Selection { ref expr, ref input, .. } =>
+            (format!("Selection: {}", format_expr(expr, &input.schema())), vec![input]),
+        LogicalPlan::Aggregate { ref group_expr, ref aggr_expr, ref input, mode, .. } => {
+            let input_schema = input.schema();
+            (format!("Aggregate: mode={:?}, group_by=[{}], aggr=[{}]",
+                     mode, list(group_expr, &input_schema), list(aggr_expr, &input_schema)), vec![input])
+        },
+        LogicalPlan::Sort { ref expr, ref input, .. } =>
+            (format!("Sort: {}", list(expr, &input.schema())), vec![input]),
+        LogicalPlan::Window { ref window_expr, ref input, .. } =>
+            (format!("Window: {}", list(window_expr, &input.schema())), vec![input]),
+        LogicalPlan::Join { ref left, ref right, ref join_type, ref on, .. } =>
+            (format!("Join: type={:?}, on=[{}]", join_type, format_join_keys(on, &left.schema(), &right.schema())),
+             vec![left, right]),
+        LogicalPlan::Limit { limit, offset, ref input, .. } =>
+            (format!("Limit: limit={}, offset={}", limit, offset), vec![input]),
+        LogicalPlan::TableScan { ref table_name, ref schema, ref projection, .. } =>
+            (format!("TableScan: {}{}", table_name, format_projection(schema, projection)), vec![]),
+        LogicalPlan::CsvFile { ref filename, ref schema, ref projection, .. } =>
+            (format!("CsvFile: {}{}", filename, format_projection(schema, projection)), vec![]),
+        LogicalPlan::Exchange { ref shuffle_id, ref workers, partition_count, .. } =>
+            (format!("Exchange: shuffle_id={}, partitions={}, workers=[{}]",
+                     shuffle_id, partition_count, workers.join(", ")), vec![]),
+        LogicalPlan::EmptyRelation { .. } => ("EmptyRelation".to_string(), vec![])
+    };
+
+    lines.push(format!("{}{}", "  ".repeat(indent), line));
+    for input in inputs {
+        format_logical_plan(input, indent + 1, lines);
+    }
+}
+
+/// Format the lines of a relation with each input indented below the relation that reads it.
+/// When metrics were collected, the rows read from the inputs, the rows returned, the elapsed
+/// time and the bytes read are shown for each relation.
+pub fn format_relation(rel: RelationRef, indent: usize, lines: &mut Vec<String>) {
+    let inputs = rel.inputs();
+    let mut line = format!("{}{}", "  ".repeat(indent), rel.describe());
+    if let Some(metrics) = rel.metrics() {
+        let rows_in: u64 = inputs.iter().filter_map(|i| i.metrics()).map(|m| m.rows).sum();
+        line.push_str(&format!(" [rows_in={}, rows_out={}, elapsed={:.3}ms, bytes_read={}]",
+                               rows_in, metrics.rows, millis(metrics.elapsed), metrics.bytes_read));
+    }
+    lines.push(line);
+    for input in inputs {
+        format_relation(input, indent + 1, lines);
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + f64::from(d.subsec_nanos()) / 1_000_000.0
+}
+
+/// Format the key pairs of a join, where the keys are expressions against the left and right
+/// inputs
+pub fn format_join_keys(on: &[(Expr, Expr)], left_schema: &Schema, right_schema: &Schema) -> String {
+    on.iter()
+        .map(|(l, r)| format!("{} = {}", format_expr(l, left_schema), format_expr(r, right_schema)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Format the columns read by a scan, or nothing when all columns are read
+fn format_projection(schema: &Schema, projection: &Option<Vec<usize>>) -> String {
+    match *projection {
+        Some(ref projection) => format!(" projection=[{}]", projection.iter()
+            .map(|&i| format_expr(&Expr::TupleValue(i), schema))
+            .collect::<Vec<String>>()
+            .join(", ")),
+        None => String::new()
+    }
+}
+
+/// Format an expression as SQL, using the names of the columns in the schema of its input
+pub fn format_expr(expr: &Expr, schema: &Schema) -> String {
+    let list = |expr: &[Expr]| expr.iter()
+        .map(|e| format_expr(e, schema))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    match *expr {
+        Expr::TupleValue(i) => match schema.columns.get(i) {
+            Some(field) => field.name.clone(),
+            None => format!("#{}", i)
+        },
+        Expr::Literal(Value::String(ref s)) => format!("'{}'", s.replace('\'', "''")),
+        Expr::Literal(Value::Null) => "NULL".to_string(),
+        Expr::Literal(ref v) => v.to_string(),
+        Expr::BinaryExpr { ref left, ref op, ref right } => format!("{} {} {}",
+            format_operand(left, schema), format_operator(op), format_operand(right, schema)),
+        Expr::Not(ref e) => format!("NOT {}", format_operand(e, schema)),
+        Expr::IsNull(ref e) => format!("{} IS NULL", format_operand(e, schema)),
+        Expr::IsNotNull(ref e) => format!("{} IS NOT NULL", format_operand(e, schema)),
+        Expr::ScalarFunction { ref name, ref args } => format!("{}({})", name, list(args)),
+        Expr::Coalesce(ref args) => format!("COALESCE({})", list(args)),
+        Expr::AggregateFunction { ref name, ref args } if args.is_empty() => format!("{}(*)", name),
+        Expr::AggregateFunction { ref name, ref args } => format!("{}({})", name, list(args)),
+        Expr::Alias { ref expr, ref alias } => format!("{} AS {}", format_expr(expr, schema), alias),
+        Expr::Sort { ref expr, asc, nulls_first } => format!("{} {} NULLS {}", format_expr(expr, schema),
+            if asc { "ASC" } else { "DESC" }, if nulls_first { "FIRST" } else { "LAST" }),
+        Expr::WindowFunction { ref name, ref args, ref partition_by, ref order_by, ref frame } => {
+            let mut window = vec![];
+            if !partition_by.is_empty() {
+                window.push(format!("PARTITION BY {}", list(partition_by)));
+            }
+            if !order_by.is_empty() {
+                window.push(format!("ORDER BY {}", list(order_by)));
+            }
+            if let Some(ref frame) = *frame {
+                window.push(format!("ROWS BETWEEN {} AND {}",
+                                    format_frame_bound(&frame.start), format_frame_bound(&frame.end)));
+            }
+            format!("{}({}) OVER ({})", name, list(args), window.join(" "))
+        }
+    }
+}
+
+/// Format an operand of an operator, in parentheses when it is itself a binary expression
+fn format_operand(expr: &Expr, schema: &Schema) -> String {
+    match *expr {
+        Expr::BinaryExpr { .. } => format!("({})", format_expr(expr, schema)),
+        _ => format_expr(expr, schema)
+    }
+}
+
+fn format_operator(op: &Operator) -> &'static str {
+    match *op {
+        Operator::Eq => "=",
+        Operator::NotEq => "!=",
+        Operator::Lt => "<",
+        Operator::LtEq => "<=",
+        Operator::Gt => ">",
+        Operator::GtEq => ">=",
+        Operator::And => "AND",
+        Operator::Or => "OR",
+        Operator::Plus => "+",
+        Operator::Minus => "-",
+        Operator::Multiply => "*",
+        Operator::Divide => "/",
+        Operator::Modulus => "%"
+    }
+}
+
+fn format_frame_bound(bound: &WindowFrameBound) -> String {
+    match *bound {
+        WindowFrameBound::UnboundedPreceding => "UNBOUNDED PRECEDING".to_string(),
+        WindowFrameBound::Preceding(n) => format!("{} PRECEDING", n),
+        WindowFrameBound::CurrentRow => "CURRENT ROW".to_string(),
+        WindowFrameBound::Following(n) => format!("{} FOLLOWING", n),
+        WindowFrameBound::UnboundedFollowing => "UNBOUNDED FOLLOWING".to_string()
+    }
+}
+
+/// Rows returned and time spent by a relation that metrics are collected for. Relations are
+/// scanned through a shared reference, so the counters are atomic.
+#[derive(Default)]
+struct MetricsRecorder {
+    rows: AtomicU64,
+    elapsed_nanos: AtomicU64
+}
+
+impl MetricsRecorder {
+
+    fn record(&self, start: Instant, rows: usize) {
+        self.rows.fetch_add(rows as u64, Ordering::Relaxed);
+        self.elapsed_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn metrics(&self, bytes_read: u64) -> Metrics {
+        Metrics {
+            rows: self.rows.load(Ordering::Relaxed),
+            elapsed: Duration::from_nanos(self.elapsed_nanos.load(Ordering::Relaxed)),
+            bytes_read
+        }
+    }
+
+    /// Time the creation of an iterator and each call to its `next`. Relations such as sorts and
+    /// aggregates consume their input before returning the iterator, so both are timed.
+    fn scan<'a, T: 'a, F>(&'a self, count: fn(&T) -> usize, create: F) -> Box<dyn Iterator<Item=Result<T, ExecutionError>> + 'a>
+        where F: FnOnce() -> Box<dyn Iterator<Item=Result<T, ExecutionError>> + 'a> {
+        let start = Instant::now();
+        let input = create();
+        self.record(start, 0);
+        Box::new(TimedIterator { recorder: self, input, count })
+    }
+}
+
+/// Iterator that records the time spent in each call to `next` of the iterator it wraps
+struct TimedIterator<'a, T> {
+    recorder: &'a MetricsRecorder,
+    input: Box<dyn Iterator<Item=Result<T, ExecutionError>> + 'a>,
+    count: fn(&T) -> usize
+}
+
+impl<'a, T> Iterator for TimedIterator<'a, T> {
+    type Item = Result<T, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let item = self.input.next();
+        let rows = match item {
+            Some(Ok(ref t)) => (self.count)(t),
+            _ => 0
+        };
+        self.recorder.record(start, rows);
+        item
+    }
+}
+
+/// Collects metrics for a relation while it is scanned. The wrapper is transparent in the tree
+/// of relations, so it is explained as the relation it wraps.
+pub struct MetricsRelation {
+    input: Box<dyn SimpleRelation>,
+    recorder: MetricsRecorder
+}
+
+impl MetricsRelation {
+
+    pub fn new(input: Box<dyn SimpleRelation>) -> Self {
+        MetricsRelation { input, recorder: MetricsRecorder::default() }
+    }
+}
+
+impl SimpleRelation for MetricsRelation {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        self.recorder.scan(|_| 1, || self.input.scan(ctx))
+    }
+
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn describe(&self) -> String {
+        self.input.describe()
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        self.input.inputs()
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.input.bytes_read()
+    }
+
+    fn metrics(&self) -> Option<Metrics> {
+        Some(self.recorder.metrics(self.input.bytes_read()))
+    }
+}
+
+/// Collects metrics for a batch relation while it is scanned, counting the rows of each batch
+pub struct MetricsBatchRelation {
+    input: Box<dyn BatchRelation>,
+    recorder: MetricsRecorder
+}
+
+impl MetricsBatchRelation {
+
+    pub fn new(input: Box<dyn BatchRelation>) -> Self {
+        MetricsBatchRelation { input, recorder: MetricsRecorder::default() }
+    }
+}
+
+impl BatchRelation for MetricsBatchRelation {
+
+    fn scan_batches<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<RecordBatch, ExecutionError>> + 'a> {
+        self.recorder.scan(RecordBatch::num_rows, || self.input.scan_batches(ctx))
+    }
+
+    fn schema(&self) -> &Schema {
+        self.input.schema()
+    }
+
+    fn describe(&self) -> String {
+        self.input.describe()
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        self.input.inputs()
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.input.bytes_read()
+    }
+
+    fn metrics(&self) -> Option<Metrics> {
+        Some(self.recorder.metrics(self.input.bytes_read()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_context() -> ExecutionContext {
+        let mut ctx = ExecutionContext::new();
+        ctx.define_schema("orders", &Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]));
+        ctx
+    }
+
+    fn explain(ctx: &mut ExecutionContext, sql: &str) -> Vec<String> {
+        ctx.sql(sql).unwrap().collect().unwrap().iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut ctx = create_context();
+        let lines = explain(&mut ctx, "EXPLAIN SELECT order_id FROM orders WHERE amount > 15.0");
+        assert_eq!(vec![
+            "Logical plan:",
+            "  Projection: order_id",
+            "    Selection: amount > 15",
+            "      TableScan: orders projection=[order_id, amount]",
+            "Physical plan:",
+            "  BatchesToRows",
+            "    ProjectBatchRelation: order_id",
+            "      FilterBatchRelation: amount > 15",
+            "        CsvBatchRelation: columns=[order_id, amount]"], lines);
+    }
+
+    #[test]
+    fn test_explain_analyze() {
+        let mut ctx = create_context();
+        let lines = explain(&mut ctx, "EXPLAIN ANALYZE SELECT customer_id, COUNT(*) FROM orders WHERE amount > 15.0 GROUP BY customer_id");
+        let line = |name: &str| lines.iter().find(|l| l.trim_start().starts_with(name)).unwrap().clone();
+
+        assert!(line("AggregateRelation: mode=Complete, group_by=[customer_id], aggr=[COUNT(*)] [rows_in=3, rows_out=3, elapsed=").ends_with("bytes_read=0]"));
+        assert!(line("FilterBatchRelation: amount > 15 [rows_in=7, rows_out=3, elapsed=").ends_with("bytes_read=0]"));
+
+        // the whole file is read, including the header
+        let file_len = ::std::fs::metadata("test/data/orders.csv").unwrap().len();
+        assert!(line("CsvBatchRelation: columns=[customer_id, amount] [rows_in=0, rows_out=7, elapsed=")
+            .ends_with(&format!("bytes_read={}]", file_len)));
+    }
+}
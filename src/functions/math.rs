@@ -19,10 +19,7 @@ impl ScalarFunction for SqrtFunction {
     }
 
     fn args(&self) -> Vec<Field> {
-        vec![
-            Field::new("x", DataType::Double, false),
-            Field::new("y", DataType::Double, false)
-        ]
+        vec![Field::new("x", DataType::Double, false)]
     }
 
     fn return_type(&self) -> DataType {
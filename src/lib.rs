@@ -18,14 +18,31 @@ pub mod parser;
 pub mod rel;
 pub mod dataframe;
 pub mod sqltorel;
+pub mod optimizer;
+pub mod stats;
 pub mod functions;
 pub mod exec;
+pub mod explain;
+pub mod batch;
+pub mod shuffle;
+pub mod client;
+pub mod membership;
+pub mod server;
+pub mod worker;
+pub mod coordinator;
 
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 
 #[macro_use]
 extern crate serde_derive;
 
 extern crate csv;
+extern crate libloading;
+extern crate num_cpus;
+extern crate threadpool;
+extern crate futures;
+extern crate hyper;
+extern crate tokio_core;
 
@@ -0,0 +1,379 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cluster membership. Workers register themselves by sending periodic heartbeats to a
+//! membership backend, and the coordinator asks the backend for the live workers when it executes
+//! a query. Workers that have not sent a heartbeat within the timeout are considered dead and are
+//! removed.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::hyper::Method;
+use super::serde_json;
+
+use super::client::*;
+use super::exec::*;
+
+/// Default time after the last heartbeat that a worker is considered dead
+pub const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Default time between heartbeats
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Heartbeat sent by a worker
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct WorkerInfo {
+    /// address that the worker can be reached at as `host:port`
+    pub address: String,
+    /// number of plans that the worker is currently executing
+    pub running_queries: usize
+}
+
+/// A live worker, as listed by the `/cluster` endpoint of the coordinator
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct WorkerStatus {
+    pub address: String,
+    pub running_queries: usize,
+    /// time since the last heartbeat in milliseconds
+    pub last_heartbeat_ms: u64
+}
+
+/// Membership backend that tracks which workers are live
+pub trait Membership: Send + Sync {
+
+    /// Record a heartbeat from a worker, registering it if it is not already a member
+    fn heartbeat(&self, worker: &WorkerInfo) -> Result<(), ExecutionError>;
+
+    /// Get the live workers, ordered by address
+    fn live_workers(&self) -> Result<Vec<WorkerStatus>, ExecutionError>;
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + u64::from(d.subsec_millis())
+}
+
+/// A fixed list of workers that are always considered live, e.g. from the command line
+pub struct StaticMembership {
+    workers: Vec<String>
+}
+
+impl StaticMembership {
+
+    pub fn new(workers: Vec<String>) -> Self {
+        StaticMembership { workers }
+    }
+}
+
+impl Membership for StaticMembership {
+
+    fn heartbeat(&self, worker: &WorkerInfo) -> Result<(), ExecutionError> {
+        if self.workers.contains(&worker.address) {
+            Ok(())
+        } else {
+            Err(ExecutionError::Custom(format!("Worker {} is not a member of the cluster", worker.address)))
+        }
+    }
+
+    fn live_workers(&self) -> Result<Vec<WorkerStatus>, ExecutionError> {
+        let mut workers: Vec<WorkerStatus> = self.workers.iter()
+            .map(|w| WorkerStatus { address: w.clone(), running_queries: 0, last_heartbeat_ms: 0 })
+            .collect();
+        workers.sort_by(|a, b| a.address.cmp(&b.address));
+        Ok(workers)
+    }
+}
+
+/// Membership held in the memory of the coordinator, which workers send heartbeats to over HTTP
+pub struct InMemoryMembership {
+    timeout: Duration,
+    workers: Mutex<HashMap<String, (WorkerInfo, Instant)>>
+}
+
+impl InMemoryMembership {
+
+    pub fn new(timeout: Duration) -> Self {
+        InMemoryMembership { timeout, workers: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl Membership for InMemoryMembership {
+
+    fn heartbeat(&self, worker: &WorkerInfo) -> Result<(), ExecutionError> {
+        self.workers.lock().unwrap().insert(worker.address.clone(), (worker.clone(), Instant::now()));
+        Ok(())
+    }
+
+    fn live_workers(&self) -> Result<Vec<WorkerStatus>, ExecutionError> {
+        let mut workers = self.workers.lock().unwrap();
+        workers.retain(|_, &mut (_, last_heartbeat)| last_heartbeat.elapsed() <= self.timeout);
+
+        let mut live: Vec<WorkerStatus> = workers.values()
+            .map(|(info, last_heartbeat)| WorkerStatus {
+                address: info.address.clone(),
+                running_queries: info.running_queries,
+                last_heartbeat_ms: millis(last_heartbeat.elapsed())
+            })
+            .collect();
+        live.sort_by(|a, b| a.address.cmp(&b.address));
+        Ok(live)
+    }
+}
+
+/// A heartbeat as stored by `FileMembership`
+#[derive(Debug,Serialize,Deserialize)]
+struct HeartbeatFile {
+    worker: WorkerInfo,
+    /// time of the heartbeat in milliseconds since the Unix epoch
+    timestamp_ms: u64
+}
+
+/// Membership stored in a directory that is shared by the coordinator and the workers, with one
+/// file per worker holding its last heartbeat
+pub struct FileMembership {
+    dir: PathBuf,
+    timeout: Duration
+}
+
+impl FileMembership {
+
+    pub fn new(dir: &Path, timeout: Duration) -> Result<Self, ExecutionError> {
+        fs::create_dir_all(dir)?;
+        Ok(FileMembership { dir: dir.to_path_buf(), timeout })
+    }
+
+    fn now_ms() -> Result<u64, ExecutionError> {
+        SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(millis)
+            .map_err(|e| ExecutionError::Custom(format!("System time is before the Unix epoch: {}", e)))
+    }
+}
+
+/// Name the heartbeat file of a worker after its address. Bytes other than ASCII letters, digits,
+/// `.` and `-` are written as `_` followed by two hex digits, so that different addresses always
+/// have different names.
+fn heartbeat_file_name(address: &str) -> String {
+    address.bytes()
+        .map(|b| if b.is_ascii_alphanumeric() || b == b'.' || b == b'-' {
+            (b as char).to_string()
+        } else {
+            format!("_{:02X}", b)
+        })
+        .collect()
+}
+
+impl Membership for FileMembership {
+
+    fn heartbeat(&self, worker: &WorkerInfo) -> Result<(), ExecutionError> {
+        let heartbeat = HeartbeatFile { worker: worker.clone(), timestamp_ms: Self::now_ms()? };
+        let name = heartbeat_file_name(&worker.address);
+
+        // write to a temporary file first so that readers never see a partial heartbeat
+        let tmp_path = self.dir.join(format!("{}.tmp", name));
+        serde_json::to_writer(File::create(&tmp_path)?, &heartbeat)
+            .map_err(|e| ExecutionError::Custom(format!("Error writing heartbeat: {}", e)))?;
+        fs::rename(&tmp_path, self.dir.join(format!("{}.json", name)))?;
+        Ok(())
+    }
+
+    fn live_workers(&self) -> Result<Vec<WorkerStatus>, ExecutionError> {
+        let now = Self::now_ms()?;
+        let mut live = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            // a file that cannot be read is skipped rather than failing every query
+            let heartbeat: HeartbeatFile = match File::open(&path) {
+                Ok(file) => match serde_json::from_reader(file) {
+                    Ok(heartbeat) => heartbeat,
+                    Err(e) => {
+                        eprintln!("Skipping invalid heartbeat file {:?}: {}", path, e);
+                        continue;
+                    }
+                },
+                // removed by another reader
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    eprintln!("Skipping heartbeat file {:?} that cannot be read: {}", path, e);
+                    continue;
+                }
+            };
+
+            let age = now.saturating_sub(heartbeat.timestamp_ms);
+            if age > millis(self.timeout) {
+                let _ = fs::remove_file(&path);
+            } else {
+                live.push(WorkerStatus {
+                    address: heartbeat.worker.address,
+                    running_queries: heartbeat.worker.running_queries,
+                    last_heartbeat_ms: age
+                });
+            }
+        }
+        live.sort_by(|a, b| a.address.cmp(&b.address));
+        Ok(live)
+    }
+}
+
+/// Membership of a remote coordinator, which is accessed through its `/cluster` endpoint
+pub struct CoordinatorMembership {
+    coordinator: String
+}
+
+impl CoordinatorMembership {
+
+    /// Create a membership for the coordinator at the `host:port` address
+    pub fn new(coordinator: &str) -> Self {
+        CoordinatorMembership { coordinator: coordinator.to_string() }
+    }
+}
+
+impl Membership for CoordinatorMembership {
+
+    fn heartbeat(&self, worker: &WorkerInfo) -> Result<(), ExecutionError> {
+        let body = serde_json::to_string(worker)
+            .map_err(|e| ExecutionError::Custom(format!("Error serializing heartbeat: {}", e)))?;
+        send_request(WorkerRequest {
+            worker: self.coordinator.clone(),
+            method: Method::Post,
+            path: "/cluster/heartbeat".to_string(),
+            body: Some(body),
+            timeout: DEFAULT_HEARTBEAT_INTERVAL
+        })?;
+        Ok(())
+    }
+
+    fn live_workers(&self) -> Result<Vec<WorkerStatus>, ExecutionError> {
+        let text = send_request(WorkerRequest {
+            worker: self.coordinator.clone(),
+            method: Method::Get,
+            path: "/cluster".to_string(),
+            body: None,
+            timeout: DEFAULT_HEARTBEAT_INTERVAL
+        })?;
+        parse_cluster_json(&text)
+    }
+}
+
+/// Create the document returned by the `/cluster` endpoint
+pub fn cluster_json(workers: &[WorkerStatus]) -> String {
+    json!({ "workers": workers }).to_string()
+}
+
+/// Parse the document returned by the `/cluster` endpoint
+pub fn parse_cluster_json(text: &str) -> Result<Vec<WorkerStatus>, ExecutionError> {
+    #[derive(Deserialize)]
+    struct Cluster { workers: Vec<WorkerStatus> }
+
+    serde_json::from_str::<Cluster>(text)
+        .map(|c| c.workers)
+        .map_err(|e| ExecutionError::Custom(format!("Invalid cluster document: {}", e)))
+}
+
+/// Start a thread that sends a heartbeat for a worker every `interval`. The load reported is
+/// read from `running_queries` each time. Failed heartbeats are logged to stderr and retried at
+/// the next interval, so workers rejoin the cluster once the backend is available again.
+pub fn spawn_heartbeat(membership: Arc<dyn Membership>, address: String, running_queries: Arc<AtomicUsize>,
+                       interval: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        let info = WorkerInfo { address: address.clone(), running_queries: running_queries.load(Ordering::SeqCst) };
+        if let Err(e) = membership.heartbeat(&info) {
+            eprintln!("Heartbeat for worker {} failed: {:?}", address, e);
+        }
+        thread::sleep(interval);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn worker(address: &str, running_queries: usize) -> WorkerInfo {
+        WorkerInfo { address: address.to_string(), running_queries }
+    }
+
+    fn addresses(membership: &dyn Membership) -> Vec<String> {
+        membership.live_workers().unwrap().into_iter().map(|w| w.address).collect()
+    }
+
+    /// Workers become live with their first heartbeat and dead once they stop sending them
+    fn check_membership(membership: &dyn Membership) {
+        assert!(addresses(membership).is_empty());
+
+        membership.heartbeat(&worker("host2:8080", 1)).unwrap();
+        membership.heartbeat(&worker("host1:8080", 0)).unwrap();
+        assert_eq!(vec!["host1:8080", "host2:8080"], addresses(membership));
+
+        membership.heartbeat(&worker("host2:8080", 3)).unwrap();
+        let live = membership.live_workers().unwrap();
+        assert_eq!(2, live.len());
+        assert_eq!(3, live[1].running_queries);
+
+        // only host1 keeps sending heartbeats
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(100));
+            membership.heartbeat(&worker("host1:8080", 0)).unwrap();
+        }
+        assert_eq!(vec!["host1:8080"], addresses(membership));
+    }
+
+    #[test]
+    fn test_in_memory_membership() {
+        check_membership(&InMemoryMembership::new(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn test_file_membership() {
+        let dir = env::temp_dir().join(format!("datafusion_membership_test_{}", process::id()));
+        let membership = FileMembership::new(&dir, Duration::from_millis(250)).unwrap();
+        check_membership(&membership);
+
+        // addresses that only differ in characters that are escaped have separate files
+        membership.heartbeat(&worker("a:b", 0)).unwrap();
+        membership.heartbeat(&worker("a_b", 0)).unwrap();
+        assert_eq!(vec!["a:b", "a_b", "host1:8080"], addresses(&membership));
+        assert_eq!("host1_3A8080", heartbeat_file_name("host1:8080"));
+
+        // invalid heartbeat files are skipped
+        fs::write(dir.join("invalid.json"), "not a heartbeat").unwrap();
+        assert_eq!(vec!["a:b", "a_b", "host1:8080"], addresses(&membership));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_static_membership() {
+        let membership = StaticMembership::new(vec!["b:1".to_string(), "a:1".to_string()]);
+        assert_eq!(vec!["a:1", "b:1"], addresses(&membership));
+        assert!(membership.heartbeat(&worker("a:1", 0)).is_ok());
+        assert!(membership.heartbeat(&worker("c:1", 0)).is_err());
+    }
+
+    #[test]
+    fn test_cluster_json() {
+        let workers = vec![WorkerStatus { address: "host1:8080".to_string(), running_queries: 2, last_heartbeat_ms: 150 }];
+        let text = cluster_json(&workers);
+        assert_eq!("{\"workers\":[{\"address\":\"host1:8080\",\"last_heartbeat_ms\":150,\"running_queries\":2}]}", text);
+        assert_eq!(workers, parse_cluster_json(&text).unwrap());
+    }
+}
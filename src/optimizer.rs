@@ -0,0 +1,1149 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rule-based logical optimizer. Each rule rewrites a logical plan into an equivalent plan that
+//! is cheaper to execute, and the rules are applied in order to the plan created by the SQL
+//! planner or the DataFrame API before it is executed.
+
+use std::cmp;
+use std::sync::Arc;
+
+use super::exec::ExecutionContext;
+use super::rel::*;
+use super::stats::*;
+
+/// A rewrite of a logical plan that produces the same rows. The context that will execute the
+/// plan provides the registered functions.
+pub trait OptimizerRule: Send + Sync {
+
+    /// Name of the rule, used in error messages
+    fn name(&self) -> &str;
+
+    fn optimize(&self, plan: &LogicalPlan, ctx: &ExecutionContext) -> Result<LogicalPlan, String>;
+}
+
+/// The rules that are applied to every plan, in order
+pub fn default_rules() -> Vec<Arc<dyn OptimizerRule>> {
+    vec![
+        Arc::new(SimplifyExpressions {}),
+        Arc::new(PredicatePushDown {}),
+        Arc::new(ReorderJoins {}),
+        Arc::new(ChooseJoinBuildSide {}),
+        Arc::new(LimitPushDown {}),
+        Arc::new(ProjectionPushDown {})
+    ]
+}
+
+/// Folds expressions that only involve literals and deterministic functions into literals and
+/// removes boolean identities such as `true AND x`. Filters that are always true are removed and
+/// filters that are never true are replaced with an empty relation.
+pub struct SimplifyExpressions {}
+
+impl OptimizerRule for SimplifyExpressions {
+
+    fn name(&self) -> &str {
+        "simplify_expressions"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan, ctx: &ExecutionContext) -> Result<LogicalPlan, String> {
+        let simplify_all = |expr: &[Expr]| expr.iter().map(|e| simplify(e, ctx)).collect::<Vec<Expr>>();
+        Ok(match *plan {
+            LogicalPlan::Selection { ref expr, ref input, ref schema } => {
+                let input = self.optimize(input, ctx)?;
+                match simplify(expr, ctx) {
+                    Expr::Literal(Value::Boolean(true)) => input,
+                    Expr::Literal(Value::Boolean(false)) | Expr::Literal(Value::Null) =>
+                        LogicalPlan::EmptyRelation { schema: schema.clone() },
+                    expr => LogicalPlan::Selection { expr, input: Box::new(input), schema: schema.clone() }
+                }
+            },
+            LogicalPlan::Projection { ref expr, ref input, ref schema } => LogicalPlan::Projection {
+                expr: simplify_all(expr),
+                input: Box::new(self.optimize(input, ctx)?),
+                schema: schema.clone()
+            },
+            LogicalPlan::Aggregate { ref group_expr, ref aggr_expr, ref input, ref schema, mode } => LogicalPlan::Aggregate {
+                group_expr: simplify_all(group_expr),
+                aggr_expr: simplify_all(aggr_expr),
+                input: Box::new(self.optimize(input, ctx)?),
+                schema: schema.clone(),
+                mode
+            },
+            LogicalPlan::Sort { ref expr, ref input, ref schema } => LogicalPlan::Sort {
+                expr: simplify_all(expr),
+                input: Box::new(self.optimize(input, ctx)?),
+                schema: schema.clone()
+            },
+            LogicalPlan::Window { ref window_expr, ref input, ref schema } => LogicalPlan::Window {
+                window_expr: simplify_all(window_expr),
+                input: Box::new(self.optimize(input, ctx)?),
+                schema: schema.clone()
+            },
+            LogicalPlan::Join { ref left, ref right, ref join_type, ref on, ref schema } => LogicalPlan::Join {
+                left: Box::new(self.optimize(left, ctx)?),
+                right: Box::new(self.optimize(right, ctx)?),
+                join_type: join_type.clone(),
+                on: on.iter().map(|(l, r)| (simplify(l, ctx), simplify(r, ctx))).collect(),
+                schema: schema.clone()
+            },
+            _ => map_inputs(plan, |input| self.optimize(input, ctx))?
+        })
+    }
+}
+
+/// Simplify an expression, working up from the leaves
+fn simplify(e: &Expr, ctx: &ExecutionContext) -> Expr {
+    let is_bool = |e: &Expr, b: bool| *e == Expr::Literal(Value::Boolean(b));
+    let e = match *e {
+        Expr::TupleValue(_) | Expr::Literal(_) => return e.clone(),
+        Expr::BinaryExpr { ref left, ref op, ref right } => {
+            let left = simplify(left, ctx);
+            let right = simplify(right, ctx);
+            match *op {
+                Operator::And if is_bool(&left, true) => return right,
+                Operator::And if is_bool(&right, true) => return left,
+                Operator::And if is_bool(&left, false) || is_bool(&right, false) => return Expr::Literal(Value::Boolean(false)),
+                Operator::Or if is_bool(&left, false) => return right,
+                Operator::Or if is_bool(&right, false) => return left,
+                Operator::Or if is_bool(&left, true) || is_bool(&right, true) => return Expr::Literal(Value::Boolean(true)),
+                _ => Expr::BinaryExpr { left: Box::new(left), op: op.clone(), right: Box::new(right) }
+            }
+        },
+        Expr::Not(ref expr) => match simplify(expr, ctx) {
+            Expr::Not(expr) => return *expr,
+            expr => expr.not()
+        },
+        Expr::IsNull(ref expr) => simplify(expr, ctx).is_null(),
+        Expr::IsNotNull(ref expr) => simplify(expr, ctx).is_not_null(),
+        Expr::ScalarFunction { ref name, ref args } => Expr::ScalarFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| simplify(a, ctx)).collect()
+        },
+        Expr::Coalesce(ref args) => Expr::Coalesce(args.iter().map(|a| simplify(a, ctx)).collect()),
+        // aggregate and window functions are evaluated over many rows, so only their arguments
+        // are simplified
+        Expr::AggregateFunction { ref name, ref args } => return Expr::AggregateFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| simplify(a, ctx)).collect()
+        },
+        Expr::WindowFunction { ref name, ref args, ref partition_by, ref order_by, ref frame } => return Expr::WindowFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| simplify(a, ctx)).collect(),
+            partition_by: partition_by.iter().map(|a| simplify(a, ctx)).collect(),
+            order_by: order_by.iter().map(|a| simplify(a, ctx)).collect(),
+            frame: frame.clone()
+        },
+        Expr::Alias { ref expr, ref alias } => return simplify(expr, ctx).alias(alias),
+        Expr::Sort { ref expr, asc, nulls_first } => return simplify(expr, ctx).sort(asc, nulls_first)
+    };
+
+    // the operands are simplified, so the expression can be folded when they are all literals.
+    // Expressions that fail to evaluate are left for execution to report the error.
+    let operands: Vec<&Expr> = match e {
+        Expr::BinaryExpr { ref left, ref right, .. } => vec![left, right],
+        Expr::Not(ref expr) | Expr::IsNull(ref expr) | Expr::IsNotNull(ref expr) => vec![expr],
+        Expr::ScalarFunction { ref args, .. } | Expr::Coalesce(ref args) => args.iter().collect(),
+        _ => vec![]
+    };
+    if operands.iter().all(|o| matches!(**o, Expr::Literal(_))) && ctx.is_deterministic(&e) {
+        if let Ok(value) = ctx.evaluate(&Row::new(vec![]), &Schema::empty(), &e) {
+            return Expr::Literal(value);
+        }
+    }
+    e
+}
+
+/// Moves filters below projections, sorts and joins so that rows are discarded as early as
+/// possible. Conjuncts of a filter over a join that only refer to one side are evaluated against
+/// that side, unless it is the side of an outer join that is filled with nulls for unmatched
+/// rows. Only the side whose rows are preserved can be filtered before the join.
+pub struct PredicatePushDown {}
+
+impl OptimizerRule for PredicatePushDown {
+
+    fn name(&self) -> &str {
+        "predicate_push_down"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan, ctx: &ExecutionContext) -> Result<LogicalPlan, String> {
+        match *plan {
+            LogicalPlan::Selection { ref expr, ref input, .. } => Ok(push_predicate(expr.clone(), self.optimize(input, ctx)?, ctx)),
+            _ => map_inputs(plan, |input| self.optimize(input, ctx))
+        }
+    }
+}
+
+/// Create a plan equivalent to filtering `input` by `predicate`, with the filter pushed as far
+/// down as possible. Conjuncts that are not deterministic are not moved, and neither are filters
+/// that would have to be moved past them or that would have to evaluate expressions of a
+/// projection that are not deterministic.
+fn push_predicate(predicate: Expr, input: LogicalPlan, ctx: &ExecutionContext) -> LogicalPlan {
+    let (pushed, fixed): (Vec<Expr>, Vec<Expr>) = split_conjunction(&predicate).into_iter()
+        .partition(|c| ctx.is_deterministic(c));
+    let plan = match conjunction(pushed) {
+        Some(predicate) => push_deterministic_predicate(predicate, input, ctx),
+        None => input
+    };
+    filter(plan, fixed)
+}
+
+fn push_deterministic_predicate(predicate: Expr, input: LogicalPlan, ctx: &ExecutionContext) -> LogicalPlan {
+    match input {
+        LogicalPlan::Selection { expr, input, .. } if ctx.is_deterministic(&expr) =>
+            push_predicate(expr.and(&predicate), *input, ctx),
+        LogicalPlan::Projection { expr, input, schema } => {
+            let (pushed, fixed): (Vec<Expr>, Vec<Expr>) = split_conjunction(&predicate).into_iter()
+                .partition(|c| expr_columns(c).iter().all(|&i| ctx.is_deterministic(&expr[i])));
+            let pushed = pushed.iter().map(|c| rewrite_columns(c, &|i| expr[i].strip_alias().clone())).collect();
+            let input = match conjunction(pushed) {
+                Some(predicate) => push_predicate(predicate, *input, ctx),
+                None => *input
+            };
+            filter(LogicalPlan::Projection { expr, input: Box::new(input), schema }, fixed)
+        },
+        LogicalPlan::Sort { expr, input, schema } =>
+            LogicalPlan::Sort { expr, input: Box::new(push_predicate(predicate, *input, ctx)), schema },
+        LogicalPlan::Join { left, right, join_type, on, schema } => {
+            let left_len = left.schema().columns.len();
+            let (push_left, push_right) = match join_type {
+                JoinType::Inner | JoinType::Cross => (true, true),
+                JoinType::Left => (true, false),
+                JoinType::Right => (false, true),
+                JoinType::Full => (false, false)
+            };
+
+            let mut left_predicates = vec![];
+            let mut right_predicates = vec![];
+            let mut remaining = vec![];
+            for conjunct in split_conjunction(&predicate) {
+                let columns = expr_columns(&conjunct);
+                if push_left && columns.iter().all(|&i| i < left_len) {
+                    left_predicates.push(conjunct);
+                } else if push_right && columns.iter().all(|&i| i >= left_len) {
+                    right_predicates.push(rewrite_columns(&conjunct, &|i| Expr::TupleValue(i - left_len)));
+                } else {
+                    remaining.push(conjunct);
+                }
+            }
+
+            let push = |input: LogicalPlan, predicates: Vec<Expr>| match conjunction(predicates) {
+                Some(predicate) => push_predicate(predicate, input, ctx),
+                None => input
+            };
+            let join = LogicalPlan::Join {
+                left: Box::new(push(*left, left_predicates)),
+                right: Box::new(push(*right, right_predicates)),
+                join_type,
+                on,
+                schema
+            };
+            filter(join, remaining)
+        },
+        input => filter(input, vec![predicate])
+    }
+}
+
+/// Filter a plan by the conjunction of predicates, if there are any
+fn filter(input: LogicalPlan, predicates: Vec<Expr>) -> LogicalPlan {
+    match conjunction(predicates) {
+        Some(predicate) => LogicalPlan::Selection { expr: predicate, schema: input.schema(), input: Box::new(input) },
+        None => input
+    }
+}
+
+/// Reorders trees of inner joins using the estimated number of rows of each input, so that the
+/// intermediate results are kept small. Starting with the smallest input, the input that produces
+/// the fewest rows when joined with the inputs joined so far is joined next, preferring inputs
+/// that have a join condition with them over cross joins. Joins are left unchanged when the size
+/// of any input cannot be estimated.
+pub struct ReorderJoins {}
+
+impl OptimizerRule for ReorderJoins {
+
+    fn name(&self) -> &str {
+        "reorder_joins"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan, ctx: &ExecutionContext) -> Result<LogicalPlan, String> {
+        match *plan {
+            LogicalPlan::Join { ref join_type, .. } if is_inner(join_type) => {
+                let mut inputs = vec![];
+                let mut conditions = vec![];
+                flatten_joins(plan, 0, &mut inputs, &mut conditions);
+                let inputs = inputs.iter().map(|input| self.optimize(input, ctx)).collect::<Result<Vec<_>, _>>()?;
+                Ok(reorder_joins(&inputs, &conditions, &plan.schema(), ctx)
+                    .unwrap_or_else(|| rebuild_joins(plan, &mut inputs.into_iter())))
+            },
+            _ => map_inputs(plan, |input| self.optimize(input, ctx))
+        }
+    }
+}
+
+fn is_inner(join_type: &JoinType) -> bool {
+    *join_type == JoinType::Inner || *join_type == JoinType::Cross
+}
+
+/// Collect the inputs of a tree of inner joins, from left to right, and the join conditions with
+/// column indices relative to the concatenation of the inputs
+fn flatten_joins(plan: &LogicalPlan, offset: usize, inputs: &mut Vec<LogicalPlan>, conditions: &mut Vec<(Expr, Expr)>) {
+    match *plan {
+        LogicalPlan::Join { ref left, ref right, ref join_type, ref on, .. } if is_inner(join_type) => {
+            let right_offset = offset + left.schema().columns.len();
+            flatten_joins(left, offset, inputs, conditions);
+            flatten_joins(right, right_offset, inputs, conditions);
+            conditions.extend(on.iter().map(|(l, r)| (
+                rewrite_columns(l, &|i| Expr::TupleValue(offset + i)),
+                rewrite_columns(r, &|i| Expr::TupleValue(right_offset + i)))));
+        },
+        _ => inputs.push(plan.clone())
+    }
+}
+
+/// Replace the inputs of a tree of inner joins, in the order they were collected by
+/// `flatten_joins`
+fn rebuild_joins<I: Iterator<Item=LogicalPlan>>(plan: &LogicalPlan, inputs: &mut I) -> LogicalPlan {
+    match *plan {
+        LogicalPlan::Join { ref left, ref right, ref join_type, ref on, ref schema } if is_inner(join_type) => {
+            let left = rebuild_joins(left, inputs);
+            let right = rebuild_joins(right, inputs);
+            LogicalPlan::Join {
+                left: Box::new(left),
+                right: Box::new(right),
+                join_type: join_type.clone(),
+                on: on.clone(),
+                schema: schema.clone()
+            }
+        },
+        _ => inputs.next().expect("join input")
+    }
+}
+
+/// Choose the order in which to join the inputs, returning `None` when the order should not
+/// change. The new joins are wrapped in a projection that restores the original column order.
+fn reorder_joins(inputs: &[LogicalPlan], conditions: &[(Expr, Expr)], schema: &Schema, ctx: &ExecutionContext) -> Option<LogicalPlan> {
+    if inputs.len() < 3 {
+        return None;
+    }
+    let rows = inputs.iter().map(|input| estimate_rows(input, ctx)).collect::<Option<Vec<f64>>>()?;
+
+    // the input that each column belongs to, and the offset of each input
+    let mut offsets = vec![];
+    let mut input_of_column = vec![];
+    for (i, input) in inputs.iter().enumerate() {
+        offsets.push(input_of_column.len());
+        input_of_column.extend(input.schema().columns.iter().map(|_| i));
+    }
+
+    // each side of a condition must refer to a single input
+    let input_of = |e: &Expr| -> Option<usize> {
+        let columns = expr_columns(e);
+        let input = input_of_column[*columns.first()?];
+        if columns.iter().all(|&c| input_of_column[c] == input) { Some(input) } else { None }
+    };
+    let condition_inputs = conditions.iter()
+        .map(|(l, r)| match (input_of(l), input_of(r)) {
+            (Some(l), Some(r)) if l != r => Some((l, r)),
+            _ => None
+        })
+        .collect::<Option<Vec<(usize, usize)>>>()?;
+
+    // the number of distinct values of a side of a condition
+    let distinct = |e: &Expr, input: usize| match *e {
+        Expr::TupleValue(c) => column_statistics(&inputs[input], c - offsets[input], ctx)
+            .map_or(rows[input], |(_, column)| (column.distinct_count.max(1) as f64).min(rows[input])),
+        _ => rows[input]
+    };
+
+    let first = (0..inputs.len()).min_by(|&a, &b| rows[a].partial_cmp(&rows[b]).unwrap_or(cmp::Ordering::Equal))?;
+    let mut order = vec![first];
+    let mut joined_rows = rows[first];
+    while order.len() < inputs.len() {
+        let (next, next_rows, _) = (0..inputs.len())
+            .filter(|i| !order.contains(i))
+            .map(|i| {
+                let key_distinct: Vec<(f64, f64)> = conditions.iter().zip(condition_inputs.iter())
+                    .filter(|&(_, &(l, r))| (l == i && order.contains(&r)) || (r == i && order.contains(&l)))
+                    .map(|((l, r), &(l_input, r_input))| (distinct(l, l_input), distinct(r, r_input)))
+                    .collect();
+                (i, estimate_join_rows(joined_rows, rows[i], &key_distinct), key_distinct.is_empty())
+            })
+            .min_by(|a, b| (a.2, a.1).partial_cmp(&(b.2, b.1)).unwrap_or(cmp::Ordering::Equal))?;
+        order.push(next);
+        joined_rows = next_rows;
+    }
+    if order.iter().enumerate().all(|(i, &input)| i == input) {
+        return None;
+    }
+
+    // join the inputs in order, keeping track of where the columns of each input are
+    let mut new_offsets = vec![0; inputs.len()];
+    let mut plan = inputs[first].clone();
+    let mut applied = vec![false; conditions.len()];
+    for &input in &order[1..] {
+        let left_len = plan.schema().columns.len();
+        let mut on = vec![];
+        for (c, ((l, r), &(l_input, r_input))) in conditions.iter().zip(condition_inputs.iter()).enumerate() {
+            if applied[c] || (l_input != input && r_input != input) ||
+                !order.iter().take_while(|&&i| i != input).any(|&i| i == l_input || i == r_input) {
+                continue;
+            }
+            applied[c] = true;
+            let (joined, (new, new_input)) = if r_input == input { ((l, l_input), (r, r_input)) } else { ((r, r_input), (l, l_input)) };
+            on.push((
+                rewrite_columns(joined.0, &|i| Expr::TupleValue(new_offsets[joined.1] + i - offsets[joined.1])),
+                rewrite_columns(new, &|i| Expr::TupleValue(i - offsets[new_input]))));
+        }
+        let join_type = if on.is_empty() { JoinType::Cross } else { JoinType::Inner };
+        new_offsets[input] = left_len;
+        plan = LogicalPlan::Join {
+            schema: Schema::join(&plan.schema(), &inputs[input].schema(), &join_type),
+            left: Box::new(plan),
+            right: Box::new(inputs[input].clone()),
+            join_type,
+            on
+        };
+    }
+
+    let expr = (0..input_of_column.len())
+        .map(|c| {
+            let input = input_of_column[c];
+            Expr::TupleValue(new_offsets[input] + c - offsets[input])
+        })
+        .collect();
+    Some(LogicalPlan::Projection { expr, input: Box::new(plan), schema: schema.clone() })
+}
+
+/// Puts the input of each join with fewer estimated rows on the right, which is the side that
+/// the hash join loads into memory. The columns are restored to their original order with a
+/// projection.
+pub struct ChooseJoinBuildSide {}
+
+impl OptimizerRule for ChooseJoinBuildSide {
+
+    fn name(&self) -> &str {
+        "choose_join_build_side"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan, ctx: &ExecutionContext) -> Result<LogicalPlan, String> {
+        let plan = map_inputs(plan, |input| self.optimize(input, ctx))?;
+        match plan {
+            LogicalPlan::Join { left, right, join_type, on, schema } => {
+                match (estimate_rows(&left, ctx), estimate_rows(&right, ctx)) {
+                    (Some(left_rows), Some(right_rows)) if left_rows < right_rows => {
+                        let (left_len, right_len) = (left.schema().columns.len(), right.schema().columns.len());
+                        let join_type = match join_type {
+                            JoinType::Left => JoinType::Right,
+                            JoinType::Right => JoinType::Left,
+                            join_type => join_type
+                        };
+                        let join = LogicalPlan::Join {
+                            schema: Schema::join(&right.schema(), &left.schema(), &join_type),
+                            left: right,
+                            right: left,
+                            join_type,
+                            on: on.into_iter().map(|(l, r)| (r, l)).collect()
+                        };
+                        let expr = (0..left_len).map(|i| Expr::TupleValue(right_len + i))
+                            .chain((0..right_len).map(Expr::TupleValue))
+                            .collect();
+                        Ok(LogicalPlan::Projection { expr, input: Box::new(join), schema })
+                    },
+                    _ => Ok(LogicalPlan::Join { left, right, join_type, on, schema })
+                }
+            },
+            plan => Ok(plan)
+        }
+    }
+}
+
+/// Moves limits below projections and into the preserved side of outer joins, and merges
+/// adjacent limits
+pub struct LimitPushDown {}
+
+impl OptimizerRule for LimitPushDown {
+
+    fn name(&self) -> &str {
+        "limit_push_down"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan, _ctx: &ExecutionContext) -> Result<LogicalPlan, String> {
+        push_limits(plan)
+    }
+}
+
+fn push_limits(plan: &LogicalPlan) -> Result<LogicalPlan, String> {
+    match *plan {
+        LogicalPlan::Limit { limit, offset, ref input, .. } => Ok(push_limit(limit, offset, push_limits(input)?)),
+        _ => map_inputs(plan, push_limits)
+    }
+}
+
+/// Create a plan equivalent to applying a limit and offset to `input`, with the limit pushed as
+/// far down as possible
+fn push_limit(limit: usize, offset: usize, input: LogicalPlan) -> LogicalPlan {
+    match input {
+        LogicalPlan::Projection { expr, input, schema } =>
+            LogicalPlan::Projection { expr, input: Box::new(push_limit(limit, offset, *input)), schema },
+        LogicalPlan::Limit { limit: inner_limit, offset: inner_offset, input, .. } => push_limit(
+            cmp::min(limit, inner_limit.saturating_sub(offset)),
+            inner_offset.saturating_add(offset),
+            *input),
+        // every row of the preserved side of an outer join produces at least one row, so the
+        // first rows of the join can be produced from that many rows of that side
+        LogicalPlan::Join { left, right, join_type, on, schema } => {
+            let rows = limit.saturating_add(offset);
+            let (left, right) = match join_type {
+                JoinType::Left => (Box::new(push_limit(rows, 0, *left)), right),
+                JoinType::Right => (left, Box::new(push_limit(rows, 0, *right))),
+                _ => (left, right)
+            };
+            let join = LogicalPlan::Join { left, right, join_type, on, schema };
+            LogicalPlan::Limit { limit, offset, schema: join.schema(), input: Box::new(join) }
+        },
+        input => LogicalPlan::Limit { limit, offset, schema: input.schema(), input: Box::new(input) }
+    }
+}
+
+/// Removes columns that are not used by the rest of the plan. Scans of tables and CSV files only
+/// read the columns that are needed, so the other fields are never parsed.
+pub struct ProjectionPushDown {}
+
+impl OptimizerRule for ProjectionPushDown {
+
+    fn name(&self) -> &str {
+        "projection_push_down"
+    }
+
+    fn optimize(&self, plan: &LogicalPlan, _ctx: &ExecutionContext) -> Result<LogicalPlan, String> {
+        let required: Vec<usize> = (0..plan.schema().columns.len()).collect();
+        Ok(prune_columns(plan, &required).0)
+    }
+}
+
+/// Rewrite a plan so that it produces at least the `required` columns, which are sorted indices
+/// into the schema of the plan. Returns the new plan along with the new index of each column of
+/// the original schema that is still produced.
+fn prune_columns(plan: &LogicalPlan, required: &[usize]) -> (LogicalPlan, Vec<Option<usize>>) {
+    let column_count = plan.schema().columns.len();
+    match *plan {
+        LogicalPlan::Projection { ref expr, ref input, ref schema } => {
+            // a projection must produce at least one column so that rows can still be counted
+            let keep = if required.is_empty() && !expr.is_empty() { vec![0] } else { required.to_vec() };
+            let kept_expr: Vec<&Expr> = keep.iter().map(|&i| &expr[i]).collect();
+
+            let mut input_required = vec![];
+            kept_expr.iter().for_each(|e| find_columns(e, &mut input_required));
+            let (input, input_mapping) = prune_columns(input, &sorted(input_required));
+
+            let plan = LogicalPlan::Projection {
+                expr: kept_expr.iter().map(|e| reindex(e, &input_mapping)).collect(),
+                input: Box::new(input),
+                schema: schema.project(&keep)
+            };
+            (plan, mapping(column_count, &keep))
+        },
+        LogicalPlan::Selection { ref expr, ref input, .. } => {
+            let mut input_required = required.to_vec();
+            find_columns(expr, &mut input_required);
+            let (input, input_mapping) = prune_columns(input, &sorted(input_required));
+            let plan = LogicalPlan::Selection { expr: reindex(expr, &input_mapping), schema: input.schema(), input: Box::new(input) };
+            (plan, input_mapping)
+        },
+        LogicalPlan::Sort { ref expr, ref input, .. } => {
+            let mut input_required = required.to_vec();
+            expr.iter().for_each(|e| find_columns(e, &mut input_required));
+            let (input, input_mapping) = prune_columns(input, &sorted(input_required));
+            let plan = LogicalPlan::Sort {
+                expr: expr.iter().map(|e| reindex(e, &input_mapping)).collect(),
+                schema: input.schema(),
+                input: Box::new(input)
+            };
+            (plan, input_mapping)
+        },
+        LogicalPlan::Limit { limit, offset, ref input, .. } => {
+            let (input, input_mapping) = prune_columns(input, required);
+            (LogicalPlan::Limit { limit, offset, schema: input.schema(), input: Box::new(input) }, input_mapping)
+        },
+        // the input of partial and final aggregates has a fixed layout, so only complete
+        // aggregates can prune their input
+        LogicalPlan::Aggregate { ref group_expr, ref aggr_expr, ref input, ref schema, mode: AggregateMode::Complete } => {
+            let mut input_required = vec![];
+            group_expr.iter().chain(aggr_expr.iter()).for_each(|e| find_columns(e, &mut input_required));
+            let (input, input_mapping) = prune_columns(input, &sorted(input_required));
+            let plan = LogicalPlan::Aggregate {
+                group_expr: group_expr.iter().map(|e| reindex(e, &input_mapping)).collect(),
+                aggr_expr: aggr_expr.iter().map(|e| reindex(e, &input_mapping)).collect(),
+                input: Box::new(input),
+                schema: schema.clone(),
+                mode: AggregateMode::Complete
+            };
+            (plan, (0..column_count).map(Some).collect())
+        },
+        LogicalPlan::Window { ref window_expr, ref input, ref schema } => {
+            let input_len = input.schema().columns.len();
+            let mut input_required: Vec<usize> = required.iter().cloned().filter(|&i| i < input_len).collect();
+            window_expr.iter().for_each(|e| find_columns(e, &mut input_required));
+            let (input, input_mapping) = prune_columns(input, &sorted(input_required));
+
+            let input_schema = input.schema();
+            let new_input_len = input_schema.columns.len();
+            let plan = LogicalPlan::Window {
+                window_expr: window_expr.iter().map(|e| reindex(e, &input_mapping)).collect(),
+                input: Box::new(input),
+                schema: Schema::new(input_schema.columns.into_iter().chain(schema.columns[input_len..].iter().cloned()).collect())
+            };
+            let window_mapping = (0..window_expr.len()).map(|i| Some(new_input_len + i));
+            (plan, input_mapping.into_iter().chain(window_mapping).collect())
+        },
+        LogicalPlan::Join { ref left, ref right, ref join_type, ref on, .. } => {
+            let left_len = left.schema().columns.len();
+            let mut left_required: Vec<usize> = required.iter().cloned().filter(|&i| i < left_len).collect();
+            let mut right_required: Vec<usize> = required.iter().filter(|&&i| i >= left_len).map(|&i| i - left_len).collect();
+            for (l, r) in on {
+                find_columns(l, &mut left_required);
+                find_columns(r, &mut right_required);
+            }
+            let (left, left_mapping) = prune_columns(left, &sorted(left_required));
+            let (right, right_mapping) = prune_columns(right, &sorted(right_required));
+
+            let new_left_len = left.schema().columns.len();
+            let plan = LogicalPlan::Join {
+                on: on.iter().map(|(l, r)| (reindex(l, &left_mapping), reindex(r, &right_mapping))).collect(),
+                schema: Schema::join(&left.schema(), &right.schema(), join_type),
+                left: Box::new(left),
+                right: Box::new(right),
+                join_type: join_type.clone()
+            };
+            let right_mapping = right_mapping.into_iter().map(|i| i.map(|i| new_left_len + i));
+            (plan, left_mapping.into_iter().chain(right_mapping).collect())
+        },
+        LogicalPlan::TableScan { ref projection, .. } | LogicalPlan::CsvFile { ref projection, .. } if required.len() < column_count => {
+            // a scan must read at least one column so that rows can still be counted
+            let keep = if required.is_empty() { vec![0] } else { required.to_vec() };
+            let new_projection = keep.iter()
+                .map(|&i| match *projection {
+                    Some(ref projection) => projection[i],
+                    None => i
+                })
+                .collect();
+            let plan = match *plan {
+                LogicalPlan::TableScan { ref schema_name, ref table_name, ref schema, .. } => LogicalPlan::TableScan {
+                    schema_name: schema_name.clone(),
+                    table_name: table_name.clone(),
+                    schema: schema.clone(),
+                    projection: Some(new_projection)
+                },
+                LogicalPlan::CsvFile { ref filename, ref schema, ref options, .. } => LogicalPlan::CsvFile {
+                    filename: filename.clone(),
+                    schema: schema.clone(),
+                    projection: Some(new_projection),
+                    options: options.clone()
+                },
+                _ => unreachable!()
+            };
+            (plan, mapping(column_count, &keep))
+        },
+        _ => (plan.clone(), (0..column_count).map(Some).collect())
+    }
+}
+
+/// Create the mapping from the columns of a schema to their index after keeping only the
+/// columns at `keep`
+fn mapping(column_count: usize, keep: &[usize]) -> Vec<Option<usize>> {
+    let mut mapping = vec![None; column_count];
+    for (new_index, &i) in keep.iter().enumerate() {
+        mapping[i] = Some(new_index);
+    }
+    mapping
+}
+
+/// Rewrite the column references of an expression using a mapping created by `prune_columns`
+fn reindex(e: &Expr, mapping: &[Option<usize>]) -> Expr {
+    rewrite_columns(e, &|i| Expr::TupleValue(mapping[i].expect("referenced column was pruned")))
+}
+
+fn sorted(mut indices: Vec<usize>) -> Vec<usize> {
+    indices.sort();
+    indices.dedup();
+    indices
+}
+
+/// Copy a plan, replacing each of its inputs with the result of `f`
+pub fn map_inputs<F>(plan: &LogicalPlan, f: F) -> Result<LogicalPlan, String>
+    where F: Fn(&LogicalPlan) -> Result<LogicalPlan, String> {
+
+    Ok(match *plan {
+        LogicalPlan::Projection { ref expr, ref input, ref schema } =>
+            LogicalPlan::Projection { expr: expr.clone(), input: Box::new(f(input)?), schema: schema.clone() },
+        LogicalPlan::Selection { ref expr, ref input, ref schema } =>
+            LogicalPlan::Selection { expr: expr.clone(), input: Box::new(f(input)?), schema: schema.clone() },
+        LogicalPlan::Aggregate { ref group_expr, ref aggr_expr, ref input, ref schema, mode } => LogicalPlan::Aggregate {
+            group_expr: group_expr.clone(),
+            aggr_expr: aggr_expr.clone(),
+            input: Box::new(f(input)?),
+            schema: schema.clone(),
+            mode
+        },
+        LogicalPlan::Sort { ref expr, ref input, ref schema } =>
+            LogicalPlan::Sort { expr: expr.clone(), input: Box::new(f(input)?), schema: schema.clone() },
+        LogicalPlan::Window { ref window_expr, ref input, ref schema } =>
+            LogicalPlan::Window { window_expr: window_expr.clone(), input: Box::new(f(input)?), schema: schema.clone() },
+        LogicalPlan::Join { ref left, ref right, ref join_type, ref on, ref schema } => LogicalPlan::Join {
+            left: Box::new(f(left)?),
+            right: Box::new(f(right)?),
+            join_type: join_type.clone(),
+            on: on.clone(),
+            schema: schema.clone()
+        },
+        LogicalPlan::Limit { limit, offset, ref input, ref schema } =>
+            LogicalPlan::Limit { limit, offset, input: Box::new(f(input)?), schema: schema.clone() },
+        LogicalPlan::TableScan { .. } | LogicalPlan::CsvFile { .. } |
+        LogicalPlan::Exchange { .. } | LogicalPlan::EmptyRelation { .. } => plan.clone()
+    })
+}
+
+/// Split a predicate into the expressions that are combined with AND
+fn split_conjunction(e: &Expr) -> Vec<Expr> {
+    match *e {
+        Expr::BinaryExpr { ref left, op: Operator::And, ref right } => {
+            let mut conjuncts = split_conjunction(left);
+            conjuncts.extend(split_conjunction(right));
+            conjuncts
+        },
+        _ => vec![e.clone()]
+    }
+}
+
+/// Combine predicates with AND, returning `None` when there are no predicates
+fn conjunction(predicates: Vec<Expr>) -> Option<Expr> {
+    predicates.into_iter().fold(None, |acc, p| match acc {
+        Some(acc) => Some(acc.and(&p)),
+        None => Some(p)
+    })
+}
+
+/// Get the sorted indices of the columns referenced by an expression
+fn expr_columns(e: &Expr) -> Vec<usize> {
+    let mut columns = vec![];
+    find_columns(e, &mut columns);
+    sorted(columns)
+}
+
+/// Collect the indices of all columns referenced by an expression
+fn find_columns(e: &Expr, columns: &mut Vec<usize>) {
+    match *e {
+        Expr::TupleValue(i) => columns.push(i),
+        Expr::Literal(_) => {},
+        Expr::BinaryExpr { ref left, ref right, .. } => {
+            find_columns(left, columns);
+            find_columns(right, columns);
+        },
+        Expr::ScalarFunction { ref args, .. } | Expr::AggregateFunction { ref args, .. } | Expr::Coalesce(ref args) =>
+            args.iter().for_each(|a| find_columns(a, columns)),
+        Expr::Not(ref expr) | Expr::IsNull(ref expr) | Expr::IsNotNull(ref expr) |
+        Expr::Alias { ref expr, .. } | Expr::Sort { ref expr, .. } => find_columns(expr, columns),
+        Expr::WindowFunction { ref args, ref partition_by, ref order_by, .. } =>
+            args.iter().chain(partition_by.iter()).chain(order_by.iter())
+                .for_each(|a| find_columns(a, columns))
+    }
+}
+
+/// Replace each column reference in an expression with the result of `f` for its index
+fn rewrite_columns<F: Fn(usize) -> Expr>(e: &Expr, f: &F) -> Expr {
+    match *e {
+        Expr::TupleValue(i) => f(i),
+        Expr::Literal(_) => e.clone(),
+        Expr::BinaryExpr { ref left, ref op, ref right } => Expr::BinaryExpr {
+            left: Box::new(rewrite_columns(left, f)),
+            op: op.clone(),
+            right: Box::new(rewrite_columns(right, f))
+        },
+        Expr::ScalarFunction { ref name, ref args } => Expr::ScalarFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| rewrite_columns(a, f)).collect()
+        },
+        Expr::AggregateFunction { ref name, ref args } => Expr::AggregateFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| rewrite_columns(a, f)).collect()
+        },
+        Expr::Coalesce(ref args) => Expr::Coalesce(args.iter().map(|a| rewrite_columns(a, f)).collect()),
+        Expr::Not(ref expr) => rewrite_columns(expr, f).not(),
+        Expr::IsNull(ref expr) => rewrite_columns(expr, f).is_null(),
+        Expr::IsNotNull(ref expr) => rewrite_columns(expr, f).is_not_null(),
+        Expr::Alias { ref expr, ref alias } => rewrite_columns(expr, f).alias(alias),
+        Expr::Sort { ref expr, asc, nulls_first } => rewrite_columns(expr, f).sort(asc, nulls_first),
+        Expr::WindowFunction { ref name, ref args, ref partition_by, ref order_by, ref frame } => Expr::WindowFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| rewrite_columns(a, f)).collect(),
+            partition_by: partition_by.iter().map(|a| rewrite_columns(a, f)).collect(),
+            order_by: order_by.iter().map(|a| rewrite_columns(a, f)).collect(),
+            frame: frame.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::api::ScalarFunction;
+    use super::super::exec::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns a different value every time it is called
+    struct CounterFunction {
+        count: AtomicUsize
+    }
+
+    impl ScalarFunction for CounterFunction {
+        fn name(&self) -> String {
+            "counter".to_string()
+        }
+
+        fn args(&self) -> Vec<Field> {
+            vec![Field::new("start", DataType::UnsignedLong, false)]
+        }
+
+        fn return_type(&self) -> DataType {
+            DataType::UnsignedLong
+        }
+
+        fn execute(&self, args: Vec<Value>) -> Result<Value, Box<String>> {
+            match args[0] {
+                Value::UnsignedLong(start) => Ok(Value::UnsignedLong(start + self.count.fetch_add(1, Ordering::SeqCst) as u64)),
+                _ => Err(Box::new("Unsupported arg type for counter".to_string()))
+            }
+        }
+
+        fn is_deterministic(&self) -> bool {
+            false
+        }
+    }
+
+    fn create_context(rules: Vec<Arc<dyn OptimizerRule>>) -> ExecutionContext {
+        let mut ctx = ExecutionContext::new();
+        ctx.define_schema("people", &Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false)]));
+        ctx.define_schema("orders", &Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]));
+        ctx.set_optimizer_rules(rules);
+        ctx
+    }
+
+    fn optimized_plan(rules: Vec<Arc<dyn OptimizerRule>>, sql: &str) -> LogicalPlan {
+        optimize_sql(&create_context(rules), sql)
+    }
+
+    fn optimize_sql(ctx: &ExecutionContext, sql: &str) -> LogicalPlan {
+        ctx.optimize(&ctx.clone().sql(sql).unwrap().plan()).unwrap()
+    }
+
+    fn execute(ctx: &ExecutionContext, plan: &LogicalPlan) -> Vec<String> {
+        let rel = ctx.create_execution_plan(plan).unwrap();
+        let mut rows: Vec<String> = rel.scan(ctx).map(|r| r.unwrap().to_string()).collect();
+        rows.sort();
+        rows
+    }
+
+    fn scan(table_name: &str, projection: Option<Vec<usize>>) -> LogicalPlan {
+        LogicalPlan::TableScan {
+            schema_name: "default".to_string(),
+            table_name: table_name.to_string(),
+            schema: Schema::new(vec![Field::new("id", DataType::UnsignedLong, false)]),
+            projection
+        }
+    }
+
+    #[test]
+    fn test_simplify_expressions() {
+        let rules: Vec<Arc<dyn OptimizerRule>> = vec![Arc::new(SimplifyExpressions {})];
+
+        let plan = optimized_plan(rules.clone(), "SELECT order_id FROM orders WHERE sqrt(4) < amount AND 1 = 1");
+        match plan {
+            LogicalPlan::Projection { ref input, .. } => match **input {
+                LogicalPlan::Selection { ref expr, .. } =>
+                    assert_eq!(Expr::Literal(Value::Double(2.0)).lt(&Expr::TupleValue(2)), *expr),
+                ref other => panic!("Expected selection but found {:?}", other)
+            },
+            other => panic!("Expected projection but found {:?}", other)
+        }
+
+        // filters that are always true are removed and filters that are never true are replaced
+        let plan = optimized_plan(rules.clone(), "SELECT order_id FROM orders WHERE NOT NOT (1 < 2 OR amount > 10)");
+        assert!(matches!(plan, LogicalPlan::Projection { ref input, .. } if matches!(**input, LogicalPlan::TableScan { .. })));
+        let plan = optimized_plan(rules.clone(), "SELECT order_id FROM orders WHERE amount > 10 AND 1 = 2");
+        match plan {
+            LogicalPlan::Projection { ref input, .. } => match **input {
+                LogicalPlan::EmptyRelation { ref schema } => assert_eq!(3, schema.columns.len()),
+                ref other => panic!("Expected empty relation but found {:?}", other)
+            },
+            other => panic!("Expected projection but found {:?}", other)
+        }
+
+        // functions that are not deterministic are evaluated for every row
+        let mut ctx = create_context(rules.clone());
+        ctx.define_function(Arc::new(CounterFunction { count: AtomicUsize::new(0) }));
+        let plan = optimize_sql(&ctx, "SELECT counter(1), sqrt(4) FROM orders");
+        match plan {
+            LogicalPlan::Projection { ref expr, .. } => {
+                assert!(matches!(expr[0], Expr::ScalarFunction { .. }));
+                assert_eq!(Expr::Literal(Value::Double(2.0)), expr[1]);
+            },
+            other => panic!("Expected projection but found {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_predicates_are_not_moved_past_non_deterministic_functions() {
+        let mut ctx = create_context(default_rules());
+        ctx.define_function(Arc::new(CounterFunction { count: AtomicUsize::new(0) }));
+
+        let orders = ctx.load("test/data/orders.csv", &Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]), &CsvOptions::default()).unwrap();
+        let counter = Expr::ScalarFunction { name: "counter".to_string(), args: vec![Expr::TupleValue(0)] };
+        let df = orders.select(vec![counter, Expr::TupleValue(1)]).unwrap()
+            .filter(Expr::TupleValue(0).gt(&Expr::Literal(Value::UnsignedLong(3)))
+                .and(&Expr::TupleValue(1).eq(&Expr::Literal(Value::UnsignedLong(2))))).unwrap();
+
+        // only the filter on the customer is evaluated before the projection
+        match ctx.optimize(&df.plan()).unwrap() {
+            LogicalPlan::Selection { ref expr, ref input, .. } => {
+                assert_eq!(Expr::TupleValue(0).gt(&Expr::Literal(Value::UnsignedLong(3))), *expr);
+                match **input {
+                    LogicalPlan::Projection { ref input, .. } =>
+                        assert!(matches!(**input, LogicalPlan::Selection { .. })),
+                    ref other => panic!("Expected projection but found {:?}", other)
+                }
+            },
+            other => panic!("Expected selection but found {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_predicate_push_down() {
+        let rules: Vec<Arc<dyn OptimizerRule>> = vec![Arc::new(PredicatePushDown {})];
+
+        // each conjunct is evaluated against the side of the join that it refers to
+        let plan = optimized_plan(rules.clone(), "SELECT name, amount FROM people \
+            JOIN orders ON people.id = orders.customer_id WHERE amount > 10 AND id < 3");
+        match plan {
+            LogicalPlan::Projection { ref input, .. } => match **input {
+                LogicalPlan::Join { ref left, ref right, .. } => {
+                    match **left {
+                        LogicalPlan::Selection { ref expr, .. } =>
+                            assert_eq!(Expr::TupleValue(0).lt(&Expr::Literal(Value::UnsignedLong(3))), *expr),
+                        ref other => panic!("Expected selection but found {:?}", other)
+                    }
+                    match **right {
+                        LogicalPlan::Selection { ref expr, .. } =>
+                            assert_eq!(Expr::TupleValue(2).gt(&Expr::Literal(Value::UnsignedLong(10))), *expr),
+                        ref other => panic!("Expected selection but found {:?}", other)
+                    }
+                },
+                ref other => panic!("Expected join but found {:?}", other)
+            },
+            other => panic!("Expected projection but found {:?}", other)
+        }
+
+        // filtering the null-extended side of an outer join must happen after the join
+        let plan = optimized_plan(rules.clone(), "SELECT name, amount FROM people \
+            LEFT JOIN orders ON people.id = orders.customer_id WHERE amount > 10 AND id < 3");
+        match plan {
+            LogicalPlan::Projection { ref input, .. } => match **input {
+                LogicalPlan::Selection { ref expr, ref input, .. } => {
+                    assert_eq!(Expr::TupleValue(4).gt(&Expr::Literal(Value::UnsignedLong(10))), *expr);
+                    assert!(matches!(**input, LogicalPlan::Join { .. }));
+                },
+                ref other => panic!("Expected selection but found {:?}", other)
+            },
+            other => panic!("Expected projection but found {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_projection_push_down() {
+        let rules: Vec<Arc<dyn OptimizerRule>> = vec![Arc::new(ProjectionPushDown {})];
+
+        let plan = optimized_plan(rules.clone(), "SELECT amount FROM orders WHERE customer_id = 2");
+        match plan {
+            LogicalPlan::Projection { ref expr, ref input, .. } => {
+                assert_eq!(vec![Expr::TupleValue(1)], *expr);
+                match **input {
+                    LogicalPlan::Selection { ref input, .. } => match **input {
+                        LogicalPlan::TableScan { ref projection, .. } => assert_eq!(Some(vec![1, 2]), *projection),
+                        ref other => panic!("Expected table scan but found {:?}", other)
+                    },
+                    ref other => panic!("Expected selection but found {:?}", other)
+                }
+            },
+            other => panic!("Expected projection but found {:?}", other)
+        }
+
+        // join keys are read even when they are not projected
+        let plan = optimized_plan(rules.clone(), "SELECT name FROM people JOIN orders ON people.id = orders.customer_id");
+        match plan {
+            LogicalPlan::Projection { ref input, .. } => match **input {
+                LogicalPlan::Join { ref left, ref right, ref on, ref schema, .. } => {
+                    assert!(matches!(**left, LogicalPlan::TableScan { projection: None, .. }));
+                    assert!(matches!(**right, LogicalPlan::TableScan { projection: Some(ref p), .. } if *p == vec![1]));
+                    assert_eq!(vec![(Expr::TupleValue(0), Expr::TupleValue(0))], *on);
+                    assert_eq!(3, schema.columns.len());
+                },
+                ref other => panic!("Expected join but found {:?}", other)
+            },
+            other => panic!("Expected projection but found {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_limit_push_down() {
+        let rules: Vec<Arc<dyn OptimizerRule>> = vec![Arc::new(LimitPushDown {})];
+
+        let plan = optimized_plan(rules.clone(), "SELECT name FROM people LEFT JOIN orders ON people.id = orders.customer_id LIMIT 3 OFFSET 1");
+        match plan {
+            LogicalPlan::Projection { ref input, .. } => match **input {
+                LogicalPlan::Limit { limit: 3, offset: 1, ref input, .. } => match **input {
+                    LogicalPlan::Join { ref left, .. } =>
+                        assert!(matches!(**left, LogicalPlan::Limit { limit: 4, offset: 0, .. })),
+                    ref other => panic!("Expected join but found {:?}", other)
+                },
+                ref other => panic!("Expected limit but found {:?}", other)
+            },
+            other => panic!("Expected projection but found {:?}", other)
+        }
+
+        // adjacent limits are merged
+        let limit = |limit: usize, offset: usize, input: LogicalPlan| LogicalPlan::Limit {
+            limit, offset, schema: input.schema(), input: Box::new(input)
+        };
+        let ctx = ExecutionContext::new();
+        let plan = LimitPushDown {}.optimize(&limit(10, 1, limit(3, 2, scan("people", None))), &ctx).unwrap();
+        assert!(matches!(plan, LogicalPlan::Limit { limit: 2, offset: 3, .. }));
+        let plan = LimitPushDown {}.optimize(&limit(2, 5, limit(3, 0, scan("people", None))), &ctx).unwrap();
+        assert!(matches!(plan, LogicalPlan::Limit { limit: 0, offset: 5, .. }));
+    }
+
+    /// Create a context with a fact table that references two dimension tables of different sizes
+    fn create_star_schema(analyze: bool) -> ExecutionContext {
+        let mut ctx = create_context(default_rules());
+        let unsigned = |name: &str| Field::new(name, DataType::UnsignedLong, false);
+        let rows = |n: u64, f: &dyn Fn(u64) -> Vec<u64>| (0..n)
+            .map(|i| Row::new(f(i).into_iter().map(Value::UnsignedLong).collect()))
+            .collect();
+        ctx.register_memory_table("sales", &Schema::new(vec![unsigned("store_id"), unsigned("product_id"), unsigned("quantity")]),
+            rows(1000, &|i| vec![i % 10, i % 100, i % 7]));
+        ctx.register_memory_table("products", &Schema::new(vec![unsigned("id"), unsigned("price")]),
+            rows(100, &|i| vec![i, i * 3]));
+        ctx.register_memory_table("stores", &Schema::new(vec![unsigned("id"), unsigned("region")]),
+            rows(10, &|i| vec![i, i % 3]));
+        if analyze {
+            for table in &["sales", "products", "stores"] {
+                ctx.sql(&format!("ANALYZE TABLE {}", table)).unwrap();
+            }
+        }
+        ctx
+    }
+
+    fn children(plan: &LogicalPlan) -> Vec<&LogicalPlan> {
+        match *plan {
+            LogicalPlan::Join { ref left, ref right, .. } => vec![left, right],
+            LogicalPlan::Projection { ref input, .. } | LogicalPlan::Selection { ref input, .. } |
+            LogicalPlan::Aggregate { ref input, .. } | LogicalPlan::Sort { ref input, .. } |
+            LogicalPlan::Window { ref input, .. } | LogicalPlan::Limit { ref input, .. } => vec![input],
+            _ => vec![]
+        }
+    }
+
+    fn find_tables(plan: &LogicalPlan, tables: &mut Vec<String>) {
+        if let LogicalPlan::TableScan { ref table_name, .. } = *plan {
+            tables.push(table_name.clone());
+        }
+        children(plan).into_iter().for_each(|input| find_tables(input, tables));
+    }
+
+    /// Get the tables on the build side of each join
+    fn build_sides(plan: &LogicalPlan, tables: &mut Vec<String>) {
+        if let LogicalPlan::Join { ref right, .. } = *plan {
+            find_tables(right, tables);
+        }
+        children(plan).into_iter().for_each(|input| build_sides(input, tables));
+    }
+
+    #[test]
+    fn test_join_order() {
+        let sql = "SELECT region, price, quantity FROM stores \
+            JOIN sales ON stores.id = sales.store_id JOIN products ON sales.product_id = products.id";
+
+        // without statistics the joins are executed as written, loading the sales into memory
+        let ctx = create_star_schema(false);
+        let plan = optimize_sql(&ctx, sql);
+        let mut tables = vec![];
+        build_sides(&plan, &mut tables);
+        assert_eq!(vec!["products", "sales"], tables);
+
+        // with statistics the sales are streamed through the dimension tables
+        let analyzed = create_star_schema(true);
+        let plan = optimize_sql(&analyzed, sql);
+        let mut tables = vec![];
+        build_sides(&plan, &mut tables);
+        tables.sort();
+        assert_eq!(vec!["products", "stores"], tables);
+        let rows = estimate_rows(&plan, &analyzed).unwrap();
+        assert!((rows - 1000.0).abs() < 50.0, "estimated {} rows", rows);
+
+        let expected = optimize_sql(&ctx, sql);
+        assert_eq!(execute(&ctx, &expected), execute(&analyzed, &plan));
+
+        // a left join keeps the rows of the sales when it is turned into a right join
+        let sql = "SELECT quantity, price FROM products LEFT JOIN sales ON products.id = sales.product_id WHERE price > 150";
+        let plan = optimize_sql(&analyzed, sql);
+        let mut tables = vec![];
+        build_sides(&plan, &mut tables);
+        assert_eq!(vec!["products"], tables);
+        let expected = optimize_sql(&ctx, sql);
+        assert_eq!(execute(&ctx, &expected), execute(&analyzed, &plan));
+    }
+
+    #[test]
+    fn test_optimized_plans_produce_same_rows() {
+        let optimized = create_context(default_rules());
+        let unoptimized = create_context(vec![]);
+
+        let queries = [
+            "SELECT amount FROM orders WHERE customer_id = 2",
+            "SELECT name, amount FROM people JOIN orders ON people.id = orders.customer_id \
+                WHERE amount > 10 AND id < 3",
+            "SELECT name, amount FROM people LEFT JOIN orders ON people.id = orders.customer_id \
+                WHERE amount > 10 OR id > 8",
+            "SELECT name, order_id FROM people RIGHT JOIN orders ON people.id = orders.customer_id WHERE order_id > 3",
+            "SELECT COUNT(*) FROM people FULL OUTER JOIN orders ON people.id = orders.customer_id WHERE id > 2",
+            "SELECT customer_id, SUM(amount) FROM orders WHERE order_id > 1 GROUP BY customer_id",
+            "SELECT COUNT(*) FROM orders",
+            "SELECT name FROM people LEFT JOIN orders ON people.id = orders.customer_id ORDER BY name LIMIT 4 OFFSET 2",
+            "SELECT order_id, ROW_NUMBER() OVER (PARTITION BY customer_id ORDER BY amount) FROM orders",
+            "SELECT order_id FROM orders WHERE sqrt(4) < amount AND 1 = 1",
+            "SELECT COUNT(*), SUM(amount) FROM orders WHERE 1 = 0",
+            "SELECT name, amount FROM people JOIN orders ON people.id = orders.customer_id WHERE id < 3 AND 2 < 1",
+        ];
+        for sql in queries.iter() {
+            let plan = optimize_sql(&optimized, sql);
+            let expected = optimize_sql(&unoptimized, sql);
+            assert_eq!(execute(&unoptimized, &expected), execute(&optimized, &plan), "{}", sql);
+        }
+    }
+}
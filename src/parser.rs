@@ -24,6 +24,8 @@ pub enum Token {
     Keyword(String),
     Operator(String),
     Number(String),
+    /// single-quoted string literal, with escape sequences already resolved
+    String(String),
     Comma,
     Whitespace,
     Eq,
@@ -36,8 +38,10 @@ pub enum Token {
     Minus,
     Mult,
     Div,
+    Mod,
     LParen,
     RParen,
+    Period,
 
     //Operator(String)
 }
@@ -51,11 +55,21 @@ pub enum ParserError {
 /// SQL keywords
 static KEYWORDS : &'static [&'static str] = &[
     "SELECT", "FROM", "WHERE", "LIMIT", "ORDER", "GROUP", "BY", "HAVING",
-    "UNION", "ALL", "INSERT", "UPDATE", "DELETE", "IN", "NOT", "NULL",
-    "SET", "CREATE", "EXTERNAL", "TABLE", 
-    "VARCHAR", "DOUBLE"
+    "UNION", "ALL", "INSERT", "UPDATE", "DELETE", "IN", "NOT", "NULL", "IS", "AND", "OR",
+    "SET", "CREATE", "EXTERNAL", "TABLE", "ASC", "DESC", "NULLS", "FIRST", "LAST",
+    "OFFSET", "JOIN", "INNER", "LEFT", "RIGHT", "FULL", "OUTER", "CROSS", "ON", "USING", "AS",
+    "VARCHAR", "DOUBLE", "FUNCTION", "RETURNS", "LIBRARY", "OVER", "PARTITION", "ROWS",
+    "BETWEEN", "UNBOUNDED", "PRECEDING", "FOLLOWING", "CURRENT", "ROW", "ANALYZE",
+    "EXPLAIN", "WITH", "OPTIONS"
 ];
 
+/// Precedence of the NOT operator, which binds more loosely than comparisons but more tightly
+/// than AND and OR
+const NOT_PRECEDENCE: u8 = 15;
+
+/// Precedence of unary minus, which binds more tightly than any binary operator
+const NEGATION_PRECEDENCE: u8 = 50;
+
 pub struct Tokenizer {
     keywords: HashSet<String>,
     pub query: String,
@@ -87,6 +101,17 @@ impl Tokenizer {
         }).collect())
     }
 
+    fn consume_digits(chars: &mut Peekable<Chars>, s: &mut String) {
+        while let Some(&ch) = chars.peek() {
+            if ch.is_ascii_digit() {
+                chars.next(); // consume
+                s.push(ch);
+            } else {
+                break;
+            }
+        }
+    }
+
     fn next_token(&self, chars: &mut Peekable<Chars>) -> Result<Option<Token>, ParserError> {
         match chars.peek() {
             Some(&ch) => match ch {
@@ -113,29 +138,74 @@ impl Tokenizer {
                         Ok(Some(Token::Identifier(s)))
                     }
                 },
-                // numbers
+                // numbers, with an optional fraction and exponent
                 '0' ... '9' => {
                     let mut s = String::new();
-                    while let Some(&ch) = chars.peek() {
-                        match ch {
-                            '0' ... '9' => {
+                    Tokenizer::consume_digits(chars, &mut s);
+                    if chars.peek() == Some(&'.') {
+                        chars.next(); // consume
+                        s.push('.');
+                        Tokenizer::consume_digits(chars, &mut s);
+                    }
+                    if let Some(&e) = chars.peek() {
+                        if e == 'e' || e == 'E' {
+                            // only treat this as an exponent if digits follow
+                            let mut lookahead = chars.clone();
+                            lookahead.next();
+                            let sign = match lookahead.peek() {
+                                Some(&sign) if sign == '+' || sign == '-' => { lookahead.next(); Some(sign) },
+                                _ => None
+                            };
+                            if matches!(lookahead.peek(), Some(c) if c.is_ascii_digit()) {
                                 chars.next(); // consume
-                                s.push(ch);
-                            },
-                            _ => break
+                                s.push(e);
+                                if let Some(sign) = sign {
+                                    chars.next(); // consume
+                                    s.push(sign);
+                                }
+                                Tokenizer::consume_digits(chars, &mut s);
+                            }
                         }
                     }
                     Ok(Some(Token::Number(s)))
                 },
+                // string literals, where a quote is escaped by doubling it or with a backslash
+                '\'' => {
+                    chars.next(); // consume opening quote
+                    let mut s = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('\'') => if chars.peek() == Some(&'\'') {
+                                chars.next(); // consume
+                                s.push('\'');
+                            } else {
+                                break;
+                            },
+                            Some('\\') => match chars.next() {
+                                Some('n') => s.push('\n'),
+                                Some('t') => s.push('\t'),
+                                Some(ch) => s.push(ch),
+                                None => return Err(ParserError::TokenizerError(
+                                    "Unterminated string literal".to_string()))
+                            },
+                            Some(ch) => s.push(ch),
+                            None => return Err(ParserError::TokenizerError(
+                                "Unterminated string literal".to_string()))
+                        }
+                    }
+                    Ok(Some(Token::String(s)))
+                },
                 // punctuation
                 ',' => { chars.next(); Ok(Some(Token::Comma)) },
                 '(' => { chars.next(); Ok(Some(Token::LParen)) },
                 ')' => { chars.next(); Ok(Some(Token::RParen)) },
+                '.' => { chars.next(); Ok(Some(Token::Period)) },
                 // operators
                 '+' => { chars.next(); Ok(Some(Token::Plus)) },
                 '-' => { chars.next(); Ok(Some(Token::Minus)) },
                 '*' => { chars.next(); Ok(Some(Token::Mult)) },
                 '/' => { chars.next(); Ok(Some(Token::Div)) },
+                '%' => { chars.next(); Ok(Some(Token::Mod)) },
                 '=' => { chars.next(); Ok(Some(Token::Eq)) },
                 '<' => {
                     chars.next(); // consume
@@ -224,6 +294,12 @@ impl Parser {
                         match k.to_uppercase().as_ref() {
                             "SELECT" => Ok(self.parse_select()?),
                             "CREATE" => Ok(self.parse_create()?),
+                            "ANALYZE" => Ok(self.parse_analyze()?),
+                            "EXPLAIN" => Ok(self.parse_explain()?),
+                            "NOT" => Ok(ASTNode::SQLUnary {
+                                operator: SQLOperator::NOT,
+                                rex: Box::new(self.parse_expr(NOT_PRECEDENCE)?)
+                            }),
                             _ => Err(ParserError::ParserError(
                                 format!("No prefix parser for keyword {}", k))),
                         }
@@ -233,17 +309,48 @@ impl Parser {
                             Some(Token::LParen) => {
                                 self.next_token(); // skip lparen
 
-                                let args = self.parse_expr_list()?;
+                                let args = if self.peek_token() == Some(Token::RParen) {
+                                    vec![]
+                                } else {
+                                    self.parse_expr_list()?
+                                };
 
-                                self.next_token(); // skip rparen
+                                self.consume_token(&Token::RParen)?;
 
-                                Ok(ASTNode::SQLFunction { id, args })
+                                let function = ASTNode::SQLFunction { id, args };
+                                if self.parse_keyword("OVER") {
+                                    self.parse_window_spec(function)
+                                } else {
+                                    Ok(function)
+                                }
+                            },
+                            Some(Token::Period) => {
+                                self.next_token(); // skip period
+                                match self.next_token() {
+                                    Some(Token::Identifier(name)) =>
+                                        Ok(ASTNode::SQLCompoundIdentifier { qualifier: id, id: name }),
+                                    Some(Token::Mult) =>
+                                        Ok(ASTNode::SQLQualifiedWildcard { qualifier: id }),
+                                    t => Err(ParserError::ParserError(
+                                        format!("Expected identifier after '{}.' but found {:?}", id, t)))
+                                }
                             },
                             _ => Ok(ASTNode::SQLIdentifier { id: id })
                         }
                     }
-                    Token::Number(n) =>
-                        Ok(ASTNode::SQLLiteralInt(n.parse::<i64>().unwrap())), //TODO: remove unwrap
+                    Token::Number(n) => Parser::parse_number(&n),
+                    Token::String(s) => Ok(ASTNode::SQLLiteralString(s)),
+                    Token::Minus => match self.parse_expr(NEGATION_PRECEDENCE)? {
+                        ASTNode::SQLLiteralInt(n) => Ok(ASTNode::SQLLiteralInt(-n)),
+                        ASTNode::SQLLiteralDouble(n) => Ok(ASTNode::SQLLiteralDouble(-n)),
+                        expr => Ok(ASTNode::SQLUnary { operator: SQLOperator::SUB, rex: Box::new(expr) })
+                    },
+                    Token::Mult => Ok(ASTNode::SQLWildcard),
+                    Token::LParen => {
+                        let expr = self.parse_expr(0)?;
+                        self.consume_token(&Token::RParen)?;
+                        Ok(expr)
+                    },
                     _ => Err(ParserError::ParserError(
                         format!("Prefix parser expected a keyword but found {:?}", t)))
                 }
@@ -257,8 +364,19 @@ impl Parser {
         match self.next_token() {
             Some(tok) => {
                 match tok {
-                    Token::Eq | Token::Gt | Token::GtEq |
-                    Token::Lt | Token::LtEq => Ok(Some(ASTNode::SQLBinaryExpr {
+                    Token::Keyword(ref k) if k == "IS" => {
+                        if self.parse_keywords(vec!["NOT", "NULL"]) {
+                            Ok(Some(ASTNode::SQLIsNotNull(Box::new(expr))))
+                        } else if self.parse_keyword("NULL") {
+                            Ok(Some(ASTNode::SQLIsNull(Box::new(expr))))
+                        } else {
+                            Err(ParserError::ParserError(format!(
+                                "Expected NULL or NOT NULL after IS but found {:?}", self.peek_token())))
+                        }
+                    },
+                    Token::Eq | Token::Neq | Token::Gt | Token::GtEq |
+                    Token::Lt | Token::LtEq | Token::Keyword(_) |
+                    Token::Plus | Token::Minus | Token::Mult | Token::Div | Token::Mod => Ok(Some(ASTNode::SQLBinaryExpr {
                         left: Box::new(expr),
                         op: self.to_sql_operator(&tok)?,
                         right: Box::new(self.parse_expr(precedence)?)
@@ -274,10 +392,18 @@ impl Parser {
     fn to_sql_operator(&self, tok: &Token) -> Result<SQLOperator, ParserError> {
         match tok {
             &Token::Eq => Ok(SQLOperator::EQ),
+            &Token::Neq => Ok(SQLOperator::NEQ),
             &Token::Lt => Ok(SQLOperator::LT),
             &Token::LtEq => Ok(SQLOperator::LTEQ),
             &Token::Gt => Ok(SQLOperator::GT),
             &Token::GtEq => Ok(SQLOperator::GTEQ),
+            &Token::Plus => Ok(SQLOperator::ADD),
+            &Token::Minus => Ok(SQLOperator::SUB),
+            &Token::Mult => Ok(SQLOperator::MULT),
+            &Token::Div => Ok(SQLOperator::DIV),
+            &Token::Mod => Ok(SQLOperator::MOD),
+            &Token::Keyword(ref k) if k == "AND" => Ok(SQLOperator::AND),
+            &Token::Keyword(ref k) if k == "OR" => Ok(SQLOperator::OR),
             //TODO: the rest
             _ => Err(ParserError::ParserError(format!("Unsupported operator {:?}", tok)))
         }
@@ -285,10 +411,13 @@ impl Parser {
 
     fn get_precedence(&self, tok: &Token) -> Result<u8, ParserError> {
         match tok {
+            &Token::Keyword(ref k) if k == "OR" => Ok(5),
+            &Token::Keyword(ref k) if k == "AND" => Ok(10),
             &Token::Eq | &Token::Lt | & Token::LtEq |
             &Token::Neq | &Token::Gt | & Token::GtEq => Ok(20),
+            &Token::Keyword(ref k) if k == "IS" => Ok(20),
             &Token::Plus | &Token::Minus => Ok(30),
-            &Token::Mult | &Token::Div => Ok(40),
+            &Token::Mult | &Token::Div | &Token::Mod => Ok(40),
             _ => Ok(0)
                 /*Err(ParserError::TokenizerError(
                 format!("invalid token {:?} for get_precedence", tok)))*/
@@ -379,21 +508,20 @@ impl Parser {
                     loop {
                         if let Some(Token::Identifier(column_name)) = self.next_token() {
                             if let Ok(data_type) = self.parse_data_type() {
-                                if self.parse_keywords(vec!["NOT", "NULL"]) {
-                                    //TODO:
-                                } else if self.parse_keyword("NULL") {
-                                    //TODO:
+                                // columns are nullable unless declared NOT NULL
+                                let allow_null = !self.parse_keywords(vec!["NOT", "NULL"]);
+                                if allow_null {
+                                    self.parse_keyword("NULL");
                                 }
 
-                                match self.peek_token() {
-                                    Some(Token::Comma) => {
-                                        self.next_token();
-                                        columns.push(SQLColumnDef {
-                                            name: column_name,
-                                            data_type: data_type,
-                                            allow_null: true // TODO
-                                        });
-                                    },
+                                columns.push(SQLColumnDef {
+                                    name: column_name,
+                                    data_type,
+                                    allow_null
+                                });
+
+                                match self.next_token() {
+                                    Some(Token::Comma) => {},
                                     Some(Token::RParen) => break,
                                     _ => return Err(ParserError::ParserError("Expected ',' or ')' after column definition".to_string()))
                                 }
@@ -406,23 +534,131 @@ impl Parser {
                         }
                     }
 
+                    let options = if self.parse_keywords(vec!["WITH", "OPTIONS"]) {
+                        self.parse_table_options()?
+                    } else {
+                        vec![]
+                    };
+
                     Ok(ASTNode::SQLCreateTable {
                         name: id,
-                        columns: columns
+                        columns,
+                        options
                     })
                 },
                 _ => Err(ParserError::ParserError(format!("Unexpected token after CREATE EXTERNAL TABLE: {:?}", self.peek_token())))
 
             }
 
+        } else if self.parse_keyword("FUNCTION") {
+            self.parse_create_function()
         } else {
             Err(ParserError::ParserError(format!("Unexpected token after CREATE: {:?}", self.peek_token())))
         }
     }
 
+    /// Parse the `(name = 'value', ...)` list following `WITH OPTIONS`. Values are string
+    /// literals or identifiers such as `true`.
+    fn parse_table_options(&mut self) -> Result<Vec<(String, String)>, ParserError> {
+        self.consume_token(&Token::LParen)?;
+        let mut options = vec![];
+        loop {
+            let name = match self.next_token() {
+                Some(Token::Identifier(id)) => id,
+                t => return Err(ParserError::ParserError(format!("Expected option name but found {:?}", t)))
+            };
+            self.consume_token(&Token::Eq)?;
+            match self.next_token() {
+                Some(Token::String(value)) | Some(Token::Identifier(value)) => options.push((name, value)),
+                t => return Err(ParserError::ParserError(format!("Expected value for option {} but found {:?}", name, t)))
+            }
+            match self.next_token() {
+                Some(Token::Comma) => {},
+                Some(Token::RParen) => break,
+                _ => return Err(ParserError::ParserError("Expected ',' or ')' after option".to_string()))
+            }
+        }
+        Ok(options)
+    }
+
+    /// Parse `ANALYZE TABLE name`
+    fn parse_analyze(&mut self) -> Result<ASTNode, ParserError> {
+        if !self.parse_keyword("TABLE") {
+            return Err(ParserError::ParserError(
+                format!("Expected TABLE after ANALYZE but found {:?}", self.peek_token())));
+        }
+        match self.next_token() {
+            Some(Token::Identifier(name)) => Ok(ASTNode::SQLAnalyzeTable { name }),
+            t => Err(ParserError::ParserError(format!("Expected table name after ANALYZE TABLE but found {:?}", t)))
+        }
+    }
+
+    /// Parse `EXPLAIN [ANALYZE] statement`
+    fn parse_explain(&mut self) -> Result<ASTNode, ParserError> {
+        let analyze = self.parse_keyword("ANALYZE");
+        let statement = self.parse()?;
+        Ok(ASTNode::SQLExplain { analyze, statement: Box::new(statement) })
+    }
+
+    /// Parse `CREATE FUNCTION name [(type, ...)] [RETURNS type] LIBRARY 'path'`
+    fn parse_create_function(&mut self) -> Result<ASTNode, ParserError> {
+        let name = match self.next_token() {
+            Some(Token::Identifier(id)) => id,
+            t => return Err(ParserError::ParserError(
+                format!("Expected function name after CREATE FUNCTION but found {:?}", t)))
+        };
+
+        let args = if self.peek_token() == Some(Token::LParen) {
+            self.next_token();
+            let mut args = vec![];
+            if self.peek_token() == Some(Token::RParen) {
+                self.next_token();
+            } else {
+                loop {
+                    args.push(self.parse_data_type()?);
+                    match self.next_token() {
+                        Some(Token::Comma) => {},
+                        Some(Token::RParen) => break,
+                        _ => return Err(ParserError::ParserError("Expected ',' or ')' after argument type".to_string()))
+                    }
+                }
+            }
+            Some(args)
+        } else {
+            None
+        };
+
+        let return_type = if self.parse_keyword("RETURNS") {
+            Some(self.parse_data_type()?)
+        } else {
+            None
+        };
+
+        if !self.parse_keyword("LIBRARY") {
+            return Err(ParserError::ParserError(
+                format!("Expected LIBRARY in CREATE FUNCTION but found {:?}", self.peek_token())));
+        }
+
+        match self.next_token() {
+            Some(Token::String(library)) => Ok(ASTNode::SQLCreateFunction { name, args, return_type, library }),
+            t => Err(ParserError::ParserError(format!("Expected library path but found {:?}", t)))
+        }
+    }
+
+    fn parse_number(n: &str) -> Result<ASTNode, ParserError> {
+        if n.contains(|c| c == '.' || c == 'e' || c == 'E') {
+            n.parse::<f64>().map(ASTNode::SQLLiteralDouble).map_err(|e| ParserError::ParserError(
+                format!("Could not parse '{}' as f64: {}", n, e)))
+        } else {
+            n.parse::<i64>().map(ASTNode::SQLLiteralInt).map_err(|e| ParserError::ParserError(
+                format!("Could not parse '{}' as i64: {}", n, e)))
+        }
+    }
+
     fn parse_literal_int(&mut self) -> Result<i64, ParserError> {
         match self.next_token() {
-            Some(Token::Number(s)) => Ok(s.parse::<i64>().unwrap()), //TODO: remove unwrap
+            Some(Token::Number(s)) => s.parse::<i64>().map_err(|e| ParserError::ParserError(
+                format!("Could not parse '{}' as i64: {}", s, e))),
             _ => Err(ParserError::ParserError("error parsing literal int".to_string()))
         }
     }
@@ -445,11 +681,10 @@ impl Parser {
 
     fn parse_select(&mut self) -> Result<ASTNode, ParserError> {
 
-        let projection = self.parse_expr_list()?;
+        let projection = self.parse_select_list()?;
 
         let relation : Option<Box<ASTNode>> = if self.parse_keyword("FROM") {
-            //TODO: add support for JOIN
-            Some(Box::new(self.parse_expr(0)?))
+            Some(Box::new(self.parse_relation()?))
         } else {
             None
         };
@@ -460,10 +695,35 @@ impl Parser {
             None
         };
 
-        //TODO: parse GROUP BY
-        //TODO: parse HAVING
-        //TODO: parse ORDER BY
-        //TODO: parse LIMIT
+        let group_by = if self.parse_keywords(vec!["GROUP", "BY"]) {
+            Some(self.parse_expr_list()?)
+        } else {
+            None
+        };
+
+        let having = if self.parse_keyword("HAVING") {
+            Some(Box::new(self.parse_expr(0)?))
+        } else {
+            None
+        };
+
+        let order = if self.parse_keywords(vec!["ORDER", "BY"]) {
+            Some(self.parse_order_by_expr_list()?)
+        } else {
+            None
+        };
+
+        let limit = if self.parse_keyword("LIMIT") {
+            Some(Box::new(self.parse_limit_expr()?))
+        } else {
+            None
+        };
+
+        let offset = if limit.is_some() && self.parse_keyword("OFFSET") {
+            Some(Box::new(self.parse_limit_expr()?))
+        } else {
+            None
+        };
 
         if let Some(next_token) = self.peek_token() {
             Err(ParserError::ParserError(format!("Unexpected token at end of SELECT: {:?}", next_token)))
@@ -472,12 +732,217 @@ impl Parser {
                 projection: projection,
                 selection: selection,
                 relation: relation,
-                limit: None,
-                order: None,
+                group_by,
+                having,
+                limit,
+                offset,
+                order,
             })
         }
     }
 
+    /// Parse a table reference followed by any number of joins
+    fn parse_relation(&mut self) -> Result<ASTNode, ParserError> {
+        let mut relation = self.parse_table_factor()?;
+        loop {
+            let join_type = if self.parse_keywords(vec!["CROSS", "JOIN"]) {
+                SQLJoinType::Cross
+            } else if self.parse_keyword("JOIN") || self.parse_keywords(vec!["INNER", "JOIN"]) {
+                SQLJoinType::Inner
+            } else if self.parse_keywords(vec!["LEFT", "JOIN"]) || self.parse_keywords(vec!["LEFT", "OUTER", "JOIN"]) {
+                SQLJoinType::Left
+            } else if self.parse_keywords(vec!["RIGHT", "JOIN"]) || self.parse_keywords(vec!["RIGHT", "OUTER", "JOIN"]) {
+                SQLJoinType::Right
+            } else if self.parse_keywords(vec!["FULL", "JOIN"]) || self.parse_keywords(vec!["FULL", "OUTER", "JOIN"]) {
+                SQLJoinType::Full
+            } else {
+                break;
+            };
+
+            let right = self.parse_table_factor()?;
+
+            let constraint = if join_type == SQLJoinType::Cross {
+                SQLJoinConstraint::None
+            } else if self.parse_keyword("ON") {
+                SQLJoinConstraint::On(Box::new(self.parse_expr(0)?))
+            } else if self.parse_keyword("USING") {
+                self.consume_token(&Token::LParen)?;
+                let mut columns = vec![];
+                loop {
+                    match self.next_token() {
+                        Some(Token::Identifier(id)) => columns.push(id),
+                        t => return Err(ParserError::ParserError(
+                            format!("Expected column name in USING but found {:?}", t)))
+                    }
+                    match self.next_token() {
+                        Some(Token::Comma) => {},
+                        Some(Token::RParen) => break,
+                        t => return Err(ParserError::ParserError(
+                            format!("Expected ',' or ')' in USING but found {:?}", t)))
+                    }
+                }
+                SQLJoinConstraint::Using(columns)
+            } else {
+                return Err(ParserError::ParserError(
+                    format!("Expected ON or USING after JOIN but found {:?}", self.peek_token())))
+            };
+
+            relation = ASTNode::SQLJoin {
+                left: Box::new(relation),
+                right: Box::new(right),
+                join_type,
+                constraint
+            };
+        }
+        Ok(relation)
+    }
+
+    /// Parse a table name with an optional alias
+    fn parse_table_factor(&mut self) -> Result<ASTNode, ParserError> {
+        let table = match self.next_token() {
+            Some(Token::Identifier(id)) => ASTNode::SQLIdentifier { id },
+            t => return Err(ParserError::ParserError(format!("Expected table name but found {:?}", t)))
+        };
+        let has_as = self.parse_keyword("AS");
+        match self.peek_token() {
+            Some(Token::Identifier(alias)) => {
+                self.next_token();
+                Ok(ASTNode::SQLAlias { expr: Box::new(table), alias })
+            },
+            t if has_as => Err(ParserError::ParserError(format!("Expected alias after AS but found {:?}", t))),
+            _ => Ok(table)
+        }
+    }
+
+    /// Parse the row count in a LIMIT or OFFSET clause
+    fn parse_limit_expr(&mut self) -> Result<ASTNode, ParserError> {
+        Ok(ASTNode::SQLLiteralInt(self.parse_literal_int()?))
+    }
+
+    /// Parse a comma-separated list of ORDER BY expressions, each optionally followed by
+    /// ASC/DESC and NULLS FIRST/LAST
+    fn parse_order_by_expr_list(&mut self) -> Result<Vec<ASTNode>, ParserError> {
+        let mut expr_list : Vec<ASTNode> = vec![];
+        loop {
+            let expr = self.parse_expr(0)?;
+
+            let asc = if self.parse_keyword("ASC") {
+                true
+            } else {
+                !self.parse_keyword("DESC")
+            };
+
+            // nulls are considered larger than any other value unless specified otherwise
+            let nulls_first = if self.parse_keywords(vec!["NULLS", "FIRST"]) {
+                true
+            } else if self.parse_keywords(vec!["NULLS", "LAST"]) {
+                false
+            } else {
+                !asc
+            };
+
+            expr_list.push(ASTNode::SQLOrderBy { expr: Box::new(expr), asc, nulls_first });
+
+            match self.peek_token() {
+                Some(Token::Comma) => { self.next_token(); },
+                _ => break
+            }
+        }
+        Ok(expr_list)
+    }
+
+    /// Parse the OVER clause of a window function:
+    /// `OVER ([PARTITION BY expr, ...] [ORDER BY expr, ...] [ROWS BETWEEN start AND end])`
+    fn parse_window_spec(&mut self, function: ASTNode) -> Result<ASTNode, ParserError> {
+        self.consume_token(&Token::LParen)?;
+
+        let partition_by = if self.parse_keywords(vec!["PARTITION", "BY"]) {
+            self.parse_expr_list()?
+        } else {
+            vec![]
+        };
+
+        let order_by = if self.parse_keywords(vec!["ORDER", "BY"]) {
+            self.parse_order_by_expr_list()?
+        } else {
+            vec![]
+        };
+
+        let frame = if self.parse_keyword("ROWS") {
+            // a single bound is the start of a frame ending at the current row
+            let (start, end) = if self.parse_keyword("BETWEEN") {
+                let start = self.parse_window_frame_bound()?;
+                if !self.parse_keyword("AND") {
+                    return Err(ParserError::ParserError(
+                        format!("Expected AND in window frame but found {:?}", self.peek_token())));
+                }
+                (start, self.parse_window_frame_bound()?)
+            } else {
+                (self.parse_window_frame_bound()?, SQLWindowFrameBound::CurrentRow)
+            };
+            if start == SQLWindowFrameBound::UnboundedFollowing || end == SQLWindowFrameBound::UnboundedPreceding {
+                return Err(ParserError::ParserError(format!("Invalid window frame {:?} to {:?}", start, end)));
+            }
+            Some(SQLWindowFrame { start, end })
+        } else {
+            None
+        };
+
+        self.consume_token(&Token::RParen)?;
+
+        Ok(ASTNode::SQLWindowFunction { function: Box::new(function), partition_by, order_by, frame })
+    }
+
+    /// Parse `UNBOUNDED PRECEDING`, `n PRECEDING`, `CURRENT ROW`, `n FOLLOWING` or
+    /// `UNBOUNDED FOLLOWING`
+    fn parse_window_frame_bound(&mut self) -> Result<SQLWindowFrameBound, ParserError> {
+        if self.parse_keywords(vec!["CURRENT", "ROW"]) {
+            return Ok(SQLWindowFrameBound::CurrentRow);
+        }
+        let n = if self.parse_keyword("UNBOUNDED") {
+            None
+        } else {
+            match self.parse_literal_int()? {
+                n if n >= 0 => Some(n as u64),
+                n => return Err(ParserError::ParserError(format!("Invalid window frame offset {}", n)))
+            }
+        };
+        if self.parse_keyword("PRECEDING") {
+            Ok(n.map_or(SQLWindowFrameBound::UnboundedPreceding, SQLWindowFrameBound::Preceding))
+        } else if self.parse_keyword("FOLLOWING") {
+            Ok(n.map_or(SQLWindowFrameBound::UnboundedFollowing, SQLWindowFrameBound::Following))
+        } else {
+            Err(ParserError::ParserError(
+                format!("Expected PRECEDING or FOLLOWING but found {:?}", self.peek_token())))
+        }
+    }
+
+    /// Parse the projection of a SELECT, where each expression may have an alias with or without
+    /// the AS keyword
+    fn parse_select_list(&mut self) -> Result<Vec<ASTNode>, ParserError> {
+        let mut expr_list : Vec<ASTNode> = vec![];
+        loop {
+            let expr = self.parse_expr(0)?;
+            let has_as = self.parse_keyword("AS");
+            let expr = match self.peek_token() {
+                Some(Token::Identifier(alias)) => {
+                    self.next_token();
+                    ASTNode::SQLAlias { expr: Box::new(expr), alias }
+                },
+                t if has_as => return Err(ParserError::ParserError(
+                    format!("Expected alias after AS but found {:?}", t))),
+                _ => expr
+            };
+            expr_list.push(expr);
+            if self.peek_token() == Some(Token::Comma) {
+                self.next_token();
+            } else {
+                break;
+            }
+        }
+        Ok(expr_list)
+    }
+
     fn parse_expr_list(&mut self) -> Result<Vec<ASTNode>, ParserError> {
         let mut expr_list : Vec<ASTNode> = vec![];
         loop {
@@ -488,6 +953,8 @@ impl Parser {
                 } else {
                     break;
                 }
+            } else {
+                break;
             }
         }
         Ok(expr_list)
@@ -551,6 +1018,85 @@ mod tests {
         compare(expected, tokens);
     }
 
+    #[test]
+    fn tokenize_literals()  {
+        let sql = String::from("SELECT 52.5, 1e3, 2.5E-2, 3e, 'it''s', 'a\\'b\\\\c' FROM t");
+        let mut tokenizer = Tokenizer::new(&sql);
+        let tokens = tokenizer.tokenize().unwrap();
+
+        let expected = vec![
+            Token::Keyword(String::from("SELECT")),
+            Token::Number(String::from("52.5")),
+            Token::Comma,
+            Token::Number(String::from("1e3")),
+            Token::Comma,
+            Token::Number(String::from("2.5E-2")),
+            Token::Comma,
+            Token::Number(String::from("3")),
+            Token::Identifier(String::from("e")),
+            Token::Comma,
+            Token::String(String::from("it's")),
+            Token::Comma,
+            Token::String(String::from("a'b\\c")),
+            Token::Keyword(String::from("FROM")),
+            Token::Identifier(String::from("t"))
+        ];
+
+        compare(expected, tokens);
+    }
+
+    #[test]
+    fn tokenize_unterminated_string()  {
+        let sql = String::from("SELECT 'abc");
+        let mut tokenizer = Tokenizer::new(&sql);
+        assert!(tokenizer.tokenize().is_err());
+    }
+
+    #[test]
+    fn parse_literals() {
+        let sql = String::from("SELECT -1, -52.5, 1.5e2, 'Elgin', -id FROM t");
+        let ast = Parser::parse_sql(sql).unwrap();
+        match ast {
+            ASTNode::SQLSelect { projection, .. } => assert_eq!(vec![
+                ASTNode::SQLLiteralInt(-1),
+                ASTNode::SQLLiteralDouble(-52.5),
+                ASTNode::SQLLiteralDouble(150.0),
+                ASTNode::SQLLiteralString("Elgin".to_string()),
+                ASTNode::SQLUnary {
+                    operator: SQLOperator::SUB,
+                    rex: Box::new(ASTNode::SQLIdentifier { id: "id".to_string() })
+                }
+            ], projection),
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_select_aliases_and_wildcards() {
+        let sql = String::from("SELECT *, p.*, id AS person_id, sqrt(id) root FROM people p");
+        let ast = Parser::parse_sql(sql).unwrap();
+        match ast {
+            ASTNode::SQLSelect { projection, .. } => assert_eq!(vec![
+                ASTNode::SQLWildcard,
+                ASTNode::SQLQualifiedWildcard { qualifier: "p".to_string() },
+                ASTNode::SQLAlias {
+                    expr: Box::new(ASTNode::SQLIdentifier { id: "id".to_string() }),
+                    alias: "person_id".to_string()
+                },
+                ASTNode::SQLAlias {
+                    expr: Box::new(ASTNode::SQLFunction {
+                        id: "sqrt".to_string(),
+                        args: vec![ASTNode::SQLIdentifier { id: "id".to_string() }]
+                    }),
+                    alias: "root".to_string()
+                }
+            ], projection),
+            _ => assert!(false)
+        }
+
+        assert!(Parser::parse_sql(String::from("SELECT id AS FROM people")).is_err());
+    }
+
     #[test]
     fn parse_simple_select() {
         let sql = String::from("SELECT id, fname, lname FROM customer WHERE id = 1");
@@ -571,20 +1117,152 @@ mod tests {
     fn parse_create_external_table() {
         let sql = String::from("CREATE EXTERNAL TABLE uk_cities (\
             name VARCHAR(100) NOT NULL,\
-            lat DOUBLE NOT NULL,\
-            lng DOUBLE NOT NULL)");
+            lat DOUBLE NULL,\
+            lng DOUBLE)");
 
         let mut tokenizer = Tokenizer::new(&sql);
         let tokens = tokenizer.tokenize().unwrap();
         let mut parser = Parser::new(tokens);
         let ast = parser.parse().unwrap();
         println!("AST = {:?}", ast);
-//        match ast {
-//            ASTNode::SQLSelect { projection, .. } => {
-//                assert_eq!(3, projection.len());
-//            },
-//            _ => assert!(false)
-//        }
+        match ast {
+            ASTNode::SQLCreateTable { columns, options, .. } => {
+                assert_eq!(vec![
+                    SQLColumnDef { name: "name".to_string(), data_type: SQLType::Varchar(100), allow_null: false },
+                    SQLColumnDef { name: "lat".to_string(), data_type: SQLType::Double, allow_null: true },
+                    SQLColumnDef { name: "lng".to_string(), data_type: SQLType::Double, allow_null: true }
+                ], columns);
+                assert!(options.is_empty());
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_create_external_table_with_options() {
+        let sql = String::from("CREATE EXTERNAL TABLE cities (name VARCHAR(100), lat DOUBLE) \
+            WITH OPTIONS (has_header = false, delimiter = '\\t', null_string = 'N/A')");
+        match Parser::parse_sql(sql).unwrap() {
+            ASTNode::SQLCreateTable { name, options, .. } => {
+                assert_eq!("cities", name);
+                assert_eq!(vec![
+                    ("has_header".to_string(), "false".to_string()),
+                    ("delimiter".to_string(), "\t".to_string()),
+                    ("null_string".to_string(), "N/A".to_string())
+                ], options);
+            },
+            other => panic!("Expected CREATE EXTERNAL TABLE but found {:?}", other)
+        }
+
+        assert!(Parser::parse_sql(String::from("CREATE EXTERNAL TABLE t (a DOUBLE) WITH OPTIONS (trim)")).is_err());
+    }
+
+    #[test]
+    fn parse_create_function() {
+        let sql = String::from("CREATE FUNCTION double_it(DOUBLE) RETURNS DOUBLE LIBRARY '/tmp/libudf.so'");
+        let ast = Parser::parse_sql(sql).unwrap();
+        assert_eq!(ASTNode::SQLCreateFunction {
+            name: "double_it".to_string(),
+            args: Some(vec![SQLType::Double]),
+            return_type: Some(SQLType::Double),
+            library: "/tmp/libudf.so".to_string()
+        }, ast);
+
+        let sql = String::from("CREATE FUNCTION halve LIBRARY 'libudf.so'");
+        let ast = Parser::parse_sql(sql).unwrap();
+        assert_eq!(ASTNode::SQLCreateFunction {
+            name: "halve".to_string(),
+            args: None,
+            return_type: None,
+            library: "libudf.so".to_string()
+        }, ast);
+
+        assert!(Parser::parse_sql(String::from("CREATE FUNCTION halve(DOUBLE)")).is_err());
+    }
+
+    #[test]
+    fn parse_analyze_table() {
+        let ast = Parser::parse_sql(String::from("ANALYZE TABLE people")).unwrap();
+        assert_eq!(ASTNode::SQLAnalyzeTable { name: "people".to_string() }, ast);
+        assert!(Parser::parse_sql(String::from("ANALYZE people")).is_err());
+    }
+
+    #[test]
+    fn parse_explain() {
+        let select = Parser::parse_sql(String::from("SELECT id FROM people")).unwrap();
+        let ast = Parser::parse_sql(String::from("EXPLAIN SELECT id FROM people")).unwrap();
+        assert_eq!(ASTNode::SQLExplain { analyze: false, statement: Box::new(select.clone()) }, ast);
+        let ast = Parser::parse_sql(String::from("EXPLAIN ANALYZE SELECT id FROM people")).unwrap();
+        assert_eq!(ASTNode::SQLExplain { analyze: true, statement: Box::new(select) }, ast);
+        assert!(Parser::parse_sql(String::from("EXPLAIN")).is_err());
+    }
+
+    #[test]
+    fn parse_window_function() {
+        let sql = String::from("SELECT SUM(amount) OVER (PARTITION BY customer_id ORDER BY order_id \
+            ROWS BETWEEN 2 PRECEDING AND UNBOUNDED FOLLOWING), ROW_NUMBER() OVER () FROM orders");
+        let ast = Parser::parse_sql(sql).unwrap();
+        match ast {
+            ASTNode::SQLSelect { projection, .. } => {
+                assert_eq!(ASTNode::SQLWindowFunction {
+                    function: Box::new(ASTNode::SQLFunction {
+                        id: "SUM".to_string(),
+                        args: vec![ASTNode::SQLIdentifier { id: "amount".to_string() }]
+                    }),
+                    partition_by: vec![ASTNode::SQLIdentifier { id: "customer_id".to_string() }],
+                    order_by: vec![ASTNode::SQLOrderBy {
+                        expr: Box::new(ASTNode::SQLIdentifier { id: "order_id".to_string() }),
+                        asc: true,
+                        nulls_first: false
+                    }],
+                    frame: Some(SQLWindowFrame {
+                        start: SQLWindowFrameBound::Preceding(2),
+                        end: SQLWindowFrameBound::UnboundedFollowing
+                    })
+                }, projection[0]);
+                assert_eq!(ASTNode::SQLWindowFunction {
+                    function: Box::new(ASTNode::SQLFunction { id: "ROW_NUMBER".to_string(), args: vec![] }),
+                    partition_by: vec![],
+                    order_by: vec![],
+                    frame: None
+                }, projection[1]);
+            },
+            _ => assert!(false)
+        }
+
+        let sql = String::from("SELECT SUM(amount) OVER (ROWS 3 PRECEDING) FROM orders");
+        match Parser::parse_sql(sql).unwrap() {
+            ASTNode::SQLSelect { projection, .. } => match projection[0] {
+                ASTNode::SQLWindowFunction { ref frame, .. } => assert_eq!(&Some(SQLWindowFrame {
+                    start: SQLWindowFrameBound::Preceding(3),
+                    end: SQLWindowFrameBound::CurrentRow
+                }), frame),
+                _ => assert!(false)
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_is_null() {
+        let sql = String::from("SELECT id FROM people WHERE name IS NULL OR NOT id IS NOT NULL");
+        let ast = Parser::parse_sql(sql).unwrap();
+
+        let expected = ASTNode::SQLBinaryExpr {
+            left: Box::new(ASTNode::SQLIsNull(Box::new(ASTNode::SQLIdentifier { id: "name".to_string() }))),
+            op: SQLOperator::OR,
+            right: Box::new(ASTNode::SQLUnary {
+                operator: SQLOperator::NOT,
+                rex: Box::new(ASTNode::SQLIsNotNull(Box::new(ASTNode::SQLIdentifier { id: "id".to_string() })))
+            })
+        };
+
+        match ast {
+            ASTNode::SQLSelect { selection, .. } => assert_eq!(Some(Box::new(expected)), selection),
+            _ => assert!(false)
+        }
+
+        assert!(Parser::parse_sql(String::from("SELECT id FROM people WHERE name IS 1")).is_err());
     }
 
     #[test]
@@ -603,6 +1281,184 @@ mod tests {
 //        }
     }
 
+    #[test]
+    fn parse_select_group_by_having() {
+        let sql = String::from("SELECT city, COUNT(*) FROM uk_cities GROUP BY city HAVING COUNT(*) > 1");
+        let ast = Parser::parse_sql(sql).unwrap();
+        match ast {
+            ASTNode::SQLSelect { projection, group_by, having, .. } => {
+                assert_eq!(2, projection.len());
+                assert_eq!(ASTNode::SQLFunction { id: "COUNT".to_string(), args: vec![ASTNode::SQLWildcard] },
+                           projection[1]);
+                assert_eq!(Some(vec![ASTNode::SQLIdentifier { id: "city".to_string() }]), group_by);
+                assert!(having.is_some());
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_select_order_by() {
+        let sql = String::from("SELECT id, name FROM people ORDER BY name DESC, id ASC NULLS FIRST, lat");
+        let ast = Parser::parse_sql(sql).unwrap();
+        match ast {
+            ASTNode::SQLSelect { order, .. } => {
+                assert_eq!(Some(vec![
+                    ASTNode::SQLOrderBy {
+                        expr: Box::new(ASTNode::SQLIdentifier { id: "name".to_string() }),
+                        asc: false,
+                        nulls_first: true
+                    },
+                    ASTNode::SQLOrderBy {
+                        expr: Box::new(ASTNode::SQLIdentifier { id: "id".to_string() }),
+                        asc: true,
+                        nulls_first: true
+                    },
+                    ASTNode::SQLOrderBy {
+                        expr: Box::new(ASTNode::SQLIdentifier { id: "lat".to_string() }),
+                        asc: true,
+                        nulls_first: false
+                    }
+                ]), order);
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_select_limit_offset() {
+        let sql = String::from("SELECT id FROM people ORDER BY id LIMIT 5 OFFSET 2");
+        let ast = Parser::parse_sql(sql).unwrap();
+        match ast {
+            ASTNode::SQLSelect { limit, offset, .. } => {
+                assert_eq!(Some(Box::new(ASTNode::SQLLiteralInt(5))), limit);
+                assert_eq!(Some(Box::new(ASTNode::SQLLiteralInt(2))), offset);
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_select_invalid_limit() {
+        let sql = String::from("SELECT id FROM people LIMIT id");
+        assert!(Parser::parse_sql(sql).is_err());
+    }
+
+    #[test]
+    fn parse_select_join() {
+        let sql = String::from("SELECT p.id, amount FROM people AS p LEFT OUTER JOIN orders o \
+            ON p.id = o.customer_id CROSS JOIN uk_cities JOIN people USING (id, name)");
+        let ast = Parser::parse_sql(sql).unwrap();
+        match ast {
+            ASTNode::SQLSelect { projection, relation, .. } => {
+                assert_eq!(ASTNode::SQLCompoundIdentifier { qualifier: "p".to_string(), id: "id".to_string() },
+                           projection[0]);
+
+                let people = ASTNode::SQLAlias {
+                    expr: Box::new(ASTNode::SQLIdentifier { id: "people".to_string() }),
+                    alias: "p".to_string()
+                };
+                let orders = ASTNode::SQLAlias {
+                    expr: Box::new(ASTNode::SQLIdentifier { id: "orders".to_string() }),
+                    alias: "o".to_string()
+                };
+                let left_join = ASTNode::SQLJoin {
+                    left: Box::new(people),
+                    right: Box::new(orders),
+                    join_type: SQLJoinType::Left,
+                    constraint: SQLJoinConstraint::On(Box::new(ASTNode::SQLBinaryExpr {
+                        left: Box::new(ASTNode::SQLCompoundIdentifier { qualifier: "p".to_string(), id: "id".to_string() }),
+                        op: SQLOperator::EQ,
+                        right: Box::new(ASTNode::SQLCompoundIdentifier { qualifier: "o".to_string(), id: "customer_id".to_string() })
+                    }))
+                };
+                let cross_join = ASTNode::SQLJoin {
+                    left: Box::new(left_join),
+                    right: Box::new(ASTNode::SQLIdentifier { id: "uk_cities".to_string() }),
+                    join_type: SQLJoinType::Cross,
+                    constraint: SQLJoinConstraint::None
+                };
+                let expected = ASTNode::SQLJoin {
+                    left: Box::new(cross_join),
+                    right: Box::new(ASTNode::SQLIdentifier { id: "people".to_string() }),
+                    join_type: SQLJoinType::Inner,
+                    constraint: SQLJoinConstraint::Using(vec!["id".to_string(), "name".to_string()])
+                };
+                assert_eq!(Some(Box::new(expected)), relation);
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_join_without_constraint() {
+        let sql = String::from("SELECT id FROM people JOIN orders");
+        assert!(Parser::parse_sql(sql).is_err());
+    }
+
+    #[test]
+    fn parse_arithmetic_precedence() {
+        let sql = String::from("SELECT a + b * (c - d) % 2 FROM t");
+        let ast = Parser::parse_sql(sql).unwrap();
+
+        let id = |name: &str| Box::new(ASTNode::SQLIdentifier { id: name.to_string() });
+
+        let expected = ASTNode::SQLBinaryExpr {
+            left: id("a"),
+            op: SQLOperator::ADD,
+            right: Box::new(ASTNode::SQLBinaryExpr {
+                left: Box::new(ASTNode::SQLBinaryExpr {
+                    left: id("b"),
+                    op: SQLOperator::MULT,
+                    right: Box::new(ASTNode::SQLBinaryExpr {
+                        left: id("c"),
+                        op: SQLOperator::SUB,
+                        right: id("d")
+                    })
+                }),
+                op: SQLOperator::MOD,
+                right: Box::new(ASTNode::SQLLiteralInt(2))
+            })
+        };
+
+        match ast {
+            ASTNode::SQLSelect { projection, .. } => assert_eq!(vec![expected], projection),
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_boolean_precedence() {
+        let sql = String::from("SELECT id FROM people WHERE NOT id = 1 AND id <> 2 OR id > 3 AND id < 5");
+        let ast = Parser::parse_sql(sql).unwrap();
+
+        let id = || Box::new(ASTNode::SQLIdentifier { id: "id".to_string() });
+        let cmp = |op: SQLOperator, n: i64| Box::new(ASTNode::SQLBinaryExpr {
+            left: id(),
+            op: op,
+            right: Box::new(ASTNode::SQLLiteralInt(n))
+        });
+
+        let expected = ASTNode::SQLBinaryExpr {
+            left: Box::new(ASTNode::SQLBinaryExpr {
+                left: Box::new(ASTNode::SQLUnary { operator: SQLOperator::NOT, rex: cmp(SQLOperator::EQ, 1) }),
+                op: SQLOperator::AND,
+                right: cmp(SQLOperator::NEQ, 2)
+            }),
+            op: SQLOperator::OR,
+            right: Box::new(ASTNode::SQLBinaryExpr {
+                left: cmp(SQLOperator::GT, 3),
+                op: SQLOperator::AND,
+                right: cmp(SQLOperator::LT, 5)
+            })
+        };
+
+        match ast {
+            ASTNode::SQLSelect { selection, .. } => assert_eq!(Some(Box::new(expected)), selection),
+            _ => assert!(false)
+        }
+    }
+
     fn compare(expected: Vec<Token>, actual: Vec<Token>) {
         println!("------------------------------");
         println!("tokens   = {:?}", actual);
@@ -12,19 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 
 /// The data types supported by this database. Currently just u64 and string but others
 /// will be added later, including complex types
-#[derive(Debug,Clone,Serialize,Deserialize)]
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
 pub enum DataType {
     UnsignedLong,
+    Long,
     String,
     Double,
+    Boolean,
     ComplexType(Vec<Field>)
 }
 
 /// Definition of a column in a relation (data set).
-#[derive(Debug,Clone,Serialize,Deserialize)]
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
 pub struct Field {
     pub name: String,
     pub data_type: DataType,
@@ -71,6 +74,27 @@ impl Schema {
             .find(|&(_,c)| c.name == name)
     }
 
+    /// Create the schema for a join of two relations. Columns from the side of an outer join that
+    /// might not have a matching row become nullable.
+    pub fn join(left: &Schema, right: &Schema, join_type: &JoinType) -> Self {
+        let (left_nullable, right_nullable) = match *join_type {
+            JoinType::Left => (false, true),
+            JoinType::Right => (true, false),
+            JoinType::Full => (true, true),
+            JoinType::Inner | JoinType::Cross => (false, false)
+        };
+        let left_columns = left.columns.iter()
+            .map(|c| Field::new(&c.name, c.data_type.clone(), c.nullable || left_nullable));
+        let right_columns = right.columns.iter()
+            .map(|c| Field::new(&c.name, c.data_type.clone(), c.nullable || right_nullable));
+        Schema::new(left_columns.chain(right_columns).collect())
+    }
+
+    /// Create the schema containing the columns at the given indices, in that order
+    pub fn project(&self, projection: &[usize]) -> Self {
+        Schema::new(projection.iter().map(|&i| self.columns[i].clone()).collect())
+    }
+
     pub fn to_string(&self) -> String {
         let s : Vec<String> = self.columns.iter()
             .map(|c| c.to_string())
@@ -80,11 +104,18 @@ impl Schema {
 
 }
 
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum FunctionType {
+    Scalar,
+    Aggregate
+}
+
 #[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct FunctionMeta {
     pub name: String,
     pub args: Vec<Field>,
-    pub return_type: DataType
+    pub return_type: DataType,
+    pub function_type: FunctionType
 }
 
 /// A tuple represents one row within a relation and is implemented as a trait to allow for
@@ -118,17 +149,75 @@ impl Row {
 #[derive(Debug,Clone,PartialEq,PartialOrd,Serialize,Deserialize)]
 pub enum Value {
     UnsignedLong(u64),
+    Long(i64),
     String(String),
     Boolean(bool),
-    Double(f64),
-    ComplexValue(Vec<Value>)
+    Double(#[serde(with = "double_serde")] f64),
+    ComplexValue(Vec<Value>),
+    Null
+}
+
+/// Serialization of doubles that round-trips NaN and infinity, which JSON numbers cannot
+/// represent. These are written as the strings `NaN`, `inf` and `-inf` and other doubles are
+/// written as numbers.
+mod double_serde {
+    use std::fmt;
+
+    use serde::{Deserializer, Serializer};
+    use serde::de::{self, Visitor};
+
+    pub fn serialize<S: Serializer>(d: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if d.is_nan() {
+            serializer.serialize_str("NaN")
+        } else if d.is_infinite() {
+            serializer.serialize_str(if *d > 0.0 { "inf" } else { "-inf" })
+        } else {
+            serializer.serialize_f64(*d)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        deserializer.deserialize_any(DoubleVisitor)
+    }
+
+    struct DoubleVisitor;
+
+    impl<'de> Visitor<'de> for DoubleVisitor {
+        type Value = f64;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a number or one of the strings NaN, inf and -inf")
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<f64, E> {
+            Ok(v)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<f64, E> {
+            Ok(v as f64)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<f64, E> {
+            Ok(v as f64)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<f64, E> {
+            match v {
+                "NaN" => Ok(f64::NAN),
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                _ => Err(E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+    }
 }
 
 impl Value {
 
-    fn to_string(&self) -> String {
+    pub fn to_string(&self) -> String {
         match self {
             &Value::UnsignedLong(l) => l.to_string(),
+            &Value::Long(l) => l.to_string(),
             &Value::Double(d) => d.to_string(),
             &Value::Boolean(b) => b.to_string(),
             &Value::String(ref s) => s.clone(),
@@ -137,13 +226,14 @@ impl Value {
                     .map(|v| v.to_string())
                     .collect();
                 s.join(",")
-            }
+            },
+            &Value::Null => String::new()
         }
     }
 
 }
 
-#[derive(Debug,Clone,Serialize,Deserialize)]
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
 pub enum Operator {
     Eq,
     NotEq,
@@ -151,10 +241,26 @@ pub enum Operator {
     LtEq,
     Gt,
     GtEq,
+    And,
+    Or,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulus,
+}
+
+impl Operator {
+
+    /// Determine whether this is an arithmetic operator rather than a comparison or logical operator
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(*self, Operator::Plus | Operator::Minus | Operator::Multiply |
+                        Operator::Divide | Operator::Modulus)
+    }
 }
 
 /// Relation Expression
-#[derive(Debug,Clone,Serialize, Deserialize)]
+#[derive(Debug,Clone,PartialEq,Serialize, Deserialize)]
 pub enum Expr {
     /// index into a value within the tuple
     TupleValue(usize),
@@ -162,8 +268,43 @@ pub enum Expr {
     Literal(Value),
     /// binary expression e.g. "age > 21"
     BinaryExpr { left: Box<Expr>, op: Operator, right: Box<Expr> },
+    /// logical negation of a boolean expression
+    Not(Box<Expr>),
+    /// true if the expression evaluates to null
+    IsNull(Box<Expr>),
+    /// true if the expression does not evaluate to null
+    IsNotNull(Box<Expr>),
+    /// the first argument that does not evaluate to null, or null if they all do
+    Coalesce(Vec<Expr>),
     /// scalar function
-    ScalarFunction { name: String, args: Vec<Expr> }
+    ScalarFunction { name: String, args: Vec<Expr> },
+    /// aggregate function e.g. "SUM(amount)". COUNT(*) is represented with an empty argument list
+    AggregateFunction { name: String, args: Vec<Expr> },
+    /// expression with a user-specified name for the column it produces
+    Alias { expr: Box<Expr>, alias: String },
+    /// sort key, only valid within a sort
+    Sort { expr: Box<Expr>, asc: bool, nulls_first: bool },
+    /// window function e.g. "RANK() OVER (PARTITION BY dept ORDER BY salary)", computed over the
+    /// rows of the same partition in the order of the sort keys in `order_by`. When `frame` is
+    /// not specified, aggregates are computed from the start of the partition up to the last row
+    /// sorting equal to the current row, or over the whole partition if there is no ORDER BY.
+    WindowFunction { name: String, args: Vec<Expr>, partition_by: Vec<Expr>, order_by: Vec<Expr>, frame: Option<WindowFrame> }
+}
+
+/// Rows of the partition that a windowed aggregate is computed over, relative to the current row
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct WindowFrame {
+    pub start: WindowFrameBound,
+    pub end: WindowFrameBound
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum WindowFrameBound {
+    UnboundedPreceding,
+    Preceding(usize),
+    CurrentRow,
+    Following(usize),
+    UnboundedFollowing
 }
 
 impl Expr {
@@ -192,6 +333,399 @@ impl Expr {
         }
     }
 
+    pub fn plus(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Plus,
+            right: Box::new(other.clone())
+        }
+    }
+
+    pub fn minus(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Minus,
+            right: Box::new(other.clone())
+        }
+    }
+
+    pub fn multiply(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Multiply,
+            right: Box::new(other.clone())
+        }
+    }
+
+    pub fn divide(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Divide,
+            right: Box::new(other.clone())
+        }
+    }
+
+    pub fn modulus(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Modulus,
+            right: Box::new(other.clone())
+        }
+    }
+
+    /// Combine this expression with another using logical AND
+    pub fn and(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::And,
+            right: Box::new(other.clone())
+        }
+    }
+
+    /// Combine this expression with another using logical OR
+    pub fn or(&self, other: &Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(self.clone()),
+            op: Operator::Or,
+            right: Box::new(other.clone())
+        }
+    }
+
+    /// Negate this boolean expression
+    pub fn not(&self) -> Expr {
+        Expr::Not(Box::new(self.clone()))
+    }
+
+    /// Name the column produced by this expression
+    pub fn alias(&self, alias: &str) -> Expr {
+        Expr::Alias {
+            expr: Box::new(self.clone()),
+            alias: alias.to_string()
+        }
+    }
+
+    /// Get the expression that an aliased expression names, or this expression if it is not
+    /// aliased
+    pub fn strip_alias(&self) -> &Expr {
+        match *self {
+            Expr::Alias { ref expr, .. } => expr.strip_alias(),
+            _ => self
+        }
+    }
+
+    pub fn is_null(&self) -> Expr {
+        Expr::IsNull(Box::new(self.clone()))
+    }
+
+    pub fn is_not_null(&self) -> Expr {
+        Expr::IsNotNull(Box::new(self.clone()))
+    }
+
+    /// Create a sort key from this expression
+    pub fn sort(&self, asc: bool, nulls_first: bool) -> Expr {
+        Expr::Sort {
+            expr: Box::new(self.clone()),
+            asc,
+            nulls_first
+        }
+    }
+
+    /// Determine the column that this expression produces when evaluated against the input
+    /// schema, using the metadata of the registered functions to type function calls. Fails if
+    /// the expression is not valid for the types of its inputs.
+    pub fn to_field(&self, input_schema: &Schema, functions: &HashMap<String, FunctionMeta>) -> Result<Field, String> {
+        match *self {
+            Expr::TupleValue(i) => match input_schema.columns.get(i) {
+                Some(field) => Ok(field.clone()),
+                None => Err(format!("Column index {} is out of range for schema {}", i, input_schema.to_string()))
+            },
+            Expr::Literal(ref value) => {
+                let data_type = match *value {
+                    Value::UnsignedLong(_) => DataType::UnsignedLong,
+                    Value::Long(_) => DataType::Long,
+                    Value::Double(_) => DataType::Double,
+                    Value::String(_) => DataType::String,
+                    Value::Boolean(_) => DataType::Boolean,
+                    _ => return Err(format!("Cannot determine the type of literal {:?}", value))
+                };
+                Ok(Field::new("literal", data_type, false))
+            },
+            // COALESCE returns the type of its first argument, which all of its arguments must
+            // have. The result is only null when every argument may be null.
+            Expr::Coalesce(ref args) => {
+                let fields = args.iter()
+                    .map(|a| a.to_field(input_schema, functions))
+                    .collect::<Result<Vec<Field>, String>>()?;
+                let data_type = match fields.first() {
+                    Some(field) => field.data_type.clone(),
+                    None => return Err("COALESCE expects at least one argument".to_string())
+                };
+                if let Some(field) = fields.iter().find(|f| f.data_type != data_type) {
+                    return Err(format!("COALESCE expects all arguments to be {:?} but found {:?}",
+                                       data_type, field.data_type));
+                }
+                Ok(Field::new("coalesce", data_type, fields.iter().all(|f| f.nullable)))
+            },
+            Expr::ScalarFunction { ref name, ref args } => {
+                let meta = match functions.get(&name.to_lowercase()) {
+                    Some(meta) if meta.function_type == FunctionType::Scalar => meta,
+                    _ => return Err(format!("Unknown function {}", name))
+                };
+                let nullable = check_function_args(meta, args, input_schema, functions)?;
+                Ok(Field::new(name, meta.return_type.clone(), nullable))
+            },
+            Expr::AggregateFunction { ref name, ref args } if !is_builtin_aggregate(name) => {
+                let meta = match functions.get(&name.to_lowercase()) {
+                    Some(meta) if meta.function_type == FunctionType::Aggregate => meta,
+                    _ => return Err(format!("Unknown aggregate function {}", name))
+                };
+                check_function_args(meta, args, input_schema, functions)?;
+                Ok(Field::new(name, meta.return_type.clone(), true))
+            },
+            Expr::AggregateFunction { ref name, ref args } => {
+                let arg = match args.len() {
+                    0 => None,
+                    1 => Some(args[0].to_field(input_schema, functions)?.data_type),
+                    _ => return Err(format!("Aggregate function {} expects one argument", name))
+                };
+                let data_type = match (name.to_lowercase().as_ref(), arg) {
+                    ("count", _) => DataType::UnsignedLong,
+                    ("avg", Some(ref t)) if is_numeric(t) => DataType::Double,
+                    ("sum", Some(t)) => if is_numeric(&t) {
+                        t
+                    } else {
+                        return Err(format!("SUM expects a numeric argument but found {:?}", t))
+                    },
+                    // MIN and MAX return the same type as their input
+                    ("min", Some(t)) | ("max", Some(t)) => t,
+                    (_, arg) => return Err(format!("Invalid aggregate function {} with argument {:?}", name, arg))
+                };
+                // only COUNT has a value when there are no input values
+                Ok(Field::new(name, data_type, name.to_lowercase() != "count"))
+            },
+            Expr::BinaryExpr { ref left, ref op, ref right } => {
+                let left = left.to_field(input_schema, functions)?;
+                let right = right.to_field(input_schema, functions)?;
+                let data_type = match *op {
+                    // integers are promoted to doubles, and unsigned integers are promoted to
+                    // signed integers when combined with them or when subtracted
+                    _ if op.is_arithmetic() => match (&left.data_type, &right.data_type) {
+                        (l, r) if !is_numeric(l) || !is_numeric(r) =>
+                            return Err(format!("Cannot apply {:?} to {:?} and {:?}", op, l, r)),
+                        (&DataType::Double, _) | (_, &DataType::Double) => DataType::Double,
+                        (&DataType::Long, _) | (_, &DataType::Long) => DataType::Long,
+                        _ if *op == Operator::Minus => DataType::Long,
+                        _ => DataType::UnsignedLong
+                    },
+                    Operator::And | Operator::Or => {
+                        if left.data_type != DataType::Boolean || right.data_type != DataType::Boolean {
+                            return Err(format!("Cannot apply {:?} to {:?} and {:?}",
+                                               op, left.data_type, right.data_type));
+                        }
+                        DataType::Boolean
+                    },
+                    _ => {
+                        let comparable = left.data_type == right.data_type ||
+                            (is_numeric(&left.data_type) && is_numeric(&right.data_type));
+                        if !comparable {
+                            return Err(format!("Cannot compare {:?} with {:?}", left.data_type, right.data_type));
+                        }
+                        DataType::Boolean
+                    }
+                };
+                Ok(Field::new("expr", data_type, left.nullable || right.nullable))
+            },
+            Expr::Not(ref expr) => {
+                let field = expr.to_field(input_schema, functions)?;
+                if field.data_type != DataType::Boolean {
+                    return Err(format!("Cannot apply NOT to {:?}", field.data_type));
+                }
+                Ok(Field::new("expr", DataType::Boolean, field.nullable))
+            },
+            Expr::IsNull(ref expr) | Expr::IsNotNull(ref expr) => {
+                expr.to_field(input_schema, functions)?;
+                Ok(Field::new("expr", DataType::Boolean, false))
+            },
+            Expr::Alias { ref expr, ref alias } => {
+                let field = expr.to_field(input_schema, functions)?;
+                Ok(Field::new(alias, field.data_type, field.nullable))
+            },
+            Expr::Sort { ref expr, .. } => expr.to_field(input_schema, functions),
+            Expr::WindowFunction { ref name, ref args, ref partition_by, ref order_by, .. } => {
+                for e in partition_by.iter().chain(order_by.iter()) {
+                    e.to_field(input_schema, functions)?;
+                }
+                match name.to_lowercase().as_ref() {
+                    "row_number" | "rank" | "dense_rank" => if args.is_empty() {
+                        Ok(Field::new(name, DataType::UnsignedLong, false))
+                    } else {
+                        Err(format!("Window function {} does not take arguments", name))
+                    },
+                    "lag" | "lead" => {
+                        if args.is_empty() || args.len() > 3 {
+                            return Err(format!("Window function {} expects between one and three arguments", name));
+                        }
+                        if args.len() > 1 && !matches!(args[1], Expr::Literal(Value::UnsignedLong(_))) {
+                            return Err(format!("The offset of {} must be a non-negative integer literal", name));
+                        }
+                        let field = args[0].to_field(input_schema, functions)?;
+                        if args.len() == 3 {
+                            let default = args[2].to_field(input_schema, functions)?;
+                            if default.data_type != field.data_type && !can_coerce(&default.data_type, &field.data_type) {
+                                return Err(format!("The default value of {} must be {:?} but found {:?}",
+                                                   name, field.data_type, default.data_type));
+                            }
+                        }
+                        Ok(Field::new(name, field.data_type, true))
+                    },
+                    // any aggregate function can be computed over a window
+                    _ => Expr::AggregateFunction { name: name.clone(), args: args.clone() }
+                        .to_field(input_schema, functions)
+                        .map_err(|e| format!("Invalid window function {}: {}", name, e))
+                }
+            }
+        }
+    }
+
+}
+
+/// Determine whether a function name refers to one of the built-in aggregate functions, which
+/// are polymorphic in their argument types
+pub fn is_builtin_aggregate(name: &str) -> bool {
+    matches!(name.to_lowercase().as_ref(), "count" | "sum" | "min" | "max" | "avg")
+}
+
+/// Check the arguments of a function call against the function metadata, returning whether the
+/// result may be null because one of the arguments may be null
+fn check_function_args(meta: &FunctionMeta, args: &[Expr], input_schema: &Schema,
+                       functions: &HashMap<String, FunctionMeta>) -> Result<bool, String> {
+    if args.len() != meta.args.len() {
+        return Err(format!("Function {} expects {} arguments but was called with {}",
+                           meta.name, meta.args.len(), args.len()));
+    }
+    let mut nullable = false;
+    for (arg, expected) in args.iter().zip(meta.args.iter()) {
+        let arg = arg.to_field(input_schema, functions)?;
+        if !can_coerce(&arg.data_type, &expected.data_type) {
+            return Err(format!("Function {} expects argument {} to be {:?} but found {:?}",
+                               meta.name, expected.name, expected.data_type, arg.data_type));
+        }
+        nullable = nullable || arg.nullable;
+    }
+    Ok(nullable)
+}
+
+fn is_numeric(data_type: &DataType) -> bool {
+    matches!(*data_type, DataType::UnsignedLong | DataType::Long | DataType::Double)
+}
+
+/// Determine whether a value of one type can be passed where another type is expected. Integers
+/// are promoted to doubles, and unsigned integers to signed integers.
+fn can_coerce(from: &DataType, to: &DataType) -> bool {
+    from == to || matches!((from, to),
+        (&DataType::UnsignedLong, &DataType::Long) |
+        (&DataType::UnsignedLong, &DataType::Double) |
+        (&DataType::Long, &DataType::Double))
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum JoinType {
+    Inner,
+    Left,
+    Right,
+    Full,
+    Cross
+}
+
+/// How a CSV file is parsed. The defaults read comma-separated files with a header row, where
+/// quotes are escaped by doubling them and empty fields are null.
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(default)]
+pub struct CsvOptions {
+    /// whether the first line of the file contains the column names rather than data
+    pub has_header: bool,
+    pub delimiter: u8,
+    pub quote: u8,
+    /// character that escapes a quote within a quoted field, in addition to doubling the quote
+    pub escape: Option<u8>,
+    /// lines starting with this character are skipped
+    pub comment: Option<u8>,
+    /// whether leading and trailing whitespace is removed from each field
+    pub trim: bool,
+    /// fields equal to this string are null
+    pub null_string: String
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            has_header: true,
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            comment: None,
+            trim: false,
+            null_string: String::new()
+        }
+    }
+}
+
+impl CsvOptions {
+
+    /// Set an option by name, as given in `CREATE EXTERNAL TABLE ... WITH OPTIONS (...)`. Names
+    /// are case-insensitive and characters must be a single ASCII character. An empty escape or
+    /// comment character disables it.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        fn boolean(name: &str, value: &str) -> Result<bool, String> {
+            match value.to_lowercase().as_ref() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(format!("Invalid value '{}' for CSV option {}, expected true or false", value, name))
+            }
+        }
+        fn character(name: &str, value: &str) -> Result<u8, String> {
+            match value.as_bytes() {
+                [c] if c.is_ascii() => Ok(*c),
+                _ => Err(format!("Invalid value '{}' for CSV option {}, expected a single ASCII character", value, name))
+            }
+        }
+        fn optional_character(name: &str, value: &str) -> Result<Option<u8>, String> {
+            if value.is_empty() { Ok(None) } else { character(name, value).map(Some) }
+        }
+
+        match name.to_lowercase().as_ref() {
+            "has_header" => self.has_header = boolean(name, value)?,
+            "delimiter" => self.delimiter = character(name, value)?,
+            "quote" => self.quote = character(name, value)?,
+            "escape" => self.escape = optional_character(name, value)?,
+            "comment" => self.comment = optional_character(name, value)?,
+            "trim" => self.trim = boolean(name, value)?,
+            "null_string" => self.null_string = value.to_string(),
+            _ => return Err(format!("Unknown CSV option {}", name))
+        }
+        Ok(())
+    }
+}
+
+/// How an aggregate combines its input. Splitting an aggregate into partial and final aggregates
+/// allows the partial aggregates to be computed for partitions of the input in parallel.
+#[derive(Debug,Clone,Copy,PartialEq,Serialize,Deserialize)]
+pub enum AggregateMode {
+    /// compute the aggregate values from the input rows
+    Complete,
+    /// compute the intermediate state of each aggregate from the input rows, e.g. the sum and
+    /// count for AVG. The schema contains the grouping columns followed by the states.
+    Partial,
+    /// merge the states produced by partial aggregates. The input contains the grouping columns
+    /// followed by a state for each aggregate expression, so the expressions are only used to
+    /// determine which aggregate functions to use.
+    Final
+}
+
+impl Default for AggregateMode {
+    fn default() -> Self {
+        AggregateMode::Complete
+    }
 }
 
 /// Relations
@@ -199,20 +733,71 @@ impl Expr {
 pub enum LogicalPlan {
     Projection { expr: Vec<Expr>, input: Box<LogicalPlan>, schema: Schema },
     Selection { expr: Expr, input: Box<LogicalPlan>, schema: Schema },
-    TableScan { schema_name: String, table_name: String, schema: Schema },
-    CsvFile { filename: String, schema: Schema },
-    EmptyRelation
+    /// Group the input by the grouping expressions and compute the aggregate expressions for each
+    /// group. The schema contains the grouping columns followed by the aggregate columns.
+    Aggregate {
+        group_expr: Vec<Expr>,
+        aggr_expr: Vec<Expr>,
+        input: Box<LogicalPlan>,
+        schema: Schema,
+        #[serde(default)]
+        mode: AggregateMode
+    },
+    /// Sort the input by the sort keys in `expr`, which are all `Expr::Sort`
+    Sort { expr: Vec<Expr>, input: Box<LogicalPlan>, schema: Schema },
+    /// Compute the window functions in `window_expr` over the input. The schema contains the
+    /// columns of the input followed by a column for each window function.
+    Window { window_expr: Vec<Expr>, input: Box<LogicalPlan>, schema: Schema },
+    /// Join two relations. Each pair in `on` is a key expression against the left input and a key
+    /// expression against the right input that must be equal. The schema contains the columns of
+    /// the left input followed by the columns of the right input.
+    Join { left: Box<LogicalPlan>, right: Box<LogicalPlan>, join_type: JoinType, on: Vec<(Expr, Expr)>, schema: Schema },
+    /// Skip the first `offset` rows of the input and then return at most `limit` rows
+    Limit { limit: usize, offset: usize, input: Box<LogicalPlan>, schema: Schema },
+    /// Read a table. The schema is the schema of the table, and when `projection` is set only
+    /// the columns at those indices are read, in that order.
+    TableScan {
+        schema_name: String,
+        table_name: String,
+        schema: Schema,
+        #[serde(default)]
+        projection: Option<Vec<usize>>
+    },
+    /// Read a CSV file, optionally reading only some of its columns as for `TableScan`
+    CsvFile {
+        filename: String,
+        schema: Schema,
+        #[serde(default)]
+        projection: Option<Vec<usize>>,
+        #[serde(default)]
+        options: CsvOptions
+    },
+    /// Read the rows written by a shuffle, which hash partitioned the output of a plan into
+    /// `partition_count` partitions on each of the `workers`. When the plan containing the
+    /// exchange is executed as one partition of several, only the matching shuffle partitions
+    /// are read from each worker.
+    Exchange { shuffle_id: String, workers: Vec<String>, partition_count: usize, schema: Schema },
+    /// A relation without any rows
+    EmptyRelation { schema: Schema }
 }
 
 impl LogicalPlan {
 
     pub fn schema(&self) -> Schema {
         match self {
-            &LogicalPlan::EmptyRelation => Schema::empty(),
+            &LogicalPlan::EmptyRelation { ref schema } => schema.clone(),
+            &LogicalPlan::TableScan { ref schema, projection: Some(ref projection), .. } |
+            &LogicalPlan::CsvFile { ref schema, projection: Some(ref projection), .. } => schema.project(projection),
             &LogicalPlan::TableScan { ref schema, .. } => schema.clone(),
             &LogicalPlan::CsvFile { ref schema, .. } => schema.clone(),
             &LogicalPlan::Projection { ref schema, .. } => schema.clone(),
             &LogicalPlan::Selection { ref schema, .. } => schema.clone(),
+            &LogicalPlan::Aggregate { ref schema, .. } => schema.clone(),
+            &LogicalPlan::Sort { ref schema, .. } => schema.clone(),
+            &LogicalPlan::Limit { ref schema, .. } => schema.clone(),
+            &LogicalPlan::Window { ref schema, .. } => schema.clone(),
+            &LogicalPlan::Join { ref schema, .. } => schema.clone(),
+            &LogicalPlan::Exchange { ref schema, .. } => schema.clone(),
         }
     }
 }
@@ -236,7 +821,7 @@ mod tests {
             ]
         };
 
-        let csv = CsvFile { filename: "test/data/people.csv".to_string(), schema: tt.clone() };
+        let csv = CsvFile { filename: "test/data/people.csv".to_string(), schema: tt.clone(), projection: None, options: CsvOptions::default() };
 
         let filter_expr = BinaryExpr {
             left: Box::new(TupleValue(0)),
@@ -255,5 +840,20 @@ mod tests {
         println!("serialized: {}", s);
     }
 
+    #[test]
+    fn serde_non_finite_doubles() {
+
+        let values = vec![Double(1.5), Double(-0.0), Double(f64::INFINITY),
+                          Double(f64::NEG_INFINITY), Double(f64::NAN)];
+
+        let s = serde_json::to_string(&values).unwrap();
+        assert_eq!(r#"[{"Double":1.5},{"Double":-0.0},{"Double":"inf"},{"Double":"-inf"},{"Double":"NaN"}]"#, s);
+
+        let values: Vec<Value> = serde_json::from_str(&s).unwrap();
+        assert_eq!("1.5,-0,inf,-inf,NaN", values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","));
+        assert_eq!(Double(0.0), values[1]);
+        assert!(serde_json::from_str::<Value>(r#"{"Double":"1.5"}"#).is_err());
+    }
+
 }
 
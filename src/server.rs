@@ -0,0 +1,245 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for the HTTP servers of the worker and coordinator: output formats, structured error
+//! responses and streaming of query results.
+
+use std::io::{self, ErrorKind};
+use std::thread;
+
+use super::csv;
+use super::futures::{self, Future, Sink};
+use super::futures::sync::oneshot;
+use super::hyper::{self, Body, Chunk, StatusCode};
+use super::hyper::header::{ContentLength, ContentType};
+use super::hyper::server::Response;
+use super::serde_json;
+
+use super::exec::*;
+use super::parser::ParserError;
+use super::rel::*;
+
+/// Approximate number of bytes of results to send in each chunk of the response
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Formats that query results can be returned in, chosen with the `format` query parameter
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum OutputFormat {
+    /// one JSON object per row, keyed by column name (the default)
+    JsonLines,
+    /// CSV with a header row
+    Csv,
+    /// one JSON array of serialized values per row, which preserves the exact type of each value
+    /// so that results can be processed further, e.g. by the coordinator
+    Values
+}
+
+impl OutputFormat {
+
+    pub fn from_query(query: Option<&str>) -> Result<Self, String> {
+        let format = query_param(query, "format");
+        match format {
+            None | Some("json") => Ok(OutputFormat::JsonLines),
+            Some("csv") => Ok(OutputFormat::Csv),
+            Some("values") => Ok(OutputFormat::Values),
+            Some(other) => Err(format!("Unsupported output format '{}'", other))
+        }
+    }
+
+    fn content_type(&self) -> ContentType {
+        match *self {
+            OutputFormat::JsonLines | OutputFormat::Values => ContentType("application/x-ndjson".parse().unwrap()),
+            OutputFormat::Csv => ContentType("text/csv".parse().unwrap())
+        }
+    }
+
+    fn write_header(&self, schema: &Schema, out: &mut Vec<u8>) {
+        if *self == OutputFormat::Csv {
+            write_csv_record(schema.columns.iter().map(|c| c.name.clone()), out);
+        }
+    }
+
+    fn write_row(&self, schema: &Schema, row: &Row, out: &mut Vec<u8>) {
+        match *self {
+            OutputFormat::JsonLines => {
+                let object: serde_json::Map<String, serde_json::Value> = schema.columns.iter()
+                    .zip(row.values.iter())
+                    .map(|(c, v)| (c.name.clone(), to_json(v)))
+                    .collect();
+                serde_json::to_writer(&mut *out, &object).unwrap();
+                out.push(b'\n');
+            },
+            OutputFormat::Csv => write_csv_record(row.values.iter().map(|v| v.to_string()), out),
+            OutputFormat::Values => {
+                serde_json::to_writer(&mut *out, &row.values).unwrap();
+                out.push(b'\n');
+            }
+        }
+    }
+}
+
+/// Get the value of a parameter from a query string. If the parameter is repeated then the last
+/// value is used.
+pub fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query.unwrap_or("")
+        .split('&')
+        .filter_map(|param| {
+            let mut parts = param.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(n), Some(value)) if n == name => Some(value),
+                _ => None
+            }
+        })
+        .next_back()
+}
+
+fn write_csv_record<I: Iterator<Item=String>>(fields: I, out: &mut Vec<u8>) {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(fields).unwrap();
+    writer.flush().unwrap();
+}
+
+/// Convert a value to JSON. Complex values are arrays and doubles that cannot be represented
+/// in JSON (NaN and infinity) are null.
+fn to_json(value: &Value) -> serde_json::Value {
+    match *value {
+        Value::Boolean(b) => json!(b),
+        Value::UnsignedLong(n) => json!(n),
+        Value::Long(n) => json!(n),
+        Value::Double(d) => json!(d),
+        Value::String(ref s) => json!(s),
+        Value::ComplexValue(ref v) => serde_json::Value::Array(v.iter().map(to_json).collect()),
+        Value::Null => serde_json::Value::Null
+    }
+}
+
+/// Create a JSON error document
+pub fn error_json(status: StatusCode, kind: &str, message: &str) -> String {
+    json!({ "error": { "status": status.as_u16(), "kind": kind, "message": message } }).to_string()
+}
+
+/// Get the message from a JSON error document, or `None` if the text is not an error document
+pub fn parse_error_json(text: &str) -> Option<String> {
+    let doc: serde_json::Value = serde_json::from_str(text).ok()?;
+    doc["error"]["message"].as_str().map(|s| s.to_string())
+}
+
+pub fn error_response(status: StatusCode, kind: &str, message: &str) -> Response {
+    let body = error_json(status, kind, message);
+    Response::new()
+        .with_status(status)
+        .with_header(ContentType::json())
+        .with_header(ContentLength(body.len() as u64))
+        .with_body(body)
+}
+
+/// Determine the HTTP status, error kind and message to report for an execution error
+fn describe_error(e: &ExecutionError) -> (StatusCode, &'static str, String) {
+    match *e {
+        ExecutionError::IoError(ref e) if e.kind() == ErrorKind::NotFound =>
+            (StatusCode::NotFound, "io", e.to_string()),
+        ExecutionError::IoError(ref e) => (StatusCode::InternalServerError, "io", e.to_string()),
+        ExecutionError::CsvError(ref e) => (StatusCode::InternalServerError, "csv", e.to_string()),
+        ExecutionError::ParserError(ParserError::TokenizerError(ref msg)) |
+        ExecutionError::ParserError(ParserError::ParserError(ref msg)) =>
+            (StatusCode::BadRequest, "parser", msg.clone()),
+        ExecutionError::NullValue(ref column) => (StatusCode::InternalServerError, "null_value",
+            format!("Null value found in column {}, which is not nullable", column)),
+        ExecutionError::ForbiddenPath(ref msg) => (StatusCode::Forbidden, "forbidden_path", msg.clone()),
+        ExecutionError::InvalidRequest(ref msg) => (StatusCode::BadRequest, "invalid_request", msg.clone()),
+        ExecutionError::Custom(ref msg) => (StatusCode::InternalServerError, "execution", msg.clone())
+    }
+}
+
+pub fn execution_error_response(e: &ExecutionError) -> Response {
+    let (status, kind, message) = describe_error(e);
+    error_response(status, kind, &message)
+}
+
+/// Execute a query on a new thread and stream the results. `create_relation` is called on that
+/// thread to create the relation to scan, along with the context to scan it with. The response is
+/// returned once the first row is available, so errors creating the relation or reading the first
+/// row have an error status. Errors after that are reported as a final JSON line, or by aborting
+/// the response for CSV.
+pub fn stream_results<F>(format: OutputFormat, create_relation: F) -> Box<dyn Future<Item=Response, Error=hyper::Error>>
+    where F: FnOnce() -> Result<(ExecutionContext, Box<dyn SimpleRelation>), ExecutionError> + Send + 'static {
+
+    // relations borrow the execution context, so they cannot be sent between threads
+    let (response_tx, response_rx) = oneshot::channel();
+    thread::spawn(move || {
+        let (ctx, rel) = match create_relation() {
+            Ok(result) => result,
+            Err(e) => {
+                let _ = response_tx.send(execution_error_response(&e));
+                return;
+            }
+        };
+
+        let mut rows = rel.scan(&ctx).peekable();
+        if let Some(&Err(_)) = rows.peek() {
+            if let Some(Err(e)) = rows.next() {
+                let _ = response_tx.send(execution_error_response(&e));
+            }
+            return;
+        }
+
+        let (mut body_tx, body) = Body::pair();
+        let response = Response::new()
+            .with_header(format.content_type())
+            .with_body(body);
+        if response_tx.send(response).is_err() {
+            return;
+        }
+
+        let schema = rel.schema();
+        let mut buffer = vec![];
+        format.write_header(schema, &mut buffer);
+
+        let mut error = None;
+        for row in rows {
+            match row {
+                Ok(row) => format.write_row(schema, &row, &mut buffer),
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+            if buffer.len() >= CHUNK_SIZE {
+                let chunk = Ok(Chunk::from(buffer.split_off(0)));
+                body_tx = match body_tx.send(chunk).wait() {
+                    Ok(tx) => tx,
+                    Err(_) => return // the client has gone away
+                };
+            }
+        }
+
+        let mut last = vec![Ok(Chunk::from(buffer))];
+        if let Some(e) = error {
+            let (status, kind, message) = describe_error(&e);
+            match format {
+                OutputFormat::JsonLines | OutputFormat::Values => {
+                    let line = format!("{}\n", error_json(status, kind, &message));
+                    last.push(Ok(Chunk::from(line)));
+                },
+                OutputFormat::Csv => {
+                    last.push(Err(hyper::Error::from(io::Error::new(io::ErrorKind::Other, message))));
+                }
+            }
+        }
+        let _ = body_tx.send_all(futures::stream::iter_ok(last)).wait();
+    });
+
+    Box::new(response_rx.or_else(|_| Ok(error_response(
+        StatusCode::InternalServerError, "execution", "Execution thread stopped unexpectedly"))))
+}
@@ -0,0 +1,246 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shuffle exchange. A shuffle hash partitions the rows of a plan by an expression so that all
+//! rows with the same value end up in the same partition, which allows joins and aggregates to be
+//! executed independently for each partition.
+//!
+//! Each worker executes the plan for its split of the input and writes the rows for each shuffle
+//! partition to a local file, named `<input partition>_<shuffle partition>.json` in a directory
+//! named after the shuffle. Workers then read the shuffle partitions they need from every worker
+//! with an `Exchange` plan, which fetches them over HTTP.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind};
+use std::io::prelude::*;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use super::serde_json;
+
+use super::client::*;
+use super::exec::*;
+use super::explain::*;
+use super::rel::*;
+
+/// Get the directory holding the files for a shuffle. Shuffle ids are used as directory names so
+/// they may only contain ASCII letters, digits, '-' and '_'.
+pub fn shuffle_dir(root: &Path, shuffle_id: &str) -> Result<PathBuf, ExecutionError> {
+    if shuffle_id.is_empty() || !shuffle_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(ExecutionError::InvalidRequest(format!("Invalid shuffle id '{}'", shuffle_id)));
+    }
+    Ok(root.join(shuffle_id))
+}
+
+/// Read one partition of a shuffle, as written by every input partition that was executed
+/// locally. The rows are returned in the `values` format.
+pub fn read_shuffle_partition(root: &Path, shuffle_id: &str, partition: usize) -> Result<Vec<u8>, ExecutionError> {
+    let suffix = format!("_{}.json", partition);
+    let mut paths = fs::read_dir(shuffle_dir(root, shuffle_id)?)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    paths.retain(|p| p.file_name().and_then(|n| n.to_str()).map_or(false, |n| n.ends_with(&suffix)));
+    paths.sort();
+
+    let mut data = vec![];
+    for path in paths {
+        File::open(path)?.read_to_end(&mut data)?;
+    }
+    Ok(data)
+}
+
+/// Delete the files written for a shuffle. Deleting a shuffle that does not exist succeeds.
+pub fn remove_shuffle(root: &Path, shuffle_id: &str) -> Result<(), ExecutionError> {
+    match fs::remove_dir_all(shuffle_dir(root, shuffle_id)?) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        other => Ok(other?)
+    }
+}
+
+/// Schema of the rows returned by a shuffle write, which are the number of rows written to each
+/// shuffle partition
+pub fn shuffle_write_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("partition", DataType::UnsignedLong, false),
+        Field::new("rows", DataType::UnsignedLong, false)])
+}
+
+/// Hash partitions the rows of its input by an expression and writes them to shuffle files
+pub struct ShuffleWriteRelation {
+    schema: Schema,
+    input: Box<dyn SimpleRelation>,
+    partition_expr: Expr,
+    partition_count: usize,
+    /// partition of the input that is being shuffled, which names the files that are written
+    input_partition: usize,
+    dir: PathBuf
+}
+
+impl ShuffleWriteRelation {
+
+    pub fn new(input: Box<dyn SimpleRelation>, partition_expr: Expr, partition_count: usize,
+               input_partition: usize, dir: PathBuf) -> Self {
+        ShuffleWriteRelation { schema: shuffle_write_schema(), input, partition_expr, partition_count, input_partition, dir }
+    }
+
+    fn write(&self, ctx: &ExecutionContext) -> Result<Vec<Row>, ExecutionError> {
+        fs::create_dir_all(&self.dir)?;
+        let mut writers = (0..self.partition_count)
+            .map(|p| {
+                let path = self.dir.join(format!("{}_{}.json", self.input_partition, p));
+                Ok(BufWriter::new(File::create(path)?))
+            })
+            .collect::<Result<Vec<BufWriter<File>>, ExecutionError>>()?;
+        let mut counts = vec![0u64; self.partition_count];
+
+        for row in self.input.scan(ctx) {
+            let row = row?;
+            let key = ctx.evaluate(&row, self.input.schema(), &self.partition_expr).map_err(|e| *e)?;
+            let partition = hash_partition(&key, self.partition_count);
+            serde_json::to_writer(&mut writers[partition], &row.values)
+                .map_err(|e| ExecutionError::Custom(format!("Error writing shuffle file: {}", e)))?;
+            writers[partition].write_all(b"\n")?;
+            counts[partition] += 1;
+        }
+
+        for writer in &mut writers {
+            writer.flush()?;
+        }
+
+        Ok(counts.into_iter().enumerate()
+            .map(|(p, n)| Row::new(vec![Value::UnsignedLong(p as u64), Value::UnsignedLong(n)]))
+            .collect())
+    }
+}
+
+impl SimpleRelation for ShuffleWriteRelation {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        match self.write(ctx) {
+            Ok(rows) => Box::new(rows.into_iter().map(Ok)),
+            Err(e) => Box::new(iter::once(Err(e)))
+        }
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        format!("ShuffleWriteRelation: partition_by={}, partitions={}",
+                format_expr(&self.partition_expr, self.input.schema()), self.partition_count)
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        vec![RelationRef::Rows(&*self.input)]
+    }
+}
+
+/// Reads shuffle partitions from the workers that wrote them
+pub struct ExchangeRelation {
+    schema: Schema,
+    shuffle_id: String,
+    workers: Vec<String>,
+    partitions: Vec<usize>
+}
+
+impl ExchangeRelation {
+
+    pub fn new(schema: Schema, shuffle_id: String, workers: Vec<String>, partitions: Vec<usize>) -> Self {
+        ExchangeRelation { schema, shuffle_id, workers, partitions }
+    }
+
+    fn fetch(&self) -> Result<Vec<Row>, ExecutionError> {
+        let requests = self.partitions.iter()
+            .flat_map(|&p| self.workers.iter().map(move |w| WorkerRequest::fetch_shuffle_partition(w, &self.shuffle_id, p)))
+            .collect();
+        Ok(send_requests(requests)?.into_iter().flat_map(|rows| rows.into_iter()).collect())
+    }
+}
+
+impl SimpleRelation for ExchangeRelation {
+
+    fn scan<'a>(&'a self, _ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        match self.fetch() {
+            Ok(rows) => Box::new(rows.into_iter().map(Ok)),
+            Err(e) => Box::new(iter::once(Err(e)))
+        }
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn describe(&self) -> String {
+        let partitions: Vec<String> = self.partitions.iter().map(|p| p.to_string()).collect();
+        format!("ExchangeRelation: shuffle_id={}, partitions=[{}], workers=[{}]",
+                self.shuffle_id, partitions.join(", "), self.workers.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_write_and_read_shuffle() {
+        let root = env::temp_dir().join(format!("datafusion_shuffle_test_{}", process::id()));
+        let mut ctx = ExecutionContext::new();
+        ctx.set_shuffle_dir(&root);
+
+        let schema = Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]);
+        let plan = ExecutionPlan::Partition {
+            plan: LogicalPlan::CsvFile { filename: "test/data/orders.csv".to_string(), schema, projection: None, options: CsvOptions::default() },
+            partition_count: 2,
+            partition_expr: Some(Expr::TupleValue(1)),
+            partition: Some(0),
+            shuffle_id: Some("test".to_string())
+        };
+
+        // the first split of the file is shuffled into two partitions
+        let rel = ctx.execute(&plan).unwrap();
+        let counts: Vec<String> = rel.scan(&ctx).map(|r| r.unwrap().to_string()).collect();
+        assert_eq!(2, counts.len());
+
+        let mut customers_by_partition = vec![];
+        for (partition, count) in counts.iter().enumerate() {
+            let data = read_shuffle_partition(&root, "test", partition).unwrap();
+            let mut customers: Vec<String> = String::from_utf8(data).unwrap().lines()
+                .map(|line| serde_json::from_str::<Vec<Value>>(line).unwrap()[1].to_string())
+                .collect();
+            assert_eq!(format!("{},{}", partition, customers.len()), *count);
+            customers.sort();
+            customers.dedup();
+            customers_by_partition.push(customers);
+        }
+
+        // each customer is only in one partition
+        let total: usize = customers_by_partition.iter().map(|c| c.len()).sum();
+        assert!(total > 0);
+        for c in &customers_by_partition[0] {
+            assert!(!customers_by_partition[1].contains(c));
+        }
+
+        remove_shuffle(&root, "test").unwrap();
+        assert!(read_shuffle_partition(&root, "test", 0).is_err());
+        remove_shuffle(&root, "test").unwrap();
+        assert!(shuffle_dir(&root, "../test").is_err());
+        let _ = fs::remove_dir(&root);
+    }
+}
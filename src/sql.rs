@@ -15,24 +15,103 @@
 #[derive(Debug,Clone,PartialEq)]
 pub enum ASTNode {
     SQLIdentifier { id: String },
+    /// qualified identifier e.g. "people.id"
+    SQLCompoundIdentifier { qualifier: String, id: String },
+    SQLWildcard,
+    SQLQualifiedWildcard { qualifier: String },
+    SQLAlias { expr: Box<ASTNode>, alias: String },
     SQLBinaryExpr { left: Box<ASTNode>, op: SQLOperator, right: Box<ASTNode> },
     SQLNested(Box<ASTNode>),
     SQLUnary { operator: SQLOperator, rex: Box<ASTNode> },
+    SQLIsNull(Box<ASTNode>),
+    SQLIsNotNull(Box<ASTNode>),
     SQLLiteralInt(i64),
+    SQLLiteralDouble(f64),
+    SQLLiteralString(String),
     SQLFunction { id: String, args: Vec<ASTNode> },
+    /// function call followed by an OVER clause, where `order_by` contains `SQLOrderBy` nodes
+    SQLWindowFunction {
+        function: Box<ASTNode>,
+        partition_by: Vec<ASTNode>,
+        order_by: Vec<ASTNode>,
+        frame: Option<SQLWindowFrame>
+    },
+    SQLOrderBy { expr: Box<ASTNode>, asc: bool, nulls_first: bool },
     SQLSelect{
         projection: Vec<ASTNode>,
         relation: Option<Box<ASTNode>>,
         selection: Option<Box<ASTNode>>,
-        order: Option<Box<ASTNode>>,
+        group_by: Option<Vec<ASTNode>>,
+        having: Option<Box<ASTNode>>,
+        order: Option<Vec<ASTNode>>,
         limit: Option<Box<ASTNode>>,
+        offset: Option<Box<ASTNode>>,
+    },
+    SQLJoin {
+        left: Box<ASTNode>,
+        right: Box<ASTNode>,
+        join_type: SQLJoinType,
+        constraint: SQLJoinConstraint
     },
+    /// Define a table stored in a CSV file, with the options for parsing the file as pairs of
+    /// option name and value
     SQLCreateTable {
         name: String,
-        columns: Vec<SQLColumnDef>
+        columns: Vec<SQLColumnDef>,
+        options: Vec<(String, String)>
+    },
+    /// Register a function from a UDF library, optionally declaring the signature that the
+    /// library's implementation is expected to have
+    SQLCreateFunction {
+        name: String,
+        args: Option<Vec<SQLType>>,
+        return_type: Option<SQLType>,
+        library: String
+    },
+    /// Collect the statistics of a table
+    SQLAnalyzeTable {
+        name: String
+    },
+    /// Show the plan for a statement, or execute it and show the plan with the metrics collected
+    /// for each relation when `analyze` is set
+    SQLExplain {
+        analyze: bool,
+        statement: Box<ASTNode>
     }
 }
 
+/// ROWS BETWEEN start AND end
+#[derive(Debug,Clone,PartialEq)]
+pub struct SQLWindowFrame {
+    pub start: SQLWindowFrameBound,
+    pub end: SQLWindowFrameBound
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum SQLWindowFrameBound {
+    UnboundedPreceding,
+    Preceding(u64),
+    CurrentRow,
+    Following(u64),
+    UnboundedFollowing
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum SQLJoinType {
+    Inner,
+    Left,
+    Right,
+    Full,
+    Cross
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum SQLJoinConstraint {
+    On(Box<ASTNode>),
+    Using(Vec<String>),
+    None
+}
+
 #[derive(Debug,Clone,PartialEq)]
 pub struct SQLColumnDef {
     pub name: String,
@@ -60,5 +139,6 @@ pub enum SQLOperator {
     EQ,
     NEQ,
     OR,
-    AND
+    AND,
+    NOT
 }
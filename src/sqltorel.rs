@@ -20,70 +20,351 @@ use super::rel::*;
 
 pub struct SqlToRel {
     //default_schema: Option<String>,
-    schemas: HashMap<String, Schema>
+    schemas: HashMap<String, Schema>,
+    functions: HashMap<String, FunctionMeta>
 }
 
 impl SqlToRel {
 
-    pub fn new(schemas: HashMap<String, Schema>) -> Self {
-        SqlToRel { /*default_schema: None,*/ schemas }
+    pub fn new(schemas: HashMap<String, Schema>, functions: HashMap<String, FunctionMeta>) -> Self {
+        SqlToRel { /*default_schema: None,*/ schemas, functions }
     }
 
     pub fn sql_to_rel(&self, sql: &ASTNode) -> Result<Box<LogicalPlan>, String> {
         match sql {
-            &ASTNode::SQLSelect { ref projection, ref relation, ref selection, .. } => {
+            &ASTNode::SQLSelect { ref projection, ref relation, ref selection, ref group_by, ref having, ref order, ref limit, ref offset } => {
 
                 // parse the input relation so we have access to the tuple type
                 let input = match relation {
                     &Some(ref r) => self.sql_to_rel(r)?,
-                    &None => Box::new(LogicalPlan::EmptyRelation)
+                    &None => Box::new(LogicalPlan::EmptyRelation { schema: Schema::empty() })
                 };
 
                 let input_schema = input.schema();
 
-                let expr : Vec<Expr> = projection.iter()
-                    .map(|e| self.sql_to_rex(&e, &input_schema) )
-                    .collect::<Result<Vec<Expr>,String>>()?;
+                let input = match *selection {
+                    Some(ref filter_expr) => {
+                        let expr = self.sql_to_predicate(filter_expr, &input_schema)?;
+                        check_no_window_functions(&expr, "WHERE")?;
+                        Box::new(LogicalPlan::Selection {
+                            expr,
+                            input,
+                            schema: input_schema.clone()
+                        })
+                    },
+                    None => input
+                };
+
+                // expand wildcards into the columns of the input
+                let mut expr : Vec<Expr> = vec![];
+                for e in projection {
+                    match *e {
+                        ASTNode::SQLWildcard =>
+                            expr.extend((0..input_schema.columns.len()).map(Expr::TupleValue)),
+                        ASTNode::SQLQualifiedWildcard { ref qualifier } => {
+                            let relation_name = relation.as_ref().and_then(|r| relation_qualifier(r));
+                            expr.extend(expand_qualified_wildcard(&input_schema, qualifier, relation_name)?)
+                        },
+                        _ => expr.push(self.sql_to_rex(e, &input_schema)?)
+                    }
+                }
+
+                let having_expr = match *having {
+                    Some(ref having_expr) => {
+                        let having_expr = self.sql_to_rex(having_expr, &input_schema)?;
+                        check_no_window_functions(&having_expr, "HAVING")?;
+                        Some(having_expr)
+                    },
+                    None => None
+                };
+
+                // sort keys that cannot be resolved against the input are resolved against the
+                // output of the projection later on
+                let order_expr : Vec<Option<Expr>> = match *order {
+                    Some(ref order) => order.iter()
+                        .map(|e| self.sql_to_rex(e, &input_schema).ok())
+                        .collect(),
+                    None => vec![]
+                };
+
+                // find the distinct aggregate expressions referenced by the projection, HAVING
+                // and ORDER BY
+                let mut aggr_expr: Vec<Expr> = vec![];
+                expr.iter().for_each(|e| find_aggregate_expr(e, &mut aggr_expr));
+                if let Some(ref having_expr) = having_expr {
+                    find_aggregate_expr(having_expr, &mut aggr_expr);
+                }
+                order_expr.iter().flat_map(|e| e.iter()).for_each(|e| find_aggregate_expr(e, &mut aggr_expr));
+
+                let (input, expr, order_expr) = if group_by.is_some() || !aggr_expr.is_empty() {
+
+                    let group_expr : Vec<Expr> = match *group_by {
+                        Some(ref group_by) => group_by.iter()
+                            .map(|e| self.sql_to_rex(e, &input_schema))
+                            .collect::<Result<Vec<Expr>,String>>()?,
+                        None => vec![]
+                    };
+                    for e in &group_expr {
+                        check_no_window_functions(e, "GROUP BY")?;
+                    }
+
+                    let aggregate_schema = Schema::new(group_expr.iter()
+                        .chain(aggr_expr.iter())
+                        .map(|e| e.to_field(&input_schema, &self.functions))
+                        .collect::<Result<Vec<Field>,String>>()?);
+
+                    let aggregate = Box::new(LogicalPlan::Aggregate {
+                        group_expr: group_expr.clone(),
+                        aggr_expr: aggr_expr.clone(),
+                        input,
+                        schema: aggregate_schema.clone(),
+                        mode: AggregateMode::Complete
+                    });
 
+                    // the projection, HAVING and ORDER BY now refer to the output of the aggregate
+                    let expr = expr.iter()
+                        .map(|e| rewrite_aggregate_expr(e, &group_expr, &aggr_expr))
+                        .collect::<Result<Vec<Expr>,String>>()?;
 
-                let projection_schema = Schema {
-                    columns: expr.iter().map( |e| match e {
-                        &Expr::TupleValue(i) => input_schema.columns[i].clone(),
-                        &Expr::ScalarFunction { ref name, .. } => Field {
-                            name: name.clone(),
-                            data_type: DataType::Double, //TODO: hard-coded until I have function metadata in place
-                            nullable: true
+                    let order_expr = order_expr.iter()
+                        .map(|e| match *e {
+                            Some(ref e) => rewrite_aggregate_expr(e, &group_expr, &aggr_expr).map(Some),
+                            None => Ok(None)
+                        })
+                        .collect::<Result<Vec<Option<Expr>>,String>>()?;
+
+                    let plan = match having_expr {
+                        Some(ref having_expr) => {
+                            let having_expr = rewrite_aggregate_expr(having_expr, &group_expr, &aggr_expr)?;
+                            check_predicate(&having_expr, &aggregate_schema, &self.functions)?;
+                            Box::new(LogicalPlan::Selection {
+                                expr: having_expr,
+                                input: aggregate,
+                                schema: aggregate_schema
+                            })
                         },
-                        _ => unimplemented!()
-                    }).collect()
+                        None => aggregate
+                    };
+
+                    (plan, expr, order_expr)
+
+                } else if having.is_some() {
+                    return Err(String::from("HAVING requires GROUP BY or an aggregate function"))
+                } else {
+                    (input, expr, order_expr)
                 };
 
-                match selection {
-                    &Some(ref filter_expr) => {
+                // find the distinct window functions referenced by the projection and ORDER BY,
+                // which are computed after any aggregation
+                let mut window_expr: Vec<Expr> = vec![];
+                expr.iter().for_each(|e| find_window_expr(e, &mut window_expr));
+                order_expr.iter().flat_map(|e| e.iter()).for_each(|e| find_window_expr(e, &mut window_expr));
 
-                        let selection_rel = LogicalPlan::Selection {
-                            expr: self.sql_to_rex(&filter_expr, &input_schema.clone())?,
-                            input: input,
-                            schema: input_schema.clone()
+                let (input, expr, order_expr) = if window_expr.is_empty() {
+                    (input, expr, order_expr)
+                } else {
+                    let window_input_schema = input.schema();
+                    let offset = window_input_schema.columns.len();
+
+                    let mut fields = window_input_schema.columns.clone();
+                    for e in &window_expr {
+                        fields.push(e.to_field(&window_input_schema, &self.functions)?);
+                    }
+
+                    // the projection and ORDER BY now refer to the window columns
+                    let expr: Vec<Expr> = expr.iter()
+                        .map(|e| rewrite_window_expr(e, &window_expr, offset))
+                        .collect();
+                    let order_expr: Vec<Option<Expr>> = order_expr.iter()
+                        .map(|e| e.as_ref().map(|e| rewrite_window_expr(e, &window_expr, offset)))
+                        .collect();
+
+                    let plan = Box::new(LogicalPlan::Window {
+                        window_expr,
+                        input,
+                        schema: Schema::new(fields)
+                    });
+
+                    (plan, expr, order_expr)
+                };
+
+                let projection_schema = Schema::new(expr.iter()
+                    .map(|e| e.to_field(&input.schema(), &self.functions))
+                    .collect::<Result<Vec<Field>,String>>()?);
+
+                let plan = match *order {
+                    Some(ref order) => {
+
+                        // express all sort keys in terms of the input to the projection
+                        let sort_expr = order.iter().zip(order_expr)
+                            .map(|(o, e)| match e {
+                                Some(e) => Ok(e),
+                                None => match self.sql_to_rex(o, &projection_schema)? {
+                                    Expr::Sort { expr: ref sort_key, asc, nulls_first } => match **sort_key {
+                                        Expr::TupleValue(i) => Ok(expr[i].sort(asc, nulls_first)),
+                                        _ => Err(format!("Unsupported ORDER BY expression {:?}", o))
+                                    },
+                                    _ => Err(format!("Invalid ORDER BY expression {:?}", o))
+                                }
+                            })
+                            .collect::<Result<Vec<Expr>,String>>()?;
+
+                        // sort the output of the projection if every sort key is projected,
+                        // otherwise sort the input to the projection
+                        let output_sort_expr = sort_expr.iter()
+                            .map(|e| match *e {
+                                Expr::Sort { expr: ref sort_key, asc, nulls_first } => expr.iter()
+                                    .position(|p| *p == **sort_key)
+                                    .map(|i| Expr::TupleValue(i).sort(asc, nulls_first)),
+                                _ => None
+                            })
+                            .collect::<Option<Vec<Expr>>>();
+
+                        match output_sort_expr {
+                            Some(output_sort_expr) => Box::new(LogicalPlan::Sort {
+                                expr: output_sort_expr,
+                                input: Box::new(LogicalPlan::Projection {
+                                    expr,
+                                    input,
+                                    schema: projection_schema.clone()
+                                }),
+                                schema: projection_schema
+                            }),
+                            None => {
+                                let sort_schema = input.schema();
+                                Box::new(LogicalPlan::Projection {
+                                    expr,
+                                    input: Box::new(LogicalPlan::Sort {
+                                        expr: sort_expr,
+                                        input,
+                                        schema: sort_schema
+                                    }),
+                                    schema: projection_schema
+                                })
+                            }
+                        }
+                    },
+                    None => Box::new(LogicalPlan::Projection {
+                        expr,
+                        input,
+                        schema: projection_schema
+                    })
+                };
+
+                match *limit {
+                    Some(ref limit) => {
+                        let limit = self.sql_to_usize(limit)?;
+                        let offset = match *offset {
+                            Some(ref offset) => self.sql_to_usize(offset)?,
+                            None => 0
                         };
+                        let schema = plan.schema();
+                        Ok(Box::new(LogicalPlan::Limit { limit, offset, input: plan, schema }))
+                    },
+                    None => Ok(plan)
+                }
+
+            },
+
+            &ASTNode::SQLAlias { ref expr, .. } => self.sql_to_rel(expr),
+
+            &ASTNode::SQLJoin { ref left, ref right, ref join_type, ref constraint } => {
+
+                let left_plan = self.sql_to_rel(left)?;
+                let right_plan = self.sql_to_rel(right)?;
+
+                // qualify column names with the table name or alias so that columns with the same
+                // name on both sides can be told apart
+                let left_schema = qualify_schema(&left_plan.schema(), relation_qualifier(left));
+                let right_schema = qualify_schema(&right_plan.schema(), relation_qualifier(right));
+                let left_len = left_schema.columns.len();
+
+                let join_type = match *join_type {
+                    SQLJoinType::Inner => JoinType::Inner,
+                    SQLJoinType::Left => JoinType::Left,
+                    SQLJoinType::Right => JoinType::Right,
+                    SQLJoinType::Full => JoinType::Full,
+                    SQLJoinType::Cross => JoinType::Cross
+                };
 
-                        Ok(Box::new(LogicalPlan::Projection {
-                            expr: expr,
-                            input: Box::new(selection_rel),
-                            schema: projection_schema.clone()
-                        }))
+                let join_schema = Schema::join(&left_schema, &right_schema, &join_type);
 
+                let (on, using) = match *constraint {
+                    SQLJoinConstraint::On(ref expr) => {
+                        let expr = self.sql_to_rex(expr, &join_schema)?;
+                        (split_join_condition(&expr, left_len)?, vec![])
                     },
-                    _ => {
+                    SQLJoinConstraint::Using(ref columns) => {
+                        let using = columns.iter()
+                            .map(|c| Ok((resolve_column(&left_schema, None, c)?, resolve_column(&right_schema, None, c)?)))
+                            .collect::<Result<Vec<(usize, usize)>, String>>()?;
+                        let on = using.iter()
+                            .map(|&(l, r)| (Expr::TupleValue(l), Expr::TupleValue(r)))
+                            .collect();
+                        (on, using)
+                    },
+                    SQLJoinConstraint::None => (vec![], vec![])
+                };
+
+                let join = Box::new(LogicalPlan::Join {
+                    left: left_plan,
+                    right: right_plan,
+                    join_type: join_type.clone(),
+                    on,
+                    schema: join_schema.clone()
+                });
+
+                if using.is_empty() {
+                    return Ok(join);
+                }
 
-                        Ok(Box::new(LogicalPlan::Projection {
-                            expr: expr,
-                            input: input,
-                            schema: projection_schema.clone()
-                        }))
+                // USING columns only appear once in the output, taking the value from whichever
+                // side is guaranteed to have a row
+                let mut expr = vec![];
+                let mut columns = vec![];
+                for (i, field) in left_schema.columns.iter().enumerate() {
+                    match using.iter().find(|&&(l, _)| l == i) {
+                        Some(&(_, r)) => {
+                            let (left_col, right_col) = (Expr::TupleValue(i), Expr::TupleValue(left_len + r));
+                            let nullable = match join_type {
+                                JoinType::Right => {
+                                    expr.push(right_col);
+                                    join_schema.columns[left_len + r].nullable
+                                },
+                                JoinType::Full => {
+                                    let coalesce = Expr::Coalesce(vec![left_col, right_col]);
+                                    let nullable = coalesce.to_field(&join_schema, &self.functions)
+                                        .map_err(|e| format!("Invalid USING column {}: {}", field.name, e))?
+                                        .nullable;
+                                    expr.push(coalesce);
+                                    nullable
+                                },
+                                _ => {
+                                    expr.push(left_col);
+                                    join_schema.columns[i].nullable
+                                }
+                            };
+                            let name = field.name.rsplit('.').next().unwrap_or(&field.name);
+                            columns.push(Field::new(name, field.data_type.clone(), nullable));
+                        },
+                        None => {
+                            expr.push(Expr::TupleValue(i));
+                            columns.push(join_schema.columns[i].clone());
+                        }
+                    }
+                }
+                for j in 0..right_schema.columns.len() {
+                    if !using.iter().any(|&(_, r)| r == j) {
+                        expr.push(Expr::TupleValue(left_len + j));
+                        columns.push(join_schema.columns[left_len + j].clone());
                     }
                 }
 
+                Ok(Box::new(LogicalPlan::Projection {
+                    expr,
+                    input: join,
+                    schema: Schema::new(columns)
+                }))
             },
 
             &ASTNode::SQLIdentifier { ref id, .. } => {
@@ -92,7 +373,8 @@ impl SqlToRel {
                     Some(schema) => Ok(Box::new(LogicalPlan::TableScan {
                         schema_name: String::from("default"),
                         table_name: id.clone(),
-                        schema: schema.clone()
+                        schema: schema.clone(),
+                        projection: None
                     })),
                     None => Err(format!("no schema found for table"))
                 }
@@ -102,18 +384,48 @@ impl SqlToRel {
         }
     }
 
+    /// Determine whether a function name refers to a built-in or registered aggregate function
+    fn is_aggregate_function(&self, name: &str) -> bool {
+        is_builtin_aggregate(name) || match self.functions.get(&name.to_lowercase()) {
+            Some(meta) => meta.function_type == FunctionType::Aggregate,
+            None => false
+        }
+    }
+
+    /// Plan a filter expression, which must be boolean
+    fn sql_to_predicate(&self, sql: &ASTNode, tt: &Schema) -> Result<Expr, String> {
+        let expr = self.sql_to_rex(sql, tt)?;
+        check_predicate(&expr, tt, &self.functions)?;
+        Ok(expr)
+    }
+
+    fn sql_to_usize(&self, sql: &ASTNode) -> Result<usize, String> {
+        match *sql {
+            ASTNode::SQLLiteralInt(n) if n >= 0 => Ok(n as usize),
+            _ => Err(format!("Expected a non-negative integer but found {:?}", sql))
+        }
+    }
+
     pub fn sql_to_rex(&self, sql: &ASTNode, tt: &Schema) -> Result<Expr, String> {
         match sql {
 
-            &ASTNode::SQLLiteralInt(n) =>
-                Ok(Expr::Literal(Value::UnsignedLong(n as u64))), //TODO
+            &ASTNode::SQLLiteralInt(n) if n < 0 => Ok(Expr::Literal(Value::Long(n))),
 
-            &ASTNode::SQLIdentifier { ref id, .. } => {
-                match tt.columns.iter().position(|c| c.name.eq(id) ) {
-                    Some(index) => Ok(Expr::TupleValue(index)),
-                    None => Err(format!("Invalid identifier {}", id))
-                }
-            },
+            &ASTNode::SQLLiteralInt(n) => Ok(Expr::Literal(Value::UnsignedLong(n as u64))),
+
+            &ASTNode::SQLLiteralDouble(n) => Ok(Expr::Literal(Value::Double(n))),
+
+            &ASTNode::SQLLiteralString(ref s) => Ok(Expr::Literal(Value::String(s.clone()))),
+
+            // negation of an expression is planned as subtraction from zero
+            &ASTNode::SQLUnary { operator: SQLOperator::SUB, ref rex } =>
+                Ok(Expr::Literal(Value::Long(0)).minus(&self.sql_to_rex(rex, tt)?)),
+
+            &ASTNode::SQLIdentifier { ref id, .. } =>
+                Ok(Expr::TupleValue(resolve_column(tt, None, id)?)),
+
+            &ASTNode::SQLCompoundIdentifier { ref qualifier, ref id } =>
+                Ok(Expr::TupleValue(resolve_column(tt, Some(qualifier), id)?)),
 
             &ASTNode::SQLBinaryExpr { ref left, ref op, ref right } => {
                 //TODO: we have this implemented somewhere else already
@@ -123,6 +435,14 @@ impl SqlToRel {
                     &SQLOperator::LT => Operator::Lt,
                     &SQLOperator::LTEQ => Operator::LtEq,
                     &SQLOperator::EQ => Operator::Eq,
+                    &SQLOperator::NEQ => Operator::NotEq,
+                    &SQLOperator::AND => Operator::And,
+                    &SQLOperator::OR => Operator::Or,
+                    &SQLOperator::ADD => Operator::Plus,
+                    &SQLOperator::SUB => Operator::Minus,
+                    &SQLOperator::MULT => Operator::Multiply,
+                    &SQLOperator::DIV => Operator::Divide,
+                    &SQLOperator::MOD => Operator::Modulus,
                     _ => unimplemented!()
                 };
                 Ok(Expr::BinaryExpr {
@@ -133,12 +453,74 @@ impl SqlToRel {
 
             },
 
+            &ASTNode::SQLUnary { operator: SQLOperator::NOT, ref rex } =>
+                Ok(self.sql_to_rex(rex, tt)?.not()),
+
+            &ASTNode::SQLIsNull(ref expr) => Ok(self.sql_to_rex(expr, tt)?.is_null()),
+
+            &ASTNode::SQLIsNotNull(ref expr) => Ok(self.sql_to_rex(expr, tt)?.is_not_null()),
+
+            &ASTNode::SQLAlias { ref expr, ref alias } => Ok(self.sql_to_rex(expr, tt)?.alias(alias)),
+
+            &ASTNode::SQLOrderBy { ref expr, asc, nulls_first } =>
+                Ok(self.sql_to_rex(expr, tt)?.sort(asc, nulls_first)),
+
             &ASTNode::SQLFunction { ref id, ref args } => {
-                let rex_args = args.iter()
-                    .map(|a| self.sql_to_rex(a, tt))
-                    .collect::<Result<Vec<Expr>, String>>()?;
+                if self.is_aggregate_function(id) {
+                    // COUNT(*) has no arguments to evaluate
+                    let rex_args = args.iter()
+                        .filter(|a| **a != ASTNode::SQLWildcard)
+                        .map(|a| self.sql_to_rex(a, tt))
+                        .collect::<Result<Vec<Expr>, String>>()?;
+
+                    Ok(Expr::AggregateFunction { name: id.clone(), args: rex_args })
+                } else if id.eq_ignore_ascii_case("coalesce") {
+                    let mut rex_args = args.iter()
+                        .map(|a| self.sql_to_rex(a, tt))
+                        .collect::<Result<Vec<Expr>, String>>()?;
+
+                    // the arguments of COALESCE must have the type of the first argument, so
+                    // numeric literals such as the 0 in COALESCE(amount, 0) are converted to it
+                    if let Some(first) = rex_args.first().cloned() {
+                        let data_type = first.to_field(tt, &self.functions)?.data_type;
+                        for arg in rex_args.iter_mut().skip(1) {
+                            if let Expr::Literal(ref mut value) = *arg {
+                                if let Some(v) = coerce_literal(value, &data_type) {
+                                    *value = v;
+                                }
+                            }
+                        }
+                    }
+
+                    Ok(Expr::Coalesce(rex_args))
+                } else {
+                    let rex_args = args.iter()
+                        .map(|a| self.sql_to_rex(a, tt))
+                        .collect::<Result<Vec<Expr>, String>>()?;
 
-                Ok(Expr::ScalarFunction { name: id.clone(), args: rex_args })
+                    Ok(Expr::ScalarFunction { name: id.clone(), args: rex_args })
+                }
+            },
+
+            &ASTNode::SQLWindowFunction { ref function, ref partition_by, ref order_by, ref frame } => {
+                let (name, args) = match **function {
+                    ASTNode::SQLFunction { ref id, ref args } => (id, args),
+                    _ => return Err(format!("Invalid window function {:?}", function))
+                };
+                let to_rex = |exprs: &Vec<ASTNode>| exprs.iter()
+                    .filter(|a| **a != ASTNode::SQLWildcard)
+                    .map(|a| self.sql_to_rex(a, tt))
+                    .collect::<Result<Vec<Expr>, String>>();
+                Ok(Expr::WindowFunction {
+                    name: name.clone(),
+                    args: to_rex(args)?,
+                    partition_by: to_rex(partition_by)?,
+                    order_by: to_rex(order_by)?,
+                    frame: frame.as_ref().map(|f| WindowFrame {
+                        start: convert_window_frame_bound(&f.start),
+                        end: convert_window_frame_bound(&f.end)
+                    })
+                })
             },
 
             _ => Err(String::from(format!("Unsupported ast node {:?}", sql)))
@@ -146,3 +528,333 @@ impl SqlToRel {
     }
 
 }
+
+/// Convert a numeric literal to a wider numeric type, or return `None` if it cannot be converted
+fn coerce_literal(value: &Value, data_type: &DataType) -> Option<Value> {
+    match (value, data_type) {
+        (&Value::UnsignedLong(n), &DataType::Long) if n <= i64::MAX as u64 => Some(Value::Long(n as i64)),
+        (&Value::UnsignedLong(n), &DataType::Double) => Some(Value::Double(n as f64)),
+        (&Value::Long(n), &DataType::Double) => Some(Value::Double(n as f64)),
+        _ => None
+    }
+}
+
+/// Convert a SQL data type to the equivalent relational data type
+pub fn convert_data_type(sql_type: &SQLType) -> DataType {
+    match *sql_type {
+        SQLType::Varchar(_) => DataType::String,
+        SQLType::Double => DataType::Double
+    }
+}
+
+fn convert_window_frame_bound(bound: &SQLWindowFrameBound) -> WindowFrameBound {
+    match *bound {
+        SQLWindowFrameBound::UnboundedPreceding => WindowFrameBound::UnboundedPreceding,
+        SQLWindowFrameBound::Preceding(n) => WindowFrameBound::Preceding(n as usize),
+        SQLWindowFrameBound::CurrentRow => WindowFrameBound::CurrentRow,
+        SQLWindowFrameBound::Following(n) => WindowFrameBound::Following(n as usize),
+        SQLWindowFrameBound::UnboundedFollowing => WindowFrameBound::UnboundedFollowing
+    }
+}
+
+/// Collect the distinct aggregate expressions contained within an expression
+fn find_aggregate_expr(e: &Expr, aggr_expr: &mut Vec<Expr>) {
+    match *e {
+        Expr::AggregateFunction { .. } => {
+            if !aggr_expr.contains(e) {
+                aggr_expr.push(e.clone());
+            }
+        },
+        Expr::BinaryExpr { ref left, ref right, .. } => {
+            find_aggregate_expr(left, aggr_expr);
+            find_aggregate_expr(right, aggr_expr);
+        },
+        Expr::ScalarFunction { ref args, .. } | Expr::Coalesce(ref args) => {
+            args.iter().for_each(|a| find_aggregate_expr(a, aggr_expr));
+        },
+        Expr::Not(ref expr) | Expr::IsNull(ref expr) | Expr::IsNotNull(ref expr) |
+        Expr::Alias { ref expr, .. } | Expr::Sort { ref expr, .. } => find_aggregate_expr(expr, aggr_expr),
+        Expr::WindowFunction { ref args, ref partition_by, ref order_by, .. } => {
+            args.iter().chain(partition_by.iter()).chain(order_by.iter())
+                .for_each(|a| find_aggregate_expr(a, aggr_expr));
+        },
+        Expr::TupleValue(_) | Expr::Literal(_) => {}
+    }
+}
+
+/// Collect the distinct window functions contained within an expression
+fn find_window_expr(e: &Expr, window_expr: &mut Vec<Expr>) {
+    match *e {
+        Expr::WindowFunction { .. } => {
+            if !window_expr.contains(e) {
+                window_expr.push(e.clone());
+            }
+        },
+        Expr::BinaryExpr { ref left, ref right, .. } => {
+            find_window_expr(left, window_expr);
+            find_window_expr(right, window_expr);
+        },
+        Expr::ScalarFunction { ref args, .. } | Expr::AggregateFunction { ref args, .. } | Expr::Coalesce(ref args) => {
+            args.iter().for_each(|a| find_window_expr(a, window_expr));
+        },
+        Expr::Not(ref expr) | Expr::IsNull(ref expr) | Expr::IsNotNull(ref expr) |
+        Expr::Alias { ref expr, .. } | Expr::Sort { ref expr, .. } => find_window_expr(expr, window_expr),
+        Expr::TupleValue(_) | Expr::Literal(_) => {}
+    }
+}
+
+/// Check that an expression does not contain window functions, which can only be used in the
+/// projection and ORDER BY
+fn check_no_window_functions(e: &Expr, clause: &str) -> Result<(), String> {
+    let mut window_expr = vec![];
+    find_window_expr(e, &mut window_expr);
+    if window_expr.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Window functions are not allowed in {}", clause))
+    }
+}
+
+/// Rewrite an expression that was planned against the input of a window relation so that it
+/// refers to the window columns, which start at `offset`, instead of the window functions
+fn rewrite_window_expr(e: &Expr, window_expr: &[Expr], offset: usize) -> Expr {
+    if let Some(i) = window_expr.iter().position(|w| w == e) {
+        return Expr::TupleValue(offset + i);
+    }
+    match *e {
+        Expr::TupleValue(_) | Expr::Literal(_) | Expr::WindowFunction { .. } => e.clone(),
+        Expr::BinaryExpr { ref left, ref op, ref right } => Expr::BinaryExpr {
+            left: Box::new(rewrite_window_expr(left, window_expr, offset)),
+            op: op.clone(),
+            right: Box::new(rewrite_window_expr(right, window_expr, offset))
+        },
+        Expr::ScalarFunction { ref name, ref args } => Expr::ScalarFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| rewrite_window_expr(a, window_expr, offset)).collect()
+        },
+        Expr::AggregateFunction { ref name, ref args } => Expr::AggregateFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| rewrite_window_expr(a, window_expr, offset)).collect()
+        },
+        Expr::Coalesce(ref args) => Expr::Coalesce(args.iter().map(|a| rewrite_window_expr(a, window_expr, offset)).collect()),
+        Expr::Not(ref expr) => rewrite_window_expr(expr, window_expr, offset).not(),
+        Expr::IsNull(ref expr) => rewrite_window_expr(expr, window_expr, offset).is_null(),
+        Expr::IsNotNull(ref expr) => rewrite_window_expr(expr, window_expr, offset).is_not_null(),
+        Expr::Alias { ref expr, ref alias } => rewrite_window_expr(expr, window_expr, offset).alias(alias),
+        Expr::Sort { ref expr, asc, nulls_first } =>
+            rewrite_window_expr(expr, window_expr, offset).sort(asc, nulls_first)
+    }
+}
+
+/// Check that a filter expression is valid for the input schema and evaluates to a boolean
+pub fn check_predicate(expr: &Expr, schema: &Schema, functions: &HashMap<String, FunctionMeta>) -> Result<(), String> {
+    match expr.to_field(schema, functions)?.data_type {
+        DataType::Boolean => Ok(()),
+        ref t => Err(format!("Filter expression must be boolean but found {:?}", t))
+    }
+}
+
+/// Rewrite an expression that was planned against the input of an aggregate so that it refers
+/// to the grouping and aggregate columns produced by the aggregate instead
+fn rewrite_aggregate_expr(e: &Expr, group_expr: &[Expr], aggr_expr: &[Expr]) -> Result<Expr, String> {
+    if let Some(i) = group_expr.iter().position(|g| g == e) {
+        return Ok(Expr::TupleValue(i));
+    }
+    if let Some(i) = aggr_expr.iter().position(|a| a == e) {
+        return Ok(Expr::TupleValue(group_expr.len() + i));
+    }
+    match *e {
+        Expr::Literal(_) => Ok(e.clone()),
+        Expr::BinaryExpr { ref left, ref op, ref right } => Ok(Expr::BinaryExpr {
+            left: Box::new(rewrite_aggregate_expr(left, group_expr, aggr_expr)?),
+            op: op.clone(),
+            right: Box::new(rewrite_aggregate_expr(right, group_expr, aggr_expr)?)
+        }),
+        Expr::ScalarFunction { ref name, ref args } => Ok(Expr::ScalarFunction {
+            name: name.clone(),
+            args: args.iter()
+                .map(|a| rewrite_aggregate_expr(a, group_expr, aggr_expr))
+                .collect::<Result<Vec<Expr>, String>>()?
+        }),
+        Expr::Coalesce(ref args) => Ok(Expr::Coalesce(args.iter()
+            .map(|a| rewrite_aggregate_expr(a, group_expr, aggr_expr))
+            .collect::<Result<Vec<Expr>, String>>()?)),
+        Expr::Not(ref expr) => Ok(rewrite_aggregate_expr(expr, group_expr, aggr_expr)?.not()),
+        Expr::IsNull(ref expr) => Ok(rewrite_aggregate_expr(expr, group_expr, aggr_expr)?.is_null()),
+        Expr::IsNotNull(ref expr) => Ok(rewrite_aggregate_expr(expr, group_expr, aggr_expr)?.is_not_null()),
+        Expr::Alias { ref expr, ref alias } => Ok(rewrite_aggregate_expr(expr, group_expr, aggr_expr)?.alias(alias)),
+        Expr::Sort { ref expr, asc, nulls_first } =>
+            Ok(rewrite_aggregate_expr(expr, group_expr, aggr_expr)?.sort(asc, nulls_first)),
+        Expr::WindowFunction { ref name, ref args, ref partition_by, ref order_by, ref frame } => {
+            let rewrite = |exprs: &Vec<Expr>| exprs.iter()
+                .map(|e| rewrite_aggregate_expr(e, group_expr, aggr_expr))
+                .collect::<Result<Vec<Expr>, String>>();
+            Ok(Expr::WindowFunction {
+                name: name.clone(),
+                args: rewrite(args)?,
+                partition_by: rewrite(partition_by)?,
+                order_by: rewrite(order_by)?,
+                frame: frame.clone()
+            })
+        },
+        Expr::TupleValue(_) => Err(String::from(
+            "Column must appear in the GROUP BY clause or be used in an aggregate function")),
+        Expr::AggregateFunction { .. } => Err(String::from("Unexpected aggregate expression"))
+    }
+}
+
+/// Find the index of a column, which may be qualified by a table name or alias. Columns of joined
+/// relations are qualified (e.g. "p.id") and can be referenced without the qualifier as long as
+/// the name is unambiguous.
+fn resolve_column(schema: &Schema, qualifier: Option<&str>, name: &str) -> Result<usize, String> {
+    let qualified_name = match qualifier {
+        Some(q) => format!("{}.{}", q, name),
+        None => name.to_string()
+    };
+
+    if let Some((i, _)) = schema.column(&qualified_name) {
+        return Ok(i);
+    }
+
+    // the relation is a single table, so the qualifier is redundant
+    if let Some((i, _)) = schema.column(name) {
+        return Ok(i);
+    }
+
+    if qualifier.is_none() {
+        let suffix = format!(".{}", name);
+        let matches: Vec<usize> = schema.columns.iter()
+            .enumerate()
+            .filter(|&(_, c)| c.name.ends_with(&suffix))
+            .map(|(i, _)| i)
+            .collect();
+        match matches.len() {
+            1 => return Ok(matches[0]),
+            n if n > 1 => return Err(format!("Ambiguous column name {}", name)),
+            _ => {}
+        }
+    }
+
+    Err(format!("Invalid identifier {}", qualified_name))
+}
+
+/// Expand `qualifier.*` into the columns of the relation with that name or alias. Columns of
+/// joined relations are qualified, whereas a single relation can only be referred to by its
+/// own name.
+fn expand_qualified_wildcard(schema: &Schema, qualifier: &str, relation_name: Option<&str>) -> Result<Vec<Expr>, String> {
+    let prefix = format!("{}.", qualifier);
+    let expr: Vec<Expr> = schema.columns.iter()
+        .enumerate()
+        .filter(|&(_, c)| c.name.starts_with(&prefix))
+        .map(|(i, _)| Expr::TupleValue(i))
+        .collect();
+    if !expr.is_empty() {
+        Ok(expr)
+    } else if relation_name == Some(qualifier) {
+        Ok((0..schema.columns.len()).map(Expr::TupleValue).collect())
+    } else {
+        Err(format!("Invalid relation {} in {}.*", qualifier, qualifier))
+    }
+}
+
+/// Get the name that columns of a relation in a join are qualified with
+fn relation_qualifier(relation: &ASTNode) -> Option<&str> {
+    match *relation {
+        ASTNode::SQLIdentifier { ref id } => Some(id),
+        ASTNode::SQLAlias { ref alias, .. } => Some(alias),
+        _ => None
+    }
+}
+
+/// Prefix each column name that is not already qualified with the qualifier
+fn qualify_schema(schema: &Schema, qualifier: Option<&str>) -> Schema {
+    Schema::new(schema.columns.iter()
+        .map(|c| match qualifier {
+            Some(q) if !c.name.contains('.') =>
+                Field::new(&format!("{}.{}", q, c.name), c.data_type.clone(), c.nullable),
+            _ => c.clone()
+        })
+        .collect())
+}
+
+/// Split a join condition planned against the combined schema of a join into pairs of key
+/// expressions against the left and right inputs
+fn split_join_condition(expr: &Expr, left_len: usize) -> Result<Vec<(Expr, Expr)>, String> {
+    match *expr {
+        Expr::BinaryExpr { ref left, op: Operator::And, ref right } => {
+            let mut keys = split_join_condition(left, left_len)?;
+            keys.extend(split_join_condition(right, left_len)?);
+            Ok(keys)
+        },
+        Expr::BinaryExpr { ref left, op: Operator::Eq, ref right } => {
+            let (mut l, mut r) = (vec![], vec![]);
+            find_column_indices(left, &mut l);
+            find_column_indices(right, &mut r);
+
+            let is_left = |indices: &Vec<usize>| !indices.is_empty() && indices.iter().all(|&i| i < left_len);
+            let is_right = |indices: &Vec<usize>| !indices.is_empty() && indices.iter().all(|&i| i >= left_len);
+
+            if is_left(&l) && is_right(&r) {
+                Ok(vec![((**left).clone(), shift_column_indices(right, left_len))])
+            } else if is_right(&l) && is_left(&r) {
+                Ok(vec![((**right).clone(), shift_column_indices(left, left_len))])
+            } else {
+                Err(format!("Join condition must compare the left relation with the right relation: {:?}", expr))
+            }
+        },
+        _ => Err(format!("Unsupported join condition {:?}, only conjunctions of equalities are supported", expr))
+    }
+}
+
+/// Collect the indices of all columns referenced by an expression
+fn find_column_indices(e: &Expr, indices: &mut Vec<usize>) {
+    match *e {
+        Expr::TupleValue(i) => indices.push(i),
+        Expr::Literal(_) => {},
+        Expr::BinaryExpr { ref left, ref right, .. } => {
+            find_column_indices(left, indices);
+            find_column_indices(right, indices);
+        },
+        Expr::ScalarFunction { ref args, .. } | Expr::AggregateFunction { ref args, .. } | Expr::Coalesce(ref args) =>
+            args.iter().for_each(|a| find_column_indices(a, indices)),
+        Expr::Not(ref expr) | Expr::IsNull(ref expr) | Expr::IsNotNull(ref expr) |
+        Expr::Alias { ref expr, .. } | Expr::Sort { ref expr, .. } => find_column_indices(expr, indices),
+        Expr::WindowFunction { ref args, ref partition_by, ref order_by, .. } =>
+            args.iter().chain(partition_by.iter()).chain(order_by.iter())
+                .for_each(|a| find_column_indices(a, indices))
+    }
+}
+
+/// Rewrite column references so that they are relative to a relation starting at `offset`
+fn shift_column_indices(e: &Expr, offset: usize) -> Expr {
+    match *e {
+        Expr::TupleValue(i) => Expr::TupleValue(i - offset),
+        Expr::Literal(_) => e.clone(),
+        Expr::BinaryExpr { ref left, ref op, ref right } => Expr::BinaryExpr {
+            left: Box::new(shift_column_indices(left, offset)),
+            op: op.clone(),
+            right: Box::new(shift_column_indices(right, offset))
+        },
+        Expr::ScalarFunction { ref name, ref args } => Expr::ScalarFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| shift_column_indices(a, offset)).collect()
+        },
+        Expr::AggregateFunction { ref name, ref args } => Expr::AggregateFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| shift_column_indices(a, offset)).collect()
+        },
+        Expr::Coalesce(ref args) => Expr::Coalesce(args.iter().map(|a| shift_column_indices(a, offset)).collect()),
+        Expr::Not(ref expr) => shift_column_indices(expr, offset).not(),
+        Expr::IsNull(ref expr) => shift_column_indices(expr, offset).is_null(),
+        Expr::IsNotNull(ref expr) => shift_column_indices(expr, offset).is_not_null(),
+        Expr::Alias { ref expr, ref alias } => shift_column_indices(expr, offset).alias(alias),
+        Expr::Sort { ref expr, asc, nulls_first } => shift_column_indices(expr, offset).sort(asc, nulls_first),
+        Expr::WindowFunction { ref name, ref args, ref partition_by, ref order_by, ref frame } => Expr::WindowFunction {
+            name: name.clone(),
+            args: args.iter().map(|a| shift_column_indices(a, offset)).collect(),
+            partition_by: partition_by.iter().map(|a| shift_column_indices(a, offset)).collect(),
+            order_by: order_by.iter().map(|a| shift_column_indices(a, offset)).collect(),
+            frame: frame.clone()
+        }
+    }
+}
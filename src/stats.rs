@@ -0,0 +1,353 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Table and column statistics, which are collected by `ANALYZE TABLE`, and the estimates of
+//! the number of rows produced by logical plans that the optimizer derives from them.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use super::exec::*;
+use super::rel::*;
+
+/// Selectivity of an equality predicate on a column without statistics
+const DEFAULT_EQUALITY_SELECTIVITY: f64 = 0.1;
+
+/// Selectivity of a range predicate on a column without usable statistics
+const DEFAULT_RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// Selectivity of any other predicate
+const DEFAULT_SELECTIVITY: f64 = 0.5;
+
+/// Statistics for one column of a table
+#[derive(Debug,Clone,PartialEq)]
+pub struct ColumnStatistics {
+    pub null_count: u64,
+    /// smallest non-null value, or `None` when all values are null
+    pub min: Option<Value>,
+    /// largest non-null value, or `None` when all values are null
+    pub max: Option<Value>,
+    /// approximate number of distinct non-null values
+    pub distinct_count: u64
+}
+
+/// Statistics for a table, with the statistics for each column in schema order
+#[derive(Debug,Clone,PartialEq)]
+pub struct TableStatistics {
+    pub row_count: u64,
+    pub columns: Vec<ColumnStatistics>
+}
+
+impl TableStatistics {
+
+    /// Compute the statistics of a relation by scanning all of its rows
+    pub fn compute(rel: &dyn SimpleRelation, ctx: &ExecutionContext) -> Result<Self, ExecutionError> {
+        let column_count = rel.schema().columns.len();
+        let mut row_count = 0;
+        let mut null_counts = vec![0; column_count];
+        let mut min: Vec<Option<Value>> = vec![None; column_count];
+        let mut max: Vec<Option<Value>> = vec![None; column_count];
+        let mut distinct: Vec<DistinctCounter> = (0..column_count).map(|_| DistinctCounter::new()).collect();
+
+        for row in rel.scan(ctx) {
+            let row = row?;
+            row_count += 1;
+            for (i, value) in row.values.iter().enumerate() {
+                if *value == Value::Null {
+                    null_counts[i] += 1;
+                    continue;
+                }
+                if min[i].as_ref().map_or(true, |m| compare_values(value, m) == Some(Ordering::Less)) {
+                    min[i] = Some(value.clone());
+                }
+                if max[i].as_ref().map_or(true, |m| compare_values(value, m) == Some(Ordering::Greater)) {
+                    max[i] = Some(value.clone());
+                }
+                distinct[i].insert(value);
+            }
+        }
+
+        let columns = (0..column_count)
+            .map(|i| ColumnStatistics {
+                null_count: null_counts[i],
+                min: min[i].take(),
+                max: max[i].take(),
+                distinct_count: distinct[i].estimate()
+            })
+            .collect();
+        Ok(TableStatistics { row_count, columns })
+    }
+}
+
+/// Number of bits of each hash used to choose a register of a `DistinctCounter`
+const DISTINCT_COUNTER_BITS: u32 = 12;
+
+/// Counts distinct values approximately in constant memory using the HyperLogLog algorithm. With
+/// 1024 registers the standard error of the estimate is about 3%.
+pub struct DistinctCounter {
+    registers: Vec<u8>
+}
+
+impl DistinctCounter {
+
+    pub fn new() -> Self {
+        DistinctCounter { registers: vec![0; 1 << DISTINCT_COUNTER_BITS] }
+    }
+
+    pub fn insert(&mut self, value: &Value) {
+        let mut hasher = DefaultHasher::new();
+        hash_value(value, &mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - DISTINCT_COUNTER_BITS)) as usize;
+        // position of the first set bit in the remaining bits, which are padded with a set bit so
+        // that the rank is at most 64 - DISTINCT_COUNTER_BITS + 1
+        let rank = ((hash << DISTINCT_COUNTER_BITS) | (1 << (DISTINCT_COUNTER_BITS - 1))).leading_zeros() + 1;
+        if rank as u8 > self.registers[register] {
+            self.registers[register] = rank as u8;
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+        let empty = self.registers.iter().filter(|&&r| r == 0).count();
+        // small cardinalities are estimated more accurately from the number of empty registers
+        if estimate <= 2.5 * m && empty > 0 {
+            (m * (m / empty as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+impl Default for DistinctCounter {
+    fn default() -> Self {
+        DistinctCounter::new()
+    }
+}
+
+/// Find the statistics of a column of a plan, when the column is read directly from an analyzed
+/// table. The statistics are returned along with those of the table.
+pub fn column_statistics<'a>(plan: &LogicalPlan, column: usize, ctx: &'a ExecutionContext) -> Option<(&'a TableStatistics, &'a ColumnStatistics)> {
+    match *plan {
+        LogicalPlan::TableScan { ref table_name, ref projection, .. } => {
+            let table = ctx.table_statistics(table_name)?;
+            let column = match *projection {
+                Some(ref projection) => *projection.get(column)?,
+                None => column
+            };
+            table.columns.get(column).map(|c| (table, c))
+        },
+        LogicalPlan::Selection { ref input, .. } | LogicalPlan::Sort { ref input, .. } |
+        LogicalPlan::Limit { ref input, .. } | LogicalPlan::Window { ref input, .. } => column_statistics(input, column, ctx),
+        LogicalPlan::Projection { ref expr, ref input, .. } => match *expr.get(column)?.strip_alias() {
+            Expr::TupleValue(i) => column_statistics(input, i, ctx),
+            _ => None
+        },
+        LogicalPlan::Join { ref left, ref right, .. } => {
+            let left_len = left.schema().columns.len();
+            if column < left_len {
+                column_statistics(left, column, ctx)
+            } else {
+                column_statistics(right, column - left_len, ctx)
+            }
+        },
+        _ => None
+    }
+}
+
+/// Estimate the number of distinct values of an expression over the rows of a plan, which
+/// produces `rows` rows
+fn estimate_distinct(plan: &LogicalPlan, expr: &Expr, rows: f64, ctx: &ExecutionContext) -> f64 {
+    match *expr.strip_alias() {
+        Expr::TupleValue(i) => match column_statistics(plan, i, ctx) {
+            Some((_, column)) => (column.distinct_count.max(1) as f64).min(rows),
+            None => rows
+        },
+        Expr::Literal(_) => 1.0,
+        _ => rows
+    }
+}
+
+/// Estimate the number of rows produced by a plan, or `None` when it reads a table that has not
+/// been analyzed
+pub fn estimate_rows(plan: &LogicalPlan, ctx: &ExecutionContext) -> Option<f64> {
+    match *plan {
+        LogicalPlan::TableScan { ref table_name, .. } => ctx.table_statistics(table_name).map(|s| s.row_count as f64),
+        LogicalPlan::EmptyRelation { .. } => Some(0.0),
+        LogicalPlan::CsvFile { .. } | LogicalPlan::Exchange { .. } => None,
+        LogicalPlan::Selection { ref expr, ref input, .. } =>
+            estimate_rows(input, ctx).map(|rows| rows * estimate_selectivity(expr, input, ctx)),
+        LogicalPlan::Projection { ref input, .. } | LogicalPlan::Sort { ref input, .. } |
+        LogicalPlan::Window { ref input, .. } => estimate_rows(input, ctx),
+        LogicalPlan::Limit { limit, offset, ref input, .. } =>
+            estimate_rows(input, ctx).map(|rows| (rows - offset as f64).max(0.0).min(limit as f64)),
+        LogicalPlan::Aggregate { ref group_expr, ref input, .. } => {
+            let rows = estimate_rows(input, ctx)?;
+            if group_expr.is_empty() {
+                return Some(1.0);
+            }
+            let groups = group_expr.iter().map(|e| estimate_distinct(input, e, rows, ctx)).product::<f64>();
+            Some(groups.min(rows))
+        },
+        LogicalPlan::Join { ref left, ref right, ref join_type, ref on, .. } => {
+            let left_rows = estimate_rows(left, ctx)?;
+            let right_rows = estimate_rows(right, ctx)?;
+            let key_distinct: Vec<(f64, f64)> = on.iter()
+                .map(|(l, r)| (estimate_distinct(left, l, left_rows, ctx), estimate_distinct(right, r, right_rows, ctx)))
+                .collect();
+            let rows = estimate_join_rows(left_rows, right_rows, &key_distinct);
+            Some(match *join_type {
+                JoinType::Inner | JoinType::Cross => rows,
+                JoinType::Left => rows.max(left_rows),
+                JoinType::Right => rows.max(right_rows),
+                JoinType::Full => rows.max(left_rows + right_rows)
+            })
+        }
+    }
+}
+
+/// Estimate the number of rows produced by an inner join, given the number of distinct values of
+/// the left and right key of each join condition. Each condition is assumed to match every value
+/// of the key with fewer distinct values.
+pub fn estimate_join_rows(left_rows: f64, right_rows: f64, key_distinct: &[(f64, f64)]) -> f64 {
+    key_distinct.iter().fold(left_rows * right_rows, |rows, &(l, r)| rows / l.max(r).max(1.0))
+}
+
+/// Estimate the fraction of the rows of a plan that satisfy a predicate
+pub fn estimate_selectivity(expr: &Expr, input: &LogicalPlan, ctx: &ExecutionContext) -> f64 {
+    match *expr {
+        Expr::BinaryExpr { ref left, op: Operator::And, ref right } =>
+            estimate_selectivity(left, input, ctx) * estimate_selectivity(right, input, ctx),
+        Expr::BinaryExpr { ref left, op: Operator::Or, ref right } => {
+            let (l, r) = (estimate_selectivity(left, input, ctx), estimate_selectivity(right, input, ctx));
+            l + r - l * r
+        },
+        Expr::Not(ref expr) => 1.0 - estimate_selectivity(expr, input, ctx),
+        Expr::IsNull(ref expr) => null_fraction(expr, input, ctx).unwrap_or(DEFAULT_SELECTIVITY),
+        Expr::IsNotNull(ref expr) => 1.0 - null_fraction(expr, input, ctx).unwrap_or(DEFAULT_SELECTIVITY),
+        Expr::BinaryExpr { ref left, ref op, ref right } => match (left.as_ref(), right.as_ref()) {
+            (&Expr::TupleValue(i), Expr::Literal(value)) => comparison_selectivity(i, op, value, input, ctx),
+            (Expr::Literal(value), &Expr::TupleValue(i)) => {
+                let op = match *op {
+                    Operator::Lt => Operator::Gt,
+                    Operator::LtEq => Operator::GtEq,
+                    Operator::Gt => Operator::Lt,
+                    Operator::GtEq => Operator::LtEq,
+                    ref op => op.clone()
+                };
+                comparison_selectivity(i, &op, value, input, ctx)
+            },
+            _ => DEFAULT_SELECTIVITY
+        },
+        Expr::Literal(Value::Boolean(true)) => 1.0,
+        Expr::Literal(_) => 0.0,
+        _ => DEFAULT_SELECTIVITY
+    }
+}
+
+fn null_fraction(expr: &Expr, input: &LogicalPlan, ctx: &ExecutionContext) -> Option<f64> {
+    match *expr {
+        Expr::TupleValue(i) => {
+            let (table, column) = column_statistics(input, i, ctx)?;
+            Some(if table.row_count == 0 { 0.0 } else { column.null_count as f64 / table.row_count as f64 })
+        },
+        _ => None
+    }
+}
+
+/// Estimate the selectivity of comparing a column with a literal value. Range comparisons of
+/// numeric columns assume that the values are uniformly distributed between the minimum and
+/// maximum.
+fn comparison_selectivity(column: usize, op: &Operator, value: &Value, input: &LogicalPlan, ctx: &ExecutionContext) -> f64 {
+    let statistics = column_statistics(input, column, ctx);
+    let not_null = statistics.map_or(1.0, |(table, column)|
+        if table.row_count == 0 { 0.0 } else { 1.0 - column.null_count as f64 / table.row_count as f64 });
+
+    let equality = match statistics {
+        Some((_, column)) if column.distinct_count > 0 => 1.0 / column.distinct_count as f64,
+        _ => DEFAULT_EQUALITY_SELECTIVITY
+    };
+    let below = statistics
+        .and_then(|(_, column)| match (column.min.as_ref(), column.max.as_ref()) {
+            (Some(min), Some(max)) => Some((to_f64(min)?, to_f64(max)?, to_f64(value)?)),
+            _ => None
+        })
+        .map(|(min, max, value)| if max > min { ((value - min) / (max - min)).clamp(0.0, 1.0) } else if value > min { 1.0 } else { 0.0 });
+
+    not_null * match *op {
+        Operator::Eq => equality,
+        Operator::NotEq => 1.0 - equality,
+        Operator::Lt | Operator::LtEq => below.unwrap_or(DEFAULT_RANGE_SELECTIVITY),
+        Operator::Gt | Operator::GtEq => below.map_or(DEFAULT_RANGE_SELECTIVITY, |b| 1.0 - b),
+        _ => DEFAULT_SELECTIVITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distinct_counter() {
+        for &n in &[0u64, 1, 10, 100, 10000] {
+            let mut counter = DistinctCounter::new();
+            // every value is inserted twice
+            for i in 0..n * 2 {
+                counter.insert(&Value::UnsignedLong(i % n.max(1)));
+            }
+            let estimate = counter.estimate() as f64;
+            assert!((estimate - n as f64).abs() <= (n as f64 * 0.05).max(1.0), "{} distinct values estimated as {}", n, estimate);
+        }
+    }
+
+    #[test]
+    fn test_analyze_table() {
+        let mut ctx = ExecutionContext::new();
+        ctx.define_schema("employees", &Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, true),
+            Field::new("manager_id", DataType::UnsignedLong, true),
+            Field::new("salary", DataType::Double, true)]));
+        assert!(ctx.table_statistics("employees").is_none());
+        assert!(ctx.sql("ANALYZE TABLE missing").is_err());
+
+        ctx.sql("ANALYZE TABLE employees").unwrap();
+        let statistics = ctx.table_statistics("employees").unwrap();
+        assert_eq!(5, statistics.row_count);
+        assert_eq!(ColumnStatistics {
+            null_count: 0,
+            min: Some(Value::UnsignedLong(1)),
+            max: Some(Value::UnsignedLong(5)),
+            distinct_count: 5
+        }, statistics.columns[0]);
+        assert_eq!(1, statistics.columns[1].null_count);
+        assert_eq!(Some(Value::String("Alice".to_string())), statistics.columns[1].min);
+        assert_eq!((2, 2), (statistics.columns[2].null_count, statistics.columns[2].distinct_count));
+        assert_eq!(Some(Value::Double(100.0)), statistics.columns[3].max);
+
+        // a filter on the id is estimated from its range and the filter on the manager from the
+        // number of distinct managers
+        let plan = ctx.sql("SELECT name FROM employees WHERE id > 4").unwrap().plan();
+        assert_eq!(Some(1.25), estimate_rows(&plan, &ctx));
+        let plan = ctx.sql("SELECT name FROM employees WHERE manager_id = 1").unwrap().plan();
+        assert_eq!(Some(1.5), estimate_rows(&plan, &ctx));
+
+        // redefining the table discards its statistics
+        ctx.define_schema("employees", &Schema::new(vec![Field::new("id", DataType::UnsignedLong, false)]));
+        assert!(ctx.table_statistics("employees").is_none());
+    }
+}
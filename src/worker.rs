@@ -0,0 +1,437 @@
+// Copyright 2018 Grove Enterprises LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Worker HTTP service, which executes plans posted by clients and the coordinator
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::futures::{self, Future, Stream};
+use super::hyper::{self, Chunk, Method, StatusCode};
+use super::hyper::header::{ContentLength, ContentType};
+use super::hyper::server::{Request, Response, Service};
+use super::serde_json;
+
+use super::exec::*;
+use super::explain::*;
+use super::rel::*;
+use super::server::*;
+use super::shuffle::*;
+
+/// Worker HTTP service. Clones share the count of running queries, so a clone can be created for
+/// each connection.
+#[derive(Clone)]
+pub struct Worker {
+    www_root: String,
+    /// directory that the shuffle files written by this worker are kept in
+    shuffle_dir: PathBuf,
+    /// directory that posted plans read CSV files from
+    data_dir: PathBuf,
+    /// number of plans currently being executed, which is reported as the load of the worker
+    running_queries: Arc<AtomicUsize>
+}
+
+impl Worker {
+
+    /// Create a worker. Posted plans can read any CSV file within `data_dir`, so clients that can
+    /// reach the worker are trusted with the contents of that directory.
+    pub fn new(www_root: &str, shuffle_dir: &Path, data_dir: &Path) -> Self {
+        Worker {
+            www_root: www_root.to_string(),
+            shuffle_dir: shuffle_dir.to_path_buf(),
+            data_dir: data_dir.to_path_buf(),
+            running_queries: Arc::new(AtomicUsize::new(0))
+        }
+    }
+
+    /// Get the count of plans that are currently being executed
+    pub fn running_queries(&self) -> Arc<AtomicUsize> {
+        self.running_queries.clone()
+    }
+
+    fn load_static_file(&self, filename: &str) -> Option<String> {
+        let mut contents = String::new();
+        File::open(filename).ok()?.read_to_string(&mut contents).ok()?;
+        Some(contents)
+    }
+
+}
+
+/// Relation that counts as a running query until it is dropped, which is once its results have
+/// been streamed
+struct RunningQuery {
+    rel: Box<dyn SimpleRelation>,
+    running_queries: Arc<AtomicUsize>
+}
+
+impl RunningQuery {
+
+    fn new(rel: Box<dyn SimpleRelation>, running_queries: Arc<AtomicUsize>) -> Self {
+        running_queries.fetch_add(1, Ordering::SeqCst);
+        RunningQuery { rel, running_queries }
+    }
+}
+
+impl Drop for RunningQuery {
+    fn drop(&mut self) {
+        self.running_queries.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl SimpleRelation for RunningQuery {
+
+    fn scan<'a>(&'a self, ctx: &'a ExecutionContext) -> Box<dyn Iterator<Item=Result<Row, ExecutionError>> + 'a> {
+        self.rel.scan(ctx)
+    }
+
+    fn schema(&self) -> &Schema {
+        self.rel.schema()
+    }
+
+    fn describe(&self) -> String {
+        self.rel.describe()
+    }
+
+    fn inputs(&self) -> Vec<RelationRef<'_>> {
+        self.rel.inputs()
+    }
+
+    fn metrics(&self) -> Option<Metrics> {
+        self.rel.metrics()
+    }
+}
+
+/// Get from the `explain` query parameter whether to explain a plan instead of returning its
+/// results. `plan` explains the plan and `analyze` executes it and explains it with the metrics
+/// collected for each relation, which is returned as `Some(true)`.
+fn explain_from_query(query: Option<&str>) -> Result<Option<bool>, String> {
+    match query_param(query, "explain") {
+        None => Ok(None),
+        Some("plan") => Ok(Some(false)),
+        Some("analyze") => Ok(Some(true)),
+        Some(other) => Err(format!("Unknown explain mode '{}', expected 'plan' or 'analyze'", other))
+    }
+}
+
+/// Execute a plan posted by a client, or explain it when `explain` is set
+fn handle_request(chunk: Chunk, format: OutputFormat, explain: Option<bool>, shuffle_dir: PathBuf,
+                  data_dir: PathBuf, running_queries: Arc<AtomicUsize>) -> Box<dyn Future<Item=Response, Error=hyper::Error>> {
+
+    let plan: ExecutionPlan = match ::std::str::from_utf8(&chunk) {
+        Ok(json_str) => match serde_json::from_str(json_str) {
+            Ok(plan) => plan,
+            Err(e) => return Box::new(futures::future::ok(error_response(
+                StatusCode::BadRequest, "invalid_plan", &format!("Invalid plan: {}", e))))
+        },
+        Err(e) => return Box::new(futures::future::ok(error_response(
+            StatusCode::BadRequest, "invalid_plan", &format!("Plan is not valid UTF-8: {}", e))))
+    };
+
+    stream_results(format, move || {
+        let mut ctx = ExecutionContext::new();
+        ctx.set_shuffle_dir(&shuffle_dir);
+        ctx.set_data_dir(&data_dir);
+        let rel = match explain {
+            Some(analyze) => {
+                let lines = ctx.explain(&plan, analyze)?;
+                let plan = explain_results(&mut ctx, lines);
+                ctx.create_execution_plan(&plan)?
+            },
+            None => ctx.execute(&plan)?
+        };
+        Ok((ctx, Box::new(RunningQuery::new(rel, running_queries)) as Box<dyn SimpleRelation>))
+    })
+}
+
+/// Handle a request for the shuffle files written by this worker. `GET /shuffle/<id>/<partition>`
+/// returns the rows of a shuffle partition in the `values` format and `DELETE /shuffle/<id>`
+/// deletes the files for a shuffle.
+fn handle_shuffle_request(method: &Method, path: &str, shuffle_dir: &Path) -> Response {
+    let parts: Vec<&str> = path.trim_start_matches("/shuffle/").split('/').collect();
+    let result = match (method, parts.as_slice()) {
+        (&Method::Get, &[shuffle_id, partition]) => match partition.parse::<usize>() {
+            Ok(partition) => read_shuffle_partition(shuffle_dir, shuffle_id, partition),
+            Err(_) => Err(ExecutionError::InvalidRequest(format!("Invalid shuffle partition '{}'", partition)))
+        },
+        (&Method::Delete, &[shuffle_id]) => remove_shuffle(shuffle_dir, shuffle_id).map(|_| vec![]),
+        _ => return error_response(StatusCode::NotFound, "invalid_request", &format!("Unknown shuffle request {}", path))
+    };
+
+    match result {
+        Ok(data) => Response::new()
+            .with_header(ContentType("application/x-ndjson".parse().unwrap()))
+            .with_header(ContentLength(data.len() as u64))
+            .with_body(data),
+        Err(e) => execution_error_response(&e)
+    }
+}
+
+impl Service for Worker {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<dyn Future<Item=Self::Response, Error=Self::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+
+        if req.path().starts_with("/shuffle/") {
+            return Box::new(futures::future::ok(handle_shuffle_request(req.method(), req.path(), &self.shuffle_dir)));
+        }
+
+        match req.method() {
+            &Method::Get => { // all UI calls are GET
+
+                //println!("path={:?}", req.path());
+
+                // is this a known file and/or valid path?
+                let filename = match req.path() {
+                    "/" => Some("/index.html"),
+                    "/css/main.css" => Some("/css/main.css"),
+                    _ => None
+                };
+
+                // server page, or a 404 not found error
+                let content = filename.and_then(|f| self.load_static_file(&format!("{}/{}", self.www_root, f)));
+                match content {
+                    Some(content) => {
+                        Box::new(futures::future::ok(
+                            Response::new()
+                                .with_header(ContentLength(content.len() as u64))
+                                .with_body(content)))
+                    }
+                    _ => {
+                        let fqpath = format!("{}/{}", self.www_root, "/404.html");
+                        let content = self.load_static_file(&fqpath).unwrap_or_default();
+                        Box::new(futures::future::ok(
+                            Response::new()
+                                .with_status(StatusCode::NotFound)
+                                .with_header(ContentLength(content.len() as u64))
+                                .with_body(content)))
+                    }
+                }
+            }
+            &Method::Post => { // all REST calls are POST
+                match (OutputFormat::from_query(req.query()), explain_from_query(req.query())) {
+                    (Ok(format), Ok(explain)) => {
+                        let shuffle_dir = self.shuffle_dir.clone();
+                        let data_dir = self.data_dir.clone();
+                        let running_queries = self.running_queries.clone();
+                        Box::new(
+                            req.body()
+                                .concat2()
+                                .and_then(move |chunk| handle_request(chunk, format, explain, shuffle_dir, data_dir, running_queries))
+                        )
+                    },
+                    (Err(message), _) => Box::new(futures::future::ok(
+                        error_response(StatusCode::BadRequest, "invalid_format", &message))),
+                    (_, Err(message)) => Box::new(futures::future::ok(
+                        error_response(StatusCode::BadRequest, "invalid_request", &message)))
+                }
+            }
+            _ => {
+                Box::new(futures::future::ok(
+                    Response::new().with_status(StatusCode::NotFound)
+                ))
+            }
+
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn shuffle_dir() -> PathBuf {
+        env::temp_dir().join(format!("datafusion_worker_test_{}", process::id()))
+    }
+
+    fn call(method: Method, path: &str) -> (StatusCode, String) {
+        let worker = Worker::new("./src/bin/worker/", &shuffle_dir(), Path::new("."));
+        let response = worker.call(Request::new(method, path.parse().unwrap())).wait().unwrap();
+        let status = response.status();
+        let body = response.body().concat2().wait().unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn post(path: &str, body: String) -> (StatusCode, String) {
+        let worker = Worker::new("./src/bin/worker/", &shuffle_dir(), Path::new("."));
+        let mut req = Request::new(Method::Post, path.parse().unwrap());
+        req.set_body(body);
+        let response = worker.call(req).wait().unwrap();
+        let status = response.status();
+        let body = response.body().concat2().wait().unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn people_plan(filename: &str) -> String {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false)]);
+
+        let plan = LogicalPlan::Limit {
+            limit: 2,
+            offset: 0,
+            input: Box::new(LogicalPlan::CsvFile { filename: filename.to_string(), schema: schema.clone(), projection: None, options: CsvOptions::default() }),
+            schema
+        };
+
+        serde_json::to_string(&ExecutionPlan::Interactive { plan }).unwrap()
+    }
+
+    #[test]
+    fn test_execute_plan() {
+        let (status, body) = post("/", people_plan("test/data/people.csv"));
+        assert_eq!(StatusCode::Ok, status);
+        assert_eq!("{\"id\":1,\"name\":\"Andy\"}\n{\"id\":2,\"name\":\"Brian\"}\n", body);
+
+        let (status, body) = post("/?format=csv", people_plan("test/data/people.csv"));
+        assert_eq!(StatusCode::Ok, status);
+        assert_eq!("id,name\n1,Andy\n2,Brian\n", body);
+
+        let (status, body) = post("/?format=values", people_plan("test/data/people.csv"));
+        assert_eq!(StatusCode::Ok, status);
+        assert_eq!("[{\"UnsignedLong\":1},{\"String\":\"Andy\"}]\n[{\"UnsignedLong\":2},{\"String\":\"Brian\"}]\n", body);
+    }
+
+    #[test]
+    fn test_explain_plan() {
+        let (status, body) = post("/?explain=plan&format=csv", people_plan("test/data/people.csv"));
+        assert_eq!(StatusCode::Ok, status);
+        // lines containing commas are quoted
+        assert_eq!("plan\nLogical plan:\n\"  Limit: limit=2, offset=0\"\n    CsvFile: test/data/people.csv\n\
+                    Physical plan:\n\"  LimitRelation: limit=2, offset=0\"\n    BatchesToRows\n\
+                    \"      CsvBatchRelation: columns=[id, name]\"\n", body);
+
+        // the limit stops reading once two rows have been returned
+        let (status, body) = post("/?explain=analyze&format=csv", people_plan("test/data/people.csv"));
+        assert_eq!(StatusCode::Ok, status);
+        assert!(body.contains("\"  LimitRelation: limit=2, offset=0 [rows_in=2, rows_out=2, elapsed="));
+
+        let (status, _) = post("/?explain=verbose", people_plan("test/data/people.csv"));
+        assert_eq!(StatusCode::BadRequest, status);
+    }
+
+    #[test]
+    fn test_execute_partition() {
+        let schema = Schema::new(vec![
+            Field::new("order_id", DataType::UnsignedLong, false),
+            Field::new("customer_id", DataType::UnsignedLong, false),
+            Field::new("amount", DataType::Double, false)]);
+        let scan = LogicalPlan::CsvFile { filename: "test/data/orders.csv".to_string(), schema, projection: None, options: CsvOptions::default() };
+
+        let lines = (0..3)
+            .map(|partition| {
+                let plan = ExecutionPlan::Partition {
+                    plan: scan.clone(),
+                    partition_count: 3,
+                    partition_expr: None,
+                    partition: Some(partition),
+                    shuffle_id: None
+                };
+                let (status, body) = post("/?format=csv", serde_json::to_string(&plan).unwrap());
+                assert_eq!(StatusCode::Ok, status);
+                body.lines().skip(1).count()
+            })
+            .sum::<usize>();
+
+        assert_eq!(7, lines);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (status, body) = post("/", "not a plan".to_string());
+        assert_eq!(StatusCode::BadRequest, status);
+        let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!("invalid_plan", error["error"]["kind"]);
+        assert_eq!(400, error["error"]["status"]);
+
+        let (status, _) = post("/?format=xml", people_plan("test/data/people.csv"));
+        assert_eq!(StatusCode::BadRequest, status);
+
+        let (status, body) = post("/", people_plan("test/data/no_such_file.csv"));
+        assert_eq!(StatusCode::NotFound, status);
+        assert_eq!(Some("No such file or directory (os error 2)".to_string()), parse_error_json(&body));
+
+        // plans can only read files within the data directory
+        let cwd = env::current_dir().unwrap();
+        for filename in &[cwd.join("test/data/people.csv").to_string_lossy().to_string(), "test/../test/data/people.csv".to_string()] {
+            let (status, body) = post("/", people_plan(filename));
+            assert_eq!(StatusCode::Forbidden, status);
+            assert_eq!(Some(format!("Cannot read {}, only files within the data directory can be read", filename)),
+                       parse_error_json(&body));
+        }
+    }
+
+    #[test]
+    fn test_error_after_first_row() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, true),
+            Field::new("manager_id", DataType::UnsignedLong, true),
+            Field::new("salary", DataType::Double, false)]);
+        let plan = LogicalPlan::CsvFile { filename: "test/data/employees.csv".to_string(), schema, projection: None, options: CsvOptions::default() };
+
+        // the second employee has no salary, which is found after the first row has been read
+        let (status, body) = post("/", serde_json::to_string(&ExecutionPlan::Interactive { plan }).unwrap());
+        assert_eq!(StatusCode::Ok, status);
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(2, lines.len());
+        assert_eq!("{\"id\":1,\"manager_id\":null,\"name\":\"Alice\",\"salary\":100.0}", lines[0]);
+        let error: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!("null_value", error["error"]["kind"]);
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UnsignedLong, false),
+            Field::new("name", DataType::String, false)]);
+        let plan = ExecutionPlan::Partition {
+            plan: LogicalPlan::CsvFile { filename: "test/data/people.csv".to_string(), schema, projection: None, options: CsvOptions::default() },
+            partition_count: 2,
+            partition_expr: Some(Expr::TupleValue(0)),
+            partition: None,
+            shuffle_id: Some("worker_test".to_string())
+        };
+
+        // the response has the number of rows written to each shuffle partition
+        let (status, body) = post("/?format=csv", serde_json::to_string(&plan).unwrap());
+        assert_eq!(StatusCode::Ok, status);
+        let counts: Vec<usize> = body.lines().skip(1)
+            .map(|line| line.split(',').nth(1).unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(10, counts.iter().sum::<usize>());
+
+        for (partition, &count) in counts.iter().enumerate() {
+            let (status, body) = call(Method::Get, &format!("/shuffle/worker_test/{}", partition));
+            assert_eq!(StatusCode::Ok, status);
+            assert_eq!(count, body.lines().count());
+        }
+
+        let (status, _) = call(Method::Get, "/shuffle/worker_test/x");
+        assert_eq!(StatusCode::BadRequest, status);
+
+        let (status, _) = call(Method::Delete, "/shuffle/worker_test");
+        assert_eq!(StatusCode::Ok, status);
+        let (status, _) = call(Method::Get, "/shuffle/worker_test/0");
+        assert_eq!(StatusCode::NotFound, status);
+    }
+}